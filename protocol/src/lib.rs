@@ -0,0 +1,815 @@
+// rustigo-protocol holds the types exchanged between the server and its clients: the requests
+// sent to the game routes and the events published on a game's event stream.
+
+pub use schemars::schema::RootSchema;
+use schemars::{schema_for, JsonSchema, JsonSchema_repr};
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use uuid::Uuid;
+
+// Coordinate is an (x, y) position on the board, (0, 0) is the top left corner.
+pub type Coordinate = (i8, i8);
+
+#[derive(Eq, PartialEq, Hash, Debug, Copy, Serialize, Deserialize, Clone, JsonSchema)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromFormField))]
+pub enum Stone {
+    Black,
+    White,
+}
+
+#[derive(Serialize_repr, Deserialize_repr, JsonSchema_repr, Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromFormField))]
+#[repr(u8)]
+pub enum Size {
+    #[cfg_attr(feature = "rocket", field(value = "9"))]
+    Nine = 9,
+    #[cfg_attr(feature = "rocket", field(value = "13"))]
+    Thirteen = 13,
+    #[cfg_attr(feature = "rocket", field(value = "17"))]
+    Seventeen = 17,
+    #[cfg_attr(feature = "rocket", field(value = "19"))]
+    Nineteen = 19,
+}
+
+impl Size {
+    // ALL lists every supported size, smallest first.
+    pub const ALL: [Size; 4] = [Size::Nine, Size::Thirteen, Size::Seventeen, Size::Nineteen];
+}
+
+impl TryFrom<usize> for Size {
+    type Error = ();
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            9 => Ok(Size::Nine),
+            13 => Ok(Size::Thirteen),
+            17 => Ok(Size::Seventeen),
+            19 => Ok(Size::Nineteen),
+            _ => Err(()),
+        }
+    }
+}
+
+// GamePhase tracks the lifecycle of a game, stones may only be placed while Playing.
+#[derive(Eq, PartialEq, Debug, Copy, Serialize, Deserialize, Clone, JsonSchema)]
+pub enum GamePhase {
+    // Handicap is entered when Black places free handicap stones before White's first move.
+    Handicap,
+    Playing,
+    // Scoring is entered after both players pass in succession, dead stones are marked until
+    // both players accept the score.
+    Scoring,
+    Finished,
+}
+
+// ScoringRules selects how a finished game is counted.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromFormField))]
+pub enum ScoringRules {
+    // Japanese counts surrounded territory plus prisoners.
+    #[cfg_attr(feature = "rocket", field(value = "japanese"))]
+    Japanese,
+    // Chinese counts stones on the board plus surrounded territory.
+    #[cfg_attr(feature = "rocket", field(value = "chinese"))]
+    Chinese,
+}
+
+// Opponent is who the creator of a game plays against, another person unless it is the server's
+// engine or a bot on the bot API.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromFormField))]
+pub enum Opponent {
+    Human,
+    Engine,
+    Bot,
+}
+
+// Color is the stone the creator of a game plays. With Random the stones are drawn when their
+// opponent is accepted.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromFormField))]
+pub enum Color {
+    Black,
+    White,
+    Random,
+}
+
+// Action is what a player did with their turn.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum Action {
+    Play(Coordinate),
+    // Handicap is a free handicap stone placed by Black before White's first move.
+    Handicap(Coordinate),
+    Pass,
+}
+
+// Move records a single turn of the game.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Move {
+    pub stone: Stone,
+    pub action: Action,
+    // captured lists the stones removed by the move. For a suicide these are the player's own
+    // stones, including the position played.
+    pub captured: Vec<Coordinate>,
+    // played_at is when the move was played, in milliseconds since the UNIX epoch. It isn't known
+    // for moves imported from a record.
+    #[serde(default)]
+    pub played_at: Option<u64>,
+}
+
+// Score is the result of counting a game, black and white include territory and prisoners.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Score {
+    pub black: f32,
+    pub white: f32,
+    // winner is None when the game is a draw (jigo).
+    pub winner: Option<Stone>,
+    pub margin: f32,
+}
+
+// PlayerClock is the time a player has left, in milliseconds. `main` is their main time, once it
+// runs out they play in overtime: `periods` byo-yomi periods of `period` each, or `stones` moves
+// within the `period` left under Canadian timing.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PlayerClock {
+    pub main: u64,
+    pub periods: u32,
+    pub period: u64,
+    pub stones: u32,
+}
+
+// GameStateMessage is an event published on a game's event stream, tagged with its `type`.
+//
+// e.g:
+//  {"type": "pass", "stone": "Black", "board": "..."}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GameStateMessage {
+    Join {
+        id: Uuid,
+    },
+    JoinAccepted {
+        id: Uuid,
+        size: u8,
+        // stone is the joining player's, the player who created the game holds the other.
+        stone: Stone,
+    },
+    // Update is a stone played, `position` and `stone` are missing when it only replaces the
+    // board. `move_number` counts the moves played so far, passes included, and `turn` is the
+    // player to move next.
+    Update {
+        board: String,
+        #[serde(default)]
+        position: Option<Coordinate>,
+        #[serde(default)]
+        stone: Option<Stone>,
+        // captured lists the positions of the stones removed by the play.
+        captured: Vec<Coordinate>,
+        // atari lists the stones, of either color, in chains left with a single liberty.
+        #[serde(default)]
+        atari: Vec<Coordinate>,
+        // black_prisoners and white_prisoners count the stones captured by each player.
+        #[serde(default)]
+        black_prisoners: usize,
+        #[serde(default)]
+        white_prisoners: usize,
+        #[serde(default)]
+        move_number: usize,
+        #[serde(default)]
+        turn: Option<Stone>,
+    },
+    Pass {
+        stone: Stone,
+        board: String,
+    },
+    Phase {
+        phase: GamePhase,
+    },
+    Score {
+        score: Score,
+    },
+    Dead {
+        board: String,
+        dead: Vec<Coordinate>,
+    },
+    Accept {
+        stone: Stone,
+        board: String,
+    },
+    UndoRequest {
+        stone: Stone,
+        board: String,
+    },
+    Undo {
+        accepted: bool,
+        board: String,
+    },
+    // Maintenance announces planned downtime to every game, an empty announcement means the
+    // server is back to normal.
+    Maintenance {
+        announcement: Option<String>,
+    },
+    // Resync replaces the client's board after it missed events, e.g. by falling behind.
+    Resync {
+        board: String,
+    },
+    // Spectators counts the people watching a game without a seat in it.
+    Spectators {
+        count: usize,
+    },
+    // Chat is a line said in a game's chat, `from` is None for spectators, whose chat is sent as
+    // a separate `spectator_chat` event so players can mute it. `at` is in milliseconds since the
+    // UNIX epoch.
+    Chat {
+        from: Option<Stone>,
+        text: String,
+        at: u64,
+    },
+    // Resign is a player giving up the game, which is then finished with their opponent the
+    // winner.
+    Resign {
+        stone: Stone,
+        board: String,
+    },
+    // Clock is the time each player has left after a move, sent while the game is timed.
+    // `running` is the player whose time is counting down.
+    Clock {
+        black: PlayerClock,
+        white: PlayerClock,
+        running: Option<Stone>,
+    },
+    // ClockSync is the time each player has left at `server_time`, overtime included, sent
+    // periodically while a player's time is running so clients can correct their countdowns.
+    // Times are in milliseconds, `server_time` since the UNIX epoch.
+    ClockSync {
+        black_ms: u64,
+        white_ms: u64,
+        server_time: u64,
+    },
+    // TimeWarning warns the player whose time is running that it is running out, from the
+    // server's clock so every client warns at the same time. `remaining_ms` is the time left
+    // before they next lose time, their main time or a byo-yomi period, `threshold_ms` the
+    // warning it fell to. `last_period` is set while they play their last byo-yomi period, a
+    // warning without a threshold is them starting it.
+    TimeWarning {
+        stone: Stone,
+        remaining_ms: u64,
+        threshold_ms: Option<u64>,
+        last_period: bool,
+    },
+    // Timeout is a player running out of time, which finishes the game with their opponent the
+    // winner.
+    Timeout {
+        stone: Stone,
+        board: String,
+    },
+    // GameExpired tells the game's subscribers it was left idle too long and is no longer being
+    // served. `archived` is set for finished games, which are kept in the store.
+    GameExpired {
+        archived: bool,
+    },
+}
+
+impl GameStateMessage {
+    // event_type names the event on the SSE stream, so clients can dispatch without parsing it.
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            GameStateMessage::Join { .. } => "join",
+            GameStateMessage::JoinAccepted { .. } => "join_accepted",
+            GameStateMessage::Update { .. } => "update",
+            GameStateMessage::Pass { .. } => "pass",
+            GameStateMessage::Phase { .. } => "phase",
+            GameStateMessage::Score { .. } => "score",
+            GameStateMessage::Dead { .. } => "dead",
+            GameStateMessage::Accept { .. } => "accept",
+            GameStateMessage::UndoRequest { .. } => "undo_request",
+            GameStateMessage::Undo { .. } => "undo",
+            GameStateMessage::Maintenance { .. } => "maintenance",
+            GameStateMessage::Resync { .. } => "resync",
+            GameStateMessage::Spectators { .. } => "spectators",
+            GameStateMessage::Chat { from: None, .. } => "spectator_chat",
+            GameStateMessage::Chat { .. } => "chat",
+            GameStateMessage::Resign { .. } => "resign",
+            GameStateMessage::Clock { .. } => "clock",
+            GameStateMessage::ClockSync { .. } => "clock_sync",
+            GameStateMessage::TimeWarning { .. } => "time_warning",
+            GameStateMessage::Timeout { .. } => "timeout",
+            GameStateMessage::GameExpired { .. } => "game_expired",
+        }
+    }
+}
+
+// GameEventMessage is an event as sent on the event stream, along with the game it is about and
+// its number in that game's events. Server wide events have no game and are numbered 0.
+//
+// e.g:
+//  {"game_id": "...", "seq": 12, "type": "pass", "stone": "Black", "board": "..."}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GameEventMessage {
+    pub game_id: Option<Uuid>,
+    pub seq: u64,
+    #[serde(flatten)]
+    pub state: GameStateMessage,
+}
+
+// ErrorMessage is the body of every error response, the same for each API.
+//
+// e.g:
+//  {"error": {"code": "NotYourTurn", "message": "It's not your turn", "details": []}}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorMessage {
+    pub error: ErrorBody,
+}
+
+impl ErrorMessage {
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> ErrorMessage {
+        ErrorMessage {
+            error: ErrorBody {
+                code: code.into(),
+                message: message.into(),
+                details: Vec::new(),
+            },
+        }
+    }
+
+    // with_details adds more about what was wrong, e.g. which values would have been accepted.
+    pub fn with_details(mut self, details: Vec<String>) -> ErrorMessage {
+        self.error.details = details;
+        self
+    }
+}
+
+// ErrorBody describes why a request was rejected, `code` is a stable name for clients to match on
+// and `message` is meant for players.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    #[serde(default)]
+    pub details: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct AcceptPlayerMessage {
+    pub size: Size,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct JoinMessage {}
+
+// PlacePieceMessage asks to place a stone. The server plays it on its own copy of the game, with
+// the stone of the player's seat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PlacePieceMessage {
+    // coordinate is the position to play, alternatively `vertex` may be given in letter + number
+    // notation, e.g. "D4".
+    #[serde(default)]
+    pub coordinate: Option<Coordinate>,
+    #[serde(default)]
+    pub vertex: Option<String>,
+    // origin is the top left corner of the client's viewport, `coordinate` is relative to it.
+    #[serde(default)]
+    pub origin: Option<Coordinate>,
+}
+
+// PassMessage and the other messages about a player's own turn name no stone, the server uses
+// the one of the player's seat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PassMessage {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ResignMessage {}
+
+// DeadStonesMessage toggles whether the chain at a position is dead during scoring.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct DeadStonesMessage {
+    #[serde(default)]
+    pub coordinate: Option<Coordinate>,
+    #[serde(default)]
+    pub vertex: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct AcceptScoreMessage {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct UndoRequestMessage {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct UndoAcceptMessage {
+    // accepted is false when the opponent declines the takeback.
+    pub accepted: bool,
+}
+
+// PreviewMessage asks what placing a stone would do without playing it, `stone` defaults to the
+// player whose turn it is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PreviewMessage {
+    #[serde(default)]
+    pub coordinate: Option<Coordinate>,
+    #[serde(default)]
+    pub vertex: Option<String>,
+    #[serde(default)]
+    pub stone: Option<Stone>,
+}
+
+// PreviewResultMessage is the game as it would be after a previewed play. For an illegal play
+// `error` says why and the board is unchanged.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PreviewResultMessage {
+    pub legal: bool,
+    pub board: String,
+    pub captured: Vec<Coordinate>,
+    pub atari: Vec<Coordinate>,
+    pub error: Option<ErrorBody>,
+}
+
+// SetupMessage is a position composed in the board editor, `turn` is the player to move first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SetupMessage {
+    pub size: Size,
+    pub stones: Vec<(Coordinate, Stone)>,
+    pub turn: Stone,
+}
+
+// NewGameMessage starts a game, everything but the size is optional. The creator plays Black
+// against another person unless `color` and `vs` say otherwise, `time` is a time control such as
+// `10m+5x30s` and `warn` when players are warned their time is running out, e.g. `30s,10s,last`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct NewGameMessage {
+    pub size: Size,
+    pub rules: Option<ScoringRules>,
+    pub komi: Option<f32>,
+    pub handicap: Option<u8>,
+    // free lets Black place the handicap stones anywhere instead of on the star points.
+    pub free: Option<bool>,
+    pub suicide: Option<bool>,
+    pub vs: Option<Opponent>,
+    pub color: Option<Color>,
+    pub time: Option<String>,
+    pub warn: Option<String>,
+}
+
+// GameCreatedMessage names a newly started game.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct GameCreatedMessage {
+    pub id: Uuid,
+}
+
+// MaintenanceMessage starts maintenance mode with an announcement for players.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct MaintenanceMessage {
+    pub announcement: String,
+}
+
+// HealthMessage answers a liveness probe, the server is up if it answers at all.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct HealthMessage {
+    pub status: String,
+}
+
+// ReadinessMessage answers a readiness probe, the server is `ready` for players when its store
+// can be written to and its events reach the other servers. `games` counts the games being
+// played on this server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ReadinessMessage {
+    pub ready: bool,
+    pub storage: bool,
+    pub events: bool,
+    pub games: usize,
+}
+
+// ViewportMessage is a region of the board for clients too small to show all of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ViewportMessage {
+    // origin is the top left corner of the region, on the board.
+    pub origin: Coordinate,
+    pub width: u8,
+    pub height: u8,
+    // tiles holds the region row by row, `b`, `w` or `.` as in the board string.
+    pub tiles: String,
+    pub turn: Stone,
+    // minimap summarizes the whole board in blocks of `block` x `block` points, one string per
+    // row of blocks, `b` or `w` where one color has more stones, `=` where they are level and `.`
+    // where the block is empty.
+    pub block: u8,
+    pub minimap: Vec<String>,
+}
+
+// GameSnapshotMessage is the whole current state of a game, for clients that can't wait for the
+// next event, e.g. bots or spectators joining part way through.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GameSnapshotMessage {
+    pub id: Uuid,
+    pub size: Size,
+    // tiles holds the board row by row, `b`, `w` or `.` as in the board string.
+    pub tiles: Vec<String>,
+    // board is the board string, as sent in events.
+    pub board: String,
+    pub turn: Stone,
+    pub phase: GamePhase,
+    pub move_number: usize,
+    // black_prisoners and white_prisoners count the stones captured by each player.
+    pub black_prisoners: usize,
+    pub white_prisoners: usize,
+    pub komi: f32,
+    pub handicap: u8,
+    pub rules: ScoringRules,
+    pub last_move: Option<Coordinate>,
+    // ko is where the player to move may not retake a stone just captured.
+    pub ko: Option<Coordinate>,
+    pub dead: Vec<Coordinate>,
+    // score is only given once play has stopped.
+    pub score: Option<Score>,
+}
+
+// MoveEntry is a move in a game's move list.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct MoveEntry {
+    // number counts the moves from 1.
+    pub number: usize,
+    pub stone: Stone,
+    pub action: Action,
+    // vertex is where the stone was placed in letter + number notation, e.g. "D4".
+    pub vertex: Option<String>,
+    pub captured: Vec<Coordinate>,
+    pub played_at: Option<u64>,
+}
+
+// MoveListMessage is a page of a game's moves, oldest first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct MoveListMessage {
+    // total is the number of moves in the game.
+    pub total: usize,
+    pub moves: Vec<MoveEntry>,
+    // next is the number of the first move on the next page, if there is one.
+    pub next: Option<usize>,
+}
+
+// ChatMessage says something in a game's chat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ChatMessage {
+    pub text: String,
+}
+
+// ChatEntry is a line of a game's chat, as in the `Chat` event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ChatEntry {
+    pub from: Option<Stone>,
+    pub text: String,
+    pub at: u64,
+}
+
+// ChatLogMessage is a game's chat so far, oldest first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ChatLogMessage {
+    pub lines: Vec<ChatEntry>,
+}
+
+// SeatDataMessage is what the server keeps about a player's seat in a game, as exported with their
+// data: their stone, None while it is still to be drawn, where they asked to be told of their
+// turns, when they are warned their time is running out and what they said in the chat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SeatDataMessage {
+    pub game_id: Uuid,
+    pub stone: Option<Stone>,
+    pub host: bool,
+    pub contact: Option<String>,
+    pub time_warnings: Option<String>,
+    pub chat: Vec<ChatEntry>,
+}
+
+// NotifyMessage asks for a player to be told when it becomes their turn, at a `mailto:` address
+// or an `http(s)://` webhook. None stops it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct NotifyMessage {
+    pub contact: Option<String>,
+}
+
+// TimeWarningsMessage chooses when a player is warned their time is running out, e.g.
+// `30s,10s,last`, None leaves it to the game. It is answered with the warnings the player gets.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TimeWarningsMessage {
+    pub warnings: Option<String>,
+}
+
+// TurnNotice is what a webhook is sent when it becomes its player's turn. deadline is when they
+// run out of time, in milliseconds since the UNIX epoch, if the game is timed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TurnNotice {
+    pub game_id: Uuid,
+    pub stone: Stone,
+    pub deadline: Option<u64>,
+    // url is the game's page, if the server knows where it is served from.
+    pub url: Option<String>,
+}
+
+// GameOverNotice is what a player's webhook is sent when their game is over. winner is None when
+// the game is a draw.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct GameOverNotice {
+    pub game_id: Uuid,
+    pub stone: Stone,
+    pub winner: Option<Stone>,
+    pub url: Option<String>,
+}
+
+// DeliveryCounts is what became of the notices sent on a channel since the server started:
+// delivered, tried again, given up on, or dropped as too many were waiting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct DeliveryCounts {
+    pub sent: u64,
+    pub retried: u64,
+    pub failed: u64,
+    pub dropped: u64,
+}
+
+// WebhookMessage registers a URL to be sent a game's joins, moves and results.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookMessage {
+    pub url: String,
+}
+
+// WebhookCreatedMessage is a registered webhook along with the secret its deliveries are signed
+// with. Each is sent with an `X-Rustigo-Signature` header of `sha256=` followed by the hex
+// HMAC-SHA256 of the body, keyed with the secret.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookCreatedMessage {
+    pub url: String,
+    pub secret: String,
+}
+
+// WebhookEvent is what a webhook is sent, `event` is the event's type as named on the event
+// stream and `at` is when it was sent, in milliseconds since the UNIX epoch. Deliveries are
+// retried, so they may arrive out of order or more than once.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookEvent {
+    pub game_id: Uuid,
+    pub event: String,
+    pub at: u64,
+    pub state: GameStateMessage,
+}
+
+// PolledEvent is an event served to a client polling for them, numbered and named as it is on
+// the event stream. Server wide events are numbered 0.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PolledEvent {
+    pub seq: u64,
+    pub event: String,
+    pub state: GameStateMessage,
+}
+
+// BotMessage registers a bot, by name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct BotMessage {
+    pub name: String,
+}
+
+// BotTokenMessage is the token a registered bot authenticates with, as a bearer token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct BotTokenMessage {
+    pub name: String,
+    pub token: String,
+}
+
+// TurnMessage is a move sent to the API by a player or a bot, a vertex in letter + number
+// notation (e.g. D4), `pass`, `resign` or `accept` to agree to the marked score.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TurnMessage {
+    #[serde(rename = "move")]
+    pub vertex: String,
+}
+
+// GameEntry is a game listed in the lobby.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct GameEntry {
+    pub id: Uuid,
+    pub size: Size,
+    // created_at is when the server first saw the game, in milliseconds since the UNIX epoch.
+    pub created_at: Option<u64>,
+    // join is the path of the page to join the game from.
+    pub join: String,
+}
+
+// GameListMessage lists games, newest first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct GameListMessage {
+    pub games: Vec<GameEntry>,
+}
+
+// LedgerEvent is something that happened in a game. A game's ledger starts with the game being
+// created, playing every later event on that board brings it back to where it is now.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum LedgerEvent {
+    // GameCreated holds the board the ledger starts from, in the compact wire format.
+    GameCreated { board: String },
+    // PlayerJoined is the opponent of whoever created the game taking their seat.
+    PlayerJoined { stone: Stone },
+    StonePlayed { stone: Stone, position: Coordinate },
+    Passed { stone: Stone },
+    Resigned { stone: Stone },
+    DeadToggled { position: Coordinate },
+    ScoreAccepted { stone: Stone },
+    UndoRequested { stone: Stone },
+    UndoAnswered { stone: Stone, accepted: bool },
+    TimedOut { stone: Stone },
+}
+
+// LedgerEntry is an event in a game's ledger, `at` is when it happened in milliseconds since the
+// UNIX epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct LedgerEntry {
+    pub at: Option<u64>,
+    pub event: LedgerEvent,
+}
+
+// LedgerMessage is a game's ledger up to some point, oldest first, along with the board it
+// leads to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct LedgerMessage {
+    pub entries: Vec<LedgerEntry>,
+    pub board: String,
+}
+
+// schema generates the JSON Schema of every event on the stream, for clients written in other
+// languages.
+pub fn schema() -> RootSchema {
+    schema_for!(GameEventMessage)
+}
+
+#[test]
+fn test_size_serializes_as_number() {
+    assert_eq!("19", serde_json::to_string(&Size::Nineteen).unwrap());
+    assert_eq!(Size::Nine, serde_json::from_str::<Size>("9").unwrap());
+}
+
+#[test]
+fn test_event_round_trip() {
+    let event = GameStateMessage::Update {
+        board: "board".to_string(),
+        position: Some((2, 4)),
+        stone: Some(Stone::Black),
+        captured: vec![(3, 4)],
+        atari: vec![(3, 5)],
+        black_prisoners: 1,
+        white_prisoners: 0,
+        move_number: 12,
+        turn: Some(Stone::White),
+    };
+    let json = serde_json::to_string(&event).unwrap();
+    assert_eq!(
+        r#"{"type":"update","board":"board","position":[2,4],"stone":"Black","captured":[[3,4]],"atari":[[3,5]],"black_prisoners":1,"white_prisoners":0,"move_number":12,"turn":"White"}"#,
+        json
+    );
+    assert_eq!(event, serde_json::from_str(&json).unwrap());
+    assert_eq!("update", event.event_type());
+
+    // Updates from before the play was described still parse
+    let bare: GameStateMessage =
+        serde_json::from_str(r#"{"type":"update","board":"board","captured":[]}"#).unwrap();
+    assert_eq!(
+        GameStateMessage::Update {
+            board: "board".to_string(),
+            position: None,
+            stone: None,
+            captured: Vec::new(),
+            atari: Vec::new(),
+            black_prisoners: 0,
+            white_prisoners: 0,
+            move_number: 0,
+            turn: None,
+        },
+        bare
+    );
+
+    let numbered = GameEventMessage {
+        game_id: Some(Uuid::nil()),
+        seq: 7,
+        state: event,
+    };
+    let json = serde_json::to_string(&numbered).unwrap();
+    assert_eq!(
+        r#"{"game_id":"00000000-0000-0000-0000-000000000000","seq":7,"type":"update","board":"board","position":[2,4],"stone":"Black","captured":[[3,4]],"atari":[[3,5]],"black_prisoners":1,"white_prisoners":0,"move_number":12,"turn":"White"}"#,
+        json
+    );
+    assert_eq!(numbered, serde_json::from_str(&json).unwrap());
+}
+
+#[test]
+fn test_schema() {
+    let schema = serde_json::to_string(&schema()).unwrap();
+    for event in ["join", "update", "pass", "phase", "score", "undo"] {
+        assert_eq!(true, schema.contains(&format!("\"{}\"", event)));
+    }
+}
+
+#[test]
+fn test_chat_channels() {
+    let chat = |from| GameStateMessage::Chat {
+        from,
+        text: "Good game".to_string(),
+        at: 1700000000000,
+    };
+    assert_eq!("chat", chat(Some(Stone::White)).event_type());
+    assert_eq!("spectator_chat", chat(None).event_type());
+}
@@ -0,0 +1,32 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use server::board::{self, Size, Stone};
+
+// play_game fills a 19x19 board with a fixed sequence of plays, with captures along the way.
+fn play_game() -> board::Game {
+    let mut game = board::new(Size::Nineteen);
+    let mut stone = Stone::Black;
+    for step in 0..361u32 {
+        let index = (step * 7 + 3) % 361;
+        let position = ((index % 19) as i8, (index / 19) as i8);
+        if game.play_stone(position, stone).is_ok() {
+            stone = match stone {
+                Stone::Black => Stone::White,
+                Stone::White => Stone::Black,
+            };
+        }
+    }
+    game
+}
+
+fn bench_play_stone(c: &mut Criterion) {
+    c.bench_function("play_stone 19x19 game", |b| b.iter(|| black_box(play_game())));
+
+    let game = play_game();
+    c.bench_function("legal_moves 19x19", |b| {
+        b.iter(|| black_box(game.legal_moves(game.turn())))
+    });
+}
+
+criterion_group!(benches, bench_play_stone);
+criterion_main!(benches);
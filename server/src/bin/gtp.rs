@@ -0,0 +1,7 @@
+use std::io;
+
+// gtp plays Go over the Go Text Protocol on stdin and stdout, e.g. for GoGui:
+//  gogui -program "cargo run --bin gtp"
+fn main() -> io::Result<()> {
+    server::gtp::run(io::stdin().lock(), io::stdout().lock())
+}
@@ -1,185 +1,1101 @@
 use std::collections::btree_map::BTreeMap;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::fmt;
 use std::iter::Iterator;
 
-use rocket::form::FromFormField;
 use rocket::serde::uuid::Uuid;
-use serde_repr::*;
 use thiserror::Error;
 
-pub type Coordinate = (i8, i8);
+use crate::bots::{self, BotSeat};
+use crate::clock::{self, Clock, TimeControl, TimeWarnings};
+use crate::grid::Grid;
+use crate::notify::{self, Contact};
+use crate::webhook::{self, Webhook, WebhookError};
 
-#[derive(Eq, PartialEq, Debug, Copy, Serialize, Deserialize, Clone)]
-pub enum Stone {
-    Black,
-    White,
-}
+use crate::ledger;
 
-#[derive(Serialize_repr, Deserialize_repr, Debug, Copy, Clone, Eq, PartialEq, FromFormField)]
-#[repr(u8)]
-pub enum Size {
-    #[field(value = "9")]
-    Nine = 9,
-    #[field(value = "13")]
-    Thirteen = 13,
-    #[field(value = "17")]
-    Seventeen = 17,
-    #[field(value = "19")]
-    Nineteen = 19,
-}
+pub use rustigo_protocol::{
+    Action, ChatEntry, Coordinate, GamePhase, LedgerEntry, LedgerEvent, Move, Score, ScoringRules,
+    Size, Stone,
+};
 
-impl TryFrom<usize> for Size {
-    type Error = ();
+pub type StoneMap = BTreeMap<Coordinate, Stone>;
 
-    fn try_from(value: usize) -> Result<Self, Self::Error> {
-        match value {
-            9 => Ok(Size::Nine),
-            13 => Ok(Size::Thirteen),
-            17 => Ok(Size::Seventeen),
-            19 => Ok(Size::Nineteen),
-            _ => Err(()),
-        }
-    }
-}
+// DEFAULT_KOMI compensates White for Black playing first.
+pub const DEFAULT_KOMI: f32 = 6.5;
 
-type StoneMap = BTreeMap<Coordinate, Stone>;
+// CHAT_HISTORY is how many of a game's latest chat lines are kept with it.
+pub const CHAT_HISTORY: usize = 200;
 
+#[derive(Clone)]
 pub struct Game {
     pub id: Uuid,
-    board: StoneMap,
+    board: Grid,
     size: Size,
     turn: Stone,
+    // hash is the Zobrist hash of the current board position.
+    hash: u64,
+    // positions holds the hash of every whole-board position seen so far, for superko.
+    positions: HashSet<u64>,
+    // black_prisoners and white_prisoners count the stones captured by each player.
+    black_prisoners: usize,
+    white_prisoners: usize,
+    last_move: Option<(Coordinate, Stone)>,
+    // passes counts the consecutive passes since the last stone was placed.
+    passes: u8,
+    // move_number counts the moves played, passes and free handicap stones included. It is the
+    // length of the history, kept apart so the compact form, which leaves the history out, has it.
+    move_number: usize,
+    phase: GamePhase,
+    rules: ScoringRules,
+    // komi is the number of points added to White's score.
+    komi: f32,
+    // handicap is the number of stones Black placed before the game started.
+    handicap: u8,
+    // placements counts the free handicap stones Black has left to place.
+    placements: u8,
+    // suicide_allowed permits plays that capture their own chain, as under New Zealand rules.
+    suicide_allowed: bool,
+    // dead holds the stones marked dead during scoring, they count as captured by the opponent.
+    dead: BTreeSet<Coordinate>,
+    // black_accepted and white_accepted record which players have agreed to the marked score.
+    black_accepted: bool,
+    white_accepted: bool,
+    // history holds every move played, in order, handicap stones placed on the star points are
+    // not moves.
+    history: Vec<Move>,
+    // undo_requested is the player asking to take back their last move, if any.
+    undo_requested: Option<Stone>,
+    // host is the stone played by whoever created the game, None while it is left to be drawn
+    // when their opponent is accepted.
+    host: Option<Stone>,
+    // guest_seated records that the other seat has been taken, by a player who joined or the
+    // engine.
+    guest_seated: bool,
+    // black_token and white_token are the secrets issued to the players holding each seat, their
+    // moves must come with them. They are only kept with the history, never sent to players.
+    black_token: Option<String>,
+    white_token: Option<String>,
+    // host_token is the secret issued to the player who created the game while their stone is
+    // still to be drawn, it becomes that stone's token once it is.
+    host_token: Option<String>,
+    // pending_token is the secret handed to the player waiting to be accepted as the guest, it
+    // becomes their stone's token once they are.
+    pending_token: Option<String>,
+    // setup holds the stones composed in the board editor before the first move.
+    setup: StoneMap,
+    // created_at is when the server first saw the game, in milliseconds since the UNIX epoch.
+    created_at: Option<u64>,
+    // chat holds the latest lines said in the game's chat, oldest first.
+    chat: Vec<ChatEntry>,
+    // resigned is the player who gave up the game, if it ended that way.
+    resigned: Option<Stone>,
+    // clock is the time the players have left, in a timed game.
+    clock: Option<Clock>,
+    // warnings are when the players of a timed game are warned their time is running out, the
+    // defaults when None. black_warnings and white_warnings are what each player chose instead.
+    warnings: Option<TimeWarnings>,
+    black_warnings: Option<TimeWarnings>,
+    white_warnings: Option<TimeWarnings>,
+    // timed_out is the player who ran out of time, if the game ended that way.
+    timed_out: Option<Stone>,
+    // black_contact and white_contact are where each player asked to be told it is their turn.
+    // They are only kept with the history, never sent to players.
+    black_contact: Option<Contact>,
+    white_contact: Option<Contact>,
+    // webhooks are the URLs the game's creator had its events posted to, with the secrets they
+    // are signed with. They are only kept with the history, never sent to players.
+    webhooks: Vec<Webhook>,
+    // bot is the guest's seat when the game's creator asked to play a bot.
+    bot: Option<BotSeat>,
+    // ledger holds everything that happened in the game since the server started keeping it,
+    // starting with the board it was created on. It is empty until the ledger is opened.
+    ledger: Vec<LedgerEntry>,
 }
 
 pub fn new(size: Size) -> Game {
     Game {
         id: Uuid::new_v4(),
-        board: BTreeMap::new(),
+        board: Grid::new(size),
         size,
         turn: Stone::Black,
+        hash: 0,
+        positions: HashSet::from([0]),
+        black_prisoners: 0,
+        white_prisoners: 0,
+        last_move: None,
+        passes: 0,
+        move_number: 0,
+        phase: GamePhase::Playing,
+        rules: ScoringRules::Japanese,
+        komi: DEFAULT_KOMI,
+        handicap: 0,
+        placements: 0,
+        suicide_allowed: false,
+        dead: BTreeSet::new(),
+        black_accepted: false,
+        white_accepted: false,
+        history: Vec::new(),
+        undo_requested: None,
+        host: Some(Stone::Black),
+        guest_seated: false,
+        black_token: None,
+        white_token: None,
+        host_token: None,
+        pending_token: None,
+        created_at: None,
+        chat: Vec::new(),
+        setup: BTreeMap::new(),
+        resigned: None,
+        clock: None,
+        warnings: None,
+        black_warnings: None,
+        white_warnings: None,
+        timed_out: None,
+        black_contact: None,
+        white_contact: None,
+        webhooks: Vec::new(),
+        bot: None,
+        ledger: Vec::new(),
     }
 }
 
-// parse creates a new game from a simple human readable string representation.
-pub fn parse(board_str: &str, turn: Stone) -> Option<Game> {
-    let mut board = BTreeMap::new();
-    let lines: Vec<&str> = board_str.trim().split('\n').collect();
+// COLUMN_LABELS are the conventional board column names, the letter I is skipped to avoid
+// confusion with J.
+const COLUMN_LABELS: &str = "ABCDEFGHJKLMNOPQRST";
+
+// format_coordinate renders a coordinate using the conventional letter + number notation, where
+// rows are numbered from the bottom of the board.
+//
+// e.g:
+//  (3, 15) on 19x19 => "D4"
+pub fn format_coordinate(size: Size, (x, y): Coordinate) -> String {
+    let column = COLUMN_LABELS.chars().nth(x as usize).unwrap_or('?');
+    format!("{}{}", column, size as i8 - y)
+}
 
-    if lines.len() < (Size::Nine as usize) || lines.len() > (Size::Nineteen as usize) {
-        return None;
-    }
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum CoordinateError {
+    #[error("Enter a column letter followed by a row number, e.g. D4")]
+    Empty,
+    #[error("Column {0} is not on the board, columns run from A to {1} skipping I")]
+    InvalidColumn(char, char),
+    #[error("Row {0} is not on the board, rows run from 1 to {1}")]
+    InvalidRow(String, u8),
+}
 
-    let line_length = lines[0].trim().len();
-    if line_length != lines.len() {
-        return None;
-    }
+// parse_coordinate reads a coordinate written in letter + number notation, it is the inverse of
+// format_coordinate. Letters are case insensitive.
+//
+// e.g:
+//  "D4" on 19x19 => (3, 15)
+pub fn parse_coordinate(size: Size, text: &str) -> Result<Coordinate, CoordinateError> {
+    let text = text.trim();
+    let mut chars = text.chars();
+    let column_label = chars.next().ok_or(CoordinateError::Empty)?;
+
+    let extent = size as u8;
+    let last_column = COLUMN_LABELS
+        .chars()
+        .nth(extent as usize - 1)
+        .unwrap_or('?');
+    let x = COLUMN_LABELS
+        .chars()
+        .take(extent as usize)
+        .position(|label| label == column_label.to_ascii_uppercase())
+        .ok_or(CoordinateError::InvalidColumn(column_label, last_column))?;
+
+    let row_label = chars.as_str().trim();
+    let row = match row_label.parse::<u8>() {
+        Ok(row) if row >= 1 && row <= extent => row,
+        _ => return Err(CoordinateError::InvalidRow(row_label.to_string(), extent)),
+    };
+
+    Ok((x as i8, (extent - row) as i8))
+}
+
+// splitmix64 scrambles a 64 bit value, used to derive Zobrist keys without a lookup table.
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
 
-    let size: Size = line_length.try_into().ok()?;
+// zobrist_key returns the hash contribution of a stone at a position on a board of the given
+// size. Keys are deterministic so the same position always hashes to the same value.
+fn zobrist_key(size: Size, (x, y): Coordinate, stone: Stone) -> u64 {
+    let index = (y as u64) * (size as u64) + (x as u64);
+    let color = match stone {
+        Stone::Black => 0,
+        Stone::White => 1,
+    };
+    splitmix64(((size as u64) << 32) | (index << 1) | color)
+}
+
+// position_hash computes the Zobrist hash of a whole board from scratch.
+fn position_hash(size: Size, board: &Grid) -> u64 {
+    board.iter().fold(0, |hash, (position, stone)| {
+        hash ^ zobrist_key(size, position, stone)
+    })
+}
+
+// SYMMETRIES are the 8 rotations and reflections of a square board, each maps a coordinate given
+// the largest index on the board.
+const SYMMETRIES: [fn(Coordinate, i8) -> Coordinate; 8] = [
+    |(x, y), _| (x, y),
+    |(x, y), n| (n - y, x),
+    |(x, y), n| (n - x, n - y),
+    |(x, y), n| (y, n - x),
+    |(x, y), n| (n - x, y),
+    |(x, y), n| (x, n - y),
+    |(x, y), _| (y, x),
+    |(x, y), n| (n - y, n - x),
+];
+
+// parse creates a new game from a simple human readable string representation, a row of tiles per
+// line.
+pub fn parse(board_str: &str, turn: Stone) -> Result<Game, BoardParseError> {
+    let lines: Vec<&str> = board_str.trim().split('\n').map(str::trim).collect();
+
+    let size: Size = lines
+        .len()
+        .try_into()
+        .map_err(|_| BoardParseError::BadSize(lines.len().to_string()))?;
+    let mut board = Grid::new(size);
 
     for (y, line) in lines.iter().enumerate() {
-        for (x, tile) in line.chars().enumerate() {
+        let tiles: Vec<char> = line.chars().collect();
+        if tiles.len() != lines.len() {
+            return Err(BoardParseError::WrongLength {
+                expected: lines.len(),
+                found: tiles.len(),
+            });
+        }
+        for (x, tile) in tiles.into_iter().enumerate() {
+            let position = (x as i8, y as i8);
             match tile {
                 'b' => {
-                    board.insert((x as i8, y as i8), Stone::Black);
+                    board.insert(position, Stone::Black);
                 }
                 'w' => {
-                    board.insert((x as i8, y as i8), Stone::White);
+                    board.insert(position, Stone::White);
                 }
-                _ => (),
+                '.' => (),
+                _ => return Err(BoardParseError::BadTile { position, tile }),
             }
         }
     }
 
-    Some(Game {
+    let hash = position_hash(size, &board);
+    Ok(Game {
         id: Uuid::nil(),
         board,
-        size,
         turn,
+        hash,
+        positions: HashSet::from([hash]),
+        ..new(size)
     })
 }
 
-#[derive(Error, Debug)]
-pub enum DecodeError {
-    #[error("Invalid or missing game ID")]
-    InvalidGameID,
-    #[error("Invalid or encoding of game size")]
-    InvalidSizeEncoding,
-    #[error("Invalid game size")]
-    InvalidSize,
-    #[error("Invalid board state")]
-    InvalidState,
-    #[error("Board state truncated")]
-    InvalidStateTruncated,
-    #[error("Invalid turn")]
-    InvalidTurn,
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum BoardParseError {
+    #[error("The board has no {0} segment")]
+    MissingSegment(&'static str),
+    #[error("{0:?} is not a game ID")]
+    BadGameId(String),
+    #[error("{0:?} is not a board size, boards are 9, 13, 17 or 19 points wide")]
+    BadSize(String),
+    #[error("{0:?} is not a turn, it is either b or w")]
+    BadTurn(String),
+    #[error("Tile {tile:?} at {position:?} is not b, w or .")]
+    BadTile { position: Coordinate, tile: char },
+    #[error("Expected {expected} tiles but found {found}")]
+    WrongLength { expected: usize, found: usize },
+    #[error("Invalid {0} segment")]
+    BadSegment(String),
+    #[error("The position can't be reached by alternating play, {0}")]
+    ImpossiblePosition(&'static str),
 }
 
-// decode reads in the wire transfer format of the game.
-pub fn decode(game_str: &str) -> Result<Game, DecodeError> {
-    let segments: Vec<&str> = game_str.trim().split(';').collect();
+#[derive(Error, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SetupError {
+    #[error("Stone at {0:?} is off the board")]
+    OutOfBounds(Coordinate),
+    #[error("Stone at {0:?} has no liberties")]
+    NoLiberties(Coordinate),
+}
+
+// setup creates a game from a composed position, with `turn` to play first. Every chain must
+// have a liberty, the position has to be one that could arise in play.
+pub fn setup(
+    size: Size,
+    stones: impl IntoIterator<Item = (Coordinate, Stone)>,
+    turn: Stone,
+) -> Result<Game, SetupError> {
+    let mut game = new(size);
+    for (position, stone) in stones {
+        if !game.valid_coordinate(position) {
+            return Err(SetupError::OutOfBounds(position));
+        }
+        game.board.insert(position, stone);
+    }
+    for position in game.board.keys() {
+        if game.liberties(position) == 0 {
+            return Err(SetupError::NoLiberties(position));
+        }
+    }
+
+    game.hash = position_hash(size, &game.board);
+    game.positions = HashSet::from([game.hash]);
+    game.turn = turn;
+    game.setup = game.board.iter().collect();
+    Ok(game)
+}
+
+#[derive(Error, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HandicapError {
+    #[error("A handicap must be between 2 and 9 stones, not {0}")]
+    InvalidCount(u8),
+    #[error("Handicap stones can only be placed before the game starts")]
+    AlreadyStarted,
+}
+
+// handicap_points returns the star points used for a fixed handicap, in the conventional order of
+// placement.
+fn handicap_points(size: Size, stones: u8) -> Option<Vec<Coordinate>> {
+    let near = if size == Size::Nine { 2 } else { 3 };
+    let far = size as i8 - 1 - near;
+    let middle = size as i8 / 2;
+
+    let corners = [(far, near), (near, far), (far, far), (near, near)];
+    let sides = [(near, middle), (far, middle), (middle, near), (middle, far)];
+    let center = (middle, middle);
+
+    let mut points = match stones {
+        2..=4 => corners[..stones as usize].to_vec(),
+        5 => corners.to_vec(),
+        6 | 7 => [&corners[..], &sides[..2]].concat(),
+        8 | 9 => [&corners[..], &sides[..]].concat(),
+        _ => return None,
+    };
+    if stones % 2 == 1 && stones >= 5 {
+        points.push(center);
+    }
+    Some(points)
+}
+
+#[derive(Error, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PlayError {
+    #[error("Coordinate is outside of the board")]
+    OutOfBounds,
+    #[error("Position is already occupied")]
+    Occupied,
+    #[error("It is not this player's turn")]
+    NotYourTurn,
+    #[error("Stone would have no liberties")]
+    Suicide,
+    #[error("Play would repeat an earlier board position")]
+    KoViolation,
+    #[error("Game is no longer in play")]
+    NotPlaying,
+    #[error("Game is not being scored")]
+    NotScoring,
+    #[error("There is no stone at that position")]
+    NoStone,
+    #[error("There is no move to undo")]
+    NothingToUndo,
+    #[error("The opponent has not asked to undo a move")]
+    NoUndoRequest,
+}
+
+// Chain is a group of connected stones of one color and the empty points next to it, both in
+// board order.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Chain {
+    pub stones: Vec<Coordinate>,
+    pub liberties: Vec<Coordinate>,
+}
+
+// PlayOutcome describes the effects of a successful play.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct PlayOutcome {
+    // captured lists the positions of any stones removed from the board by the play.
+    pub captured: Vec<Coordinate>,
+}
+
+// parse_pair reads a comma separated pair of numbers, e.g. "3,15".
+fn parse_pair<T: std::str::FromStr>(value: &str) -> Option<(T, T)> {
+    let (first, second) = value.split_once(',')?;
+    Some((first.parse().ok()?, second.parse().ok()?))
+}
+
+// parse_move reads a move from the history segment.
+//
+// e.g:
+//  "b3,4" => Black plays (3, 4)
+//  "w2,3x3,4" => White plays (2, 3) capturing (3, 4)
+//  "bh2,2" => Black places a free handicap stone at (2, 2)
+//  "bpass" => Black passes
+//  "w4,4@1700000000000" => White plays (4, 4), at a time in milliseconds since the UNIX epoch
+fn parse_move(value: &str) -> Option<Move> {
+    let (value, played_at) = match value.split_once('@') {
+        Some((value, at)) => (value, Some(at.parse().ok()?)),
+        None => (value, None),
+    };
+    let mut chars = value.chars();
+    let stone = match chars.next()? {
+        'b' => Stone::Black,
+        'w' => Stone::White,
+        _ => return None,
+    };
+
+    let mut parts = chars.as_str().split('x');
+    let action = match parts.next()? {
+        "pass" => Action::Pass,
+        pair => match pair.strip_prefix('h') {
+            Some(pair) => Action::Handicap(parse_pair(pair)?),
+            None => Action::Play(parse_pair(pair)?),
+        },
+    };
+    let captured = parts.map(parse_pair).collect::<Option<Vec<_>>>()?;
+
+    Some(Move {
+        stone,
+        action,
+        captured,
+        played_at,
+    })
+}
+
+// format_move writes a move for the history segment, it is the inverse of parse_move.
+fn format_move(played: &Move) -> String {
+    let mut output = match played.stone {
+        Stone::Black => "b".to_string(),
+        Stone::White => "w".to_string(),
+    };
+    match played.action {
+        Action::Play((x, y)) => output.push_str(&format!("{},{}", x, y)),
+        Action::Handicap((x, y)) => output.push_str(&format!("h{},{}", x, y)),
+        Action::Pass => output.push_str("pass"),
+    }
+    for (x, y) in played.captured.iter() {
+        output.push_str(&format!("x{},{}", x, y));
+    }
+    if let Some(at) = played.played_at {
+        output.push_str(&format!("@{}", at));
+    }
+    output
+}
 
-    let id = match segments[0].parse::<Uuid>() {
-        Ok(id) => id,
-        _ => return Err(DecodeError::InvalidGameID),
+// parse_chat reads a line of chat from the chat segment, the text is hex encoded UTF-8 so it can
+// hold any of the separators.
+//
+// e.g:
+//  "b1700000000000@6869" => Black said "hi", at a time in milliseconds since the UNIX epoch
+//  "s1700000000000@6869" => A spectator said "hi"
+fn parse_chat(value: &str) -> Option<ChatEntry> {
+    let mut chars = value.chars();
+    let from = match chars.next()? {
+        'b' => Some(Stone::Black),
+        'w' => Some(Stone::White),
+        's' => None,
+        _ => return None,
     };
-    let size_value = match segments[1].parse::<usize>() {
-        Ok(size_value) => size_value,
-        _ => return Err(DecodeError::InvalidSizeEncoding),
+    let (at, hex) = chars.as_str().split_once('@')?;
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    Some(ChatEntry {
+        from,
+        text: String::from_utf8(bytes).ok()?,
+        at: at.parse().ok()?,
+    })
+}
+
+// format_chat writes a line of chat for the chat segment, it is the inverse of parse_chat.
+fn format_chat(line: &ChatEntry) -> String {
+    let from = match line.from {
+        Some(Stone::Black) => 'b',
+        Some(Stone::White) => 'w',
+        None => 's',
     };
-    let size: Size = match size_value.try_into() {
-        Ok(size) => size,
-        _ => return Err(DecodeError::InvalidSize),
+    let hex: String = line.text.bytes().map(|b| format!("{:02x}", b)).collect();
+    format!("{}{}@{}", from, line.at, hex)
+}
+
+// decode reads in the wire transfer format of the game.
+pub fn decode(game_str: &str) -> Result<Game, BoardParseError> {
+    let segments: Vec<&str> = game_str.trim().split(';').collect();
+    let segment = |index: usize, name| {
+        segments
+            .get(index)
+            .copied()
+            .ok_or(BoardParseError::MissingSegment(name))
     };
 
-    let mut board = BTreeMap::new();
-    let square = (size as usize) * (size as usize);
-    let tiles: Vec<char> = segments[2].chars().collect();
-    for index in 1..square {
-        let x = index % size_value;
-        let y = index / size_value;
-        match tiles.get(index) {
-            Some('b') => {
-                board.insert((x as i8, y as i8), Stone::Black);
-            }
-            Some('w') => {
-                board.insert((x as i8, y as i8), Stone::White);
-            }
-            Some('.') => (),
-            Some(_) => return Err(DecodeError::InvalidState),
-            None => return Err(DecodeError::InvalidStateTruncated),
+    let id_value = segment(0, "game ID")?;
+    let id = id_value
+        .parse::<Uuid>()
+        .map_err(|_| BoardParseError::BadGameId(id_value.to_string()))?;
+    let size_value = segment(1, "size")?;
+    let size: Size = size_value
+        .parse::<usize>()
+        .ok()
+        .and_then(|size| Size::try_from(size).ok())
+        .ok_or_else(|| BoardParseError::BadSize(size_value.to_string()))?;
+
+    let extent = size as usize;
+    let mut board = Grid::new(size);
+    let tiles: Vec<char> = segment(2, "tiles")?.chars().collect();
+    if tiles.len() != extent * extent {
+        return Err(BoardParseError::WrongLength {
+            expected: extent * extent,
+            found: tiles.len(),
+        });
+    }
+    for (index, tile) in tiles.into_iter().enumerate() {
+        let position = ((index % extent) as i8, (index / extent) as i8);
+        match tile {
+            'b' => {
+                board.insert(position, Stone::Black);
+            }
+            'w' => {
+                board.insert(position, Stone::White);
+            }
+            '.' => (),
+            _ => return Err(BoardParseError::BadTile { position, tile }),
         };
     }
 
-    let turn = match segments[3] {
+    let turn = match segment(3, "turn")? {
         "b" => Stone::Black,
         "w" => Stone::White,
-        _ => return Err(DecodeError::InvalidTurn),
+        turn => return Err(BoardParseError::BadTurn(turn.to_string())),
     };
 
-    Ok(Game {
+    let hash = position_hash(size, &board);
+    let mut game = Game {
         id,
         board,
-        size,
         turn,
-    })
+        hash,
+        positions: HashSet::from([hash]),
+        ..new(size)
+    };
+
+    let (mut move_number, mut ko) = (None, None);
+    let (mut control, mut clock, mut lag) = (None, None, 0);
+    // Any further segments are optional `key=value` pairs, unknown keys are ignored so older
+    // servers can read boards produced by newer ones.
+    for segment in segments.iter().skip(4) {
+        let (key, value) = segment
+            .split_once('=')
+            .ok_or_else(|| BoardParseError::BadSegment(segment.to_string()))?;
+        let invalid = || BoardParseError::BadSegment(key.to_string());
+        match key {
+            "prisoners" => {
+                (game.black_prisoners, game.white_prisoners) =
+                    parse_pair(value).ok_or_else(invalid)?;
+            }
+            "last" => {
+                let position = parse_pair(value).ok_or_else(invalid)?;
+                let stone = *game.board.get(&position).ok_or_else(invalid)?;
+                game.last_move = Some((position, stone));
+            }
+            "passes" => {
+                game.passes = value.parse().map_err(|_| invalid())?;
+            }
+            "move" => {
+                move_number = Some(value.parse().map_err(|_| invalid())?);
+            }
+            "ko" => {
+                let position = parse_pair(value).ok_or_else(invalid)?;
+                if !game.valid_coordinate(position) || game.board.contains_key(&position) {
+                    return Err(invalid());
+                }
+                ko = Some(position);
+            }
+            "phase" => {
+                game.phase = match value {
+                    "handicap" => GamePhase::Handicap,
+                    "playing" => GamePhase::Playing,
+                    "scoring" => GamePhase::Scoring,
+                    "finished" => GamePhase::Finished,
+                    _ => return Err(invalid()),
+                };
+            }
+            "komi" => {
+                game.komi = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|komi| komi.is_finite())
+                    .ok_or_else(invalid)?;
+            }
+            "handicap" => {
+                game.handicap = value.parse().map_err(|_| invalid())?;
+            }
+            "placements" => {
+                game.placements = value.parse().map_err(|_| invalid())?;
+            }
+            "dead" => {
+                for pair in value.split(':') {
+                    let position = parse_pair(pair).ok_or_else(invalid)?;
+                    if !game.board.contains_key(&position) {
+                        return Err(invalid());
+                    }
+                    game.dead.insert(position);
+                }
+            }
+            "accepted" => {
+                for player in value.chars() {
+                    match player {
+                        'b' => game.black_accepted = true,
+                        'w' => game.white_accepted = true,
+                        _ => return Err(invalid()),
+                    }
+                }
+            }
+            "moves" => {
+                game.history = value
+                    .split(':')
+                    .map(parse_move)
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(invalid)?;
+            }
+            "setup" => {
+                for entry in value.split(':') {
+                    let stone = match entry.get(..1) {
+                        Some("b") => Stone::Black,
+                        Some("w") => Stone::White,
+                        _ => return Err(invalid()),
+                    };
+                    let position = parse_pair(&entry[1..]).ok_or_else(invalid)?;
+                    if !game.valid_coordinate(position) {
+                        return Err(invalid());
+                    }
+                    game.setup.insert(position, stone);
+                }
+            }
+            "btoken" | "wtoken" | "htoken" | "ptoken" => {
+                if value.is_empty() || !value.chars().all(|c| c.is_ascii_alphanumeric()) {
+                    return Err(invalid());
+                }
+                let token = Some(value.to_string());
+                match key {
+                    "btoken" => game.black_token = token,
+                    "wtoken" => game.white_token = token,
+                    "htoken" => game.host_token = token,
+                    _ => game.pending_token = token,
+                }
+            }
+            "chat" => {
+                game.chat = value
+                    .split(':')
+                    .map(parse_chat)
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(invalid)?;
+            }
+            "bnotify" => {
+                game.black_contact = Some(notify::parse_contact(value).ok_or_else(invalid)?)
+            }
+            "wnotify" => {
+                game.white_contact = Some(notify::parse_contact(value).ok_or_else(invalid)?)
+            }
+            "bot" => game.bot = Some(bots::parse_seat(value).ok_or_else(invalid)?),
+            "hooks" => {
+                game.webhooks = value
+                    .split(':')
+                    .map(webhook::parse_webhook)
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(invalid)?;
+            }
+            "ledger" => {
+                game.ledger = value
+                    .split(':')
+                    .map(ledger::parse_entry)
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(invalid)?;
+            }
+            "resigned" => {
+                game.resigned = match value {
+                    "b" => Some(Stone::Black),
+                    "w" => Some(Stone::White),
+                    _ => return Err(invalid()),
+                };
+            }
+            "time" => {
+                control = Some(TimeControl::parse(value).map_err(|_| invalid())?);
+            }
+            "clock" => {
+                clock = Some(value);
+            }
+            "lag" => {
+                lag = value.parse().map_err(|_| invalid())?;
+            }
+            "warn" | "bwarn" | "wwarn" => {
+                let warnings = Some(TimeWarnings::parse(value).map_err(|_| invalid())?);
+                match key {
+                    "warn" => game.warnings = warnings,
+                    "bwarn" => game.black_warnings = warnings,
+                    _ => game.white_warnings = warnings,
+                }
+            }
+            "timeout" => {
+                game.timed_out = match value {
+                    "b" => Some(Stone::Black),
+                    "w" => Some(Stone::White),
+                    _ => return Err(invalid()),
+                };
+            }
+            "created" => {
+                game.created_at = Some(value.parse().map_err(|_| invalid())?);
+            }
+            "host" => {
+                game.host = match value {
+                    "w" => Some(Stone::White),
+                    "random" => None,
+                    _ => return Err(invalid()),
+                };
+            }
+            "seated" => {
+                game.guest_seated = match value {
+                    "b" | "w" => true,
+                    _ => return Err(invalid()),
+                };
+            }
+            "undo" => {
+                game.undo_requested = match value {
+                    "b" => Some(Stone::Black),
+                    "w" => Some(Stone::White),
+                    _ => return Err(invalid()),
+                };
+            }
+            "suicide" => {
+                game.suicide_allowed = match value {
+                    "allowed" => true,
+                    "forbidden" => false,
+                    _ => return Err(invalid()),
+                };
+            }
+            "rules" => {
+                game.rules = match value {
+                    "japanese" => ScoringRules::Japanese,
+                    "chinese" => ScoringRules::Chinese,
+                    _ => return Err(invalid()),
+                };
+            }
+            _ => (),
+        }
+    }
+
+    // The time left is read once the control is known, a clock without one is meaningless
+    game.clock = match (control, clock) {
+        (Some(control), Some(clock)) => Some(
+            clock::parse_clock(control, clock)
+                .ok_or_else(|| BoardParseError::BadSegment("clock".to_string()))?,
+        ),
+        (Some(control), None) => Some(Clock::new(control)),
+        (None, Some(_)) => return Err(BoardParseError::BadSegment("clock".to_string())),
+        (None, None) => None,
+    };
+    if let Some(clock) = &mut game.clock {
+        clock.set_lag(lag);
+    }
+
+    // Boards from before the move number was kept count the history
+    game.move_number = move_number.unwrap_or(game.history.len());
+    check_history(&game)?;
+
+    if !game.history.is_empty() {
+        rewind_positions(&mut game);
+    } else if let Some(ko) = ko {
+        // Retaking the ko would repeat the position before the last move
+        let ((position, stone), captured) = game
+            .last_move
+            .map(|last| (last, game.foe(last.1)))
+            .ok_or(BoardParseError::BadSegment("ko".to_string()))?;
+        let previous =
+            game.hash ^ zobrist_key(size, position, stone) ^ zobrist_key(size, ko, captured);
+        game.positions.insert(previous);
+    }
+    Ok(game)
+}
+
+// rewind_positions adds every position the history passed through to the positions seen, so
+// superko still holds for a decoded game.
+fn rewind_positions(game: &mut Game) {
+    let mut hash = game.hash;
+    for played in game.history.iter().rev() {
+        match played.action {
+            Action::Pass => continue,
+            Action::Handicap(position) => hash ^= zobrist_key(game.size, position, played.stone),
+            // A suicide took the player's own chain, the played point was empty before
+            Action::Play(position) if played.captured.contains(&position) => {
+                for captured in played.captured.iter().filter(|c| **c != position) {
+                    hash ^= zobrist_key(game.size, *captured, played.stone);
+                }
+            }
+            Action::Play(position) => {
+                hash ^= zobrist_key(game.size, position, played.stone);
+                for captured in played.captured.iter() {
+                    hash ^= zobrist_key(game.size, *captured, game.foe(played.stone));
+                }
+            }
+        }
+        game.positions.insert(hash);
+    }
+}
+
+// check_history tests that a game's history could have produced its position: the players took
+// turns, the prisoners are the stones the history captured, and every stone still on the board
+// was played or set up before the game. Without a history there is nothing to check, any player
+// may have passed any number of times.
+fn check_history(game: &Game) -> Result<(), BoardParseError> {
+    if game.history.is_empty() {
+        return Ok(());
+    }
+    let impossible = BoardParseError::ImpossiblePosition;
+    if game.move_number != game.history.len() {
+        return Err(impossible("the move number doesn't match the history"));
+    }
+    let mut previous: Option<Stone> = None;
+    // Stones placed and taken off the board for Black then White
+    let (mut placed, mut removed) = ([0; 2], [0; 2]);
+    let side = |stone| match stone {
+        Stone::Black => 0,
+        Stone::White => 1,
+    };
+
+    for played in game.history.iter() {
+        match played.action {
+            Action::Handicap(_) => {
+                if played.stone != Stone::Black {
+                    return Err(impossible("handicap stones are Black's"));
+                }
+                placed[0] += 1;
+                continue;
+            }
+            Action::Play(position) => {
+                placed[side(played.stone)] += 1;
+                // A suicide takes the player's own chain, including the stone played
+                let owner = match played.captured.contains(&position) {
+                    true => played.stone,
+                    false => game.foe(played.stone),
+                };
+                removed[side(owner)] += played.captured.len();
+            }
+            Action::Pass => (),
+        }
+        if previous == Some(played.stone) {
+            return Err(impossible("a player moved twice in a row"));
+        }
+        previous = Some(played.stone);
+    }
+
+    if let Some(last) = previous {
+        if game.phase != GamePhase::Handicap && game.turn == last {
+            return Err(impossible("it is the turn of the player who moved last"));
+        }
+    }
+    if (game.black_prisoners, game.white_prisoners) != (removed[1], removed[0]) {
+        return Err(impossible("the prisoners don't match the captures"));
+    }
+    for stone in [Stone::Black, Stone::White] {
+        let on_board = game.board.iter().filter(|(_, tile)| *tile == stone).count();
+        if on_board + removed[side(stone)] < placed[side(stone)] {
+            return Err(impossible("stones are missing from the board"));
+        }
+    }
+    Ok(())
 }
 
 // encode produces a tightly packed ASCII safe representation of a game that can be shipped over
-// the wire safely.
+// the wire safely, it is the inverse of decode.
 pub fn encode(game: &Game) -> String {
-    let mut output = String::new();
+    let mut tiles = String::new();
 
     let extent = game.size as i8;
     for row in 0..extent {
         for column in 0..extent {
-            output.push_str(match game.board.get(&(column, row)) {
+            tiles.push_str(match game.board.get(&(column, row)) {
                 Some(&Stone::Black) => "b",
                 Some(&Stone::White) => "w",
                 None => ".",
             });
         }
     }
+
+    let turn = match game.turn {
+        Stone::Black => "b",
+        Stone::White => "w",
+    };
+
+    let mut output = format!(
+        "{};{};{};{};prisoners={},{};komi={}",
+        game.id,
+        game.size as u8,
+        tiles,
+        turn,
+        game.black_prisoners,
+        game.white_prisoners,
+        game.komi
+    );
+    if let Some(((x, y), _)) = game.last_move {
+        output.push_str(&format!(";last={},{}", x, y));
+    }
+    if game.passes > 0 {
+        output.push_str(&format!(";passes={}", game.passes));
+    }
+    if game.move_number > 0 {
+        output.push_str(&format!(";move={}", game.move_number));
+    }
+    if let Some((x, y)) = game.ko_point() {
+        output.push_str(&format!(";ko={},{}", x, y));
+    }
+    match game.phase {
+        GamePhase::Handicap => {
+            output.push_str(&format!(";phase=handicap;placements={}", game.placements))
+        }
+        GamePhase::Playing => (),
+        GamePhase::Scoring => output.push_str(";phase=scoring"),
+        GamePhase::Finished => output.push_str(";phase=finished"),
+    }
+    if !game.dead.is_empty() {
+        let dead: Vec<String> = game
+            .dead
+            .iter()
+            .map(|(x, y)| format!("{},{}", x, y))
+            .collect();
+        output.push_str(&format!(";dead={}", dead.join(":")));
+    }
+    if game.black_accepted || game.white_accepted {
+        output.push_str(";accepted=");
+        if game.black_accepted {
+            output.push('b');
+        }
+        if game.white_accepted {
+            output.push('w');
+        }
+    }
+    if game.handicap > 0 {
+        output.push_str(&format!(";handicap={}", game.handicap));
+    }
+    match game.rules {
+        ScoringRules::Japanese => (),
+        ScoringRules::Chinese => output.push_str(";rules=chinese"),
+    }
+    if game.suicide_allowed {
+        output.push_str(";suicide=allowed");
+    }
+    match game.undo_requested {
+        Some(Stone::Black) => output.push_str(";undo=b"),
+        Some(Stone::White) => output.push_str(";undo=w"),
+        None => (),
+    }
+    match game.resigned {
+        Some(Stone::Black) => output.push_str(";resigned=b"),
+        Some(Stone::White) => output.push_str(";resigned=w"),
+        None => (),
+    }
+    match game.timed_out {
+        Some(Stone::Black) => output.push_str(";timeout=b"),
+        Some(Stone::White) => output.push_str(";timeout=w"),
+        None => (),
+    }
+    if let Some(clock) = &game.clock {
+        output.push_str(&format!(
+            ";time={};clock={}",
+            clock.control(),
+            clock::format_clock(clock)
+        ));
+        if clock.lag() > 0 {
+            output.push_str(&format!(";lag={}", clock.lag()));
+        }
+    }
+    if let Some(warnings) = &game.warnings {
+        output.push_str(&format!(";warn={}", warnings));
+    }
+    match game.host {
+        Some(Stone::Black) => (),
+        Some(Stone::White) => output.push_str(";host=w"),
+        None => output.push_str(";host=random"),
+    }
+    match game.guest() {
+        Some(Stone::Black) if game.guest_seated => output.push_str(";seated=b"),
+        Some(Stone::White) if game.guest_seated => output.push_str(";seated=w"),
+        _ => (),
+    }
+    output
+}
+
+// encode_with_history is encode followed by the history of moves, which is left out of the
+// compact form to keep the board string small, when the game was created, its chat, its ledger
+// and the players' tokens. It is for the server's own records, players are only ever sent the
+// compact form.
+pub fn encode_with_history(game: &Game) -> String {
+    let mut output = encode(game);
+    if !game.history.is_empty() {
+        let moves: Vec<String> = game.history.iter().map(format_move).collect();
+        output.push_str(&format!(";moves={}", moves.join(":")));
+    }
+    if !game.setup.is_empty() {
+        let stones: Vec<String> = game
+            .setup
+            .iter()
+            .map(|((x, y), stone)| match stone {
+                Stone::Black => format!("b{},{}", x, y),
+                Stone::White => format!("w{},{}", x, y),
+            })
+            .collect();
+        output.push_str(&format!(";setup={}", stones.join(":")));
+    }
+    if let Some(token) = &game.black_token {
+        output.push_str(&format!(";btoken={}", token));
+    }
+    if let Some(token) = &game.white_token {
+        output.push_str(&format!(";wtoken={}", token));
+    }
+    if let Some(at) = game.created_at {
+        output.push_str(&format!(";created={}", at));
+    }
+    if !game.chat.is_empty() {
+        let lines: Vec<String> = game.chat.iter().map(format_chat).collect();
+        output.push_str(&format!(";chat={}", lines.join(":")));
+    }
+    if let Some(token) = &game.host_token {
+        output.push_str(&format!(";htoken={}", token));
+    }
+    if let Some(token) = &game.pending_token {
+        output.push_str(&format!(";ptoken={}", token));
+    }
+    if !game.ledger.is_empty() {
+        let entries: Vec<String> = game.ledger.iter().map(ledger::format_entry).collect();
+        output.push_str(&format!(";ledger={}", entries.join(":")));
+    }
+    if let Some(contact) = &game.black_contact {
+        output.push_str(&format!(";bnotify={}", notify::format_contact(contact)));
+    }
+    if let Some(contact) = &game.white_contact {
+        output.push_str(&format!(";wnotify={}", notify::format_contact(contact)));
+    }
+    if let Some(warnings) = &game.black_warnings {
+        output.push_str(&format!(";bwarn={}", warnings));
+    }
+    if let Some(warnings) = &game.white_warnings {
+        output.push_str(&format!(";wwarn={}", warnings));
+    }
+    if !game.webhooks.is_empty() {
+        let hooks: Vec<String> = game.webhooks.iter().map(webhook::format_webhook).collect();
+        output.push_str(&format!(";hooks={}", hooks.join(":")));
+    }
+    if let Some(seat) = &game.bot {
+        output.push_str(&format!(";bot={}", bots::format_seat(seat)));
+    }
     output
 }
 
@@ -188,6 +1104,10 @@ impl Game {
         self.turn
     }
 
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
     // valid_coordinate determines if a coordinate is within the bounds of the game board.
     fn valid_coordinate(&self, (x, y): Coordinate) -> bool {
         let extent = self.size as i8;
@@ -206,10 +1126,22 @@ impl Game {
             .collect()
     }
 
-    // can_play tests if a position is valid and the tile is empty, it DOES NOT check for allies
-    // with liberties or foes without.
-    fn can_play(&self, position: Coordinate, stone: Stone) -> bool {
-        self.turn == stone && self.valid_coordinate(position) && !self.has_stone(position)
+    // check_play tests if it is the player's turn, the position is valid and the tile is empty, it
+    // DOES NOT check for allies with liberties or foes without.
+    fn check_play(&self, position: Coordinate, stone: Stone) -> Result<(), PlayError> {
+        if self.phase != GamePhase::Playing && self.phase != GamePhase::Handicap {
+            return Err(PlayError::NotPlaying);
+        }
+        if self.turn != stone {
+            return Err(PlayError::NotYourTurn);
+        }
+        if !self.valid_coordinate(position) {
+            return Err(PlayError::OutOfBounds);
+        }
+        if self.has_stone(position) {
+            return Err(PlayError::Occupied);
+        }
+        Ok(())
     }
 
     // advance_turn sets the game state so that it's the next player's turn.
@@ -225,84 +1157,96 @@ impl Game {
         }
     }
 
-    // remove_chain removes all pieces in a chain from the board.
+    // remove_chain removes all pieces in a chain from the board, crediting the captures to the
+    // foe of each removed stone.
     fn remove_chain(&mut self, chain: &[Coordinate]) {
-        for position in chain.iter() {
-            self.board.remove(position);
+        for (position, stone) in self.board.remove_chain(chain) {
+            self.hash ^= zobrist_key(self.size, position, stone);
+            match stone {
+                Stone::Black => self.white_prisoners += 1,
+                Stone::White => self.black_prisoners += 1,
+            }
         }
     }
 
-    // attack returns a chain at `to` being attacked by `from` if it has no liberties
-    fn attack(&self, from: Coordinate, to: Coordinate, stone: Stone) -> Option<Vec<Coordinate>> {
-        let mut chain = vec![to];
-
-        let mut searched_tiles = HashSet::<Coordinate>::new();
-        searched_tiles.insert(from);
-        searched_tiles.insert(to);
-
-        let mut positions_to_search = vec![to];
-
-        while let Some(position) = positions_to_search.pop() {
-            for search_position in self.adjacent_positions(position) {
-                if !searched_tiles.contains(&search_position) {
-                    match self.board.get(&search_position) {
-                        Some(tile) if *tile != stone => {
-                            positions_to_search.push(search_position);
-                            chain.push(search_position);
-                        }
-                        Some(_) => {}
-                        None => {
-                            // Found an empty tile near this chain, it's safe!
-                            return None;
-                        }
+    // repeats_position returns true if placing `stone` at `position` and removing the captured
+    // chains would recreate an earlier whole-board position (positional superko).
+    fn repeats_position(
+        &self,
+        position: Coordinate,
+        stone: Stone,
+        captured: &[Vec<Coordinate>],
+    ) -> bool {
+        let mut hash = self.hash ^ zobrist_key(self.size, position, stone);
+        let mut removed = HashSet::new();
+        for chain in captured.iter() {
+            for captured_position in chain.iter() {
+                // Chains captured from different directions can overlap, only remove once.
+                if removed.insert(*captured_position) {
+                    if let Some(captured_stone) = self.board.get(captured_position) {
+                        hash ^= zobrist_key(self.size, *captured_position, *captured_stone);
                     }
                 }
             }
-
-            searched_tiles.insert(position);
         }
+        self.positions.contains(&hash)
+    }
 
-        Some(chain)
+    // attack returns a chain at `to` being attacked by `from` if it has no liberties
+    fn attack(&self, from: Coordinate, to: Coordinate) -> Option<Vec<Coordinate>> {
+        if !self.board.last_liberty(to, from) {
+            // Found an empty tile near this chain, it's safe!
+            return None;
+        }
+        Some(self.board.chain(to))
     }
 
     // allie_has_liberty returns true if the chain attached to proposed (indicated by allie) has a
     // liberty.
-    fn allie_has_liberty(&self, proposed: Coordinate, allie: Coordinate, stone: Stone) -> bool {
-        let mut searched_tiles = HashSet::<Coordinate>::new();
-        searched_tiles.insert(proposed);
-        searched_tiles.insert(allie);
-
-        let mut positions_to_search = vec![allie];
+    fn allie_has_liberty(&self, proposed: Coordinate, allie: Coordinate) -> bool {
+        self.board.contains_key(&allie) && !self.board.last_liberty(allie, proposed)
+    }
 
-        while let Some(position) = positions_to_search.pop() {
-            for search_position in self.adjacent_positions(position) {
-                if !searched_tiles.contains(&search_position) {
-                    match self.board.get(&search_position) {
-                        Some(tile) if *tile == stone => {
-                            positions_to_search.push(search_position);
-                        }
-                        Some(_) => {}
-                        None => {
-                            // Found an empty tile near this chain, it's safe!
-                            return true;
-                        }
-                    }
-                }
+    // play_stone places a stone on the board, capturing any defending stones without any
+    // liberties. Returns the reason the play was rejected if it is invalid.
+    pub fn play_stone(
+        &mut self,
+        position: Coordinate,
+        stone: Stone,
+    ) -> Result<PlayOutcome, PlayError> {
+        self.check_play(position, stone)?;
+
+        let (safe, routed_defenders) = self.survey(position, stone);
+        if !safe {
+            if self.suicide_allowed {
+                return self.play_suicide(position, stone);
             }
-
-            searched_tiles.insert(position);
+            return Err(PlayError::Suicide);
         }
 
-        false
-    }
+        if self.repeats_position(position, stone, &routed_defenders) {
+            return Err(PlayError::KoViolation);
+        }
 
-    // play_stone places a stone on the board, capturing any defending stones without any
-    // liberties. Returns false if the play is invalid, true otherwise.
-    pub fn play_stone(&mut self, position: Coordinate, stone: Stone) -> bool {
-        if !self.can_play(position, stone) {
-            return false;
+        for defending_chain in routed_defenders.iter() {
+            self.remove_chain(defending_chain);
         }
 
+        self.board.insert(position, stone);
+        self.hash ^= zobrist_key(self.size, position, stone);
+        self.positions.insert(self.hash);
+        self.last_move = Some((position, stone));
+
+        let mut captured: Vec<Coordinate> = routed_defenders.into_iter().flatten().collect();
+        captured.sort();
+        captured.dedup();
+        self.finish_play(position, stone, captured.clone());
+        Ok(PlayOutcome { captured })
+    }
+
+    // survey returns whether a stone placed at `position` would have a liberty, and the foe
+    // chains the stone would capture.
+    fn survey(&self, position: Coordinate, stone: Stone) -> (bool, Vec<Vec<Coordinate>>) {
         let mut safe = false;
         let mut routed_defenders = Vec::<Vec<Coordinate>>::new();
 
@@ -312,11 +1256,11 @@ impl Game {
                     if !safe {
                         // safe has not yet been toggled to true, search for a liberty through this
                         // adjacent chain
-                        safe = self.allie_has_liberty(position, neighbour, stone);
+                        safe = self.allie_has_liberty(position, neighbour);
                     }
                 }
                 Some(_) => {
-                    if let Some(chain) = self.attack(position, neighbour, stone) {
+                    if let Some(chain) = self.attack(position, neighbour) {
                         routed_defenders.push(chain);
                         safe = true;
                     }
@@ -328,42 +1272,1118 @@ impl Game {
             }
         }
 
-        if safe {
-            for defending_chain in routed_defenders.iter() {
-                self.remove_chain(defending_chain);
-            }
+        (safe, routed_defenders)
+    }
 
-            self.board.insert(position, stone);
-            self.advance_turn();
-            return true;
+    // legal_moves lists every empty position `stone` could play on, leaving out suicides and
+    // plays that repeat an earlier position. It is empty when `stone` may not place stones, but
+    // doesn't otherwise depend on whose turn it is.
+    pub fn legal_moves(&self, stone: Stone) -> Vec<Coordinate> {
+        let placing = match self.phase {
+            GamePhase::Playing => true,
+            GamePhase::Handicap => stone == Stone::Black,
+            GamePhase::Scoring | GamePhase::Finished => false,
+        };
+        if !placing {
+            return Vec::new();
         }
 
-        false
+        let extent = self.size as i8;
+        (0..extent)
+            .flat_map(|y| (0..extent).map(move |x| (x, y)))
+            .filter(|position| !self.has_stone(*position))
+            .filter(|position| {
+                let (safe, routed_defenders) = self.survey(*position, stone);
+                if safe {
+                    return !self.repeats_position(*position, stone, &routed_defenders);
+                }
+                let chain = self.suicide_chain(*position, stone);
+                self.suicide_allowed
+                    && chain.len() > 1
+                    && !self.suicide_repeats(*position, stone, &chain)
+            })
+            .collect()
     }
 
-    pub fn has_stone(&self, position: Coordinate) -> bool {
-        self.board.contains_key(&position)
-    }
+    // play_suicide places a stone that leaves its own chain without liberties and removes that
+    // chain, the removed stones count as prisoners for the opponent.
+    fn play_suicide(
+        &mut self,
+        position: Coordinate,
+        stone: Stone,
+    ) -> Result<PlayOutcome, PlayError> {
+        let chain = self.suicide_chain(position, stone);
+        if chain.len() == 1 {
+            // Suiciding a lone stone would leave the board unchanged, it is never allowed
+            return Err(PlayError::Suicide);
+        }
 
-    pub fn stones(&self) -> usize {
-        self.board.len()
-    }
+        if self.suicide_repeats(position, stone, &chain) {
+            return Err(PlayError::KoViolation);
+        }
 
-    pub fn player_stones(&self, stone: Stone) -> usize {
-        self.board
-            .iter()
-            .filter(|&(_, piece)| *piece == stone)
-            .count()
-    }
+        self.board.insert(position, stone);
+        self.hash ^= zobrist_key(self.size, position, stone);
+        self.remove_chain(&chain);
+        self.positions.insert(self.hash);
+        // The played stone is no longer on the board, so there is no last move to mark
+        self.last_move = None;
 
-    //pub fn player_score(&self, stone: Stone) -> usize {
-    //self.board.iter().filter(|&(_, piece)| *piece == stone).count()
-    //}
+        let mut captured = chain;
+        captured.sort();
+        self.finish_play(position, stone, captured.clone());
 
-    pub fn winner(&self) -> Stone {
-        Stone::Black
+        // The removed chain is reported like a capture, so clients take the stones off the board
+        Ok(PlayOutcome { captured })
     }
-}
+
+    // suicide_repeats returns true if removing `chain` after a suicidal play at `position` would
+    // recreate an earlier whole-board position.
+    fn suicide_repeats(&self, position: Coordinate, stone: Stone, chain: &[Coordinate]) -> bool {
+        let hash = chain
+            .iter()
+            .filter(|chain_position| **chain_position != position)
+            .fold(self.hash, |hash, chain_position| {
+                hash ^ zobrist_key(self.size, *chain_position, stone)
+            });
+        self.positions.contains(&hash)
+    }
+
+    // suicide_chain returns the stones removed by a suicidal play at `position`, the new stone and
+    // every friendly stone connected to it.
+    fn suicide_chain(&self, position: Coordinate, stone: Stone) -> Vec<Coordinate> {
+        let mut chain = vec![position];
+        let mut positions_to_search = vec![position];
+
+        while let Some(current) = positions_to_search.pop() {
+            for neighbour in self.adjacent_positions(current) {
+                if self.board.get(&neighbour) == Some(&stone) && !chain.contains(&neighbour) {
+                    chain.push(neighbour);
+                    positions_to_search.push(neighbour);
+                }
+            }
+        }
+
+        chain
+    }
+
+    // finish_play records a placed stone in the history and hands the move to the next player,
+    // Black keeps the move until every free handicap stone has been placed.
+    fn finish_play(&mut self, position: Coordinate, stone: Stone, captured: Vec<Coordinate>) {
+        let action = match self.phase {
+            GamePhase::Handicap => Action::Handicap(position),
+            _ => Action::Play(position),
+        };
+        self.history.push(Move {
+            stone,
+            action,
+            captured,
+            played_at: None,
+        });
+        self.record(LedgerEvent::StonePlayed { stone, position });
+        self.move_number += 1;
+        self.passes = 0;
+        self.undo_requested = None;
+        if self.phase == GamePhase::Handicap {
+            self.placements -= 1;
+            if self.placements == 0 {
+                self.phase = GamePhase::Playing;
+                self.advance_turn();
+            }
+        } else {
+            self.advance_turn();
+        }
+    }
+
+    // canonical_hash returns a hash of the board position that is the same for all 8 rotations
+    // and reflections of it, so equivalent positions can share cache entries.
+    pub fn canonical_hash(&self) -> u64 {
+        let extent = self.size as i8 - 1;
+        SYMMETRIES
+            .iter()
+            .map(|symmetry| {
+                self.board.iter().fold(0, |hash, (position, stone)| {
+                    hash ^ zobrist_key(self.size, symmetry(position, extent), stone)
+                })
+            })
+            .min()
+            .unwrap_or(self.hash)
+    }
+
+    pub fn has_stone(&self, position: Coordinate) -> bool {
+        self.board.contains_key(&position)
+    }
+
+    pub fn stone_at(&self, position: Coordinate) -> Option<Stone> {
+        self.board.get(&position).copied()
+    }
+
+    // tiles renders a `width` x `height` region of the board starting at `origin`, row by row, as
+    // in the board string. Points off the board are left out.
+    pub fn tiles(&self, (x, y): Coordinate, width: u8, height: u8) -> String {
+        let mut tiles = String::new();
+        for row in y..y.saturating_add(height as i8) {
+            for column in x..x.saturating_add(width as i8) {
+                if !self.valid_coordinate((column, row)) {
+                    continue;
+                }
+                tiles.push(match self.board.get(&(column, row)) {
+                    Some(&Stone::Black) => 'b',
+                    Some(&Stone::White) => 'w',
+                    None => '.',
+                });
+            }
+        }
+        tiles
+    }
+
+    // minimap summarizes the board in blocks of `block` x `block` points, each block is the color
+    // with the most stones in it, `=` when level or `.` when empty.
+    pub fn minimap(&self, block: u8) -> Vec<String> {
+        let block = block.max(1) as i8;
+        let extent = self.size as i8;
+        (0..extent)
+            .step_by(block as usize)
+            .map(|y| {
+                (0..extent)
+                    .step_by(block as usize)
+                    .map(|x| {
+                        let tiles = self.tiles((x, y), block as u8, block as u8);
+                        let black = tiles.matches('b').count();
+                        let white = tiles.matches('w').count();
+                        match black.cmp(&white) {
+                            std::cmp::Ordering::Greater => 'b',
+                            std::cmp::Ordering::Less => 'w',
+                            std::cmp::Ordering::Equal if black == 0 => '.',
+                            std::cmp::Ordering::Equal => '=',
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    // chain_at returns the chain of stones connected to the stone at `position`, and its
+    // liberties.
+    pub fn chain_at(&self, position: Coordinate) -> Option<Chain> {
+        if !self.board.contains_key(&position) {
+            return None;
+        }
+
+        let stones: BTreeSet<Coordinate> = self.board.chain(position).into_iter().collect();
+        let liberties: BTreeSet<Coordinate> = stones
+            .iter()
+            .flat_map(|stone| self.adjacent_positions(*stone))
+            .filter(|neighbour| !self.board.contains_key(neighbour))
+            .collect();
+
+        // Coordinates order by column first, board order is row by row
+        let board_order = |positions: BTreeSet<Coordinate>| {
+            let mut positions: Vec<Coordinate> = positions.into_iter().collect();
+            positions.sort_by_key(|(x, y)| (*y, *x));
+            positions
+        };
+        Some(Chain {
+            stones: board_order(stones),
+            liberties: board_order(liberties),
+        })
+    }
+
+    // liberties counts the empty points next to the chain at `position`, 0 if there is no stone.
+    pub fn liberties(&self, position: Coordinate) -> usize {
+        self.chain_at(position)
+            .map_or(0, |chain| chain.liberties.len())
+    }
+
+    // chains_in_atari returns the chains of `stone` left with a single liberty, which the foe
+    // could capture with their next play.
+    pub fn chains_in_atari(&self, stone: Stone) -> Vec<Chain> {
+        let mut searched = BTreeSet::new();
+        let mut chains = Vec::new();
+        for (position, _) in self.board.iter().filter(|(_, tile)| *tile == stone) {
+            if searched.contains(&position) {
+                continue;
+            }
+            if let Some(chain) = self.chain_at(position) {
+                searched.extend(chain.stones.iter().copied());
+                if chain.liberties.len() == 1 {
+                    chains.push(chain);
+                }
+            }
+        }
+        chains.sort_by_key(|chain| chain.stones[0].1 * (self.size as i8) + chain.stones[0].0);
+        chains
+    }
+
+    // stones_in_atari lists every stone of either color in a chain with a single liberty.
+    pub fn stones_in_atari(&self) -> Vec<Coordinate> {
+        let mut stones: Vec<Coordinate> = [Stone::Black, Stone::White]
+            .iter()
+            .flat_map(|stone| self.chains_in_atari(*stone))
+            .flat_map(|chain| chain.stones)
+            .collect();
+        stones.sort_by_key(|(x, y)| (*y, *x));
+        stones
+    }
+
+    pub fn stones(&self) -> usize {
+        self.board.len()
+    }
+
+    pub fn player_stones(&self, stone: Stone) -> usize {
+        self.board
+            .iter()
+            .filter(|(_, piece)| *piece == stone)
+            .count()
+    }
+
+    // prisoners returns the number of stones captured by the given player.
+    pub fn prisoners(&self, stone: Stone) -> usize {
+        match stone {
+            Stone::Black => self.black_prisoners,
+            Stone::White => self.white_prisoners,
+        }
+    }
+
+    pub fn last_move(&self) -> Option<(Coordinate, Stone)> {
+        self.last_move
+    }
+
+    // move_number returns how many moves have been played, passes included.
+    pub fn move_number(&self) -> usize {
+        self.move_number
+    }
+
+    // ko_point returns where the single stone captured by the last move was, if retaking it at
+    // once would repeat the position from before that move.
+    pub fn ko_point(&self) -> Option<Coordinate> {
+        let (position, stone) = self.last_move?;
+        let hash = self.hash ^ zobrist_key(self.size, position, stone);
+        self.adjacent_positions(position)
+            .into_iter()
+            .filter(|neighbour| !self.board.contains_key(neighbour))
+            .find(|neighbour| {
+                self.positions
+                    .contains(&(hash ^ zobrist_key(self.size, *neighbour, self.foe(stone))))
+            })
+    }
+
+    // history returns every move played so far, oldest first.
+    pub fn history(&self) -> &[Move] {
+        &self.history
+    }
+
+    // stamp_moves records when the moves from move `from` onwards were played, `at` is in
+    // milliseconds since the UNIX epoch.
+    pub fn stamp_moves(&mut self, from: usize, at: u64) {
+        for played in self.history.iter_mut().skip(from) {
+            played.played_at = Some(at);
+        }
+    }
+
+    // created_at returns when the server first saw the game, in milliseconds since the UNIX
+    // epoch.
+    pub fn created_at(&self) -> Option<u64> {
+        self.created_at
+    }
+
+    // active_at returns when anything last happened in the game, in milliseconds since the UNIX
+    // epoch, None if the game was never timed.
+    pub fn active_at(&self) -> Option<u64> {
+        let moved = self.history.last().and_then(|played| played.played_at);
+        let logged = self.ledger.last().and_then(|entry| entry.at);
+        let said = self.chat.last().map(|line| line.at);
+        [self.created_at, moved, logged, said]
+            .into_iter()
+            .flatten()
+            .max()
+    }
+
+    // chat returns the latest lines said in the game's chat, oldest first.
+    pub fn chat(&self) -> &[ChatEntry] {
+        &self.chat
+    }
+
+    // say adds a line to the game's chat, only the latest CHAT_HISTORY lines are kept.
+    pub fn say(&mut self, line: ChatEntry) {
+        if self.chat.len() == CHAT_HISTORY {
+            self.chat.remove(0);
+        }
+        self.chat.push(line);
+    }
+
+    // forget_chat drops the lines said in the game's chat before `before`, in milliseconds since
+    // the UNIX epoch, returning whether there were any.
+    pub fn forget_chat(&mut self, before: u64) -> bool {
+        let said = self.chat.len();
+        self.chat.retain(|line| line.at >= before);
+        self.chat.len() != said
+    }
+
+    // ledger returns everything that happened in the game since its ledger was opened, oldest
+    // first.
+    pub fn ledger(&self) -> &[LedgerEntry] {
+        &self.ledger
+    }
+
+    // open_ledger starts keeping the game's ledger from its current board, unless it already is.
+    pub fn open_ledger(&mut self) {
+        if self.ledger.is_empty() {
+            let board = encode(self);
+            self.ledger.push(LedgerEntry {
+                at: None,
+                event: LedgerEvent::GameCreated { board },
+            });
+        }
+    }
+
+    // forget_ledger stops keeping the game's ledger, returning whether it was kept. The moves
+    // played are kept with the history.
+    pub fn forget_ledger(&mut self) -> bool {
+        let kept = !self.ledger.is_empty();
+        self.ledger.clear();
+        kept
+    }
+
+    // record adds an event to the ledger, if it is being kept.
+    fn record(&mut self, event: LedgerEvent) {
+        if !self.ledger.is_empty() {
+            self.ledger.push(LedgerEntry { at: None, event });
+        }
+    }
+
+    // stamp_ledger records when the ledger entries from entry `from` onwards happened, `at` is in
+    // milliseconds since the UNIX epoch.
+    pub fn stamp_ledger(&mut self, from: usize, at: u64) {
+        for entry in self.ledger.iter_mut().skip(from) {
+            entry.at.get_or_insert(at);
+        }
+    }
+
+    // stamp_created records when the server first saw the game, unless it already has been.
+    pub fn stamp_created(&mut self, at: u64) {
+        self.created_at.get_or_insert(at);
+    }
+
+    // undo takes back the last move, restoring any stones it captured and giving the turn back to
+    // the player who made it. Undoing a pass that ended play returns the game to play. Free
+    // handicap stones can't be taken back.
+    pub fn undo(&mut self) -> Result<Move, PlayError> {
+        if self.phase == GamePhase::Finished || self.phase == GamePhase::Handicap {
+            return Err(PlayError::NotPlaying);
+        }
+        let undone = match self.history.last() {
+            Some(Move {
+                action: Action::Play(_) | Action::Pass,
+                ..
+            }) => self.history.pop().unwrap(),
+            _ => return Err(PlayError::NothingToUndo),
+        };
+        self.move_number -= 1;
+
+        if let Action::Play(position) = undone.action {
+            self.positions.remove(&self.hash);
+
+            // A suicide removed the player's own chain, including the position played
+            let suicide = undone.captured.contains(&position);
+            let restored = if suicide {
+                undone.stone
+            } else {
+                self.board.remove(&position);
+                self.hash ^= zobrist_key(self.size, position, undone.stone);
+                self.foe(undone.stone)
+            };
+            for captured in undone
+                .captured
+                .iter()
+                .filter(|captured| **captured != position)
+            {
+                self.board.insert(*captured, restored);
+                self.hash ^= zobrist_key(self.size, *captured, restored);
+            }
+            match restored {
+                Stone::Black => self.white_prisoners -= undone.captured.len(),
+                Stone::White => self.black_prisoners -= undone.captured.len(),
+            }
+        }
+
+        self.turn = undone.stone;
+        self.passes = self
+            .history
+            .iter()
+            .rev()
+            .take_while(|played| played.action == Action::Pass)
+            .count() as u8;
+        self.last_move = match self.history.last() {
+            Some(Move {
+                stone,
+                action: Action::Play(position) | Action::Handicap(position),
+                ..
+            }) if self.board.get(position) == Some(stone) => Some((*position, *stone)),
+            _ => None,
+        };
+        if self.phase == GamePhase::Scoring {
+            self.phase = GamePhase::Playing;
+            self.dead.clear();
+            self.black_accepted = false;
+            self.white_accepted = false;
+        }
+        self.undo_requested = None;
+        Ok(undone)
+    }
+
+    // request_undo asks the opponent to let `stone` take back their last move.
+    pub fn request_undo(&mut self, stone: Stone) -> Result<(), PlayError> {
+        if self.phase == GamePhase::Finished || self.phase == GamePhase::Handicap {
+            return Err(PlayError::NotPlaying);
+        }
+        match self.history.last() {
+            Some(Move {
+                stone: mover,
+                action: Action::Play(_) | Action::Pass,
+                ..
+            }) if *mover == stone => (),
+            _ => return Err(PlayError::NothingToUndo),
+        }
+        self.undo_requested = Some(stone);
+        self.record(LedgerEvent::UndoRequested { stone });
+        Ok(())
+    }
+
+    // answer_undo lets the opponent of the requesting player accept or decline the request, the
+    // last move is taken back if they accept.
+    pub fn answer_undo(&mut self, stone: Stone, accepted: bool) -> Result<Option<Move>, PlayError> {
+        if self.undo_requested != Some(self.foe(stone)) {
+            return Err(PlayError::NoUndoRequest);
+        }
+        let undone = match accepted {
+            true => Some(self.undo()?),
+            false => {
+                self.undo_requested = None;
+                None
+            }
+        };
+        self.record(LedgerEvent::UndoAnswered { stone, accepted });
+        Ok(undone)
+    }
+
+    // host returns the stone played by whoever created the game, None until it is drawn.
+    pub fn host(&self) -> Option<Stone> {
+        self.host
+    }
+
+    // set_host picks the stone played by whoever created the game, None leaves it to be drawn
+    // when their opponent is accepted.
+    pub fn set_host(&mut self, host: Option<Stone>) {
+        self.host = host;
+    }
+
+    // guest returns the stone played by whoever joins the game, None until it is drawn.
+    pub fn guest(&self) -> Option<Stone> {
+        self.host.map(|host| self.foe(host))
+    }
+
+    // guest_seated returns true once a player, or the engine, holds the guest's seat.
+    pub fn guest_seated(&self) -> bool {
+        self.guest_seated
+    }
+
+    // seat_guest gives the guest's seat to a player, returning false if it was already taken.
+    pub fn seat_guest(&mut self) -> bool {
+        if self.guest_seated {
+            return false;
+        }
+        self.guest_seated = true;
+        if let Some(stone) = self.guest() {
+            self.record(LedgerEvent::PlayerJoined { stone });
+        }
+        true
+    }
+
+    // issue_token gives the player holding a seat a new secret, replacing any issued before.
+    pub fn issue_token(&mut self, stone: Stone) -> String {
+        let token = Uuid::new_v4().simple().to_string();
+        match stone {
+            Stone::Black => self.black_token = Some(token.clone()),
+            Stone::White => self.white_token = Some(token.clone()),
+        }
+        token
+    }
+
+    // issue_host_token gives the player who created the game a new secret, kept aside until their
+    // stone is drawn when it hasn't been yet.
+    pub fn issue_host_token(&mut self) -> String {
+        match self.host {
+            Some(host) => self.issue_token(host),
+            None => {
+                let token = Uuid::new_v4().simple().to_string();
+                self.host_token = Some(token.clone());
+                token
+            }
+        }
+    }
+
+    // request_seat hands a player asking to join the secret they will hold the guest's seat with
+    // once accepted, replacing any earlier request. It returns None when the seat is taken.
+    pub fn request_seat(&mut self) -> Option<String> {
+        if self.guest_seated {
+            return None;
+        }
+        let token = Uuid::new_v4().simple().to_string();
+        self.pending_token = Some(token.clone());
+        Some(token)
+    }
+
+    // accept_join gives the guest's seat to the player waiting for it, drawing the stones first
+    // when the player who created the game left them to chance (nigiri). It returns the guest's
+    // stone, or None if no one is waiting or the seat is taken.
+    pub fn accept_join(&mut self) -> Option<Stone> {
+        if self.guest_seated || self.pending_token.is_none() {
+            return None;
+        }
+        let host = match self.host {
+            Some(host) => host,
+            None => {
+                let host = match Uuid::new_v4().as_bytes()[0] % 2 {
+                    0 => Stone::Black,
+                    _ => Stone::White,
+                };
+                match host {
+                    Stone::Black => self.black_token = self.host_token.take(),
+                    Stone::White => self.white_token = self.host_token.take(),
+                }
+                self.host = Some(host);
+                host
+            }
+        };
+        let guest = self.foe(host);
+        match guest {
+            Stone::Black => self.black_token = self.pending_token.take(),
+            Stone::White => self.white_token = self.pending_token.take(),
+        }
+        self.guest_seated = true;
+        self.record(LedgerEvent::PlayerJoined { stone: guest });
+        Some(guest)
+    }
+
+    // token_matches tests a player's secret against the one issued for their seat. A seat that
+    // was never issued one, e.g. the engine's, can't be held with any, the guest's seat has to be
+    // taken first and neither is held until the stones are drawn.
+    pub fn token_matches(&self, stone: Stone, token: &str) -> bool {
+        if self.host.is_none() || (self.guest() == Some(stone) && !self.guest_seated) {
+            return false;
+        }
+        let issued = match stone {
+            Stone::Black => &self.black_token,
+            Stone::White => &self.white_token,
+        };
+        issued.as_deref() == Some(token)
+    }
+
+    // seat_of finds the stone held with a player's secret, None when it isn't one issued for a
+    // seat or the stones are still to be drawn.
+    pub fn seat_of(&self, token: &str) -> Option<Stone> {
+        let host = self.host?;
+        [host, self.foe(host)]
+            .into_iter()
+            .find(|stone| self.token_matches(*stone, token))
+    }
+
+    // is_host tests a player's secret against the one issued to whoever created the game.
+    pub fn is_host(&self, token: &str) -> bool {
+        match self.host {
+            Some(host) => self.token_matches(host, token),
+            None => self.host_token.as_deref() == Some(token),
+        }
+    }
+
+    // undo_requested returns the player waiting for their opponent to answer an undo request.
+    pub fn undo_requested(&self) -> Option<Stone> {
+        self.undo_requested
+    }
+
+    // pass gives up the player's turn without placing a stone, after both players pass in
+    // succession the game moves on to scoring.
+    pub fn pass(&mut self, stone: Stone) -> Result<(), PlayError> {
+        if self.phase != GamePhase::Playing {
+            return Err(PlayError::NotPlaying);
+        }
+        if self.turn != stone {
+            return Err(PlayError::NotYourTurn);
+        }
+
+        self.passes += 1;
+        self.history.push(Move {
+            stone,
+            action: Action::Pass,
+            captured: Vec::new(),
+            played_at: None,
+        });
+        self.record(LedgerEvent::Passed { stone });
+        self.move_number += 1;
+        self.undo_requested = None;
+        self.advance_turn();
+        if self.passes >= 2 {
+            self.phase = GamePhase::Scoring;
+        }
+        Ok(())
+    }
+
+    pub fn phase(&self) -> GamePhase {
+        self.phase
+    }
+
+    pub fn rules(&self) -> ScoringRules {
+        self.rules
+    }
+
+    // set_rules changes how the game will be scored, it should be chosen before play starts.
+    pub fn set_rules(&mut self, rules: ScoringRules) {
+        self.rules = rules;
+    }
+
+    // place_handicap puts `stones` black stones on the star points and gives the first move to
+    // White.
+    pub fn place_handicap(&mut self, stones: u8) -> Result<(), HandicapError> {
+        self.check_handicap(stones)?;
+        let points =
+            handicap_points(self.size, stones).ok_or(HandicapError::InvalidCount(stones))?;
+
+        for position in points {
+            self.board.insert(position, Stone::Black);
+            self.hash ^= zobrist_key(self.size, position, Stone::Black);
+        }
+        self.positions.insert(self.hash);
+        self.handicap = stones;
+        self.advance_turn();
+        Ok(())
+    }
+
+    // start_free_handicap lets Black place `stones` handicap stones anywhere on the board, one per
+    // play, before White's first move.
+    pub fn start_free_handicap(&mut self, stones: u8) -> Result<(), HandicapError> {
+        self.check_handicap(stones)?;
+
+        self.handicap = stones;
+        self.placements = stones;
+        self.phase = GamePhase::Handicap;
+        Ok(())
+    }
+
+    // check_handicap tests that no stones have been played, handicaps can only be given at the
+    // start of a game.
+    fn check_handicap(&self, stones: u8) -> Result<(), HandicapError> {
+        if !self.board.is_empty()
+            || self.turn != Stone::Black
+            || self.handicap > 0
+            || self.phase != GamePhase::Playing
+        {
+            return Err(HandicapError::AlreadyStarted);
+        }
+        if !(2..=9).contains(&stones) {
+            return Err(HandicapError::InvalidCount(stones));
+        }
+        Ok(())
+    }
+
+    pub fn handicap(&self) -> u8 {
+        self.handicap
+    }
+
+    // handicap_stones returns where Black's handicap stones were placed, either freely or on the
+    // star points.
+    pub fn handicap_stones(&self) -> Vec<Coordinate> {
+        let placed: Vec<Coordinate> = self
+            .history
+            .iter()
+            .filter_map(|played| match played.action {
+                Action::Handicap(position) => Some(position),
+                _ => None,
+            })
+            .collect();
+        if !placed.is_empty() || self.phase == GamePhase::Handicap {
+            return placed;
+        }
+        handicap_points(self.size, self.handicap).unwrap_or_default()
+    }
+
+    // setup_stones returns the stones composed in the board editor before the first move.
+    pub fn setup_stones(&self) -> Vec<(Coordinate, Stone)> {
+        self.setup
+            .iter()
+            .map(|(position, stone)| (*position, *stone))
+            .collect()
+    }
+
+    // placements returns the number of free handicap stones Black has left to place.
+    pub fn placements(&self) -> u8 {
+        self.placements
+    }
+
+    pub fn suicide_allowed(&self) -> bool {
+        self.suicide_allowed
+    }
+
+    // set_suicide_allowed chooses whether multi-stone suicide is legal, it should be chosen before
+    // play starts.
+    pub fn set_suicide_allowed(&mut self, allowed: bool) {
+        self.suicide_allowed = allowed;
+    }
+
+    pub fn komi(&self) -> f32 {
+        self.komi
+    }
+
+    // set_komi changes the points given to White, it should be chosen before play starts.
+    pub fn set_komi(&mut self, komi: f32) {
+        self.komi = komi;
+    }
+
+    // passes returns the number of consecutive passes since the last stone was placed.
+    pub fn passes(&self) -> u8 {
+        self.passes
+    }
+
+    // living_stone returns the stone at a position unless it has been marked dead.
+    fn living_stone(&self, position: Coordinate) -> Option<Stone> {
+        if self.dead.contains(&position) {
+            return None;
+        }
+        self.board.get(&position).copied()
+    }
+
+    // dead_stones counts the stones of a color that have been marked dead.
+    fn dead_stones(&self, stone: Stone) -> usize {
+        self.dead
+            .iter()
+            .filter(|position| self.board.get(position) == Some(&stone))
+            .count()
+    }
+
+    // toggle_dead marks the chain at `position` dead, or alive again if it was already marked.
+    // Any change to the marking withdraws both players' acceptance of the score.
+    pub fn toggle_dead(&mut self, position: Coordinate) -> Result<(), PlayError> {
+        if self.phase != GamePhase::Scoring {
+            return Err(PlayError::NotScoring);
+        }
+        let chain = self.chain_at(position).ok_or(PlayError::NoStone)?;
+
+        let dead = !self.dead.contains(&position);
+        for chain_position in chain.stones {
+            if dead {
+                self.dead.insert(chain_position);
+            } else {
+                self.dead.remove(&chain_position);
+            }
+        }
+        self.black_accepted = false;
+        self.white_accepted = false;
+        self.record(LedgerEvent::DeadToggled { position });
+        Ok(())
+    }
+
+    // dead returns the positions of every stone marked dead.
+    pub fn dead(&self) -> Vec<Coordinate> {
+        self.dead.iter().cloned().collect()
+    }
+
+    // accept_score records a player's agreement with the marked dead stones, once both players
+    // accept the game is finished.
+    pub fn accept_score(&mut self, stone: Stone) -> Result<(), PlayError> {
+        if self.phase != GamePhase::Scoring {
+            return Err(PlayError::NotScoring);
+        }
+        match stone {
+            Stone::Black => self.black_accepted = true,
+            Stone::White => self.white_accepted = true,
+        }
+        if self.black_accepted && self.white_accepted {
+            self.phase = GamePhase::Finished;
+        }
+        self.record(LedgerEvent::ScoreAccepted { stone });
+        Ok(())
+    }
+
+    // resign gives up the game, finishing it with the opponent the winner.
+    pub fn resign(&mut self, stone: Stone) -> Result<(), PlayError> {
+        if self.phase == GamePhase::Finished {
+            return Err(PlayError::NotPlaying);
+        }
+        self.resigned = Some(stone);
+        self.phase = GamePhase::Finished;
+        self.undo_requested = None;
+        self.record(LedgerEvent::Resigned { stone });
+        Ok(())
+    }
+
+    // resigned returns the player who gave up the game, if it ended that way.
+    pub fn resigned(&self) -> Option<Stone> {
+        self.resigned
+    }
+
+    // clock returns the time the players have left, if the game is timed.
+    pub fn clock(&self) -> Option<&Clock> {
+        self.clock.as_ref()
+    }
+
+    // set_time_control times the game, both players start with all their time. Up to `lag`
+    // milliseconds of every move aren't charged.
+    pub fn set_time_control(&mut self, control: TimeControl, lag: u64) {
+        let mut clock = Clock::new(control);
+        clock.set_lag(lag);
+        self.clock = Some(clock);
+    }
+
+    // run_clock keeps a timed game's clock in step after a change made at `at`, in milliseconds
+    // since the UNIX epoch. A player who moved since move `from` is charged the time they took,
+    // then the time of the player whose turn it is runs while both seats are taken and the game
+    // is being played.
+    pub fn run_clock(&mut self, from: usize, at: u64) {
+        let moved = self.history.get(from..).and_then(|moves| moves.last());
+        let moved = moved.map(|played| played.stone);
+        let clock = match &mut self.clock {
+            Some(clock) => clock,
+            None => return,
+        };
+        // A move that beat the flag to the server still came too late, even a pass that ended play
+        if let Some(stone) = moved.filter(|stone| !clock.press(*stone, at)) {
+            self.phase = GamePhase::Playing;
+            let _ = self.time_out(stone);
+        }
+        let running = self.phase == GamePhase::Playing && self.guest_seated;
+        let turn = self.turn;
+        if let Some(clock) = &mut self.clock {
+            if running {
+                clock.start(turn, at);
+            } else {
+                clock.stop();
+            }
+        }
+    }
+
+    // time_warnings returns when a player is warned their time is running out: what they chose,
+    // otherwise what the game was created with, otherwise the defaults.
+    pub fn time_warnings(&self, stone: Stone) -> TimeWarnings {
+        let chosen = match stone {
+            Stone::Black => &self.black_warnings,
+            Stone::White => &self.white_warnings,
+        };
+        chosen
+            .as_ref()
+            .or(self.warnings.as_ref())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // set_time_warnings changes when the game's players are warned their time is running out,
+    // None warns them at the defaults.
+    pub fn set_time_warnings(&mut self, warnings: Option<TimeWarnings>) {
+        self.warnings = warnings;
+    }
+
+    // choose_time_warnings changes when a player is warned their time is running out, None
+    // leaves it to the game.
+    pub fn choose_time_warnings(&mut self, stone: Stone, warnings: Option<TimeWarnings>) {
+        match stone {
+            Stone::Black => self.black_warnings = warnings,
+            Stone::White => self.white_warnings = warnings,
+        }
+    }
+
+    // resume_clock leaves a pause from `paused` until `at` uncharged, see Clock::resume.
+    pub fn resume_clock(&mut self, paused: u64, at: u64) {
+        if let Some(clock) = &mut self.clock {
+            clock.resume(paused, at);
+        }
+    }
+
+    // flag ends the game if the player whose time is running has run out of it by `at`,
+    // returning them.
+    pub fn flag(&mut self, at: u64) -> Option<Stone> {
+        let stone = self.clock.as_ref()?.flagged(at)?;
+        self.time_out(stone).ok()?;
+        Some(stone)
+    }
+
+    // time_out finishes the game with the player out of time, their opponent wins.
+    pub fn time_out(&mut self, stone: Stone) -> Result<(), PlayError> {
+        if self.phase != GamePhase::Playing {
+            return Err(PlayError::NotPlaying);
+        }
+        if let Some(clock) = &mut self.clock {
+            clock.run_out(stone);
+        }
+        self.timed_out = Some(stone);
+        self.phase = GamePhase::Finished;
+        self.undo_requested = None;
+        self.record(LedgerEvent::TimedOut { stone });
+        Ok(())
+    }
+
+    // timed_out returns the player who ran out of time, if the game ended that way.
+    pub fn timed_out(&self) -> Option<Stone> {
+        self.timed_out
+    }
+
+    // deadline is when the player whose time is running runs out of it, in milliseconds since
+    // the UNIX epoch.
+    pub fn deadline(&self) -> Option<u64> {
+        let (_, deadline) = self.clock.as_ref()?.deadline()?;
+        Some(deadline)
+    }
+
+    // contact returns where a player asked to be told it is their turn.
+    pub fn contact(&self, stone: Stone) -> Option<&Contact> {
+        match stone {
+            Stone::Black => self.black_contact.as_ref(),
+            Stone::White => self.white_contact.as_ref(),
+        }
+    }
+
+    // bot returns the guest's seat if the game's creator asked to play a bot.
+    pub fn bot(&self) -> Option<&BotSeat> {
+        self.bot.as_ref()
+    }
+
+    pub fn set_bot(&mut self, seat: Option<BotSeat>) {
+        self.bot = seat;
+    }
+
+    // webhooks returns the URLs the game's events are posted to.
+    pub fn webhooks(&self) -> &[Webhook] {
+        &self.webhooks
+    }
+
+    // add_webhook has the game's events posted to another URL, up to MAX_WEBHOOKS.
+    pub fn add_webhook(&mut self, hook: Webhook) -> Result<(), WebhookError> {
+        if self.webhooks.len() >= webhook::MAX_WEBHOOKS {
+            return Err(WebhookError::TooMany);
+        }
+        self.webhooks.push(hook);
+        Ok(())
+    }
+
+    // set_contact changes where a player is told it is their turn, None stops telling them.
+    pub fn set_contact(&mut self, stone: Stone, contact: Option<Contact>) {
+        match stone {
+            Stone::Black => self.black_contact = contact,
+            Stone::White => self.white_contact = contact,
+        }
+    }
+
+    // forget_player strips a player's identity from the game and keeps their moves: the secret
+    // their seat was held with, where they were told of their turns, the warnings they chose and
+    // what they said in the chat. The webhooks go with whoever created the game.
+    pub fn forget_player(&mut self, stone: Stone) {
+        match stone {
+            Stone::Black => self.black_token = None,
+            Stone::White => self.white_token = None,
+        }
+        self.set_contact(stone, None);
+        self.choose_time_warnings(stone, None);
+        self.chat.retain(|line| line.from != Some(stone));
+        if self.host == Some(stone) {
+            self.webhooks.clear();
+        }
+    }
+
+    // accepted returns true if the player has agreed to the marked score.
+    pub fn accepted(&self, stone: Stone) -> bool {
+        match stone {
+            Stone::Black => self.black_accepted,
+            Stone::White => self.white_accepted,
+        }
+    }
+
+    // territory flood fills every empty region of the board, returning each empty position that
+    // belongs to a player. A region belongs to a player when it only borders that player's stones.
+    // Stones marked dead are treated as empty points.
+    pub fn territory(&self) -> StoneMap {
+        let mut territory = StoneMap::new();
+        let mut searched_tiles = HashSet::<Coordinate>::new();
+
+        let extent = self.size as i8;
+        for row in 0..extent {
+            for column in 0..extent {
+                let start = (column, row);
+                if self.living_stone(start).is_some() || searched_tiles.contains(&start) {
+                    continue;
+                }
+
+                let mut region = vec![start];
+                let mut borders = HashSet::<Stone>::new();
+                let mut positions_to_search = vec![start];
+                searched_tiles.insert(start);
+
+                while let Some(position) = positions_to_search.pop() {
+                    for search_position in self.adjacent_positions(position) {
+                        match self.living_stone(search_position) {
+                            Some(stone) => {
+                                borders.insert(stone);
+                            }
+                            None => {
+                                if searched_tiles.insert(search_position) {
+                                    positions_to_search.push(search_position);
+                                    region.push(search_position);
+                                }
+                            }
+                        }
+                    }
+                }
+
+                if borders.len() == 1 {
+                    let owner = *borders.iter().next().unwrap();
+                    for position in region {
+                        territory.insert(position, owner);
+                    }
+                }
+            }
+        }
+
+        territory
+    }
+
+    // score counts the game according to its rules. With territory scoring (Japanese rules) each
+    // player scores the empty points they surround plus the stones they captured, with area
+    // scoring (Chinese rules) they score the empty points they surround plus their stones on the
+    // board. Dead stones count as captured by the opponent. White also receives komi.
+    pub fn score(&self) -> Score {
+        let territory = self.territory();
+        let count = |stone: Stone| {
+            let points = territory.values().filter(|owner| **owner == stone).count();
+            let bonus = match self.rules {
+                ScoringRules::Japanese => self.prisoners(stone) + self.dead_stones(self.foe(stone)),
+                ScoringRules::Chinese => self.player_stones(stone) - self.dead_stones(stone),
+            };
+            (points + bonus) as f32
+        };
+        let black = count(Stone::Black);
+        let white = count(Stone::White) + self.komi;
+
+        let winner = if black > white {
+            Some(Stone::Black)
+        } else if white > black {
+            Some(Stone::White)
+        } else {
+            None
+        };
+
+        Score {
+            black,
+            white,
+            winner,
+            margin: (black - white).abs(),
+        }
+    }
+
+    // winner is the player who won the game, by resignation, on time or on the score.
+    pub fn winner(&self) -> Option<Stone> {
+        match self.resigned.or(self.timed_out) {
+            Some(stone) => Some(self.foe(stone)),
+            None => self.score().winner,
+        }
+    }
+}
 
 impl fmt::Debug for Game {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
@@ -383,6 +2403,59 @@ impl fmt::Debug for Game {
     }
 }
 
+// Display renders a labelled board along with the turn, captures and last move, suitable for
+// reading in a terminal or with a screen reader.
+//
+// e.g:
+//     A B C D E F G H J
+//   9 . . . . . . . . . 9
+//   ...
+impl fmt::Display for Game {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let extent = self.size as i8;
+        let labels: Vec<String> = COLUMN_LABELS
+            .chars()
+            .take(extent as usize)
+            .map(String::from)
+            .collect();
+        let header = format!("    {}", labels.join(" "));
+
+        writeln!(formatter, "{}", header)?;
+        for row in 0..extent {
+            write!(formatter, "{:>3}", extent - row)?;
+            for column in 0..extent {
+                formatter.write_str(match self.board.get(&(column, row)) {
+                    Some(&Stone::Black) => " X",
+                    Some(&Stone::White) => " O",
+                    None => " .",
+                })?
+            }
+            writeln!(formatter, " {}", extent - row)?;
+        }
+        writeln!(formatter, "{}", header)?;
+        writeln!(formatter)?;
+
+        writeln!(formatter, "Turn: {:?}", self.turn)?;
+        writeln!(
+            formatter,
+            "Captures: Black {}, White {}",
+            self.black_prisoners, self.white_prisoners
+        )?;
+        match self.last_move {
+            _ if self.passes > 0 => {
+                writeln!(formatter, "Last move: {:?} pass", self.foe(self.turn))
+            }
+            Some((position, stone)) => writeln!(
+                formatter,
+                "Last move: {:?} {}",
+                stone,
+                format_coordinate(self.size, position)
+            ),
+            None => writeln!(formatter, "Last move: none"),
+        }
+    }
+}
+
 #[test]
 fn test_new() {
     let game = new(Size::Nine);
@@ -392,48 +2465,809 @@ fn test_new() {
 }
 
 #[test]
-fn test_play_stone() {
-    let mut game = new(Size::Nine);
-    assert_eq!(false, game.has_stone((0, 0)));
-
-    game.play_stone((0, 0), Stone::Black);
-    assert_eq!(true, game.has_stone((0, 0)));
-    assert_eq!(1, game.stones());
-    assert_eq!(1, game.player_stones(Stone::Black));
-    assert_eq!(0, game.player_stones(Stone::White));
+fn test_play_stone() {
+    let mut game = new(Size::Nine);
+    assert_eq!(false, game.has_stone((0, 0)));
+
+    game.play_stone((0, 0), Stone::Black).unwrap();
+    assert_eq!(true, game.has_stone((0, 0)));
+    assert_eq!(1, game.stones());
+    assert_eq!(1, game.player_stones(Stone::Black));
+    assert_eq!(0, game.player_stones(Stone::White));
+}
+
+#[test]
+fn test_play_stone_switches_players() {
+    let mut game = new(Size::Nine);
+    assert_eq!(true, game.play_stone((0, 0), Stone::Black).is_ok());
+    assert_eq!(
+        Err(PlayError::NotYourTurn),
+        game.play_stone((1, 0), Stone::Black)
+    );
+}
+
+#[test]
+fn test_play_stone_rejects_invalid_plays() {
+    let mut game = new(Size::Nine);
+    assert_eq!(
+        Err(PlayError::OutOfBounds),
+        game.play_stone((-1, 0), Stone::Black)
+    );
+    assert_eq!(
+        Err(PlayError::OutOfBounds),
+        game.play_stone((-1, -1), Stone::Black)
+    );
+    assert_eq!(
+        Err(PlayError::OutOfBounds),
+        game.play_stone((0, -1), Stone::Black)
+    );
+    assert_eq!(
+        Err(PlayError::OutOfBounds),
+        game.play_stone((9, 0), Stone::Black)
+    );
+    assert_eq!(
+        Err(PlayError::OutOfBounds),
+        game.play_stone((9, 9), Stone::Black)
+    );
+    assert_eq!(
+        Err(PlayError::OutOfBounds),
+        game.play_stone((0, 9), Stone::Black)
+    );
+}
+
+#[test]
+fn test_play_stone_rejects_duplicate_plays() {
+    let mut game = new(Size::Nine);
+    assert_eq!(true, game.play_stone((0, 0), Stone::Black).is_ok());
+    assert_eq!(
+        Err(PlayError::Occupied),
+        game.play_stone((0, 0), Stone::White)
+    );
+}
+
+#[test]
+fn test_parse() {
+    let game = parse(
+        "
+.b.......
+b........
+.........
+.........
+.........
+.........
+.........
+.........
+.........",
+        Stone::Black,
+    )
+    .unwrap();
+
+    assert_eq!(false, game.has_stone((0, 0)));
+    assert_eq!(true, game.has_stone((1, 0)));
+    assert_eq!(true, game.has_stone((0, 1)));
+    assert_eq!(false, game.has_stone((1, 1)));
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!(
+        Err(BoardParseError::BadSize("3".to_string())),
+        parse("...\n...\n...", Stone::Black).map(|_| ())
+    );
+
+    let mut rows = ["........."; 9];
+    rows[4] = "....b...";
+    assert_eq!(
+        Err(BoardParseError::WrongLength {
+            expected: 9,
+            found: 8
+        }),
+        parse(&rows.join("\n"), Stone::Black).map(|_| ())
+    );
+
+    rows[4] = "....x....";
+    assert_eq!(
+        Err(BoardParseError::BadTile {
+            position: (4, 4),
+            tile: 'x'
+        }),
+        parse(&rows.join("\n"), Stone::Black).map(|_| ())
+    );
+}
+
+#[test]
+fn test_decode_errors() {
+    let id = Uuid::nil();
+    let tiles = ".".repeat(81);
+    let decode_error = |board: String| decode(&board).map(|_| ()).unwrap_err();
+
+    assert_eq!(
+        BoardParseError::MissingSegment("size"),
+        decode_error(id.to_string())
+    );
+    assert_eq!(
+        BoardParseError::MissingSegment("turn"),
+        decode_error(format!("{};9;{}", id, tiles))
+    );
+    assert_eq!(
+        BoardParseError::BadGameId("game".to_string()),
+        decode_error(format!("game;9;{};b", tiles))
+    );
+    assert_eq!(
+        BoardParseError::BadSize("10".to_string()),
+        decode_error(format!("{};10;{};b", id, tiles))
+    );
+    assert_eq!(
+        BoardParseError::WrongLength {
+            expected: 81,
+            found: 80
+        },
+        decode_error(format!("{};9;{};b", id, &tiles[1..]))
+    );
+    assert_eq!(
+        BoardParseError::BadTile {
+            position: (1, 2),
+            tile: 'B'
+        },
+        decode_error(format!("{};9;{}B{};b", id, &tiles[..19], &tiles[20..]))
+    );
+    assert_eq!(
+        BoardParseError::BadTurn("x".to_string()),
+        decode_error(format!("{};9;{};x", id, tiles))
+    );
+    assert_eq!(
+        BoardParseError::BadSegment("komi".to_string()),
+        decode_error(format!("{};9;{};b;komi=lots", id, tiles))
+    );
+}
+
+#[test]
+fn test_decode_impossible_history() {
+    let mut game = new(Size::Nine);
+    assert_eq!(true, game.play_stone((4, 4), Stone::Black).is_ok());
+    assert_eq!(true, game.play_stone((3, 3), Stone::White).is_ok());
+    assert_eq!(true, game.play_stone((0, 0), Stone::Black).is_ok());
+    let encoded = encode_with_history(&game);
+    assert_eq!(true, decode(&encoded).is_ok());
+
+    let impossible = |tampered: String| {
+        matches!(
+            decode(&tampered),
+            Err(BoardParseError::ImpossiblePosition(_))
+        )
+    };
+    assert_eq!(
+        true,
+        impossible(encoded.replace("prisoners=0,0", "prisoners=1,0"))
+    );
+    assert_eq!(true, impossible(encoded.replace("w3,3", "b3,3")));
+    assert_eq!(true, impossible(encoded.replace(";w;", ";b;")));
+    // White's stone taken off the board without being captured
+    let tiles = encode(&game).split(';').nth(2).unwrap().to_string();
+    let missing = tiles.replacen('w', ".", 1);
+    assert_eq!(true, impossible(encoded.replace(&tiles, &missing)));
+}
+
+#[test]
+fn test_play_stone_no_liberties() {
+    let mut game = parse(
+        "
+.b.....b.
+b.bbbbbb.
+.b....bbb
+..bbbbb..
+.....b...
+.........
+.........
+b.......b
+.b.....b.",
+        Stone::White,
+    )
+    .unwrap();
+
+    // Top left corner
+    assert_eq!(
+        Err(PlayError::Suicide),
+        game.play_stone((0, 0), Stone::White)
+    );
+
+    // Surrounded stone
+    assert_eq!(
+        Err(PlayError::Suicide),
+        game.play_stone((1, 1), Stone::White)
+    );
+
+    // Bottom right corner
+    assert_eq!(
+        Err(PlayError::Suicide),
+        game.play_stone((8, 8), Stone::White)
+    );
+
+    // Bottom left corner
+    assert_eq!(
+        Err(PlayError::Suicide),
+        game.play_stone((0, 8), Stone::White)
+    );
+
+    // Top right corner
+    assert_eq!(true, game.play_stone((8, 0), Stone::White).is_ok());
+    assert_eq!(
+        Err(PlayError::NotYourTurn),
+        game.play_stone((8, 1), Stone::White)
+    );
+}
+
+#[test]
+fn test_play_stone_empty_neighbour() {
+    let mut game = parse(
+        "
+.b.....b.
+b.bbbbbb.
+.b....bbb
+..bbb.b..
+.....b...
+.........
+.........
+b.......b
+.b.....b.",
+        Stone::White,
+    )
+    .unwrap();
+
+    assert_eq!(true, game.play_stone((2, 0), Stone::White).is_ok());
+}
+
+#[test]
+fn test_play_stone_capture_piece() {
+    let mut game = parse(
+        "
+.........
+bwb......
+.b.......
+.........
+.........
+.........
+.........
+.........
+.........",
+        Stone::Black,
+    )
+    .unwrap();
+
+    assert_eq!(true, game.play_stone((1, 0), Stone::Black).is_ok());
+    assert_eq!(false, game.has_stone((1, 1)));
+    assert_eq!(1, game.prisoners(Stone::Black));
+    assert_eq!(0, game.prisoners(Stone::White));
+    assert_eq!(Some(Stone::Black), game.winner());
+}
+
+#[test]
+fn test_play_stone_capture_piece_exchange() {
+    let mut game = parse(
+        "
+.bw......
+bw.w.....
+.bw......
+.........
+.........
+.........
+.........
+.........
+.........",
+        Stone::Black,
+    )
+    .unwrap();
+    game.set_komi(0.0);
+
+    let outcome = game.play_stone((2, 1), Stone::Black).unwrap();
+    assert_eq!(vec![(1, 1)], outcome.captured);
+    assert_eq!(false, game.has_stone((1, 1)));
+    assert_eq!(Some(Stone::Black), game.winner());
+}
+
+#[test]
+fn test_play_stone_cannot_place_neighbour_has_no_liberties() {
+    let mut game = parse(
+        "
+bb.w.....
+www......
+.........
+.........
+.........
+.........
+.........
+.........
+.........",
+        Stone::Black,
+    )
+    .unwrap();
+
+    assert_eq!(
+        Err(PlayError::Suicide),
+        game.play_stone((2, 0), Stone::Black)
+    );
+}
+
+#[test]
+fn test_play_stone_rejects_superko() {
+    let mut game = parse(
+        "
+.bw......
+bw.w.....
+.bw......
+.........
+.........
+.........
+.........
+.........
+.........",
+        Stone::Black,
+    )
+    .unwrap();
+
+    // Black takes the ko
+    assert_eq!(true, game.play_stone((2, 1), Stone::Black).is_ok());
+    assert_eq!(false, game.has_stone((1, 1)));
+
+    // White may not immediately retake, it would repeat the previous position
+    assert_eq!(
+        Err(PlayError::KoViolation),
+        game.play_stone((1, 1), Stone::White)
+    );
+    assert_eq!(Stone::White, game.turn());
+
+    // After an exchange elsewhere the position is new and the ko can be retaken
+    assert_eq!(true, game.play_stone((8, 8), Stone::White).is_ok());
+    assert_eq!(true, game.play_stone((8, 7), Stone::Black).is_ok());
+    assert_eq!(true, game.play_stone((1, 1), Stone::White).is_ok());
+    assert_eq!(false, game.has_stone((2, 1)));
+}
+
+#[test]
+fn test_position_hash_is_incremental() {
+    let mut game = new(Size::Nine);
+    assert_eq!(true, game.play_stone((4, 4), Stone::Black).is_ok());
+    assert_eq!(true, game.play_stone((3, 3), Stone::White).is_ok());
+    assert_eq!(position_hash(game.size, &game.board), game.hash);
+}
+
+#[test]
+fn test_format_coordinate() {
+    assert_eq!("A9", format_coordinate(Size::Nine, (0, 0)));
+    assert_eq!("J1", format_coordinate(Size::Nine, (8, 8)));
+    assert_eq!("D4", format_coordinate(Size::Nineteen, (3, 15)));
+    assert_eq!("T19", format_coordinate(Size::Nineteen, (18, 0)));
+}
+
+#[test]
+fn test_encode_decode_round_trip() {
+    let mut game = new(Size::Nine);
+    assert_eq!(true, game.play_stone((0, 0), Stone::Black).is_ok());
+    assert_eq!(true, game.play_stone((1, 0), Stone::White).is_ok());
+    assert_eq!(true, game.play_stone((8, 8), Stone::Black).is_ok());
+    assert_eq!(true, game.play_stone((0, 1), Stone::White).is_ok());
+
+    let decoded = decode(&encode(&game)).unwrap();
+    assert_eq!(game.id, decoded.id);
+    assert_eq!(game.board, decoded.board);
+    assert_eq!(Stone::Black, decoded.turn());
+    assert_eq!(1, decoded.prisoners(Stone::White));
+    assert_eq!(Some(((0, 1), Stone::White)), decoded.last_move());
+    assert_eq!(4, decoded.move_number());
+    assert_eq!(encode(&game), encode(&decoded));
+
+    assert_eq!(false, decoded.guest_seated());
+    assert_eq!(true, game.seat_guest());
+    assert_eq!(false, game.seat_guest());
+    assert_eq!(true, decode(&encode(&game)).unwrap().guest_seated());
+}
+
+#[test]
+fn test_chat() {
+    let mut game = new(Size::Nine);
+    let line = |from, text: &str| ChatEntry {
+        from,
+        text: text.to_string(),
+        at: 1700000000000,
+    };
+    game.say(line(Some(Stone::Black), "Have a good game; gl:hf"));
+    game.say(line(None, "Nice opening ✓"));
+
+    // Chat is kept with the history, the text can hold the record's separators
+    assert_eq!(false, encode(&game).contains("chat="));
+    let decoded = decode(&encode_with_history(&game)).unwrap();
+    assert_eq!(game.chat(), decoded.chat());
+    assert_eq!(
+        Some(BoardParseError::BadSegment("chat".to_string())),
+        decode(&format!("{};chat=b1@6", encode(&game))).err()
+    );
+
+    // Only the latest lines are kept
+    for _ in 0..CHAT_HISTORY {
+        game.say(line(Some(Stone::White), "..."));
+    }
+    assert_eq!(CHAT_HISTORY, game.chat().len());
+    assert_eq!(Some(Stone::White), game.chat()[0].from);
+}
+
+#[test]
+fn test_forget_player() {
+    let mut game = new(Size::Nine);
+    let black = game.issue_host_token();
+    let white = game.request_seat().unwrap();
+    assert_eq!(Some(Stone::White), game.accept_join());
+    game.set_contact(
+        Stone::White,
+        Some(Contact::Webhook("https://example.com/white".to_string())),
+    );
+    game.add_webhook(Webhook::new("https://example.com/hook").unwrap())
+        .unwrap();
+    for (from, at) in [(Stone::Black, 1), (Stone::White, 2), (Stone::White, 3)] {
+        game.say(ChatEntry {
+            from: Some(from),
+            text: "gg".to_string(),
+            at,
+        });
+    }
+    game.open_ledger();
+    assert_eq!(true, game.pass(Stone::Black).is_ok());
+
+    // Chat said before a time goes, the ledger can go whole
+    assert_eq!(true, game.forget_chat(2));
+    assert_eq!(false, game.forget_chat(2));
+    assert_eq!(2, game.chat().len());
+    assert_eq!(true, game.forget_ledger());
+    assert_eq!(false, game.forget_ledger());
+
+    // The guest's identity goes, their moves and the game's webhooks stay
+    game.forget_player(Stone::White);
+    assert_eq!(None, game.seat_of(&white));
+    assert_eq!(Some(Stone::Black), game.seat_of(&black));
+    assert_eq!(None, game.contact(Stone::White));
+    assert_eq!(true, game.chat().is_empty());
+    assert_eq!(1, game.history().len());
+    assert_eq!(1, game.webhooks().len());
+    // The webhooks go with whoever created the game
+    game.forget_player(Stone::Black);
+    assert_eq!(true, game.webhooks().is_empty());
+}
+
+#[test]
+fn test_tokens() {
+    let mut game = new(Size::Nine);
+    assert_eq!(false, game.token_matches(Stone::Black, "anything"));
+    assert_eq!(false, game.token_matches(Stone::Black, ""));
+    let black = game.issue_token(Stone::Black);
+    assert_eq!(true, game.token_matches(Stone::Black, &black));
+    assert_eq!(false, game.token_matches(Stone::Black, "anything"));
+
+    // White's token is handed out when a player asks to join, and only holds the seat once they
+    // are accepted
+    assert_eq!(None, game.accept_join());
+    let first = game.request_seat().unwrap();
+    let white = game.request_seat().unwrap();
+    assert_eq!(false, game.token_matches(Stone::White, &white));
+    // A request waiting to be accepted is kept in the server's records
+    let mut decoded = decode(&encode_with_history(&game)).unwrap();
+    assert_eq!(Some(Stone::White), decoded.accept_join());
+    assert_eq!(true, decoded.token_matches(Stone::White, &white));
+    assert_eq!(Some(Stone::White), game.accept_join());
+    assert_eq!(true, game.guest_seated());
+    assert_eq!(true, game.token_matches(Stone::White, &white));
+    assert_eq!(false, game.token_matches(Stone::White, &first));
+    assert_eq!(false, game.token_matches(Stone::White, &black));
+    assert_eq!(None, game.request_seat());
+    assert_eq!(None, game.accept_join());
+    assert_eq!(Some(Stone::Black), game.seat_of(&black));
+    assert_eq!(Some(Stone::White), game.seat_of(&white));
+    assert_eq!(None, game.seat_of(&first));
+
+    // Tokens are kept in the server's records, but never sent to players
+    assert_eq!(false, encode(&game).contains(&black));
+    let decoded = decode(&encode_with_history(&game)).unwrap();
+    assert_eq!(true, decoded.token_matches(Stone::Black, &black));
+    assert_eq!(true, decoded.token_matches(Stone::White, &white));
+    assert_eq!(
+        Some(BoardParseError::BadSegment("btoken".to_string())),
+        decode(&format!("{};btoken=", encode(&game))).err()
+    );
+}
+
+#[test]
+fn test_host_color() {
+    // A player may create a game to play White, the player joining gets Black
+    let mut game = new(Size::Nine);
+    game.set_host(Some(Stone::White));
+    let host = game.issue_host_token();
+    let guest = game.request_seat().unwrap();
+    assert_eq!(Some(Stone::White), game.seat_of(&host));
+    assert_eq!(Some(Stone::Black), game.accept_join());
+    assert_eq!(Some(Stone::Black), game.seat_of(&guest));
+    let decoded = decode(&encode_with_history(&game)).unwrap();
+    assert_eq!(Some(Stone::White), decoded.host());
+    assert_eq!(true, decoded.guest_seated());
+    assert_eq!(Some(Stone::Black), decoded.seat_of(&guest));
+
+    // Or leave the stones to be drawn when their opponent is accepted
+    let mut game = new(Size::Nine);
+    game.set_host(None);
+    let host = game.issue_host_token();
+    assert_eq!(None, game.guest());
+    assert_eq!(None, game.seat_of(&host));
+    assert_eq!(true, game.is_host(&host));
+    assert_eq!(false, game.is_host("anything"));
+    let guest = game.request_seat().unwrap();
+    let mut decoded = decode(&encode_with_history(&game)).unwrap();
+    assert_eq!(None, decoded.host());
+    assert_eq!(true, decoded.is_host(&host));
+    let stone = game.accept_join().unwrap();
+    assert_eq!(Some(stone), game.guest());
+    assert_eq!(Some(stone), game.seat_of(&guest));
+    assert_eq!(game.host(), game.seat_of(&host));
+    assert_eq!(true, game.is_host(&host));
+    let stone = decoded.accept_join().unwrap();
+    assert_eq!(Some(stone), decoded.seat_of(&guest));
+    assert_eq!(decoded.host(), decode(&encode(&decoded)).unwrap().host());
+}
+
+#[test]
+fn test_encode_decode_keeps_ko() {
+    // Black captures a white stone in a ko shape
+    let mut game = new(Size::Nine);
+    for (position, stone) in [
+        ((1, 0), Stone::Black),
+        ((2, 0), Stone::White),
+        ((0, 1), Stone::Black),
+        ((3, 1), Stone::White),
+        ((1, 2), Stone::Black),
+        ((2, 2), Stone::White),
+        ((8, 8), Stone::Black),
+        ((1, 1), Stone::White),
+        ((2, 1), Stone::Black),
+    ] {
+        assert_eq!(true, game.play_stone(position, stone).is_ok());
+    }
+    assert_eq!(vec![(1, 1)], game.history().last().unwrap().captured);
+
+    let encoded = encode(&game);
+    assert_eq!(true, encoded.contains(";ko=1,1"));
+    for mut decoded in [
+        decode(&encoded).unwrap(),
+        decode(&encode_with_history(&game)).unwrap(),
+    ] {
+        assert_eq!(
+            Err(PlayError::KoViolation),
+            decoded.play_stone((1, 1), Stone::White).map(|_| ())
+        );
+    }
+
+    let mut without_ko = decode(&encoded.replace(";ko=1,1", "")).unwrap();
+    assert_eq!(true, without_ko.play_stone((1, 1), Stone::White).is_ok());
+    assert_eq!(
+        Err(BoardParseError::BadSegment("ko".to_string())),
+        decode(&encoded.replace(";ko=1,1", ";ko=2,1")).map(|_| ())
+    );
+}
+
+// RandomStep is something a player may try during a randomly generated game.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+enum RandomStep {
+    Play(Coordinate),
+    Pass,
+    RequestUndo,
+    Undo,
+    Dead(Coordinate),
+    Accept(Stone),
+}
+
+// random_game generates games reached by playing random steps, illegal steps are skipped.
+#[cfg(test)]
+fn random_game() -> impl proptest::strategy::Strategy<Value = Game> {
+    use proptest::prelude::*;
+
+    // Plays are kept to a corner so there are fights, and captures, on every size
+    let point = (0..9i8, 0..9i8);
+    let stone = prop_oneof![Just(Stone::Black), Just(Stone::White)];
+    let step = prop_oneof![
+        12 => point.clone().prop_map(RandomStep::Play),
+        2 => Just(RandomStep::Pass),
+        1 => Just(RandomStep::RequestUndo),
+        1 => Just(RandomStep::Undo),
+        2 => point.prop_map(RandomStep::Dead),
+        1 => stone.prop_map(RandomStep::Accept),
+    ];
+    let options = (
+        proptest::sample::select(Size::ALL.to_vec()),
+        prop_oneof![Just(0u8), 2..5u8],
+        any::<bool>(),
+        any::<bool>(),
+        any::<bool>(),
+        -20i8..20,
+    );
+    (options, proptest::collection::vec(step, 0..300)).prop_map(
+        |((size, handicap, free, chinese, suicide, komi), steps)| {
+            let mut game = new(size);
+            if handicap > 0 {
+                let _ = match free {
+                    true => game.start_free_handicap(handicap),
+                    false => game.place_handicap(handicap),
+                };
+            }
+            if chinese {
+                game.set_rules(ScoringRules::Chinese);
+            }
+            game.set_suicide_allowed(suicide);
+            game.set_komi(komi as f32 + 0.5);
+            for step in steps {
+                let _ = match step {
+                    RandomStep::Play(position) => {
+                        game.play_stone(position, game.turn()).map(|_| ())
+                    }
+                    RandomStep::Pass => game.pass(game.turn()),
+                    RandomStep::RequestUndo => game.request_undo(game.foe(game.turn())),
+                    RandomStep::Undo => game.undo().map(|_| ()),
+                    RandomStep::Dead(position) => game.toggle_dead(position),
+                    RandomStep::Accept(stone) => game.accept_score(stone),
+                };
+            }
+            game
+        },
+    )
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_encode_decode_round_trips_any_game(game in random_game()) {
+        let compact = decode(&encode(&game)).unwrap();
+        proptest::prop_assert_eq!(encode(&game), encode(&compact));
+        proptest::prop_assert_eq!(game.move_number(), compact.move_number());
+
+        let full = decode(&encode_with_history(&game)).unwrap();
+        proptest::prop_assert_eq!(encode_with_history(&game), encode_with_history(&full));
+        proptest::prop_assert_eq!(&game.history, &full.history);
+        // Superko survives along with the history
+        proptest::prop_assert_eq!(
+            game.legal_moves(game.turn()),
+            full.legal_moves(game.turn())
+        );
+    }
+}
+
+#[test]
+fn test_display() {
+    let mut game = parse(
+        "
+.........
+bwb......
+.b.......
+.........
+.........
+.........
+.........
+.........
+.........",
+        Stone::Black,
+    )
+    .unwrap();
+    assert_eq!(true, game.play_stone((1, 0), Stone::Black).is_ok());
+
+    let text = game.to_string();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!("    A B C D E F G H J", lines[0]);
+    assert_eq!("  9 . X . . . . . . . 9", lines[1]);
+    assert_eq!("  8 X . X . . . . . . 8", lines[2]);
+    assert_eq!("Turn: White", lines[12]);
+    assert_eq!("Captures: Black 1, White 0", lines[13]);
+    assert_eq!("Last move: Black B9", lines[14]);
 }
 
 #[test]
-fn test_play_stone_switches_players() {
-    let mut game = new(Size::Nine);
-    assert_eq!(true, game.play_stone((0, 0), Stone::Black));
-    assert_eq!(false, game.play_stone((1, 0), Stone::Black));
+fn test_parse_coordinate() {
+    assert_eq!(Ok((3, 15)), parse_coordinate(Size::Nineteen, "D4"));
+    assert_eq!(Ok((3, 15)), parse_coordinate(Size::Nineteen, " d4 "));
+    assert_eq!(Ok((8, 0)), parse_coordinate(Size::Nine, "J9"));
+    assert_eq!(Ok((0, 8)), parse_coordinate(Size::Nine, "A1"));
+    assert_eq!(
+        Err(CoordinateError::Empty),
+        parse_coordinate(Size::Nine, "")
+    );
+    assert_eq!(
+        Err(CoordinateError::InvalidColumn('I', 'J')),
+        parse_coordinate(Size::Nine, "I5")
+    );
+    assert_eq!(
+        Err(CoordinateError::InvalidColumn('K', 'J')),
+        parse_coordinate(Size::Nine, "K5")
+    );
+    assert_eq!(
+        Err(CoordinateError::InvalidRow("10".to_string(), 9)),
+        parse_coordinate(Size::Nine, "A10")
+    );
+    assert_eq!(
+        Err(CoordinateError::InvalidRow("".to_string(), 9)),
+        parse_coordinate(Size::Nine, "A")
+    );
+
+    for x in 0..19 {
+        for y in 0..19 {
+            let label = format_coordinate(Size::Nineteen, (x, y));
+            assert_eq!(Ok((x, y)), parse_coordinate(Size::Nineteen, &label));
+        }
+    }
 }
 
 #[test]
-fn test_play_stone_rejects_invalid_plays() {
+fn test_pass() {
     let mut game = new(Size::Nine);
-    assert_eq!(false, game.play_stone((-1, 0), Stone::Black));
-    assert_eq!(false, game.play_stone((-1, -1), Stone::Black));
-    assert_eq!(false, game.play_stone((0, -1), Stone::Black));
-    assert_eq!(false, game.play_stone((9, 0), Stone::Black));
-    assert_eq!(false, game.play_stone((9, 9), Stone::Black));
-    assert_eq!(false, game.play_stone((0, 9), Stone::Black));
+    assert_eq!(Err(PlayError::NotYourTurn), game.pass(Stone::White));
+    assert_eq!(Ok(()), game.pass(Stone::Black));
+    assert_eq!(Stone::White, game.turn());
+    assert_eq!(1, game.passes());
+    assert_eq!(true, game.play_stone((4, 4), Stone::White).is_ok());
+    assert_eq!(0, game.passes());
+
+    assert_eq!(Ok(()), game.pass(Stone::Black));
+    let decoded = decode(&encode(&game)).unwrap();
+    assert_eq!(1, decoded.passes());
+    assert_eq!(true, decoded.to_string().contains("Last move: Black pass"));
 }
 
 #[test]
-fn test_play_stone_rejects_duplicate_plays() {
+fn test_two_passes_end_play() {
     let mut game = new(Size::Nine);
-    assert_eq!(true, game.play_stone((0, 0), Stone::Black));
-    assert_eq!(false, game.play_stone((0, 0), Stone::White));
+    assert_eq!(GamePhase::Playing, game.phase());
+    assert_eq!(Ok(()), game.pass(Stone::Black));
+    assert_eq!(GamePhase::Playing, game.phase());
+    assert_eq!(Ok(()), game.pass(Stone::White));
+    assert_eq!(GamePhase::Scoring, game.phase());
+
+    assert_eq!(
+        Err(PlayError::NotPlaying),
+        game.play_stone((4, 4), Stone::Black)
+    );
+    assert_eq!(Err(PlayError::NotPlaying), game.pass(Stone::Black));
+
+    let decoded = decode(&encode(&game)).unwrap();
+    assert_eq!(GamePhase::Scoring, decoded.phase());
 }
 
 #[test]
-fn test_parse() {
+fn test_score_territory() {
     let game = parse(
         "
-.b.......
-b........
+..bw.....
+..bw.....
+..bw.....
+bbbw.....
+wwww.....
+.........
+.........
+.........
+.......w.",
+        Stone::Black,
+    )
+    .unwrap();
+
+    let territory = game.territory();
+    assert_eq!(Some(&Stone::Black), territory.get(&(0, 0)));
+    assert_eq!(Some(&Stone::White), territory.get(&(8, 8)));
+    assert_eq!(None, territory.get(&(3, 3)));
+
+    let score = game.score();
+    assert_eq!(6.0, score.black);
+    assert_eq!(66.5, score.white);
+    assert_eq!(Some(Stone::White), score.winner);
+    assert_eq!(60.5, score.margin);
+}
+
+#[test]
+fn test_score_counts_prisoners() {
+    let mut game = parse(
+        "
+.w.......
+wbw......
 .........
 .........
 .........
@@ -441,77 +3275,362 @@ b........
 .........
 .........
 .........",
+        Stone::White,
+    )
+    .unwrap();
+
+    assert_eq!(true, game.play_stone((1, 2), Stone::White).is_ok());
+    let score = game.score();
+    assert_eq!(0.0, score.black);
+    // 77 empty points plus one prisoner and komi
+    assert_eq!(84.5, score.white);
+}
+
+#[test]
+fn test_score_draw() {
+    let mut game = parse(
+        "
+...b.w...
+...b.w...
+...b.w...
+...b.w...
+...b.w...
+...b.w...
+...b.w...
+...b.w...
+...b.w...",
         Stone::Black,
     )
     .unwrap();
+    game.set_komi(0.0);
 
-    assert_eq!(false, game.has_stone((0, 0)));
-    assert_eq!(true, game.has_stone((1, 0)));
-    assert_eq!(true, game.has_stone((0, 1)));
-    assert_eq!(false, game.has_stone((1, 1)));
+    let score = game.score();
+    assert_eq!(27.0, score.black);
+    assert_eq!(27.0, score.white);
+    assert_eq!(None, score.winner);
+    assert_eq!(0.0, score.margin);
 }
 
 #[test]
-fn test_play_stone_no_liberties() {
+fn test_score_area() {
     let mut game = parse(
         "
-.b.....b.
-b.bbbbbb.
-.b....bbb
-..bbbbb..
-.....b...
+.w.......
+wbw......
 .........
+...b.....
 .........
-b.......b
-.b.....b.",
+.........
+.........
+.........
+.........",
         Stone::White,
     )
     .unwrap();
+    game.set_rules(ScoringRules::Chinese);
 
-    // Top left corner
-    assert_eq!(false, game.play_stone((0, 0), Stone::White));
+    assert_eq!(true, game.play_stone((1, 2), Stone::White).is_ok());
+    let score = game.score();
+    // One stone, no territory and the prisoner does not count
+    assert_eq!(1.0, score.black);
+    // Four stones plus (0, 0), the captured point and komi
+    assert_eq!(12.5, score.white);
 
-    // Surrounded stone
-    assert_eq!(false, game.play_stone((1, 1), Stone::White));
+    let decoded = decode(&encode(&game)).unwrap();
+    assert_eq!(ScoringRules::Chinese, decoded.rules());
+}
 
-    // Bottom right corner
-    assert_eq!(false, game.play_stone((8, 8), Stone::White));
+#[test]
+fn test_canonical_hash() {
+    let game = parse(
+        "
+.........
+..b......
+.........
+...w.....
+.........
+.........
+.........
+.........
+.........",
+        Stone::Black,
+    )
+    .unwrap();
+    let rotated = parse(
+        "
+.........
+.........
+.........
+.........
+.........
+.....w...
+.........
+......b..
+.........",
+        Stone::Black,
+    )
+    .unwrap();
+    let reflected = parse(
+        "
+.........
+......b..
+.........
+.....w...
+.........
+.........
+.........
+.........
+.........",
+        Stone::Black,
+    )
+    .unwrap();
+    let different = parse(
+        "
+.........
+..w......
+.........
+...b.....
+.........
+.........
+.........
+.........
+.........",
+        Stone::Black,
+    )
+    .unwrap();
 
-    // Bottom left corner
-    assert_eq!(false, game.play_stone((0, 8), Stone::White));
+    assert_eq!(game.canonical_hash(), rotated.canonical_hash());
+    assert_eq!(game.canonical_hash(), reflected.canonical_hash());
+    assert_ne!(game.canonical_hash(), different.canonical_hash());
+    assert_ne!(game.hash, rotated.hash);
+}
 
-    // Top right corner
-    assert_eq!(true, game.play_stone((8, 0), Stone::White));
-    assert_eq!(false, game.play_stone((8, 1), Stone::White));
+#[test]
+fn test_komi() {
+    let mut game = new(Size::Nine);
+    assert_eq!(DEFAULT_KOMI, game.komi());
+    assert_eq!(Some(Stone::White), game.winner());
+
+    game.set_komi(-0.5);
+    assert_eq!(Some(Stone::Black), game.winner());
+
+    game.set_komi(0.5);
+    let decoded = decode(&encode(&game)).unwrap();
+    assert_eq!(0.5, decoded.komi());
 }
 
 #[test]
-fn test_play_stone_empty_neighbour() {
+fn test_place_handicap() {
+    let mut game = new(Size::Nineteen);
+    assert_eq!(Ok(()), game.place_handicap(4));
+    assert_eq!(4, game.player_stones(Stone::Black));
+    assert_eq!(Stone::White, game.turn());
+    for label in ["D4", "Q16", "Q4", "D16"] {
+        let position = parse_coordinate(Size::Nineteen, label).unwrap();
+        assert_eq!(true, game.has_stone(position));
+    }
+    assert_eq!(Err(HandicapError::AlreadyStarted), game.place_handicap(2));
+
+    let decoded = decode(&encode(&game)).unwrap();
+    assert_eq!(4, decoded.handicap());
+    assert_eq!(game.board, decoded.board);
+    assert_eq!(Stone::White, decoded.turn());
+}
+
+#[test]
+fn test_place_handicap_counts() {
+    for size in [Size::Nine, Size::Thirteen, Size::Seventeen, Size::Nineteen] {
+        for stones in 2..=9 {
+            let mut game = new(size);
+            assert_eq!(Ok(()), game.place_handicap(stones));
+            assert_eq!(stones as usize, game.player_stones(Stone::Black));
+        }
+    }
+
+    let mut game = new(Size::Nineteen);
+    assert_eq!(Err(HandicapError::InvalidCount(1)), game.place_handicap(1));
+    assert_eq!(
+        Err(HandicapError::InvalidCount(10)),
+        game.place_handicap(10)
+    );
+
+    assert_eq!(Ok(()), game.place_handicap(5));
+    assert_eq!(true, game.has_stone((9, 9)));
+}
+
+#[test]
+fn test_free_handicap() {
+    let mut game = new(Size::Nine);
+    assert_eq!(Ok(()), game.start_free_handicap(3));
+    assert_eq!(GamePhase::Handicap, game.phase());
+
+    assert_eq!(true, game.play_stone((0, 0), Stone::Black).is_ok());
+    assert_eq!(
+        Err(PlayError::NotYourTurn),
+        game.play_stone((1, 1), Stone::White)
+    );
+    assert_eq!(Err(PlayError::NotPlaying), game.pass(Stone::Black));
+    assert_eq!(true, game.play_stone((1, 1), Stone::Black).is_ok());
+    assert_eq!(1, game.placements());
+
+    let mut game = decode(&encode(&game)).unwrap();
+    assert_eq!(GamePhase::Handicap, game.phase());
+    assert_eq!(1, game.placements());
+    assert_eq!(Stone::Black, game.turn());
+
+    assert_eq!(true, game.play_stone((2, 2), Stone::Black).is_ok());
+    assert_eq!(GamePhase::Playing, game.phase());
+    assert_eq!(Stone::White, game.turn());
+    assert_eq!(3, game.handicap());
+    assert_eq!(3, game.player_stones(Stone::Black));
+    assert_eq!(
+        Err(HandicapError::AlreadyStarted),
+        game.start_free_handicap(2)
+    );
+}
+
+#[test]
+fn test_play_stone_rejects_filling_last_eye() {
     let mut game = parse(
         "
-.b.....b.
-b.bbbbbb.
-.b....bbb
-..bbb.b..
-.....b...
+.bw......
+bbw......
+ww.......
 .........
 .........
-b.......b
-.b.....b.",
-        Stone::White,
+.........
+.........
+.........
+.........",
+        Stone::Black,
     )
     .unwrap();
 
-    assert_eq!(true, game.play_stone((2, 0), Stone::White));
+    // Filling the last eye would leave the whole black chain without liberties
+    assert_eq!(
+        Err(PlayError::Suicide),
+        game.play_stone((0, 0), Stone::Black)
+    );
+    assert_eq!(3, game.player_stones(Stone::Black));
+
+    // White playing in the eye captures the chain instead
+    assert_eq!(true, game.play_stone((8, 8), Stone::Black).is_ok());
+    let outcome = game.play_stone((0, 0), Stone::White).unwrap();
+    assert_eq!(vec![(0, 1), (1, 0), (1, 1)], outcome.captured);
 }
 
 #[test]
-fn test_play_stone_capture_piece() {
+fn test_play_stone_suicide_allowed() {
     let mut game = parse(
         "
+.bw......
+bbw......
+ww.......
 .........
-bwb......
-.b.......
+.........
+.........
+.........
+.........
+.........",
+        Stone::Black,
+    )
+    .unwrap();
+    game.set_suicide_allowed(true);
+
+    let mut game = decode(&encode(&game)).unwrap();
+    assert_eq!(true, game.suicide_allowed());
+
+    // Black sacrifices the chain, the stones go to White as prisoners
+    assert_eq!(
+        Ok(vec![(0, 0), (0, 1), (1, 0), (1, 1)]),
+        game.play_stone((0, 0), Stone::Black)
+            .map(|outcome| outcome.captured)
+    );
+    assert_eq!(0, game.player_stones(Stone::Black));
+    assert_eq!(4, game.prisoners(Stone::White));
+    assert_eq!(Stone::White, game.turn());
+    assert_eq!(position_hash(Size::Nine, &game.board), game.hash);
+
+    // A lone stone suicide leaves the board unchanged and is still rejected
+    assert_eq!(true, game.play_stone((8, 7), Stone::White).is_ok());
+    assert_eq!(true, game.play_stone((4, 4), Stone::Black).is_ok());
+    assert_eq!(true, game.play_stone((7, 8), Stone::White).is_ok());
+    assert_eq!(
+        Err(PlayError::Suicide),
+        game.play_stone((8, 8), Stone::Black)
+    );
+}
+
+#[test]
+fn test_toggle_dead() {
+    let mut game = parse(
+        "
+..bw.....
+..bw.....
+..bw.....
+bbbw.....
+wwww.....
+.........
+......b..
+.........
+.......w.",
+        Stone::Black,
+    )
+    .unwrap();
+
+    assert_eq!(Err(PlayError::NotScoring), game.toggle_dead((6, 6)));
+    assert_eq!(Ok(()), game.pass(Stone::Black));
+    assert_eq!(Ok(()), game.pass(Stone::White));
+
+    assert_eq!(Err(PlayError::NoStone), game.toggle_dead((5, 5)));
+    assert_eq!(Ok(()), game.toggle_dead((6, 6)));
+    assert_eq!(vec![(6, 6)], game.dead());
+
+    // The dead stone's point becomes White territory and the stone a White prisoner
+    let score = game.score();
+    assert_eq!(6.0, score.black);
+    assert_eq!(67.5, score.white);
+
+    // Toggling a stone marks its whole chain
+    assert_eq!(Ok(()), game.toggle_dead((2, 0)));
+    assert_eq!(7, game.dead().len());
+    assert_eq!(Ok(()), game.toggle_dead((0, 3)));
+    assert_eq!(vec![(6, 6)], game.dead());
+}
+
+#[test]
+fn test_accept_score() {
+    let mut game = new(Size::Nine);
+    assert_eq!(true, game.play_stone((4, 4), Stone::Black).is_ok());
+    assert_eq!(Err(PlayError::NotScoring), game.accept_score(Stone::Black));
+    assert_eq!(Ok(()), game.pass(Stone::White));
+    assert_eq!(Ok(()), game.pass(Stone::Black));
+
+    assert_eq!(Ok(()), game.accept_score(Stone::Black));
+    assert_eq!(true, game.accepted(Stone::Black));
+
+    // Changing the marking withdraws acceptance
+    assert_eq!(Ok(()), game.toggle_dead((4, 4)));
+    assert_eq!(false, game.accepted(Stone::Black));
+    assert_eq!(Ok(()), game.accept_score(Stone::White));
+
+    let mut game = decode(&encode(&game)).unwrap();
+    assert_eq!(vec![(4, 4)], game.dead());
+    assert_eq!(true, game.accepted(Stone::White));
+    assert_eq!(GamePhase::Scoring, game.phase());
+
+    assert_eq!(Ok(()), game.accept_score(Stone::Black));
+    assert_eq!(GamePhase::Finished, game.phase());
+    assert_eq!(Err(PlayError::NotScoring), game.toggle_dead((4, 4)));
+}
+
+#[test]
+fn test_history() {
+    let mut game = parse(
+        "
+.bw......
+bw.w.....
+.bw......
 .........
 .........
 .........
@@ -522,13 +3641,48 @@ bwb......
     )
     .unwrap();
 
-    assert_eq!(true, game.play_stone((1, 0), Stone::Black));
-    assert_eq!(false, game.has_stone((1, 1)));
-    assert_eq!(Stone::Black, game.winner());
+    assert_eq!(true, game.play_stone((2, 1), Stone::Black).is_ok());
+    assert_eq!(Ok(()), game.pass(Stone::White));
+    assert_eq!(true, game.play_stone((8, 8), Stone::Black).is_ok());
+
+    let expected = vec![
+        Move {
+            stone: Stone::Black,
+            action: Action::Play((2, 1)),
+            captured: vec![(1, 1)],
+            played_at: None,
+        },
+        Move {
+            stone: Stone::White,
+            action: Action::Pass,
+            captured: vec![],
+            played_at: None,
+        },
+        Move {
+            stone: Stone::Black,
+            action: Action::Play((8, 8)),
+            captured: vec![],
+            played_at: None,
+        },
+    ];
+    assert_eq!(expected, game.history());
+
+    // The compact encoding leaves the history out
+    assert_eq!(true, decode(&encode(&game)).unwrap().history().is_empty());
+
+    let encoded = encode_with_history(&game);
+    assert_eq!(true, encoded.ends_with(";moves=b2,1x1,1:wpass:b8,8"));
+    assert_eq!(expected, decode(&encoded).unwrap().history());
+
+    // When the moves were played survives too
+    game.stamp_moves(2, 1700000000000);
+    let encoded = encode_with_history(&game);
+    assert_eq!(true, encoded.ends_with(":wpass:b8,8@1700000000000"));
+    assert_eq!(game.history(), decode(&encoded).unwrap().history());
 }
 
 #[test]
-fn test_play_stone_capture_piece_exchange() {
+fn test_undo() {
     let mut game = parse(
         "
 .bw......
@@ -543,28 +3697,224 @@ bw.w.....
         Stone::Black,
     )
     .unwrap();
+    let start = encode(&game);
+    assert_eq!(Err(PlayError::NothingToUndo), game.undo());
+
+    // Taking back a capture restores the captured stone and the prisoner count
+    assert_eq!(true, game.play_stone((2, 1), Stone::Black).is_ok());
+    assert_eq!(1, game.prisoners(Stone::Black));
+    let undone = game.undo().unwrap();
+    assert_eq!(Action::Play((2, 1)), undone.action);
+    assert_eq!(start, encode(&game));
+    assert_eq!(position_hash(Size::Nine, &game.board), game.hash);
+
+    // The position is no longer part of the game, so the capture can be played again
+    assert_eq!(true, game.play_stone((2, 1), Stone::Black).is_ok());
+
+    // Undoing the pass that ended play resumes the game
+    assert_eq!(Ok(()), game.pass(Stone::White));
+    assert_eq!(Ok(()), game.pass(Stone::Black));
+    assert_eq!(GamePhase::Scoring, game.phase());
+    assert_eq!(Action::Pass, game.undo().unwrap().action);
+    assert_eq!(GamePhase::Playing, game.phase());
+    assert_eq!(Stone::Black, game.turn());
+    assert_eq!(1, game.passes());
+}
 
-    assert_eq!(true, game.play_stone((2, 1), Stone::Black));
-    assert_eq!(false, game.has_stone((1, 1)));
-    assert_eq!(Stone::Black, game.winner());
+#[test]
+fn test_undo_request() {
+    let mut game = new(Size::Nine);
+    assert_eq!(Ok(()), game.start_free_handicap(2));
+    assert_eq!(true, game.play_stone((2, 2), Stone::Black).is_ok());
+    assert_eq!(Err(PlayError::NotPlaying), game.request_undo(Stone::Black));
+    assert_eq!(true, game.play_stone((6, 6), Stone::Black).is_ok());
+    assert_eq!(Err(PlayError::NothingToUndo), game.undo());
+
+    assert_eq!(true, game.play_stone((4, 4), Stone::White).is_ok());
+    assert_eq!(
+        Err(PlayError::NothingToUndo),
+        game.request_undo(Stone::Black)
+    );
+    assert_eq!(Ok(()), game.request_undo(Stone::White));
+
+    let mut game = decode(&encode_with_history(&game)).unwrap();
+    assert_eq!(Some(Stone::White), game.undo_requested());
+    assert_eq!(
+        Err(PlayError::NoUndoRequest),
+        game.answer_undo(Stone::White, true)
+    );
+    assert_eq!(Ok(None), game.answer_undo(Stone::Black, false));
+    assert_eq!(true, game.has_stone((4, 4)));
+
+    assert_eq!(Ok(()), game.request_undo(Stone::White));
+    let undone = game.answer_undo(Stone::Black, true).unwrap().unwrap();
+    assert_eq!(Action::Play((4, 4)), undone.action);
+    assert_eq!(false, game.has_stone((4, 4)));
+    assert_eq!(Stone::White, game.turn());
+    assert_eq!(Some(((6, 6), Stone::Black)), game.last_move());
 }
 
 #[test]
-fn test_play_stone_cannot_place_neighbour_has_no_liberties() {
+fn test_setup() {
+    let stones = [
+        ((0, 0), Stone::White),
+        ((1, 0), Stone::Black),
+        ((4, 4), Stone::Black),
+    ];
+    let mut game = setup(Size::Nine, stones, Stone::White).unwrap();
+    assert_eq!(Stone::White, game.turn());
+    assert_eq!(3, game.stones());
+    assert_eq!(stones.to_vec(), game.setup_stones());
+    assert_eq!(Err(PlayError::NothingToUndo), game.undo());
+
+    assert_eq!(true, game.play_stone((0, 1), Stone::White).is_ok());
+    assert_eq!(stones.to_vec(), game.setup_stones());
+
+    // The composed position is kept with the game record
+    let decoded = decode(&encode_with_history(&game)).unwrap();
+    assert_eq!(stones.to_vec(), decoded.setup_stones());
+    assert_eq!(crate::sgf::export(&game), crate::sgf::export(&decoded));
+
+    assert_eq!(
+        Some(SetupError::NoLiberties((0, 0))),
+        setup(
+            Size::Nine,
+            [
+                ((0, 0), Stone::White),
+                ((1, 0), Stone::Black),
+                ((0, 1), Stone::Black)
+            ],
+            Stone::Black
+        )
+        .err()
+    );
+    assert_eq!(
+        Some(SetupError::OutOfBounds((9, 0))),
+        setup(Size::Nine, [((9, 0), Stone::Black)], Stone::Black).err()
+    );
+}
+
+#[test]
+fn test_legal_moves() {
+    let stones = [((1, 0), Stone::White), ((0, 1), Stone::White)];
+    let game = setup(Size::Nine, stones, Stone::Black).unwrap();
+    assert_eq!(81 - 3, game.legal_moves(Stone::Black).len());
+    assert_eq!(false, game.legal_moves(Stone::Black).contains(&(0, 0)));
+    assert_eq!(true, game.legal_moves(Stone::White).contains(&(0, 0)));
+
     let mut game = parse(
         "
-bb.w.....
-www......
+.b.......
+bw.......
+w........
 .........
 .........
 .........
 .........
 .........
 .........
-.........",
+",
+        Stone::White,
+    )
+    .unwrap();
+    assert_eq!(true, game.play_stone((0, 0), Stone::White).is_ok());
+    // Retaking the ko straight away would repeat the position
+    assert_eq!(false, game.legal_moves(Stone::Black).contains(&(0, 1)));
+    assert_eq!(true, game.legal_moves(Stone::Black).contains(&(2, 0)));
+
+    let mut scoring = new(Size::Nine);
+    assert_eq!(Ok(()), scoring.pass(Stone::Black));
+    assert_eq!(Ok(()), scoring.pass(Stone::White));
+    assert_eq!(true, scoring.legal_moves(Stone::Black).is_empty());
+}
+
+#[test]
+fn test_chain_at() {
+    let game = parse(
+        "
+bb.......
+wb.......
+.w.......
+.........
+.........
+.........
+.........
+.........
+.........
+",
+        Stone::Black,
+    )
+    .unwrap();
+
+    assert_eq!(
+        Some(Chain {
+            stones: vec![(0, 0), (1, 0), (1, 1)],
+            liberties: vec![(2, 0), (2, 1)],
+        }),
+        game.chain_at((1, 0))
+    );
+    assert_eq!(2, game.liberties((0, 0)));
+    assert_eq!(1, game.liberties((0, 1)));
+    assert_eq!(3, game.liberties((1, 2)));
+    assert_eq!(None, game.chain_at((4, 4)));
+    assert_eq!(0, game.liberties((4, 4)));
+}
+
+#[test]
+fn test_chains_in_atari() {
+    let game = parse(
+        "
+bw.......
+.b.......
+.........
+.........
+....bw...
+....w....
+.........
+.........
+.........
+",
+        Stone::Black,
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec![Chain {
+            stones: vec![(0, 0)],
+            liberties: vec![(0, 1)],
+        }],
+        game.chains_in_atari(Stone::Black)
+    );
+    assert_eq!(
+        vec![Chain {
+            stones: vec![(1, 0)],
+            liberties: vec![(2, 0)],
+        }],
+        game.chains_in_atari(Stone::White)
+    );
+    assert_eq!(vec![(0, 0), (1, 0)], game.stones_in_atari());
+}
+
+#[test]
+fn test_viewport() {
+    let game = parse(
+        "
+bb.......
+.w.......
+.........
+.........
+.........
+.........
+.........
+.......ww
+........b
+",
         Stone::Black,
     )
     .unwrap();
 
-    assert_eq!(false, game.play_stone((2, 0), Stone::Black));
+    assert_eq!("bb..w.", game.tiles((0, 0), 3, 2));
+    assert_eq!("ww.b", game.tiles((7, 7), 4, 4));
+    assert_eq!(vec!["b..", "...", "..w"], game.minimap(3));
+    assert_eq!(vec!["b.", ".w"], game.minimap(5));
 }
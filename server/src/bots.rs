@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use rocket::figment::Figment;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::uuid::Uuid;
+use thiserror::Error;
+
+use crate::board::{Game, Stone};
+
+// MAX_NAME_LENGTH is the longest name a bot can have.
+pub const MAX_NAME_LENGTH: usize = 32;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum BotError {
+    #[error("Bot names are 1 to 32 letters, digits, '-' or '_', not {0:?}")]
+    BadName(String),
+    #[error("This game isn't waiting for a bot")]
+    NotWanted,
+    #[error("Another bot is playing in this game")]
+    NotYourGame,
+}
+
+// Bots holds the tokens bots authenticate with, mapped to the bot's name. Bots listed in the
+// config are known from the start, others are registered while the server runs and must be
+// registered again after a restart.
+//
+// e.g. in Rocket.toml:
+//  [default.bots]
+//  gnugo = "4d6f1c8a0b2e4f7a9c3d5e6f7a8b9c0d"
+#[derive(Default)]
+pub struct Bots {
+    tokens: Mutex<HashMap<String, String>>,
+}
+
+impl Bots {
+    pub fn from_figment(figment: &Figment) -> Bots {
+        let named: HashMap<String, String> = figment.extract_inner("bots").unwrap_or_default();
+        Bots {
+            tokens: Mutex::new(
+                named
+                    .into_iter()
+                    .map(|(name, token)| (token, name))
+                    .collect(),
+            ),
+        }
+    }
+
+    // register issues a bot a new token, any issued to it before stop working.
+    pub fn register(&self, name: &str) -> Result<String, BotError> {
+        let valid = (1..=MAX_NAME_LENGTH).contains(&name.len())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(BotError::BadName(name.to_string()));
+        }
+        let token = Uuid::new_v4().simple().to_string();
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.retain(|_, bot| bot != name);
+        tokens.insert(token.clone(), name.to_string());
+        Ok(token)
+    }
+
+    // name_of returns the bot a token was issued to.
+    pub fn name_of(&self, token: &str) -> Option<String> {
+        self.tokens.lock().unwrap().get(token).cloned()
+    }
+}
+
+// Bot is a request guard for the bot API, a bot authenticates with the bearer token it was
+// registered with.
+pub struct Bot {
+    pub name: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Bot {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let given = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let name = match (request.rocket().state::<Bots>(), given) {
+            (Some(bots), Some(token)) => bots.name_of(token),
+            _ => None,
+        };
+        match name {
+            Some(name) => Outcome::Success(Bot { name }),
+            None => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+// BotSeat is a game's guest seat when its creator asked to play a bot, Wanted until a bot takes
+// it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BotSeat {
+    Wanted,
+    Taken(String),
+}
+
+// parse_seat reads the `bot` segment of a game record, `*` while the seat is wanted and the name
+// of the bot once it is taken.
+pub fn parse_seat(value: &str) -> Option<BotSeat> {
+    match value {
+        "*" => Some(BotSeat::Wanted),
+        "" => None,
+        name if name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+        {
+            Some(BotSeat::Taken(name.to_string()))
+        }
+        _ => None,
+    }
+}
+
+// format_seat writes the `bot` segment of a game record, it is the inverse of parse_seat.
+pub fn format_seat(seat: &BotSeat) -> &str {
+    match seat {
+        BotSeat::Wanted => "*",
+        BotSeat::Taken(name) => name,
+    }
+}
+
+// seat gives a bot the guest's seat of a game waiting for one, returning the bot's stone.
+pub fn seat(game: &mut Game, name: &str) -> Result<Stone, BotError> {
+    if game.bot() != Some(&BotSeat::Wanted) || game.guest_seated() {
+        return Err(BotError::NotWanted);
+    }
+    game.request_seat().ok_or(BotError::NotWanted)?;
+    let stone = game.accept_join().ok_or(BotError::NotWanted)?;
+    game.set_bot(Some(BotSeat::Taken(name.to_string())));
+    Ok(stone)
+}
+
+// stone_of returns the stone a bot plays in a game.
+pub fn stone_of(game: &Game, name: &str) -> Result<Stone, BotError> {
+    match game.bot() {
+        Some(BotSeat::Taken(bot)) if bot == name => game.guest().ok_or(BotError::NotYourGame),
+        _ => Err(BotError::NotYourGame),
+    }
+}
+
+#[test]
+fn test_bots() {
+    let bots = Bots::default();
+    let token = bots.register("gnugo").unwrap();
+    assert_eq!(Some("gnugo".to_string()), bots.name_of(&token));
+    // Registering again replaces the token
+    let renewed = bots.register("gnugo").unwrap();
+    assert_eq!(None, bots.name_of(&token));
+    assert_eq!(Some("gnugo".to_string()), bots.name_of(&renewed));
+    for name in ["", "gnu go", "a;b", &"x".repeat(33)] {
+        assert_eq!(
+            Err(BotError::BadName(name.to_string())),
+            bots.register(name)
+        );
+    }
+}
+
+#[test]
+fn test_seat() {
+    use crate::board::{self, Size};
+
+    let mut game = board::new(Size::Nine);
+    game.set_host(Some(Stone::Black));
+    assert_eq!(Err(BotError::NotWanted), seat(&mut game, "gnugo"));
+
+    game.set_bot(Some(BotSeat::Wanted));
+    assert_eq!(Ok(Stone::White), seat(&mut game, "gnugo"));
+    assert_eq!(Err(BotError::NotWanted), seat(&mut game, "katago"));
+    assert_eq!(Ok(Stone::White), stone_of(&game, "gnugo"));
+    assert_eq!(Err(BotError::NotYourGame), stone_of(&game, "katago"));
+
+    // The bot's seat survives the game's record
+    let decoded = board::decode(&board::encode_with_history(&game)).unwrap();
+    assert_eq!(Some(&BotSeat::Taken("gnugo".to_string())), decoded.bot());
+}
@@ -0,0 +1,84 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::figment::Figment;
+use rocket::tokio::time::sleep;
+use rocket::{Data, Request};
+
+// Chaos makes the server misbehave on purpose so reconnection and resync in clients can be
+// exercised locally. It is configured under `chaos` and is always disabled in release builds.
+//
+// e.g. in Rocket.toml:
+//  [debug.chaos]
+//  latency_ms = 250
+//  drop_rate = 0.1
+//  disconnect_rate = 0.02
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Chaos {
+    // latency_ms delays every request by this many milliseconds.
+    pub latency_ms: u64,
+    // drop_rate is the chance of an event not reaching a subscriber.
+    pub drop_rate: f64,
+    // disconnect_rate is the chance of an event stream closing after an event.
+    pub disconnect_rate: f64,
+}
+
+impl Chaos {
+    // from_figment reads the chaos settings, a release build ignores them.
+    pub fn from_figment(figment: &Figment) -> Chaos {
+        if !cfg!(debug_assertions) {
+            return Chaos::default();
+        }
+        figment.extract_inner("chaos").unwrap_or_default()
+    }
+
+    pub fn drop_event(&self) -> bool {
+        chance(self.drop_rate)
+    }
+
+    pub fn disconnect(&self) -> bool {
+        chance(self.disconnect_rate)
+    }
+}
+
+// chance returns true with the given probability, randomly keyed hashers are random enough for
+// injecting faults without pulling in a random number generator.
+fn chance(rate: f64) -> bool {
+    if rate <= 0.0 {
+        return false;
+    }
+    let roll = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    roll < rate
+}
+
+#[rocket::async_trait]
+impl Fairing for Chaos {
+    fn info(&self) -> Info {
+        Info {
+            name: "Chaos",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, _request: &mut Request<'_>, _data: &mut Data<'_>) {
+        if self.latency_ms > 0 {
+            sleep(Duration::from_millis(self.latency_ms)).await;
+        }
+    }
+}
+
+#[test]
+fn test_chance() {
+    let chaos = Chaos {
+        latency_ms: 0,
+        drop_rate: 0.0,
+        disconnect_rate: 1.0,
+    };
+    for _ in 0..100 {
+        assert_eq!(false, chaos.drop_event());
+        assert_eq!(true, chaos.disconnect());
+    }
+}
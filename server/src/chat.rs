@@ -0,0 +1,92 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use rocket::serde::uuid::Uuid;
+use thiserror::Error;
+
+// MAX_CHAT_LENGTH is the longest line of chat accepted, in characters.
+pub const MAX_CHAT_LENGTH: usize = 500;
+
+// CHAT_BURST is how many lines each speaker in a game may say within CHAT_WINDOW milliseconds.
+pub const CHAT_BURST: usize = 5;
+pub const CHAT_WINDOW: u64 = 10_000;
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatError {
+    #[error("There is nothing to say")]
+    Empty,
+    #[error("Chat lines can't be longer than 500 characters")]
+    TooLong,
+    #[error("You are chatting too fast, wait a moment")]
+    TooFast,
+}
+
+// check_text trims a line of chat, refusing empty and overlong lines.
+pub fn check_text(text: &str) -> Result<String, ChatError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(ChatError::Empty);
+    }
+    if text.chars().count() > MAX_CHAT_LENGTH {
+        return Err(ChatError::TooLong);
+    }
+    Ok(text.to_string())
+}
+
+// ChatLimiter keeps each speaker in a game to CHAT_BURST lines per CHAT_WINDOW. Players are told
+// apart by their stone, spectators by their address.
+#[derive(Default)]
+pub struct ChatLimiter {
+    said: Mutex<HashMap<(Uuid, String), VecDeque<u64>>>,
+}
+
+impl ChatLimiter {
+    // allow records a line said by a speaker at `at`, in milliseconds since the UNIX epoch,
+    // failing when they have said too much lately.
+    pub fn allow(&self, game_id: Uuid, speaker: String, at: u64) -> Result<(), ChatError> {
+        let mut said = self.said.lock().unwrap();
+        // Speakers who have been quiet for a while are forgotten
+        said.retain(|_, times| times.back().is_some_and(|last| last + CHAT_WINDOW > at));
+        let times = said.entry((game_id, speaker)).or_default();
+        while times.front().is_some_and(|first| first + CHAT_WINDOW <= at) {
+            times.pop_front();
+        }
+        if times.len() >= CHAT_BURST {
+            return Err(ChatError::TooFast);
+        }
+        times.push_back(at);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_check_text() {
+    assert_eq!(Ok("gg".to_string()), check_text("  gg\n"));
+    assert_eq!(Err(ChatError::Empty), check_text(" \t"));
+    assert_eq!(true, check_text(&"碁".repeat(MAX_CHAT_LENGTH)).is_ok());
+    assert_eq!(
+        Err(ChatError::TooLong),
+        check_text(&"a".repeat(MAX_CHAT_LENGTH + 1))
+    );
+}
+
+#[test]
+fn test_chat_limiter() {
+    let limiter = ChatLimiter::default();
+    let game_id = Uuid::new_v4();
+    let black = || "Black".to_string();
+
+    for at in 0..CHAT_BURST as u64 {
+        assert_eq!(Ok(()), limiter.allow(game_id, black(), at));
+    }
+    assert_eq!(Err(ChatError::TooFast), limiter.allow(game_id, black(), 10));
+    // Other speakers, and the same speaker in other games, aren't held back
+    assert_eq!(Ok(()), limiter.allow(game_id, "White".to_string(), 10));
+    assert_eq!(Ok(()), limiter.allow(Uuid::new_v4(), black(), 10));
+    // Lines older than the window no longer count
+    assert_eq!(Ok(()), limiter.allow(game_id, black(), CHAT_WINDOW));
+    assert_eq!(
+        Err(ChatError::TooFast),
+        limiter.allow(game_id, black(), CHAT_WINDOW)
+    );
+}
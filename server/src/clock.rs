@@ -0,0 +1,1283 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::figment::Figment;
+use rocket::form::{self, FromFormField, ValueField};
+use rocket::serde::uuid::Uuid;
+use rocket::tokio::select;
+use rocket::tokio::task::spawn_blocking;
+use rocket::tokio::time::interval;
+use rocket::{Orbit, Rocket};
+use rustigo_protocol::GameStateMessage;
+use thiserror::Error;
+
+use crate::board::{self, GamePhase, Stone};
+use crate::feed::EventQueue;
+use crate::maintenance::Maintenance;
+use crate::registry::{self, GameRegistry};
+
+pub use rustigo_protocol::PlayerClock;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TimeControlError {
+    #[error("Time is given in days, hours, minutes and seconds, e.g. 10m or 1h30m, not {0:?}")]
+    BadDuration(String),
+    #[error("Overtime is byo-yomi periods (5x30s), an increment (10s) or stones per period (25/10m), not {0:?}")]
+    BadOvertime(String),
+    #[error("Players need some time to play")]
+    NoTime,
+    #[error("Time warnings are times left and `last` for the last byo-yomi period, e.g. 30s,10s,last or off, not {0:?}")]
+    BadWarnings(String),
+}
+
+// TimeControl is how much time each player has for the game, in milliseconds. Every control
+// starts with `main` time, what follows once it runs out differs.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TimeControl {
+    // Absolute is main time alone, a player who runs out of it loses.
+    Absolute {
+        main: u64,
+    },
+    // ByoYomi follows main time with `periods` periods of `period` each. A move played within a
+    // period keeps it, running over uses it up.
+    ByoYomi {
+        main: u64,
+        periods: u32,
+        period: u64,
+    },
+    // Fischer adds `increment` to a player's time after every move they play.
+    Fischer {
+        main: u64,
+        increment: u64,
+    },
+    // Canadian follows main time with periods of `period` in which `stones` moves must be
+    // played, a new period starts once they are.
+    Canadian {
+        main: u64,
+        stones: u32,
+        period: u64,
+    },
+    // Correspondence gives a player `per_move` for every move, for games played over days. Each
+    // move played in time starts the next with the full allowance.
+    Correspondence {
+        per_move: u64,
+    },
+}
+
+// parse_duration reads a duration in days, hours, minutes and seconds, returning it in
+// milliseconds.
+//
+// e.g:
+//  "10m" => 600000
+//  "1h30m" => 5400000
+//  "45s" => 45000
+//  "3d" => 259200000
+fn parse_duration(value: &str) -> Option<u64> {
+    let (mut total, mut digits) = (0u64, String::new());
+    for c in value.chars() {
+        let unit = match c {
+            '0'..='9' => {
+                digits.push(c);
+                continue;
+            }
+            'd' => 86_400_000,
+            'h' => 3_600_000,
+            'm' => 60_000,
+            's' => 1_000,
+            _ => return None,
+        };
+        let amount: u64 = digits.parse().ok()?;
+        total = total.checked_add(amount.checked_mul(unit)?)?;
+        digits.clear();
+    }
+    (!value.is_empty() && digits.is_empty()).then_some(total)
+}
+
+// format_duration writes a duration in milliseconds the way parse_duration reads it, any part of
+// a second is dropped.
+pub fn format_duration(milliseconds: u64) -> String {
+    let seconds = milliseconds / 1000;
+    let parts = [
+        (seconds / 86400, 'd'),
+        (seconds / 3600 % 24, 'h'),
+        (seconds / 60 % 60, 'm'),
+        (seconds % 60, 's'),
+    ];
+    let output: String = parts
+        .iter()
+        .filter(|(amount, _)| *amount > 0)
+        .map(|(amount, unit)| format!("{}{}", amount, unit))
+        .collect();
+    match output.is_empty() {
+        true => "0s".to_string(),
+        false => output,
+    }
+}
+
+impl TimeControl {
+    // parse reads a time control as main time, optionally followed by overtime after a `+`.
+    // Query strings turn the `+` into a space, so a space is read the same way. Correspondence
+    // games give the time for each move followed by `/move`.
+    //
+    // e.g:
+    //  "10m" => 10 minutes absolute
+    //  "10m+5x30s" => 10 minutes, then 5 byo-yomi periods of 30 seconds
+    //  "10m+10s" => 10 minutes, 10 seconds added after every move
+    //  "10m+25/5m" => 10 minutes, then 25 moves every 5 minutes
+    //  "3d/move" => 3 days for every move
+    pub fn parse(value: &str) -> Result<TimeControl, TimeControlError> {
+        let value = value.trim();
+        if let Some(per_move) = value.strip_suffix("/move") {
+            let per_move = parse_duration(per_move)
+                .filter(|per_move| *per_move > 0)
+                .ok_or_else(|| TimeControlError::BadDuration(per_move.to_string()))?;
+            return Ok(TimeControl::Correspondence { per_move });
+        }
+        let (main, overtime) = match value.split_once(['+', ' ']) {
+            Some((main, overtime)) => (main, Some(overtime)),
+            None => (value, None),
+        };
+        let main =
+            parse_duration(main).ok_or_else(|| TimeControlError::BadDuration(main.to_string()))?;
+        let bad_overtime = || TimeControlError::BadOvertime(overtime.unwrap_or("").to_string());
+        let count = |count: &str| count.parse().ok().filter(|count| *count > 0);
+
+        let control = match overtime {
+            None => TimeControl::Absolute { main },
+            Some(overtime) => match (overtime.split_once('x'), overtime.split_once('/')) {
+                (Some((periods, period)), _) => TimeControl::ByoYomi {
+                    main,
+                    periods: count(periods).ok_or_else(bad_overtime)?,
+                    period: parse_duration(period)
+                        .filter(|period| *period > 0)
+                        .ok_or_else(bad_overtime)?,
+                },
+                (_, Some((stones, period))) => TimeControl::Canadian {
+                    main,
+                    stones: count(stones).ok_or_else(bad_overtime)?,
+                    period: parse_duration(period)
+                        .filter(|period| *period > 0)
+                        .ok_or_else(bad_overtime)?,
+                },
+                (None, None) => TimeControl::Fischer {
+                    main,
+                    increment: parse_duration(overtime).ok_or_else(bad_overtime)?,
+                },
+            },
+        };
+        if budget(control, &control.fresh()) == 0 {
+            return Err(TimeControlError::NoTime);
+        }
+        Ok(control)
+    }
+
+    // fresh is the time a player starts the game with.
+    pub fn fresh(&self) -> PlayerClock {
+        match *self {
+            TimeControl::Absolute { main }
+            | TimeControl::Fischer { main, .. }
+            | TimeControl::Correspondence { per_move: main } => PlayerClock {
+                main,
+                ..PlayerClock::default()
+            },
+            TimeControl::ByoYomi {
+                main,
+                periods,
+                period,
+            } => PlayerClock {
+                main,
+                periods,
+                period,
+                stones: 0,
+            },
+            TimeControl::Canadian {
+                main,
+                stones,
+                period,
+            } => PlayerClock {
+                main,
+                periods: 0,
+                period,
+                stones,
+            },
+        }
+    }
+}
+
+// TimeControl is written the way it is parsed.
+impl fmt::Display for TimeControl {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TimeControl::Absolute { main } => write!(formatter, "{}", format_duration(main)),
+            TimeControl::ByoYomi {
+                main,
+                periods,
+                period,
+            } => write!(
+                formatter,
+                "{}+{}x{}",
+                format_duration(main),
+                periods,
+                format_duration(period)
+            ),
+            TimeControl::Fischer { main, increment } => write!(
+                formatter,
+                "{}+{}",
+                format_duration(main),
+                format_duration(increment)
+            ),
+            TimeControl::Canadian {
+                main,
+                stones,
+                period,
+            } => write!(
+                formatter,
+                "{}+{}/{}",
+                format_duration(main),
+                stones,
+                format_duration(period)
+            ),
+            TimeControl::Correspondence { per_move } => {
+                write!(formatter, "{}/move", format_duration(per_move))
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'v> FromFormField<'v> for TimeControl {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        TimeControl::parse(field.value)
+            .map_err(|err| form::Error::validation(err.to_string()).into())
+    }
+}
+
+// TimeWarnings are when a player whose time is running is warned it is running out: once the time
+// left before they next lose time, their main time or a byo-yomi period, falls to any of
+// `thresholds` in milliseconds, and with `last_period` once they start their last byo-yomi period.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TimeWarnings {
+    pub thresholds: Vec<u64>,
+    pub last_period: bool,
+}
+
+// Players are warned at 30 and 10 seconds, and when they start their last period.
+impl Default for TimeWarnings {
+    fn default() -> TimeWarnings {
+        TimeWarnings {
+            thresholds: vec![30_000, 10_000],
+            last_period: true,
+        }
+    }
+}
+
+impl TimeWarnings {
+    // parse reads comma separated durations, along with `last` for the last byo-yomi period.
+    // `off` warns of nothing.
+    //
+    // e.g:
+    //  "30s,10s,last" => warned at 30 and 10 seconds left, and in the last period
+    //  "1m" => warned at a minute left
+    //  "off" => never warned
+    pub fn parse(value: &str) -> Result<TimeWarnings, TimeControlError> {
+        let bad = || TimeControlError::BadWarnings(value.to_string());
+        let mut warnings = TimeWarnings {
+            thresholds: Vec::new(),
+            last_period: false,
+        };
+        if value.trim() == "off" {
+            return Ok(warnings);
+        }
+        for part in value.split(',').map(str::trim) {
+            match part {
+                "last" => warnings.last_period = true,
+                _ => warnings.thresholds.push(
+                    parse_duration(part)
+                        .filter(|threshold| *threshold > 0)
+                        .ok_or_else(bad)?,
+                ),
+            }
+        }
+        warnings.thresholds.sort_unstable_by(|a, b| b.cmp(a));
+        warnings.thresholds.dedup();
+        Ok(warnings)
+    }
+}
+
+// TimeWarnings are written the way they are parsed.
+impl fmt::Display for TimeWarnings {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts: Vec<String> = self
+            .thresholds
+            .iter()
+            .map(|threshold| format_duration(*threshold))
+            .collect();
+        if self.last_period {
+            parts.push("last".to_string());
+        }
+        match parts.is_empty() {
+            true => write!(formatter, "off"),
+            false => write!(formatter, "{}", parts.join(",")),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'v> FromFormField<'v> for TimeWarnings {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        TimeWarnings::parse(field.value)
+            .map_err(|err| form::Error::validation(err.to_string()).into())
+    }
+}
+
+// Warned is what a player was last warned of, so each warning is only sent once. `until` is when
+// the time they were warned about runs out, a new period or move starts over.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Warned {
+    until: u64,
+    threshold: Option<u64>,
+    last_period: bool,
+}
+
+// budget is all the time a player has left before they run out, overtime included. Controls
+// given more time than can be counted have all the time there is.
+fn budget(control: TimeControl, clock: &PlayerClock) -> u64 {
+    match control {
+        TimeControl::Absolute { .. }
+        | TimeControl::Fischer { .. }
+        | TimeControl::Correspondence { .. } => clock.main,
+        TimeControl::ByoYomi { period, .. } => clock
+            .main
+            .saturating_add(u64::from(clock.periods).saturating_mul(period)),
+        TimeControl::Canadian { .. } => clock.main.saturating_add(clock.period),
+    }
+}
+
+// spend charges a player the time they took over a move, returning false when it was more than
+// they had.
+fn spend(control: TimeControl, clock: &mut PlayerClock, elapsed: u64) -> bool {
+    if elapsed >= budget(control, clock) {
+        return false;
+    }
+    let over = elapsed.saturating_sub(clock.main);
+    clock.main = clock.main.saturating_sub(elapsed);
+    match control {
+        TimeControl::Absolute { .. } => (),
+        TimeControl::Fischer { increment, .. } => clock.main = clock.main.saturating_add(increment),
+        TimeControl::Correspondence { per_move } => clock.main = per_move,
+        // Each period run over is used up, the one the move was played in is kept
+        TimeControl::ByoYomi { period, .. } => clock.periods -= (over / period) as u32,
+        TimeControl::Canadian { stones, period, .. } => {
+            if clock.main == 0 {
+                clock.period -= over;
+                clock.stones -= 1;
+                if clock.stones == 0 {
+                    clock.period = period;
+                    clock.stones = stones;
+                }
+            }
+        }
+    }
+    true
+}
+
+// Clock is the time both players of a timed game have left. Only one player's time runs at once,
+// from the moment it is started, in milliseconds since the UNIX epoch.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Clock {
+    control: TimeControl,
+    black: PlayerClock,
+    white: PlayerClock,
+    running: Option<(Stone, u64)>,
+    // lag is how much of each move's time isn't charged, in milliseconds, for the time it took to
+    // reach the server.
+    lag: u64,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Clock {
+        Clock {
+            control,
+            black: control.fresh(),
+            white: control.fresh(),
+            running: None,
+            lag: 0,
+        }
+    }
+
+    pub fn control(&self) -> TimeControl {
+        self.control
+    }
+
+    pub fn lag(&self) -> u64 {
+        self.lag
+    }
+
+    // set_lag forgives up to `lag` milliseconds of every move.
+    pub fn set_lag(&mut self, lag: u64) {
+        self.lag = lag;
+    }
+
+    // left is the time a player had when their clock was last stopped or started.
+    pub fn left(&self, stone: Stone) -> PlayerClock {
+        match stone {
+            Stone::Black => self.black,
+            Stone::White => self.white,
+        }
+    }
+
+    fn left_mut(&mut self, stone: Stone) -> &mut PlayerClock {
+        match stone {
+            Stone::Black => &mut self.black,
+            Stone::White => &mut self.white,
+        }
+    }
+
+    // running returns the player whose time is running and since when.
+    pub fn running(&self) -> Option<(Stone, u64)> {
+        self.running
+    }
+
+    // start runs a player's time from `at`, unless it already is running.
+    pub fn start(&mut self, stone: Stone, at: u64) {
+        if self.running.map(|(running, _)| running) != Some(stone) {
+            self.running = Some((stone, at));
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.running = None;
+    }
+
+    // resume moves the running time's start past a pause from `paused` until `at`, so none of the
+    // pause is charged.
+    pub fn resume(&mut self, paused: u64, at: u64) {
+        if let Some((stone, since)) = self.running {
+            let pause = at.saturating_sub(since.max(paused));
+            self.running = Some((stone, since.saturating_add(pause)));
+        }
+    }
+
+    // press stops the time of a player who moved at `at`, charging them the time they took less
+    // the lag. Returns false if they ran out of time before moving.
+    pub fn press(&mut self, stone: Stone, at: u64) -> bool {
+        let since = match self.running {
+            Some((running, since)) if running == stone => since,
+            _ => return true,
+        };
+        self.running = None;
+        let (control, lag) = (self.control, self.lag);
+        let elapsed = at.saturating_sub(since).saturating_sub(lag);
+        spend(control, self.left_mut(stone), elapsed)
+    }
+
+    // flagged returns the player whose time has run out by `at`, if any. The lag is waited out, a
+    // move may still be on its way.
+    pub fn flagged(&self, at: u64) -> Option<Stone> {
+        let (stone, since) = self.running?;
+        let elapsed = at.saturating_sub(since).saturating_sub(self.lag);
+        let out = elapsed >= budget(self.control, &self.left(stone));
+        out.then_some(stone)
+    }
+
+    // remaining is all the time a player has left at `at`, overtime included, in milliseconds.
+    pub fn remaining(&self, stone: Stone, at: u64) -> u64 {
+        let budget = budget(self.control, &self.left(stone));
+        match self.running {
+            Some((running, since)) if running == stone => {
+                budget.saturating_sub(at.saturating_sub(since))
+            }
+            _ => budget,
+        }
+    }
+
+    // deadline is when the running player runs out of time, in milliseconds since the UNIX
+    // epoch, the lag included.
+    pub fn deadline(&self) -> Option<(Stone, u64)> {
+        let (stone, since) = self.running?;
+        let budget = budget(self.control, &self.left(stone));
+        Some((stone, since.saturating_add(budget).saturating_add(self.lag)))
+    }
+
+    // run_out stops the clock with a player out of time.
+    pub fn run_out(&mut self, stone: Stone) {
+        let left = self.left_mut(stone);
+        (left.main, left.periods, left.period) = (0, 0, 0);
+        self.running = None;
+    }
+
+    // message describes the time both players have left for their subscribers.
+    pub fn message(&self) -> GameStateMessage {
+        GameStateMessage::Clock {
+            black: self.black,
+            white: self.white,
+            running: self.running.map(|(stone, _)| stone),
+        }
+    }
+
+    // countdown is the time the running player has left at `at` before they next lose time, their
+    // main time or a byo-yomi period, along with when that runs out and whether it is their last
+    // byo-yomi period.
+    fn countdown(&self, at: u64) -> Option<(Stone, u64, u64, bool)> {
+        let (stone, since) = self.running?;
+        let left = self.left(stone);
+        let elapsed = at.saturating_sub(since);
+        match self.control {
+            TimeControl::ByoYomi { period, .. } if elapsed >= left.main => {
+                let used = (elapsed - left.main) / period;
+                let periods = u64::from(left.periods)
+                    .checked_sub(used)
+                    .filter(|n| *n > 0)?;
+                let until = since
+                    .saturating_add(left.main)
+                    .saturating_add((used + 1).saturating_mul(period));
+                Some((stone, until - at, until, periods == 1))
+            }
+            TimeControl::ByoYomi { .. } | TimeControl::Canadian { .. } if elapsed < left.main => {
+                let until = since.saturating_add(left.main);
+                Some((stone, until - at, until, false))
+            }
+            control => {
+                let until = since.saturating_add(budget(control, &left));
+                Some((stone, until.checked_sub(at)?, until, false))
+            }
+        }
+    }
+
+    // warning warns the running player at `at` when their time falls to one of `warnings` they
+    // weren't warned of yet, keeping what they were warned of in `warned`.
+    pub fn warning(
+        &self,
+        at: u64,
+        warnings: &TimeWarnings,
+        warned: &mut Warned,
+    ) -> Option<GameStateMessage> {
+        let (stone, left, until, last_period) = self.countdown(at)?;
+        if left == 0 {
+            return None;
+        }
+        if warned.until != until {
+            *warned = Warned {
+                until,
+                ..Warned::default()
+            };
+        }
+        let warn = |threshold| GameStateMessage::TimeWarning {
+            stone,
+            remaining_ms: left,
+            threshold_ms: threshold,
+            last_period,
+        };
+        if warnings.last_period && last_period && !warned.last_period {
+            warned.last_period = true;
+            return Some(warn(None));
+        }
+        // Only the smallest threshold passed is warned of, a player can't be warned twice at once
+        let threshold = warnings
+            .thresholds
+            .iter()
+            .copied()
+            .filter(|threshold| left <= *threshold)
+            .min()?;
+        if warned.threshold.is_some_and(|warned| warned <= threshold) {
+            return None;
+        }
+        warned.threshold = Some(threshold);
+        Some(warn(Some(threshold)))
+    }
+
+    // sync describes the time both players have left at `at`, for clients to correct their
+    // countdowns against.
+    pub fn sync(&self, at: u64) -> GameStateMessage {
+        GameStateMessage::ClockSync {
+            black_ms: self.remaining(Stone::Black, at),
+            white_ms: self.remaining(Stone::White, at),
+            server_time: at,
+        }
+    }
+}
+
+// announce sends the time both players have left to a timed game's subscribers.
+pub fn announce(registry: &GameRegistry, queue: &EventQueue, game_id: Uuid) {
+    let message = registry.with(&game_id, |game| game.clock().map(Clock::message));
+    if let Some(message) = message.flatten() {
+        // No one may be listening
+        let _ = queue.send(Some(game_id), message);
+    }
+}
+
+// TICK is how often running clocks are checked for players out of time.
+const TICK: Duration = Duration::from_secs(1);
+
+// sync_clocks sends the time left in every game with a running clock to its subscribers, it
+// returns how many games were sent it.
+pub fn sync_clocks(registry: &GameRegistry, queue: &EventQueue, now: u64) -> usize {
+    let running = registry.filter_map(|(game_id, game)| {
+        let clock = game.clock()?;
+        clock.running()?;
+        Some((*game_id, clock.sync(now)))
+    });
+    for (game_id, sync) in &running {
+        // No one may be listening
+        let _ = queue.send(Some(*game_id), sync.clone());
+    }
+    running.len()
+}
+
+// flag_fallen ends every game whose running player is out of time at `now`, in milliseconds since
+// the UNIX epoch, telling their subscribers. It returns how many games ended.
+pub fn flag_fallen(registry: &GameRegistry, queue: &EventQueue, now: u64) -> usize {
+    let fallen = registry.filter_map(|(game_id, game)| {
+        game.clock()?.flagged(now)?;
+        Some(*game_id)
+    });
+    let mut flagged = 0;
+    for game_id in fallen {
+        let timed_out = registry.update(
+            game_id,
+            || Err(()),
+            |game| {
+                let stone = game.flag(now).ok_or(())?;
+                Ok((stone, board::encode(game)))
+            },
+        );
+        let (stone, board) = match timed_out {
+            Ok(Ok(timed_out)) => timed_out,
+            // The player moved in time after all
+            Ok(Err(())) => continue,
+            Err(err) => {
+                tracing::error!(%game_id, error = ?err, "Failed to time out game");
+                continue;
+            }
+        };
+        for message in [
+            GameStateMessage::Timeout { stone, board },
+            GameStateMessage::Phase {
+                phase: GamePhase::Finished,
+            },
+        ] {
+            let _ = queue.send(Some(game_id), message);
+        }
+        announce(registry, queue, game_id);
+        flagged += 1;
+    }
+    flagged
+}
+
+// resume_clocks restarts the clocks of every running game after maintenance that started at
+// `paused`, none of it is charged, and sends their subscribers the time left at `now`. It returns
+// how many games were resumed.
+pub fn resume_clocks(registry: &GameRegistry, queue: &EventQueue, paused: u64, now: u64) -> usize {
+    let running = registry.filter_map(|(game_id, game)| {
+        game.clock()?.running()?;
+        Some(*game_id)
+    });
+    let mut resumed = 0;
+    for game_id in running {
+        let moved = registry.update(
+            game_id,
+            || Err(()),
+            |game| {
+                game.resume_clock(paused, now);
+                game.clock().map(|clock| clock.sync(now)).ok_or(())
+            },
+        );
+        match moved {
+            Ok(Ok(sync)) => {
+                // No one may be listening
+                let _ = queue.send(Some(game_id), sync);
+                resumed += 1;
+            }
+            Ok(Err(())) => (),
+            Err(err) => tracing::error!(%game_id, error = ?err, "Failed to resume the clock"),
+        }
+    }
+    resumed
+}
+
+// warn_players warns the running player of every timed game whose time falls to one of their
+// warnings at `now`, see Clock::warning. What players were warned of is kept in `warned`, games
+// no longer running are dropped from it. It returns how many players were warned.
+pub fn warn_players(
+    registry: &GameRegistry,
+    queue: &EventQueue,
+    warned: &mut HashMap<Uuid, Warned>,
+    now: u64,
+) -> usize {
+    let running = registry.filter_map(|(game_id, game)| {
+        let (stone, _) = game.clock()?.running()?;
+        Some((*game_id, game.clock()?.clone(), game.time_warnings(stone)))
+    });
+    warned.retain(|game_id, _| running.iter().any(|(running, ..)| running == game_id));
+    let mut sent = 0;
+    for (game_id, clock, warnings) in running {
+        let warning = clock.warning(now, &warnings, warned.entry(game_id).or_default());
+        if let Some(warning) = warning {
+            // No one may be listening
+            let _ = queue.send(Some(game_id), warning);
+            sent += 1;
+        }
+    }
+    sent
+}
+
+// DEFAULT_SYNC is how often, in seconds, the time left in running games is sent by default.
+pub const DEFAULT_SYNC: u64 = 10;
+
+// Ticker watches the clocks of timed games in the background, a player who runs out of time
+// loses without having to move and players are warned as their time runs out. Clocks are left
+// alone during maintenance. Every `sync` seconds the time left in running games is sent so
+// clients can correct their countdowns, 0 never sends it. Up to `lag_ms` of every move isn't
+// charged, to make up for slow connections.
+//
+// e.g. in Rocket.toml:
+//  [default.clock]
+//  lag_ms = 500
+//  sync = 10
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Ticker {
+    pub lag_ms: u64,
+    pub sync: u64,
+}
+
+impl Default for Ticker {
+    fn default() -> Ticker {
+        Ticker {
+            lag_ms: 0,
+            sync: DEFAULT_SYNC,
+        }
+    }
+}
+
+impl Ticker {
+    pub fn from_figment(figment: &Figment) -> Ticker {
+        figment.extract_inner("clock").unwrap_or_default()
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Ticker {
+    fn info(&self) -> Info {
+        Info {
+            name: "Ticker",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let (registry, queue) = match (rocket.state::<GameRegistry>(), rocket.state::<EventQueue>())
+        {
+            (Some(registry), Some(queue)) => (registry.clone(), queue.clone()),
+            _ => return,
+        };
+        let maintenance = rocket.state::<Maintenance>().cloned().unwrap_or_default();
+        let sync = self.sync;
+        let mut end = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            let mut ticks = interval(TICK);
+            let warned = Arc::new(Mutex::new(HashMap::new()));
+            for tick in 1u64.. {
+                select! {
+                    _ = ticks.tick() => (),
+                    _ = &mut end => break,
+                }
+                // Clocks are paused, they are resumed when maintenance ends
+                if maintenance.active() {
+                    continue;
+                }
+                // Stores block, keep them off the server's threads
+                let (registry, queue, warned) = (registry.clone(), queue.clone(), warned.clone());
+                let checked = spawn_blocking(move || {
+                    let now = registry::now();
+                    flag_fallen(&registry, &queue, now);
+                    warn_players(&registry, &queue, &mut warned.lock().unwrap(), now);
+                    if sync > 0 && tick % sync == 0 {
+                        sync_clocks(&registry, &queue, now);
+                    }
+                })
+                .await;
+                if let Err(err) = checked {
+                    tracing::error!(error = ?err, "Failed to check the clocks");
+                }
+            }
+        });
+    }
+}
+
+// parse_clock reads the time players have left from the `clock` segment of a board, written by
+// format_clock.
+//
+// e.g:
+//  "600000,5,30000,0:598000,5,30000,0:w@1700000000000" => Black has 10 minutes and 5 periods of
+//  30 seconds, White a little less with their time running since the given time
+pub fn parse_clock(control: TimeControl, value: &str) -> Option<Clock> {
+    let mut parts = value.split(':');
+    let mut side = || {
+        let values: Vec<u64> = parts
+            .next()?
+            .split(',')
+            .map(|value| value.parse().ok())
+            .collect::<Option<_>>()?;
+        match values[..] {
+            [main, periods, period, stones] => Some(PlayerClock {
+                main,
+                periods: periods.try_into().ok()?,
+                period,
+                stones: stones.try_into().ok()?,
+            }),
+            _ => None,
+        }
+    };
+    let (black, white) = (side()?, side()?);
+    let running = match parts.next() {
+        Some(running) => {
+            let (stone, at) = running.split_once('@')?;
+            let stone = match stone {
+                "b" => Stone::Black,
+                "w" => Stone::White,
+                _ => return None,
+            };
+            Some((stone, at.parse().ok()?))
+        }
+        None => None,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(Clock {
+        control,
+        black,
+        white,
+        running,
+        lag: 0,
+    })
+}
+
+// format_clock writes the time players have left for the `clock` segment of a board.
+pub fn format_clock(clock: &Clock) -> String {
+    let side = |left: PlayerClock| {
+        format!(
+            "{},{},{},{}",
+            left.main, left.periods, left.period, left.stones
+        )
+    };
+    let mut output = format!("{}:{}", side(clock.black), side(clock.white));
+    match clock.running {
+        Some((Stone::Black, at)) => output.push_str(&format!(":b@{}", at)),
+        Some((Stone::White, at)) => output.push_str(&format!(":w@{}", at)),
+        None => (),
+    }
+    output
+}
+
+#[test]
+fn test_parse_time_control() {
+    let cases = [
+        ("10m", TimeControl::Absolute { main: 600_000 }),
+        (
+            "10m+5x30s",
+            TimeControl::ByoYomi {
+                main: 600_000,
+                periods: 5,
+                period: 30_000,
+            },
+        ),
+        (
+            "1h30m+10s",
+            TimeControl::Fischer {
+                main: 5_400_000,
+                increment: 10_000,
+            },
+        ),
+        (
+            "0s+25/5m",
+            TimeControl::Canadian {
+                main: 0,
+                stones: 25,
+                period: 300_000,
+            },
+        ),
+        (
+            "3d/move",
+            TimeControl::Correspondence {
+                per_move: 259_200_000,
+            },
+        ),
+        ("1d12h", TimeControl::Absolute { main: 129_600_000 }),
+    ];
+    for (value, control) in cases {
+        assert_eq!(Ok(control), TimeControl::parse(value));
+        assert_eq!(value, control.to_string());
+    }
+    assert_eq!(
+        Ok(TimeControl::ByoYomi {
+            main: 600_000,
+            periods: 5,
+            period: 30_000,
+        }),
+        TimeControl::parse("10m 5x30s")
+    );
+    assert_eq!(
+        Err(TimeControlError::BadDuration("10".to_string())),
+        TimeControl::parse("10")
+    );
+    assert_eq!(
+        Err(TimeControlError::BadOvertime("0x30s".to_string())),
+        TimeControl::parse("10m+0x30s")
+    );
+    assert_eq!(Err(TimeControlError::NoTime), TimeControl::parse("0s"));
+    assert_eq!(
+        Err(TimeControlError::BadDuration("0d".to_string())),
+        TimeControl::parse("0d/move")
+    );
+}
+
+#[test]
+fn test_clock() {
+    // Byo-yomi periods are only used up when run over
+    let mut clock = Clock::new(TimeControl::parse("1m+3x10s").unwrap());
+    clock.start(Stone::Black, 0);
+    assert_eq!(true, clock.press(Stone::Black, 65_000));
+    assert_eq!(
+        PlayerClock {
+            main: 0,
+            periods: 3,
+            period: 10_000,
+            stones: 0
+        },
+        clock.left(Stone::Black)
+    );
+    clock.start(Stone::Black, 100_000);
+    assert_eq!(true, clock.press(Stone::Black, 125_000));
+    assert_eq!(1, clock.left(Stone::Black).periods);
+    clock.start(Stone::Black, 200_000);
+    assert_eq!(None, clock.flagged(209_999));
+    assert_eq!(Some(Stone::Black), clock.flagged(210_000));
+    assert_eq!(false, clock.press(Stone::Black, 210_000));
+
+    // Fischer adds the increment after every move
+    let mut clock = Clock::new(TimeControl::parse("1m+5s").unwrap());
+    clock.start(Stone::White, 0);
+    assert_eq!(true, clock.press(Stone::White, 20_000));
+    assert_eq!(45_000, clock.left(Stone::White).main);
+    assert_eq!(None, clock.running());
+
+    // Canadian periods start over once their stones are played
+    let mut clock = Clock::new(TimeControl::parse("0s+2/1m").unwrap());
+    clock.start(Stone::Black, 0);
+    assert_eq!(true, clock.press(Stone::Black, 40_000));
+    assert_eq!((20_000, 1), {
+        let left = clock.left(Stone::Black);
+        (left.period, left.stones)
+    });
+    clock.start(Stone::Black, 50_000);
+    assert_eq!(true, clock.press(Stone::Black, 60_000));
+    assert_eq!((60_000, 2), {
+        let left = clock.left(Stone::Black);
+        (left.period, left.stones)
+    });
+
+    // Correspondence moves each start with the full allowance, whenever the deadline is
+    let mut clock = Clock::new(TimeControl::parse("3d/move").unwrap());
+    clock.start(Stone::Black, 0);
+    assert_eq!(Some((Stone::Black, 259_200_000)), clock.deadline());
+    assert_eq!(true, clock.press(Stone::Black, 200_000_000));
+    assert_eq!(259_200_000, clock.left(Stone::Black).main);
+    assert_eq!(None, clock.deadline());
+
+    // Clocks survive the board string
+    clock.start(Stone::White, 70_000);
+    let formatted = format_clock(&clock);
+    assert_eq!(
+        Some(clock.clone()),
+        parse_clock(clock.control(), &formatted)
+    );
+    assert_eq!(None, parse_clock(clock.control(), "1,2,3:4,5,6,7"));
+}
+
+#[test]
+fn test_flag_fallen() {
+    let registry = GameRegistry::default();
+    let queue = EventQueue::new(16);
+    let mut rx = queue.subscribe();
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    game.set_time_control(TimeControl::parse("1m").unwrap(), 0);
+    game.seat_guest();
+    registry.insert(game_id, game).unwrap();
+
+    // Black's time runs from the start, the clock survives the game's record
+    let (running, record) = registry
+        .with(&game_id, |game| {
+            (
+                game.clock().and_then(Clock::running),
+                board::encode_with_history(game),
+            )
+        })
+        .unwrap();
+    let (_, started) = running.unwrap();
+    assert_eq!(Some(Stone::Black), running.map(|(stone, _)| stone));
+    assert_eq!(
+        registry
+            .with(&game_id, |game| game.clock().cloned())
+            .flatten(),
+        board::decode(&record).unwrap().clock().cloned()
+    );
+
+    assert_eq!(0, flag_fallen(&registry, &queue, started + 59_999));
+    assert_eq!(1, flag_fallen(&registry, &queue, started + 60_000));
+    assert_eq!(
+        Some((Some(Stone::Black), Some(Stone::White))),
+        registry.with(&game_id, |game| (game.timed_out(), game.winner()))
+    );
+    let sent: Vec<&str> = (0..3)
+        .map(|_| rx.try_recv().unwrap().state.event_type())
+        .collect();
+    assert_eq!(vec!["timeout", "phase", "clock"], sent);
+    assert_eq!(0, flag_fallen(&registry, &queue, started + 120_000));
+}
+
+#[test]
+fn test_resume_clocks() {
+    let registry = GameRegistry::default();
+    let queue = EventQueue::new(16);
+    let mut rx = queue.subscribe();
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    game.set_time_control(TimeControl::parse("1m").unwrap(), 0);
+    game.seat_guest();
+    registry.insert(game_id, game).unwrap();
+    let started = registry
+        .with(&game_id, |game| game.clock().and_then(Clock::running))
+        .flatten()
+        .map(|(_, since)| since)
+        .unwrap();
+
+    // Ten seconds were played before a minute of maintenance, which isn't charged
+    let (paused, now) = (started + 10_000, started + 70_000);
+    assert_eq!(1, resume_clocks(&registry, &queue, paused, now));
+    assert_eq!(
+        Some(50_000),
+        registry.with(&game_id, |game| game
+            .clock()
+            .unwrap()
+            .remaining(Stone::Black, now))
+    );
+    assert_eq!("clock_sync", rx.try_recv().unwrap().state.event_type());
+    assert_eq!(0, flag_fallen(&registry, &queue, started + 119_999));
+    assert_eq!(1, flag_fallen(&registry, &queue, started + 120_000));
+
+    // Moves made during maintenance start the next player's time, none of which is charged
+    let mut clock = Clock::new(TimeControl::parse("1m").unwrap());
+    clock.start(Stone::White, 5_000);
+    clock.resume(1_000, 9_000);
+    assert_eq!(Some((Stone::White, 9_000)), clock.running());
+}
+
+#[test]
+fn test_overflow() {
+    // Controls with more time than can be counted don't overflow
+    let control = TimeControl::parse("1s+100x200000000000d").unwrap();
+    let mut clock = Clock::new(control);
+    clock.start(Stone::Black, 1_000);
+    assert_eq!(Some((Stone::Black, u64::MAX)), clock.deadline());
+    assert_eq!(None, clock.flagged(u64::MAX - 1));
+    assert_eq!(true, clock.press(Stone::Black, 5_000));
+
+    let mut clock = Clock::new(TimeControl::parse("200000000000d+200000000000d").unwrap());
+    clock.start(Stone::Black, 0);
+    assert_eq!(true, clock.press(Stone::Black, 0));
+    assert_eq!(u64::MAX, clock.left(Stone::Black).main);
+}
+
+#[test]
+fn test_lag() {
+    let mut clock = Clock::new(TimeControl::parse("1m").unwrap());
+    clock.set_lag(500);
+    clock.start(Stone::Black, 0);
+
+    // The flag waits out the lag, which isn't charged
+    assert_eq!(None, clock.flagged(60_499));
+    assert_eq!(Some(Stone::Black), clock.flagged(60_500));
+    assert_eq!(
+        GameStateMessage::ClockSync {
+            black_ms: 50_000,
+            white_ms: 60_000,
+            server_time: 10_000,
+        },
+        clock.sync(10_000)
+    );
+    assert_eq!(true, clock.press(Stone::Black, 10_000));
+    assert_eq!(50_500, clock.left(Stone::Black).main);
+
+    let registry = GameRegistry::default();
+    let queue = EventQueue::new(16);
+    let mut rx = queue.subscribe();
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    game.set_time_control(TimeControl::parse("1m").unwrap(), 500);
+    registry.insert(game_id, game.clone()).unwrap();
+    // Nothing is sent while no one's time is running
+    assert_eq!(0, sync_clocks(&registry, &queue, registry::now()));
+    game.seat_guest();
+    registry.insert(game_id, game).unwrap();
+    assert_eq!(1, sync_clocks(&registry, &queue, registry::now()));
+    assert_eq!("clock_sync", rx.try_recv().unwrap().state.event_type());
+    let record = registry.with(&game_id, board::encode).unwrap();
+    assert_eq!(
+        Some(500),
+        board::decode(&record).unwrap().clock().map(Clock::lag)
+    );
+}
+
+#[test]
+fn test_time_warnings() {
+    let warnings = TimeWarnings::parse("10s, 1m,last").unwrap();
+    assert_eq!(vec![60_000, 10_000], warnings.thresholds);
+    assert_eq!(true, warnings.last_period);
+    assert_eq!("1m,10s,last", warnings.to_string());
+    assert_eq!(
+        "30s,10s,last",
+        <TimeWarnings as Default>::default().to_string()
+    );
+    assert_eq!(
+        Ok("off".to_string()),
+        TimeWarnings::parse("off").map(|warnings| warnings.to_string())
+    );
+    for value in ["", "0s", "10", "30s,first"] {
+        assert_eq!(
+            Err(TimeControlError::BadWarnings(value.to_string())),
+            TimeWarnings::parse(value)
+        );
+    }
+
+    // Each threshold is warned of once, main time and every byo-yomi period count down to it
+    let warnings = <TimeWarnings as Default>::default();
+    let mut warned = Warned::default();
+    let mut clock = Clock::new(TimeControl::parse("1m+2x20s").unwrap());
+    let mut warn = |clock: &Clock, at| {
+        clock
+            .warning(at, &warnings, &mut warned)
+            .map(|warning| match warning {
+                GameStateMessage::TimeWarning {
+                    remaining_ms,
+                    threshold_ms,
+                    last_period,
+                    ..
+                } => (remaining_ms, threshold_ms, last_period),
+                _ => unreachable!(),
+            })
+    };
+    assert_eq!(None, warn(&clock, 0));
+    clock.start(Stone::Black, 0);
+    assert_eq!(None, warn(&clock, 29_999));
+    assert_eq!(Some((30_000, Some(30_000), false)), warn(&clock, 30_000));
+    assert_eq!(None, warn(&clock, 31_000));
+    // A tick past two thresholds only warns of the smaller
+    assert_eq!(Some((5_000, Some(10_000), false)), warn(&clock, 55_000));
+    assert_eq!(None, warn(&clock, 56_000));
+    // The first period starts over, its 20 seconds are already under 30
+    assert_eq!(Some((19_000, Some(30_000), false)), warn(&clock, 61_000));
+    assert_eq!(Some((9_000, Some(10_000), false)), warn(&clock, 71_000));
+    // The last period is warned of as it starts, then counted down
+    assert_eq!(Some((19_000, None, true)), warn(&clock, 81_000));
+    assert_eq!(Some((18_000, Some(30_000), true)), warn(&clock, 82_000));
+    assert_eq!(None, warn(&clock, 100_000));
+
+    // A move starts the warnings over
+    assert_eq!(true, clock.press(Stone::Black, 89_000));
+    clock.start(Stone::Black, 100_000);
+    assert_eq!(Some((19_000, None, true)), warn(&clock, 101_000));
+
+    let mut clock = Clock::new(TimeControl::parse("1m").unwrap());
+    clock.start(Stone::White, 0);
+    let mut warned = Warned::default();
+    let off = TimeWarnings::parse("off").unwrap();
+    assert_eq!(None, clock.warning(55_000, &off, &mut warned));
+    assert_eq!(None, clock.warning(60_000, &warnings, &mut warned));
+}
+
+#[test]
+fn test_warn_players() {
+    let registry = GameRegistry::default();
+    let queue = EventQueue::new(16);
+    let mut rx = queue.subscribe();
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    game.set_time_control(TimeControl::parse("1m").unwrap(), 0);
+    game.set_time_warnings(Some(TimeWarnings::parse("20s").unwrap()));
+    game.choose_time_warnings(Stone::White, Some(TimeWarnings::parse("off").unwrap()));
+    game.seat_guest();
+    registry.insert(game_id, game).unwrap();
+    let started = registry
+        .with(&game_id, |game| game.clock().and_then(Clock::running))
+        .flatten()
+        .map(|(_, since)| since)
+        .unwrap();
+
+    // Black is warned at the game's threshold, once
+    let mut warned = HashMap::new();
+    assert_eq!(
+        0,
+        warn_players(&registry, &queue, &mut warned, started + 35_000)
+    );
+    assert_eq!(
+        1,
+        warn_players(&registry, &queue, &mut warned, started + 40_000)
+    );
+    assert_eq!(
+        0,
+        warn_players(&registry, &queue, &mut warned, started + 41_000)
+    );
+    assert_eq!(
+        GameStateMessage::TimeWarning {
+            stone: Stone::Black,
+            remaining_ms: 20_000,
+            threshold_ms: Some(20_000),
+            last_period: false,
+        },
+        rx.try_recv().unwrap().state
+    );
+
+    // The game's warnings are part of its board, the players' own only of its record
+    let record = registry.with(&game_id, board::encode).unwrap();
+    assert_eq!(
+        true,
+        record.contains(";warn=20s") && !record.contains("wwarn")
+    );
+    let record = registry.with(&game_id, board::encode_with_history).unwrap();
+    let decoded = board::decode(&record).unwrap();
+    assert_eq!("off", decoded.time_warnings(Stone::White).to_string());
+    assert_eq!("20s", decoded.time_warnings(Stone::Black).to_string());
+
+    // White chose not to be warned
+    registry
+        .update(
+            game_id,
+            || Err(()),
+            |game| {
+                let moved = game.history().len();
+                game.play_stone((2, 2), Stone::Black).map_err(|_| ())?;
+                game.run_clock(moved, started + 50_000);
+                Ok(())
+            },
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        0,
+        warn_players(&registry, &queue, &mut warned, started + 109_000)
+    );
+
+    // Games no longer running are forgotten
+    registry.expire(&game_id).unwrap();
+    assert_eq!(
+        0,
+        warn_players(&registry, &queue, &mut warned, started + 110_000)
+    );
+    assert_eq!(true, warned.is_empty());
+}
@@ -0,0 +1,157 @@
+use std::io::Cursor;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::figment::Figment;
+use rocket::http::{Header, Method, Status};
+use rocket::{Request, Response};
+
+// Cors lets pages served from other origins call the JSON API, answering their preflight
+// requests. It is configured under `cors`, no origins are allowed by default and `*` allows any.
+// Credentials are only sent when `allow_credentials` is set, e.g. for clients seated by cookie, and
+// only to origins that are listed, never with `*`.
+//
+// e.g. in Rocket.toml:
+//  [default.cors]
+//  allowed_origins = ["https://example.com"]
+//  allowed_methods = ["GET", "POST", "PUT"]
+//  allowed_headers = ["Authorization", "Content-Type"]
+//  allow_credentials = false
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Cors {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    // max_age is how long, in seconds, a browser may remember the answer to a preflight request.
+    pub max_age: u64,
+    // api is the path of the routes cross-origin requests are allowed to.
+    #[serde(skip)]
+    api: String,
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".to_string(), "POST".to_string(), "PUT".to_string()],
+            allowed_headers: vec!["Authorization".to_string(), "Content-Type".to_string()],
+            allow_credentials: false,
+            max_age: 3600,
+            api: "/api/".to_string(),
+        }
+    }
+}
+
+impl Cors {
+    pub fn from_figment(figment: &Figment) -> Cors {
+        let mut cors: Cors = figment.extract_inner("cors").unwrap_or_default();
+        // Any website could act with a player's seat cookie and read the answer
+        if cors.allow_credentials && cors.allows_any() {
+            tracing::warn!("Credentials can't be allowed from any origin, turning them off");
+            cors.allow_credentials = false;
+        }
+        cors
+    }
+
+    fn allows_any(&self) -> bool {
+        self.allowed_origins.iter().any(|allowed| allowed == "*")
+    }
+
+    // under applies the settings to the API mounted at `api`, e.g. behind a prefix.
+    pub fn under(mut self, api: String) -> Cors {
+        self.api = api;
+        self
+    }
+
+    // allow_origin returns the origin to allow a request from, if it is allowed at all. Only
+    // listed origins are given back, browsers don't send credentials to the others allowed by `*`.
+    pub fn allow_origin<'a>(&self, origin: &'a str) -> Option<&'a str> {
+        if self.allowed_origins.iter().any(|allowed| allowed == origin) {
+            return Some(origin);
+        }
+        self.allows_any().then_some("*")
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if !request.uri().path().starts_with(self.api.as_str()) {
+            return;
+        }
+        let origin = match request.headers().get_one("Origin") {
+            Some(origin) => origin,
+            None => return,
+        };
+        let allowed = match self.allow_origin(origin) {
+            Some(allowed) => allowed.to_string(),
+            None => return,
+        };
+        response.set_header(Header::new("Access-Control-Allow-Origin", allowed));
+        response.set_header(Header::new("Vary", "Origin"));
+        if self.allow_credentials {
+            response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+        }
+
+        // Preflight requests have no route, they are answered here instead of by the 404 catcher
+        let preflight = request.method() == Method::Options
+            && request.headers().contains("Access-Control-Request-Method");
+        if preflight && response.status() == Status::NotFound {
+            response.set_status(Status::NoContent);
+            response.set_sized_body(0, Cursor::new(""));
+            response.remove_header("Content-Type");
+            response.set_header(Header::new(
+                "Access-Control-Allow-Methods",
+                self.allowed_methods.join(", "),
+            ));
+            response.set_header(Header::new(
+                "Access-Control-Allow-Headers",
+                self.allowed_headers.join(", "),
+            ));
+            response.set_header(Header::new(
+                "Access-Control-Max-Age",
+                self.max_age.to_string(),
+            ));
+        }
+    }
+}
+
+#[test]
+fn test_allow_origin() {
+    let mut cors = Cors::default();
+    assert_eq!(None, cors.allow_origin("https://example.com"));
+
+    cors.allowed_origins = vec!["https://example.com".to_string()];
+    assert_eq!(
+        Some("https://example.com"),
+        cors.allow_origin("https://example.com")
+    );
+    assert_eq!(None, cors.allow_origin("https://example.org"));
+
+    cors.allowed_origins.push("*".to_string());
+    assert_eq!(Some("*"), cors.allow_origin("https://example.org"));
+    cors.allow_credentials = true;
+    assert_eq!(Some("*"), cors.allow_origin("https://example.org"));
+    assert_eq!(
+        Some("https://example.com"),
+        cors.allow_origin("https://example.com")
+    );
+}
+
+#[test]
+fn test_any_origin_credentials() {
+    let figment = Figment::new()
+        .merge(("cors.allowed_origins", ["*"]))
+        .merge(("cors.allow_credentials", true));
+    let cors = Cors::from_figment(&figment);
+    assert_eq!(false, cors.allow_credentials);
+    assert_eq!(Some("*"), cors.allow_origin("https://example.org"));
+}
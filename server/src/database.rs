@@ -0,0 +1,299 @@
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use rocket::serde::uuid::Uuid;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::board::{self, Action, Game, GamePhase};
+use crate::store;
+
+// SCHEMA creates the tables games are kept in. The record is the game as encoded with its history,
+// which is what games are loaded from, the other columns and tables are kept alongside it so
+// games can be queried without decoding them, e.g. for the correspondence games past their
+// deadline.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS games (
+    id TEXT PRIMARY KEY,
+    record TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    phase TEXT NOT NULL,
+    created_at INTEGER,
+    deadline INTEGER
+);
+CREATE TABLE IF NOT EXISTS moves (
+    game_id TEXT NOT NULL REFERENCES games (id),
+    number INTEGER NOT NULL,
+    stone TEXT NOT NULL,
+    action TEXT NOT NULL,
+    x INTEGER,
+    y INTEGER,
+    played_at INTEGER,
+    PRIMARY KEY (game_id, number)
+);
+CREATE TABLE IF NOT EXISTS players (
+    game_id TEXT NOT NULL REFERENCES games (id),
+    role TEXT NOT NULL,
+    stone TEXT,
+    seated INTEGER NOT NULL,
+    PRIMARY KEY (game_id, role)
+);
+";
+
+// MIGRATIONS add the columns that came after a database was created, each is run if its column
+// is missing.
+const MIGRATIONS: [(&str, &str); 1] =
+    [("deadline", "ALTER TABLE games ADD COLUMN deadline INTEGER")];
+
+// Database keeps every game in a SQLite database, each accepted change is written in a single
+// transaction. Restarting the server loads the games still being played, finished games are
+// loaded when they are asked for.
+//
+// e.g. in Rocket.toml:
+//  [default]
+//  database = "/var/lib/rustigo/games.sqlite"
+pub struct Database {
+    connection: Mutex<Connection>,
+}
+
+// sql_error reports a database error the way the other stores do.
+fn sql_error(err: rusqlite::Error) -> io::Error {
+    io::Error::other(err)
+}
+
+impl Database {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Database> {
+        let connection = Connection::open(path).map_err(sql_error)?;
+        connection
+            .pragma_update(None, "journal_mode", "WAL")
+            .map_err(sql_error)?;
+        connection.execute_batch(SCHEMA).map_err(sql_error)?;
+        for (column, migration) in MIGRATIONS {
+            let missing: bool = connection
+                .query_row(
+                    "SELECT COUNT(*) = 0 FROM pragma_table_info('games') WHERE name = ?1",
+                    params![column],
+                    |row| row.get(0),
+                )
+                .map_err(sql_error)?;
+            if missing {
+                connection.execute_batch(migration).map_err(sql_error)?;
+            }
+        }
+        Ok(Database {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    // ping checks the database still answers queries.
+    pub fn ping(&self) -> io::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection
+            .query_row("SELECT 1", [], |_| Ok(()))
+            .map_err(sql_error)
+    }
+
+    // append writes the latest state of a game, its moves and its players, the game is only safe
+    // to apply once this returns.
+    pub fn append(&self, game_id: &Uuid, game: &Game) -> io::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(sql_error)?;
+        let id = game_id.to_string();
+        transaction
+            .execute(
+                "INSERT INTO games (id, record, size, phase, created_at, deadline)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT (id) DO UPDATE SET record = ?2, phase = ?4, deadline = ?6",
+                params![
+                    id,
+                    board::encode_with_history(game),
+                    game.size() as u8,
+                    store::phase_name(game.phase()),
+                    game.created_at(),
+                    game.deadline(),
+                ],
+            )
+            .map_err(sql_error)?;
+
+        // Moves taken back are dropped along with the rest, so the table always matches the record
+        transaction
+            .execute("DELETE FROM moves WHERE game_id = ?1", params![id])
+            .map_err(sql_error)?;
+        for (index, played) in game.history().iter().enumerate() {
+            let (action, position) = match played.action {
+                Action::Play(position) => ("play", Some(position)),
+                Action::Handicap(position) => ("handicap", Some(position)),
+                Action::Pass => ("pass", None),
+            };
+            transaction
+                .execute(
+                    "INSERT INTO moves (game_id, number, stone, action, x, y, played_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        id,
+                        index + 1,
+                        format!("{:?}", played.stone),
+                        action,
+                        position.map(|(x, _)| x),
+                        position.map(|(_, y)| y),
+                        played.played_at,
+                    ],
+                )
+                .map_err(sql_error)?;
+        }
+
+        let host = game.host().map(|stone| format!("{:?}", stone));
+        let guest = game.guest().map(|stone| format!("{:?}", stone));
+        for (role, stone, seated) in [("host", host, true), ("guest", guest, game.guest_seated())] {
+            transaction
+                .execute(
+                    "INSERT INTO players (game_id, role, stone, seated) VALUES (?1, ?2, ?3, ?4)
+                     ON CONFLICT (game_id, role) DO UPDATE SET stone = ?3, seated = ?4",
+                    params![id, role, stone, seated],
+                )
+                .map_err(sql_error)?;
+        }
+        transaction.commit().map_err(sql_error)
+    }
+
+    // remove deletes a game along with its moves and players.
+    pub fn remove(&self, game_id: &Uuid) -> io::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(sql_error)?;
+        for table in ["moves", "players"] {
+            transaction
+                .execute(
+                    &format!("DELETE FROM {} WHERE game_id = ?1", table),
+                    params![game_id.to_string()],
+                )
+                .map_err(sql_error)?;
+        }
+        transaction
+            .execute(
+                "DELETE FROM games WHERE id = ?1",
+                params![game_id.to_string()],
+            )
+            .map_err(sql_error)?;
+        transaction.commit().map_err(sql_error)
+    }
+
+    // replay reads back every game still being played. Records that can't be read are reported
+    // and skipped.
+    pub fn replay(&self) -> io::Result<Vec<(Uuid, Game)>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare("SELECT id, record FROM games WHERE phase != ?1")
+            .map_err(sql_error)?;
+        let rows = statement
+            .query_map(params![store::phase_name(GamePhase::Finished)], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(sql_error)?;
+        let mut games = Vec::new();
+        for row in rows {
+            let (id, record) = row.map_err(sql_error)?;
+            match (id.parse::<Uuid>(), board::decode(&record)) {
+                (Ok(game_id), Ok(game)) => games.push((game_id, game)),
+                (_, Err(err)) => tracing::error!(game_id = id, error = ?err, "Failed to load game"),
+                (Err(err), _) => tracing::error!(game_id = id, error = ?err, "Failed to load game"),
+            }
+        }
+        Ok(games)
+    }
+
+    // finished lists the finished games, which aren't replayed.
+    pub fn finished(&self) -> io::Result<Vec<Uuid>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare("SELECT id FROM games WHERE phase = ?1")
+            .map_err(sql_error)?;
+        let rows = statement
+            .query_map(params![store::phase_name(GamePhase::Finished)], |row| {
+                row.get::<_, String>(0)
+            })
+            .map_err(sql_error)?;
+        let mut games = Vec::new();
+        for row in rows {
+            let id = row.map_err(sql_error)?;
+            match id.parse::<Uuid>() {
+                Ok(game_id) => games.push(game_id),
+                Err(err) => tracing::error!(game_id = id, error = ?err, "Failed to list game"),
+            }
+        }
+        Ok(games)
+    }
+
+    // load reads back a single game, None if it was never stored.
+    pub fn load(&self, game_id: &Uuid) -> io::Result<Option<Game>> {
+        let connection = self.connection.lock().unwrap();
+        let record: Option<String> = connection
+            .query_row(
+                "SELECT record FROM games WHERE id = ?1",
+                params![game_id.to_string()],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(sql_error)?;
+        record
+            .map(|record| board::decode(&record))
+            .transpose()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err)))
+    }
+}
+
+#[test]
+fn test_database() {
+    let path = std::env::temp_dir().join(format!("rustigo-{}.sqlite", Uuid::new_v4()));
+    let database = Database::open(&path).unwrap();
+    let game_id = Uuid::new_v4();
+
+    let mut game = board::new(board::Size::Nine);
+    game.id = game_id;
+    database.append(&game_id, &game).unwrap();
+    assert_eq!(true, game.play_stone((2, 2), board::Stone::Black).is_ok());
+    assert_eq!(true, game.play_stone((3, 3), board::Stone::White).is_ok());
+    database.append(&game_id, &game).unwrap();
+    assert_eq!(true, game.undo().is_ok());
+    database.append(&game_id, &game).unwrap();
+
+    // Games are loaded from their records, the moves are kept alongside them
+    let database = Database::open(&path).unwrap();
+    let games = database.replay().unwrap();
+    assert_eq!(1, games.len());
+    assert_eq!(game_id, games[0].0);
+    assert_eq!(
+        board::encode_with_history(&game),
+        board::encode_with_history(&games[0].1)
+    );
+    let moves: i64 = database
+        .connection
+        .lock()
+        .unwrap()
+        .query_row("SELECT COUNT(*) FROM moves", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(1, moves);
+
+    // Finished games are only loaded when asked for
+    for stone in [board::Stone::White, board::Stone::Black] {
+        assert_eq!(true, game.pass(stone).is_ok());
+    }
+    for stone in [board::Stone::White, board::Stone::Black] {
+        assert_eq!(true, game.accept_score(stone).is_ok());
+    }
+    database.append(&game_id, &game).unwrap();
+    assert_eq!(true, database.replay().unwrap().is_empty());
+    assert_eq!(vec![game_id], database.finished().unwrap());
+    assert_eq!(
+        Some(board::encode_with_history(&game)),
+        database
+            .load(&game_id)
+            .unwrap()
+            .map(|game| board::encode_with_history(&game))
+    );
+    assert_eq!(true, database.load(&Uuid::new_v4()).unwrap().is_none());
+
+    database.remove(&game_id).unwrap();
+    assert_eq!(true, database.load(&game_id).unwrap().is_none());
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use rocket::serde::uuid::Uuid;
+use rustigo_protocol::{DeliveryCounts, GamePhase, GameStateMessage};
+use thiserror::Error;
+
+use crate::feed::EventQueue;
+use crate::notify::Notifier;
+use crate::outbound::{Outbound, OutboundError};
+use crate::registry::GameRegistry;
+use crate::webhook::{self, Delivery};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DispatchError {
+    #[error("The notice could not be delivered: {0}")]
+    Failed(String),
+    #[error("The notice was refused: {0}")]
+    Refused(String),
+    #[error("Too many notices are waiting to be sent")]
+    Busy,
+}
+
+impl DispatchError {
+    // retryable tests if a notice that failed may be taken if it is sent again.
+    pub fn retryable(&self) -> bool {
+        matches!(self, DispatchError::Failed(_))
+    }
+}
+
+impl From<OutboundError> for DispatchError {
+    fn from(error: OutboundError) -> Self {
+        if error.retryable() {
+            DispatchError::Failed(error.to_string())
+        } else {
+            DispatchError::Refused(error.to_string())
+        }
+    }
+}
+
+// Notice is what a channel sends: the event's type, a subject and text for people to read and a
+// JSON body for webhooks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notice {
+    pub event: String,
+    pub subject: String,
+    pub text: String,
+    pub body: String,
+}
+
+// Channel is a way of telling someone of an event, an email or a POST to a webhook.
+pub trait Channel: Send + Sync {
+    // kind names the channel in the dispatcher's counts.
+    fn kind(&self) -> &'static str;
+
+    // send tries to deliver a notice once.
+    fn send(&self, outbound: &Outbound, notice: &Notice) -> Result<(), DispatchError>;
+}
+
+// Dispatcher delivers notices on their channels, trying those that fail again as Delivery says,
+// and counts what became of them by the kind of channel.
+#[derive(Clone)]
+pub struct Dispatcher {
+    delivery: Delivery,
+    outbound: Outbound,
+    counts: Arc<Mutex<BTreeMap<&'static str, DeliveryCounts>>>,
+}
+
+impl Dispatcher {
+    pub fn new(delivery: Delivery, outbound: Outbound) -> Dispatcher {
+        Dispatcher {
+            delivery,
+            outbound,
+            counts: Arc::default(),
+        }
+    }
+
+    // deliver sends a notice on a channel, retrying until it is taken or the retries run out.
+    pub fn deliver(&self, channel: &dyn Channel, notice: &Notice) -> Result<(), DispatchError> {
+        let mut retry = 0;
+        let delivered = loop {
+            let err = match channel.send(&self.outbound, notice) {
+                Ok(()) => break Ok(()),
+                Err(err) => err,
+            };
+            if !err.retryable() || retry >= self.delivery.retries {
+                break Err(err);
+            }
+            self.count(channel.kind(), |counts| counts.retried += 1);
+            thread::sleep(self.delivery.backoff(retry));
+            retry += 1;
+        };
+        self.count(channel.kind(), |counts| match delivered {
+            Ok(()) => counts.sent += 1,
+            Err(_) => counts.failed += 1,
+        });
+        delivered
+    }
+
+    // send delivers a notice on one of Outbound's workers so a slow channel doesn't hold up the
+    // others, it is dropped when too many are waiting for a worker.
+    pub fn send(&self, channel: Box<dyn Channel>, notice: Notice) -> Result<(), DispatchError> {
+        let kind = channel.kind();
+        let dispatcher = self.clone();
+        let queued = self.outbound.spawn(move || {
+            if let Err(err) = dispatcher.deliver(channel.as_ref(), &notice) {
+                tracing::warn!(channel = kind, event = %notice.event, error = %err, "Failed to deliver a notice");
+            }
+        });
+        queued.map_err(|_| {
+            self.count(kind, |counts| counts.dropped += 1);
+            DispatchError::Busy
+        })
+    }
+
+    // counts is what became of the notices sent on each kind of channel.
+    pub fn counts(&self) -> BTreeMap<String, DeliveryCounts> {
+        self.counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(kind, counts)| (kind.to_string(), *counts))
+            .collect()
+    }
+
+    fn count(&self, kind: &'static str, change: impl FnOnce(&mut DeliveryCounts)) {
+        change(self.counts.lock().unwrap().entry(kind).or_default());
+    }
+}
+
+// notices finds who is to be told of an event in a game and what: its joins, moves and results
+// go to the game's webhooks, and the players who asked to be told of their turns are told when
+// it is over.
+pub fn notices(
+    registry: &GameRegistry,
+    notifier: &Notifier,
+    game_id: Uuid,
+    state: &GameStateMessage,
+) -> Vec<(Box<dyn Channel>, Notice)> {
+    let mut notices = Vec::new();
+    if webhook::hooked(state) {
+        let webhooks = registry
+            .with(&game_id, |game| game.webhooks().to_vec())
+            .unwrap_or_default();
+        if !webhooks.is_empty() {
+            let notice = webhook::notice(game_id, state);
+            for webhook in webhooks {
+                notices.push((Box::new(webhook) as Box<dyn Channel>, notice.clone()));
+            }
+        }
+    }
+    if let GameStateMessage::Phase {
+        phase: GamePhase::Finished,
+    } = state
+    {
+        for (contact, over) in notifier.over(registry, game_id) {
+            match notifier.channel(&contact) {
+                Ok(channel) => notices.push((channel, notifier.over_notice(&over))),
+                Err(err) => {
+                    tracing::warn!(%game_id, error = %err, "Can't tell a player the game is over")
+                }
+            }
+        }
+    }
+    notices
+}
+
+// fan_out sends the notices for every game's events sent on the queue, returning the queue to
+// send them on. Only events sent on this server are told of, so servers sharing events deliver
+// each once.
+pub fn fan_out(
+    queue: EventQueue,
+    registry: GameRegistry,
+    notifier: Notifier,
+    dispatcher: Dispatcher,
+) -> EventQueue {
+    let (outbox, outgoing) = mpsc::channel::<(Option<Uuid>, GameStateMessage)>();
+    thread::spawn(move || {
+        for (game_id, state) in outgoing {
+            let game_id = match game_id {
+                Some(game_id) => game_id,
+                None => continue,
+            };
+            for (channel, notice) in notices(&registry, &notifier, game_id, &state) {
+                if let Err(err) = dispatcher.send(channel, notice) {
+                    tracing::warn!(%game_id, error = %err, "Dropped a notice");
+                }
+            }
+        }
+    });
+    queue.relayed(outbox)
+}
+
+#[test]
+fn test_deliver() {
+    use crate::outbound;
+
+    // Flaky fails as many times as it is told to, then takes the notice
+    struct Flaky(Mutex<Vec<DispatchError>>);
+    impl Channel for Flaky {
+        fn kind(&self) -> &'static str {
+            "flaky"
+        }
+
+        fn send(&self, _: &Outbound, _: &Notice) -> Result<(), DispatchError> {
+            self.0.lock().unwrap().pop().map_or(Ok(()), Err)
+        }
+    }
+
+    let dispatcher = Dispatcher::new(
+        Delivery {
+            retries: 2,
+            backoff_ms: 1,
+        },
+        Outbound::new(outbound::Settings::default()),
+    );
+    let notice = Notice {
+        event: "turn".to_string(),
+        subject: "It's your turn".to_string(),
+        text: String::new(),
+        body: "{}".to_string(),
+    };
+    let failed = DispatchError::Failed("503".to_string());
+    let flaky = Flaky(Mutex::new(vec![failed.clone(), failed.clone()]));
+    assert_eq!(Ok(()), dispatcher.deliver(&flaky, &notice));
+    let flaky = Flaky(Mutex::new(vec![failed.clone(); 3]));
+    assert_eq!(Err(failed.clone()), dispatcher.deliver(&flaky, &notice));
+    // Refusals aren't tried again
+    let refused = DispatchError::Refused("404".to_string());
+    let flaky = Flaky(Mutex::new(vec![refused.clone(), failed.clone()]));
+    assert_eq!(Err(refused), dispatcher.deliver(&flaky, &notice));
+    assert_eq!(
+        Some(&DeliveryCounts {
+            sent: 1,
+            retried: 5,
+            failed: 2,
+            dropped: 0,
+        }),
+        dispatcher.counts().get("flaky")
+    );
+}
+
+#[test]
+fn test_notices() {
+    use crate::board::{self, Size, Stone};
+    use crate::notify::Contact;
+    use crate::webhook::Webhook;
+
+    let registry = GameRegistry::default();
+    let notifier = Notifier::default();
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(Size::Nine);
+    game.set_contact(
+        Stone::Black,
+        Some(Contact::Webhook("https://example.com/black".to_string())),
+    );
+    game.add_webhook(Webhook::new("https://example.com/hook").unwrap())
+        .unwrap();
+    registry.insert(game_id, game).unwrap();
+
+    let pass = GameStateMessage::Pass {
+        stone: Stone::Black,
+        board: String::new(),
+    };
+    let kinds = |state: &GameStateMessage| {
+        notices(&registry, &notifier, game_id, state)
+            .into_iter()
+            .map(|(channel, notice)| (channel.kind(), notice.event))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(vec![("webhook", "pass".to_string())], kinds(&pass));
+    assert_eq!(
+        true,
+        kinds(&GameStateMessage::Spectators { count: 1 }).is_empty()
+    );
+    // The player is told the game is over along with the game's webhooks
+    let over = GameStateMessage::Phase {
+        phase: GamePhase::Finished,
+    };
+    assert_eq!(
+        vec![
+            ("webhook", "phase".to_string()),
+            ("notify_webhook", "game_over".to_string())
+        ],
+        kinds(&over)
+    );
+}
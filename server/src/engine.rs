@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use rocket::serde::uuid::Uuid;
+use rustigo_protocol::GameStateMessage;
+use thiserror::Error;
+
+use crate::board::{self, Action, Coordinate, Game, GamePhase, PlayError, Stone};
+use crate::clock;
+use crate::feed::EventQueue;
+use crate::registry::GameRegistry;
+use crate::turn::{self, Turn};
+
+#[derive(Error, Debug)]
+pub enum EngineError {
+    #[error("No engine is configured")]
+    NotConfigured,
+    #[error("Failed to talk to the engine: {0}")]
+    Io(#[from] io::Error),
+    #[error("The engine closed its output")]
+    Closed,
+    #[error("The engine rejected {0:?}: {1}")]
+    Rejected(String, String),
+    #[error("The engine played an invalid move {0:?}")]
+    InvalidMove(String),
+    #[error("The engine's move was refused: {0}")]
+    Refused(#[from] PlayError),
+    #[error("Failed to journal the engine's move: {0}")]
+    Journal(io::Error),
+    #[error("The game changed while the engine was thinking")]
+    Stale,
+}
+
+// Engine is an external Go program, such as GNU Go or KataGo, driven over the Go Text Protocol
+// as a child process.
+pub struct Engine {
+    child: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+}
+
+impl Engine {
+    // spawn starts an engine from a command line, e.g. "gnugo --mode gtp".
+    pub fn spawn(command_line: &str) -> Result<Engine, EngineError> {
+        let mut words = command_line.split_whitespace();
+        let program = words.next().ok_or(EngineError::NotConfigured)?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let input = child.stdin.take().ok_or(EngineError::Closed)?;
+        let output = BufReader::new(child.stdout.take().ok_or(EngineError::Closed)?);
+        Ok(Engine {
+            child,
+            input,
+            output,
+        })
+    }
+
+    // send runs a single command, returning the engine's response without the leading `=`.
+    fn send(&mut self, command: &str) -> Result<String, EngineError> {
+        writeln!(self.input, "{}", command)?;
+        self.input.flush()?;
+
+        let mut response = String::new();
+        loop {
+            let mut line = String::new();
+            if self.output.read_line(&mut line)? == 0 {
+                return Err(EngineError::Closed);
+            }
+            // A blank line ends the response
+            if line.trim().is_empty() {
+                if response.is_empty() {
+                    continue;
+                }
+                break;
+            }
+            response.push_str(&line);
+        }
+
+        match response.trim().strip_prefix('=') {
+            Some(text) => Ok(text.trim().to_string()),
+            None => Err(EngineError::Rejected(
+                command.to_string(),
+                response.trim().trim_start_matches('?').trim().to_string(),
+            )),
+        }
+    }
+
+    // genmove replays a game on the engine and asks for the next move, None is a pass. An engine
+    // resigning is treated as a pass, the game is then settled by scoring.
+    pub fn genmove(&mut self, game: &Game) -> Result<Option<Coordinate>, EngineError> {
+        for command in replay_commands(game) {
+            self.send(&command)?;
+        }
+        let vertex = self.send(&format!("genmove {}", color(game.turn())))?;
+        if vertex.eq_ignore_ascii_case("pass") || vertex.eq_ignore_ascii_case("resign") {
+            return Ok(None);
+        }
+        board::parse_coordinate(game.size(), &vertex)
+            .map(Some)
+            .map_err(|_| EngineError::InvalidMove(vertex))
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        let _ = writeln!(self.input, "quit");
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// color is the GTP name of a player.
+fn color(stone: Stone) -> &'static str {
+    match stone {
+        Stone::Black => "black",
+        Stone::White => "white",
+    }
+}
+
+// replay_commands are the GTP commands that set up a fresh engine board to match a game, so the
+// engine never drifts from the server after undos or restarts.
+fn replay_commands(game: &Game) -> Vec<String> {
+    let size = game.size();
+    let vertex = |position| board::format_coordinate(size, position);
+    let mut commands = vec![
+        format!("boardsize {}", size as u8),
+        "clear_board".to_string(),
+        format!("komi {}", game.komi()),
+    ];
+    for (position, stone) in game.setup_stones() {
+        commands.push(format!("play {} {}", color(stone), vertex(position)));
+    }
+    for position in game.handicap_stones() {
+        commands.push(format!("play black {}", vertex(position)));
+    }
+    for played in game.history() {
+        match played.action {
+            Action::Play(position) => {
+                commands.push(format!("play {} {}", color(played.stone), vertex(position)))
+            }
+            Action::Pass => commands.push(format!("play {} pass", color(played.stone))),
+            // Already placed with the rest of the handicap
+            Action::Handicap(_) => (),
+        }
+    }
+    commands
+}
+
+// ENGINE_STONE is the color the engine plays, whoever creates a game takes black.
+const ENGINE_STONE: Stone = Stone::White;
+
+// Engines holds the engine playing in each game against the computer. The engine program is
+// configured with `engine`. An engine is stopped once its game finishes or expires.
+//
+// e.g. in Rocket.toml:
+//  [default]
+//  engine = "gnugo --mode gtp"
+#[derive(Default, Clone)]
+pub struct Engines {
+    command_line: Option<String>,
+    games: Arc<Mutex<HashMap<Uuid, Arc<Mutex<Engine>>>>>,
+}
+
+impl Engines {
+    pub fn new(command_line: Option<String>) -> Engines {
+        Engines {
+            command_line,
+            games: Arc::default(),
+        }
+    }
+
+    // watch stops the engine of every game that finishes or expires on the queue, returning the
+    // queue to send events on.
+    pub fn watch(&self, queue: EventQueue) -> EventQueue {
+        let (outbox, outgoing) = mpsc::channel::<(Option<Uuid>, GameStateMessage)>();
+        let engines = self.clone();
+        thread::spawn(move || {
+            for (game_id, state) in outgoing {
+                let game_id = match game_id {
+                    Some(game_id) => game_id,
+                    None => continue,
+                };
+                match state {
+                    GameStateMessage::Phase {
+                        phase: GamePhase::Finished,
+                    }
+                    | GameStateMessage::GameExpired { .. } => engines.stop(game_id),
+                    _ => (),
+                }
+            }
+        });
+        queue.relayed(outbox)
+    }
+
+    // start seats a new engine in a game.
+    pub fn start(&self, game_id: Uuid) -> Result<(), EngineError> {
+        let command_line = self
+            .command_line
+            .as_ref()
+            .ok_or(EngineError::NotConfigured)?;
+        let engine = Engine::spawn(command_line)?;
+        self.games
+            .lock()
+            .unwrap()
+            .insert(game_id, Arc::new(Mutex::new(engine)));
+        Ok(())
+    }
+
+    // stop lets go of the engine playing in a game, the engine quits once it is done with any
+    // move it is thinking about.
+    pub fn stop(&self, game_id: Uuid) {
+        if self.games.lock().unwrap().remove(&game_id).is_some() {
+            tracing::debug!(%game_id, "Stopped engine");
+        }
+    }
+
+    // respond lets the engine take its turn in a game, if it is playing in it, in the background.
+    // The engine plays when it is White's move and accepts the score once Black has.
+    pub fn respond(&self, game_id: Uuid, registry: &GameRegistry, queue: &EventQueue) {
+        let engine = match self.games.lock().unwrap().get(&game_id) {
+            Some(engine) => engine.clone(),
+            None => return,
+        };
+        let registry = registry.clone();
+        let queue = queue.clone();
+        thread::spawn(move || {
+            let mut engine = engine.lock().unwrap();
+            if let Err(err) = take_turn(&mut engine, game_id, &registry, &queue) {
+                tracing::error!(%game_id, error = ?err, "Engine failed to respond");
+            }
+        });
+    }
+}
+
+// take_turn plays the engine's move, or accepts the score, and broadcasts the result.
+fn take_turn(
+    engine: &mut Engine,
+    game_id: Uuid,
+    registry: &GameRegistry,
+    queue: &EventQueue,
+) -> Result<(), EngineError> {
+    let thinking = match registry.with(&game_id, Game::clone) {
+        Some(game) => game,
+        None => return Ok(()),
+    };
+
+    let phase = thinking.phase();
+    // None accepts the score, otherwise it is the engine's move, None again being a pass
+    let choice = match phase {
+        GamePhase::Playing if thinking.turn() == ENGINE_STONE => Some(engine.genmove(&thinking)?),
+        GamePhase::Scoring
+            if thinking.accepted(Stone::Black) && !thinking.accepted(ENGINE_STONE) =>
+        {
+            None
+        }
+        _ => return Ok(()),
+    };
+
+    let events = registry
+        .update(
+            game_id,
+            || Err(EngineError::Stale),
+            |game| {
+                // The player may have taken a move back while the engine was thinking
+                if board::encode(game) != board::encode(&thinking) {
+                    return Err(EngineError::Stale);
+                }
+                let turn = match choice {
+                    Some(Some(position)) => Turn::Play(position),
+                    Some(None) => Turn::Pass,
+                    None => Turn::Accept,
+                };
+                Ok(turn::take(game, ENGINE_STONE, turn)?)
+            },
+        )
+        .map_err(EngineError::Journal)??;
+
+    for event in events {
+        let _ = queue.send(Some(game_id), event);
+    }
+    clock::announce(registry, queue, game_id);
+    Ok(())
+}
+
+#[test]
+fn test_replay_commands() {
+    let mut game = board::new(board::Size::Nine);
+    assert_eq!(Ok(()), game.place_handicap(2));
+    assert_eq!(true, game.play_stone((4, 4), Stone::White).is_ok());
+    assert_eq!(Ok(()), game.pass(Stone::Black));
+
+    assert_eq!(
+        vec![
+            "boardsize 9",
+            "clear_board",
+            "komi 6.5",
+            "play black G7",
+            "play black C3",
+            "play white E5",
+            "play black pass",
+        ],
+        replay_commands(&game)
+    );
+}
+
+#[test]
+fn test_engines_stop() {
+    use std::time::{Duration, Instant};
+
+    let engines = Engines::new(Some("cat".to_string()));
+    let queue = engines.watch(EventQueue::new(16));
+    let (game_id, other) = (Uuid::new_v4(), Uuid::new_v4());
+    engines.start(game_id).unwrap();
+    engines.start(other).unwrap();
+    let playing = |game_id: &Uuid| engines.games.lock().unwrap().contains_key(game_id);
+    // stopped waits for the engine of a game to be let go of
+    let stopped = |game_id: &Uuid| {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while playing(game_id) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        !playing(game_id)
+    };
+
+    let _ = queue.send(
+        Some(game_id),
+        GameStateMessage::Phase {
+            phase: GamePhase::Scoring,
+        },
+    );
+    let _ = queue.send(
+        Some(game_id),
+        GameStateMessage::Phase {
+            phase: GamePhase::Finished,
+        },
+    );
+    assert_eq!(true, stopped(&game_id));
+    assert_eq!(true, playing(&other));
+
+    let _ = queue.send(
+        Some(other),
+        GameStateMessage::GameExpired { archived: false },
+    );
+    assert_eq!(true, stopped(&other));
+}
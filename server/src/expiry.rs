@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::figment::Figment;
+use rocket::tokio::select;
+use rocket::tokio::task::spawn_blocking;
+use rocket::tokio::time::interval;
+use rocket::{Orbit, Rocket};
+use rustigo_protocol::GameStateMessage;
+
+use crate::feed::EventQueue;
+use crate::registry::{self, GameRegistry};
+
+// DEFAULT_TTL is how long a game can be left idle before it expires, in seconds.
+pub const DEFAULT_TTL: u64 = 7 * 24 * 60 * 60;
+
+// SWEEP_INTERVAL is how often idle games are looked for.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// Expiry lets go of games nothing has happened in for longer than `game_ttl` seconds, so
+// abandoned games and their events don't live forever. A TTL of 0 keeps every game.
+//
+// e.g. in Rocket.toml:
+//  [default]
+//  game_ttl = 86400
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Expiry {
+    pub ttl: u64,
+}
+
+impl Expiry {
+    pub fn from_figment(figment: &Figment) -> Expiry {
+        Expiry {
+            ttl: figment.extract_inner("game_ttl").unwrap_or(DEFAULT_TTL),
+        }
+    }
+}
+
+// expire_idle expires the games left idle for longer than `ttl` seconds at `now`, in milliseconds
+// since the UNIX epoch. Their subscribers are told and their events are dropped.
+pub fn expire_idle(registry: &GameRegistry, queue: &EventQueue, ttl: u64, now: u64) -> usize {
+    let expired = registry.expire_idle(now.saturating_sub(ttl * 1000));
+    for (game_id, archived) in &expired {
+        // No one may be listening
+        let _ = queue.send(
+            Some(*game_id),
+            GameStateMessage::GameExpired {
+                archived: *archived,
+            },
+        );
+        queue.forget(game_id);
+    }
+    expired.len()
+}
+
+#[rocket::async_trait]
+impl Fairing for Expiry {
+    fn info(&self) -> Info {
+        Info {
+            name: "Expiry",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let ttl = self.ttl;
+        let (registry, queue) = match (rocket.state::<GameRegistry>(), rocket.state::<EventQueue>())
+        {
+            (Some(registry), Some(queue)) if ttl > 0 => (registry.clone(), queue.clone()),
+            _ => return,
+        };
+        let mut end = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            let mut sweeps = interval(SWEEP_INTERVAL);
+            loop {
+                select! {
+                    _ = sweeps.tick() => (),
+                    _ = &mut end => break,
+                }
+                // Stores block, keep them off the server's threads
+                let (registry, queue) = (registry.clone(), queue.clone());
+                let swept =
+                    spawn_blocking(move || expire_idle(&registry, &queue, ttl, registry::now()))
+                        .await;
+                match swept {
+                    Ok(0) => (),
+                    Ok(expired) => tracing::info!(expired, "Expired idle games"),
+                    Err(err) => tracing::error!(error = ?err, "Failed to expire idle games"),
+                }
+            }
+        });
+    }
+}
+
+#[test]
+fn test_expire_idle() {
+    use crate::board::{self, Size, Stone};
+    use rocket::serde::uuid::Uuid;
+
+    let registry = GameRegistry::default();
+    let queue = EventQueue::new(16);
+    let mut rx = queue.subscribe();
+    let (abandoned, finished) = (Uuid::new_v4(), Uuid::new_v4());
+    registry.insert(abandoned, board::new(Size::Nine)).unwrap();
+    let mut game = board::new(Size::Nine);
+    for stone in [Stone::Black, Stone::White] {
+        assert_eq!(true, game.pass(stone).is_ok());
+    }
+    for stone in [Stone::Black, Stone::White] {
+        assert_eq!(true, game.accept_score(stone).is_ok());
+    }
+    registry.insert(finished, game).unwrap();
+    queue
+        .send(Some(abandoned), GameStateMessage::Join { id: abandoned })
+        .unwrap();
+    let _ = rx.try_recv();
+
+    // Nothing expires before its time
+    let now = registry::now();
+    assert_eq!(0, expire_idle(&registry, &queue, 60, now));
+    assert_eq!(2, registry.filter_map(|(id, _)| Some(*id)).len());
+
+    assert_eq!(2, expire_idle(&registry, &queue, 60, now + 61_000));
+    assert_eq!(true, registry.filter_map(|(id, _)| Some(*id)).is_empty());
+    let mut sent: Vec<_> = (0..2)
+        .map(|_| {
+            let event = rx.try_recv().unwrap();
+            (event.game_id == Some(finished), event.state)
+        })
+        .collect();
+    sent.sort_by_key(|(archived, _)| *archived);
+    assert_eq!(
+        vec![
+            (false, GameStateMessage::GameExpired { archived: false }),
+            (true, GameStateMessage::GameExpired { archived: true }),
+        ],
+        sent
+    );
+    assert_eq!(0, queue.sequence(&abandoned));
+}
@@ -0,0 +1,388 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::uuid::Uuid;
+use rocket::tokio::sync::broadcast::{channel, Receiver, Sender};
+use rustigo_protocol::GameStateMessage;
+use thiserror::Error;
+
+// GameEvent is a message on the event queue, tagged with the game it is about. Messages for a
+// game are numbered from 1 in the order they are sent, so a client can tell when it missed one.
+// Server wide messages, e.g. maintenance, have no game and are numbered 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameEvent {
+    pub game_id: Option<Uuid>,
+    pub seq: u64,
+    pub state: GameStateMessage,
+}
+
+impl GameEvent {
+    // concerns tests if a subscriber to a game's events should see this event.
+    pub fn concerns(&self, game_id: &Uuid) -> bool {
+        match self.game_id {
+            Some(id) => id == *game_id,
+            None => true,
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum FeedError {
+    // Unheard is a message sent while no one is subscribed, it is still kept for replay.
+    #[error("No one is subscribed to the events")]
+    Unheard,
+}
+
+// REPLAY_LENGTH is how many of a game's latest events are kept for clients that reconnect.
+pub const REPLAY_LENGTH: usize = 64;
+
+// Outbox carries the messages sent on this server elsewhere, e.g. to the other servers of a
+// deployment.
+pub type Outbox = mpsc::Sender<(Option<Uuid>, GameStateMessage)>;
+
+// EventQueue publishes the events of every game to the SSE subscribers.
+#[derive(Clone)]
+pub struct EventQueue {
+    sender: Sender<GameEvent>,
+    games: Arc<Mutex<HashMap<Uuid, Recent>>>,
+    outboxes: Vec<Arc<Mutex<Outbox>>>,
+    // closed is set once an outbox stops taking messages.
+    closed: Arc<AtomicBool>,
+}
+
+// Recent is the latest sequence number of a game and the events leading up to it, along with how
+// many people are watching it.
+#[derive(Default)]
+struct Recent {
+    seq: u64,
+    events: VecDeque<GameEvent>,
+    spectators: usize,
+}
+
+impl EventQueue {
+    pub fn new(capacity: usize) -> EventQueue {
+        EventQueue {
+            sender: channel(capacity).0,
+            games: Arc::new(Mutex::new(HashMap::new())),
+            outboxes: Vec::new(),
+            closed: Arc::default(),
+        }
+    }
+
+    // relayed sends every message posted on this server to `outbox` as well, e.g. for subscribers
+    // connected to other servers. Messages delivered from elsewhere aren't relayed.
+    pub fn relayed(mut self, outbox: Outbox) -> EventQueue {
+        self.outboxes.push(Arc::new(Mutex::new(outbox)));
+        self
+    }
+
+    // send posts a message about a game, or to every game when `game_id` is None.
+    pub fn send(&self, game_id: Option<Uuid>, state: GameStateMessage) -> Result<u64, FeedError> {
+        // Other servers may have subscribers even when this one has none
+        for outbox in &self.outboxes {
+            if outbox
+                .lock()
+                .unwrap()
+                .send((game_id, state.clone()))
+                .is_err()
+            {
+                self.closed.store(true, Ordering::Relaxed);
+            }
+        }
+        self.deliver(game_id, state)
+    }
+
+    // deliver posts a message to the subscribers of this server only, e.g. one relayed from
+    // another server. Each server numbers the events its subscribers see.
+    pub fn deliver(
+        &self,
+        game_id: Option<Uuid>,
+        state: GameStateMessage,
+    ) -> Result<u64, FeedError> {
+        // The lock is held while sending so events reach the queue in sequence order
+        let mut games = self.games.lock().unwrap();
+        self.publish(&mut games, game_id, state)
+    }
+
+    // publish numbers and sends a message while the games are locked.
+    fn publish(
+        &self,
+        games: &mut HashMap<Uuid, Recent>,
+        game_id: Option<Uuid>,
+        state: GameStateMessage,
+    ) -> Result<u64, FeedError> {
+        let event = match game_id {
+            Some(game_id) => {
+                let recent = games.entry(game_id).or_default();
+                recent.seq += 1;
+                let event = GameEvent {
+                    game_id: Some(game_id),
+                    seq: recent.seq,
+                    state,
+                };
+                if recent.events.len() == REPLAY_LENGTH {
+                    recent.events.pop_front();
+                }
+                recent.events.push_back(event.clone());
+                event
+            }
+            None => GameEvent {
+                game_id,
+                seq: 0,
+                state,
+            },
+        };
+        let seq = event.seq;
+        self.sender.send(event).map_err(|_| FeedError::Unheard)?;
+        Ok(seq)
+    }
+
+    // relaying tests if every outbox still takes messages, an outbox closes when whatever reads it
+    // has stopped, e.g. the relay after losing Redis.
+    pub fn relaying(&self) -> bool {
+        !self.closed.load(Ordering::Relaxed)
+    }
+
+    pub fn subscribe(&self) -> Receiver<GameEvent> {
+        self.sender.subscribe()
+    }
+
+    // watch counts a spectator of a game until the Watcher returned is dropped, the game's
+    // subscribers are sent the count whenever it changes.
+    pub fn watch(&self, game_id: Uuid) -> Watcher {
+        self.count_spectator(game_id, true);
+        Watcher {
+            queue: self.clone(),
+            game_id,
+        }
+    }
+
+    // forget drops everything kept about a game's events once it has expired, its sequence starts
+    // over if it is ever sent another.
+    pub fn forget(&self, game_id: &Uuid) {
+        self.games.lock().unwrap().remove(game_id);
+    }
+
+    // count_spectator adds or removes a spectator of a game and sends the new count.
+    fn count_spectator(&self, game_id: Uuid, arrived: bool) {
+        let mut games = self.games.lock().unwrap();
+        // Spectators leaving a forgotten game have no one left to tell
+        if !arrived && !games.contains_key(&game_id) {
+            return;
+        }
+        let recent = games.entry(game_id).or_default();
+        if arrived {
+            recent.spectators += 1;
+        } else {
+            recent.spectators = recent.spectators.saturating_sub(1);
+        }
+        let count = recent.spectators;
+        // No one may be left listening
+        let _ = self.publish(
+            &mut games,
+            Some(game_id),
+            GameStateMessage::Spectators { count },
+        );
+    }
+
+    // spectators is the number of people watching a game.
+    pub fn spectators(&self, game_id: &Uuid) -> usize {
+        let games = self.games.lock().unwrap();
+        games.get(game_id).map_or(0, |recent| recent.spectators)
+    }
+
+    // sequence is the number of the latest event sent about a game.
+    pub fn sequence(&self, game_id: &Uuid) -> u64 {
+        let games = self.games.lock().unwrap();
+        games.get(game_id).map_or(0, |recent| recent.seq)
+    }
+
+    // replay returns the events of a game sent after event `seq`, None when some of them are no
+    // longer kept, or `seq` is from before the server restarted.
+    pub fn replay(&self, game_id: &Uuid, seq: u64) -> Option<Vec<GameEvent>> {
+        let games = self.games.lock().unwrap();
+        let recent = match games.get(game_id) {
+            Some(recent) => recent,
+            None if seq == 0 => return Some(Vec::new()),
+            None => return None,
+        };
+        let oldest = recent
+            .events
+            .front()
+            .map_or(recent.seq + 1, |event| event.seq);
+        if seq > recent.seq || seq + 1 < oldest {
+            return None;
+        }
+        Some(
+            recent
+                .events
+                .iter()
+                .filter(|event| event.seq > seq)
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+// Watcher is a spectator counted while they are subscribed to a game's events.
+pub struct Watcher {
+    queue: EventQueue,
+    game_id: Uuid,
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.queue.count_spectator(self.game_id, false);
+    }
+}
+
+// LastEventId is the id of the last event seen by a reconnecting EventSource, from the
+// `Last-Event-ID` header.
+pub struct LastEventId(pub u64);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request
+            .headers()
+            .get_one("Last-Event-ID")
+            .and_then(|id| id.trim().parse().ok())
+        {
+            Some(seq) => Outcome::Success(LastEventId(seq)),
+            None => Outcome::Forward(()),
+        }
+    }
+}
+
+#[test]
+fn test_event_queue() {
+    let queue = EventQueue::new(16);
+    let mut rx = queue.subscribe();
+    let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+    let join = |id| GameStateMessage::Join { id };
+
+    assert_eq!(1, queue.send(Some(first), join(first)).unwrap());
+    assert_eq!(1, queue.send(Some(second), join(second)).unwrap());
+    assert_eq!(
+        0,
+        queue
+            .send(None, GameStateMessage::Maintenance { announcement: None })
+            .unwrap()
+    );
+    assert_eq!(2, queue.send(Some(first), join(first)).unwrap());
+
+    let received: Vec<GameEvent> = (0..4).map(|_| rx.try_recv().unwrap()).collect();
+    let seen: Vec<u64> = received
+        .iter()
+        .filter(|event| event.concerns(&first))
+        .map(|event| event.seq)
+        .collect();
+    assert_eq!(vec![1, 0, 2], seen);
+    assert_eq!(false, received[1].concerns(&first));
+    assert_eq!(true, received[2].concerns(&second));
+}
+
+#[test]
+fn test_event_replay() {
+    let queue = EventQueue::new(REPLAY_LENGTH * 2);
+    let _rx = queue.subscribe();
+    let game_id = Uuid::new_v4();
+    let pass = || GameStateMessage::Pass {
+        stone: crate::board::Stone::Black,
+        board: String::new(),
+    };
+
+    assert_eq!(Some(Vec::new()), queue.replay(&game_id, 0));
+    assert_eq!(None, queue.replay(&game_id, 3));
+    for _ in 0..3 {
+        queue.send(Some(game_id), pass()).unwrap();
+    }
+    assert_eq!(3, queue.sequence(&game_id));
+    let missed: Vec<u64> = queue
+        .replay(&game_id, 1)
+        .unwrap()
+        .iter()
+        .map(|event| event.seq)
+        .collect();
+    assert_eq!(vec![2, 3], missed);
+    assert_eq!(Some(Vec::new()), queue.replay(&game_id, 3));
+    // An id from before a restart can't be trusted
+    assert_eq!(None, queue.replay(&game_id, 4));
+
+    // Only the latest events are kept
+    for _ in 0..REPLAY_LENGTH {
+        queue.send(Some(game_id), pass()).unwrap();
+    }
+    assert_eq!(None, queue.replay(&game_id, 2));
+    assert_eq!(REPLAY_LENGTH, queue.replay(&game_id, 3).unwrap().len());
+}
+
+#[test]
+fn test_spectators() {
+    let queue = EventQueue::new(16);
+    let mut rx = queue.subscribe();
+    let game_id = Uuid::new_v4();
+
+    let first = queue.watch(game_id);
+    let second = queue.watch(game_id);
+    assert_eq!(2, queue.spectators(&game_id));
+    drop(first);
+    assert_eq!(1, queue.spectators(&game_id));
+    drop(second);
+    assert_eq!(0, queue.spectators(&game_id));
+
+    let counts: Vec<GameStateMessage> = (0..4).map(|_| rx.try_recv().unwrap().state).collect();
+    let count = |count| GameStateMessage::Spectators { count };
+    assert_eq!(vec![count(1), count(2), count(1), count(0)], counts);
+    // Counts are numbered with the game's other events
+    assert_eq!(4, queue.sequence(&game_id));
+}
+
+#[test]
+fn test_relayed_queue() {
+    let (outbox, relayed) = mpsc::channel();
+    let queue = EventQueue::new(16).relayed(outbox);
+    let game_id = Uuid::new_v4();
+    let join = GameStateMessage::Join { id: game_id };
+
+    // Messages are relayed even without subscribers here
+    assert_eq!(true, queue.send(Some(game_id), join.clone()).is_err());
+    assert_eq!(Ok((Some(game_id), join.clone())), relayed.try_recv());
+
+    // Messages from other servers, and spectator counts, aren't sent back
+    let mut rx = queue.subscribe();
+    assert_eq!(2, queue.deliver(Some(game_id), join.clone()).unwrap());
+    let watcher = queue.watch(game_id);
+    drop(watcher);
+    assert_eq!(true, relayed.try_recv().is_err());
+    assert_eq!(join, rx.try_recv().unwrap().state);
+
+    // The queue notices once the relay stops reading
+    assert_eq!(true, queue.relaying());
+    drop(relayed);
+    let _ = queue.send(Some(game_id), join);
+    assert_eq!(false, queue.relaying());
+}
+
+#[test]
+fn test_forget() {
+    let queue = EventQueue::new(16);
+    let _rx = queue.subscribe();
+    let game_id = Uuid::new_v4();
+
+    let watcher = queue.watch(game_id);
+    queue
+        .send(Some(game_id), GameStateMessage::Join { id: game_id })
+        .unwrap();
+    assert_eq!(2, queue.sequence(&game_id));
+    queue.forget(&game_id);
+    drop(watcher);
+    assert_eq!(0, queue.sequence(&game_id));
+    assert_eq!(0, queue.spectators(&game_id));
+    assert_eq!(Some(Vec::new()), queue.replay(&game_id, 0));
+}
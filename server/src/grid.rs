@@ -0,0 +1,331 @@
+use std::cell::Cell;
+
+use crate::board::{Coordinate, Size, Stone};
+
+// Grid holds the stones on a board in a flat array indexed by `y * size + x`, so looking up a
+// point is a single index rather than a tree traversal.
+//
+// Chains are tracked incrementally with a union-find over the occupied points. Every chain keeps
+// its pseudo-liberty count, the number of (stone, empty neighbour) pairs, which is zero exactly
+// when the chain has no liberties. Stones of a chain are linked in a circular list so the chain
+// can be listed without searching the board.
+#[derive(Debug, Clone)]
+pub struct Grid {
+    size: usize,
+    tiles: Vec<Option<Stone>>,
+    // stones counts the occupied tiles.
+    stones: usize,
+    // parent links every stone towards the root of its chain, lookups compress the path.
+    parent: Vec<Cell<usize>>,
+    // next links the stones of a chain in a circle.
+    next: Vec<usize>,
+    // members and pseudo_liberties are only meaningful at the root of a chain.
+    members: Vec<usize>,
+    pseudo_liberties: Vec<usize>,
+}
+
+impl PartialEq for Grid {
+    // Two grids are equal when the same stones are on the same points, however the chains were
+    // joined.
+    fn eq(&self, other: &Grid) -> bool {
+        self.size == other.size && self.tiles == other.tiles
+    }
+}
+
+impl Eq for Grid {}
+
+impl Grid {
+    pub fn new(size: Size) -> Grid {
+        let size = size as usize;
+        let points = size * size;
+        Grid {
+            size,
+            tiles: vec![None; points],
+            stones: 0,
+            parent: (0..points).map(Cell::new).collect(),
+            next: (0..points).collect(),
+            members: vec![1; points],
+            pseudo_liberties: vec![0; points],
+        }
+    }
+
+    // index returns where a coordinate is stored, None if it is off the board.
+    fn index(&self, (x, y): Coordinate) -> Option<usize> {
+        let (x, y) = (usize::try_from(x).ok()?, usize::try_from(y).ok()?);
+        (x < self.size && y < self.size).then(|| y * self.size + x)
+    }
+
+    fn coordinate(&self, index: usize) -> Coordinate {
+        ((index % self.size) as i8, (index / self.size) as i8)
+    }
+
+    // neighbours returns the indexes of the points above, right, below and left of `index` that
+    // are on the board.
+    fn neighbours(&self, index: usize) -> impl Iterator<Item = usize> {
+        let size = self.size;
+        let (x, y) = (index % size, index / size);
+        [
+            (y > 0).then(|| index - size),
+            (x + 1 < size).then(|| index + 1),
+            (y + 1 < size).then(|| index + size),
+            (x > 0).then(|| index - 1),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    // root finds the root of the chain holding the stone at `index`, halving the path on the way.
+    fn root(&self, mut index: usize) -> usize {
+        while self.parent[index].get() != index {
+            let grandparent = self.parent[self.parent[index].get()].get();
+            self.parent[index].set(grandparent);
+            index = grandparent;
+        }
+        index
+    }
+
+    // join merges the chains holding `a` and `b`, the smaller chain is attached to the larger.
+    fn join(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.root(a), self.root(b));
+        if a == b {
+            return;
+        }
+        if self.members[a] < self.members[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parent[b].set(a);
+        self.members[a] += self.members[b];
+        self.pseudo_liberties[a] += self.pseudo_liberties[b];
+        self.next.swap(a, b);
+    }
+
+    // link adds the stone at `index` to the chains, as a chain of its own joined to any friendly
+    // neighbours.
+    fn link(&mut self, index: usize, stone: Stone) {
+        self.parent[index].set(index);
+        self.next[index] = index;
+        self.members[index] = 1;
+        self.pseudo_liberties[index] = 0;
+
+        let neighbours: Vec<usize> = self.neighbours(index).collect();
+        for neighbour in neighbours.iter() {
+            match self.tiles[*neighbour] {
+                None => self.pseudo_liberties[index] += 1,
+                Some(_) => {
+                    let root = self.root(*neighbour);
+                    self.pseudo_liberties[root] -= 1;
+                }
+            }
+        }
+        for neighbour in neighbours {
+            if self.tiles[neighbour] == Some(stone) {
+                self.join(index, neighbour);
+            }
+        }
+    }
+
+    // relink rebuilds every chain from scratch, used when a stone is taken out of the middle of a
+    // chain, which a union-find can't split.
+    fn relink(&mut self) {
+        for index in 0..self.tiles.len() {
+            self.pseudo_liberties[index] = 0;
+        }
+        let occupied: Vec<(usize, Stone)> = self
+            .tiles
+            .iter()
+            .enumerate()
+            .filter_map(|(index, tile)| tile.map(|stone| (index, stone)))
+            .collect();
+        for (index, _) in occupied.iter() {
+            self.tiles[*index] = None;
+        }
+        for (index, stone) in occupied {
+            self.tiles[index] = Some(stone);
+            self.link(index, stone);
+        }
+    }
+
+    pub fn get(&self, position: &Coordinate) -> Option<&Stone> {
+        self.index(*position)
+            .and_then(|index| self.tiles[index].as_ref())
+    }
+
+    pub fn contains_key(&self, position: &Coordinate) -> bool {
+        self.get(position).is_some()
+    }
+
+    // insert places a stone, returning the stone it replaced. The position must be on the board.
+    pub fn insert(&mut self, position: Coordinate, stone: Stone) -> Option<Stone> {
+        let index = self.index(position).expect("position is off the board");
+        let previous = self.tiles[index].replace(stone);
+        match previous {
+            None => {
+                self.stones += 1;
+                self.link(index, stone);
+            }
+            Some(_) => self.relink(),
+        }
+        previous
+    }
+
+    // remove takes a single stone off the board.
+    pub fn remove(&mut self, position: &Coordinate) -> Option<Stone> {
+        let index = self.index(*position)?;
+        let previous = self.tiles[index].take()?;
+        self.stones -= 1;
+        if self
+            .neighbours(index)
+            .any(|neighbour| self.tiles[neighbour] == Some(previous))
+        {
+            self.relink();
+        } else {
+            self.free(&[index]);
+        }
+        Some(previous)
+    }
+
+    // remove_chain takes whole chains off the board, e.g. captured stones, returning the stones
+    // removed. Points already empty are skipped.
+    pub fn remove_chain(&mut self, chain: &[Coordinate]) -> Vec<(Coordinate, Stone)> {
+        let mut removed = Vec::new();
+        let mut freed = Vec::new();
+        for position in chain {
+            if let Some(index) = self.index(*position) {
+                if let Some(stone) = self.tiles[index].take() {
+                    removed.push((*position, stone));
+                    freed.push(index);
+                }
+            }
+        }
+        self.stones -= removed.len();
+
+        // Leaving part of a chain behind splits it
+        let split = removed.iter().zip(freed.iter()).any(|((_, stone), index)| {
+            self.neighbours(*index)
+                .any(|neighbour| self.tiles[neighbour] == Some(*stone))
+        });
+        if split {
+            self.relink();
+        } else {
+            self.free(&freed);
+        }
+        removed
+    }
+
+    // free gives a liberty back to every chain next to the emptied points.
+    fn free(&mut self, emptied: &[usize]) {
+        for index in emptied {
+            let neighbours: Vec<usize> = self.neighbours(*index).collect();
+            for neighbour in neighbours {
+                if self.tiles[neighbour].is_some() {
+                    let root = self.root(neighbour);
+                    self.pseudo_liberties[root] += 1;
+                }
+            }
+        }
+    }
+
+    // chain lists the stones connected to the stone at `position`.
+    pub fn chain(&self, position: Coordinate) -> Vec<Coordinate> {
+        let start = match self.index(position) {
+            Some(index) if self.tiles[index].is_some() => index,
+            _ => return Vec::new(),
+        };
+        let mut chain = vec![self.coordinate(start)];
+        let mut index = self.next[start];
+        while index != start {
+            chain.push(self.coordinate(index));
+            index = self.next[index];
+        }
+        chain
+    }
+
+    // last_liberty tests if `point` is the only liberty of the chain at `position`, so a foe
+    // playing there captures it, or a friend playing there fills its last liberty.
+    pub fn last_liberty(&self, position: Coordinate, point: Coordinate) -> bool {
+        let (index, point) = match (self.index(position), self.index(point)) {
+            (Some(index), Some(point)) if self.tiles[index].is_some() => (index, point),
+            _ => return false,
+        };
+        let root = self.root(index);
+        // Each stone of the chain next to the point counts it once
+        let touching = self
+            .neighbours(point)
+            .filter(|neighbour| self.tiles[*neighbour].is_some() && self.root(*neighbour) == root)
+            .count();
+        self.pseudo_liberties[root] == touching
+    }
+
+    pub fn len(&self) -> usize {
+        self.stones
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stones == 0
+    }
+
+    // iter visits every stone in board order, row by row from the top left.
+    pub fn iter(&self) -> impl Iterator<Item = (Coordinate, Stone)> + '_ {
+        self.tiles
+            .iter()
+            .enumerate()
+            .filter_map(move |(index, tile)| tile.map(|stone| (self.coordinate(index), stone)))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = Coordinate> + '_ {
+        self.iter().map(|(position, _)| position)
+    }
+}
+
+#[test]
+fn test_grid() {
+    let mut grid = Grid::new(Size::Nine);
+    assert_eq!(true, grid.is_empty());
+    assert_eq!(None, grid.insert((3, 1), Stone::Black));
+    assert_eq!(None, grid.insert((1, 3), Stone::White));
+    assert_eq!(Some(Stone::Black), grid.insert((3, 1), Stone::White));
+    assert_eq!(2, grid.len());
+
+    assert_eq!(Some(&Stone::White), grid.get(&(3, 1)));
+    assert_eq!(None, grid.get(&(9, 1)));
+    assert_eq!(None, grid.get(&(-1, 1)));
+    assert_eq!(
+        vec![((3, 1), Stone::White), ((1, 3), Stone::White)],
+        grid.iter().collect::<Vec<_>>()
+    );
+
+    assert_eq!(Some(Stone::White), grid.remove(&(1, 3)));
+    assert_eq!(None, grid.remove(&(1, 3)));
+    assert_eq!(vec![(3, 1)], grid.keys().collect::<Vec<_>>());
+    assert_eq!(false, grid.contains_key(&(1, 3)));
+}
+
+#[test]
+fn test_grid_chains() {
+    let mut grid = Grid::new(Size::Nine);
+    // A black chain along the top edge, hemmed in by white below
+    for x in 0..3 {
+        grid.insert((x, 0), Stone::Black);
+        grid.insert((x, 1), Stone::White);
+    }
+    let mut chain = grid.chain((1, 0));
+    chain.sort();
+    assert_eq!(vec![(0, 0), (1, 0), (2, 0)], chain);
+    assert_eq!(true, grid.last_liberty((0, 0), (3, 0)));
+    assert_eq!(false, grid.last_liberty((0, 1), (3, 1)));
+
+    // Capturing it frees liberties for White
+    let removed = grid.remove_chain(&grid.chain((0, 0)));
+    assert_eq!(3, removed.len());
+    assert_eq!(false, grid.last_liberty((0, 1), (3, 1)));
+    assert_eq!(Vec::<Coordinate>::new(), grid.chain((1, 0)));
+
+    // Taking a stone out of the middle splits the chain
+    grid.remove(&(1, 1));
+    assert_eq!(vec![(0, 1)], grid.chain((0, 1)));
+    assert_eq!(vec![(2, 1)], grid.chain((2, 1)));
+    grid.insert((0, 0), Stone::Black);
+    grid.insert((1, 1), Stone::Black);
+    assert_eq!(true, grid.last_liberty((0, 1), (0, 2)));
+    assert_eq!(false, grid.last_liberty((2, 1), (3, 1)));
+}
@@ -0,0 +1,249 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, Write};
+
+use crate::board::{self, Coordinate, Game, GamePhase, Size, Stone};
+
+// COMMANDS are the GTP (Go Text Protocol) commands understood by the engine, in the order
+// `list_commands` reports them.
+const COMMANDS: [&str; 12] = [
+    "protocol_version",
+    "name",
+    "version",
+    "known_command",
+    "list_commands",
+    "quit",
+    "boardsize",
+    "clear_board",
+    "komi",
+    "play",
+    "genmove",
+    "showboard",
+];
+
+// Engine answers GTP commands about a single game, so rustigo's rules can be driven by standard
+// Go tools such as GoGui.
+struct Engine {
+    game: Game,
+}
+
+// parse_color reads a GTP color, either the letter or the whole word in any case.
+fn parse_color(text: &str) -> Result<Stone, String> {
+    match text.to_ascii_lowercase().as_str() {
+        "b" | "black" => Ok(Stone::Black),
+        "w" | "white" => Ok(Stone::White),
+        _ => Err("syntax error".to_string()),
+    }
+}
+
+// random returns a number below `bound`, randomly keyed hashers are random enough for picking
+// moves without pulling in a random number generator.
+fn random(bound: usize) -> usize {
+    RandomState::new().build_hasher().finish() as usize % bound
+}
+
+impl Engine {
+    // command runs a single command, returning the response text or the error message.
+    fn command(&mut self, name: &str, arguments: &[&str]) -> Result<String, String> {
+        match (name, arguments) {
+            ("protocol_version", []) => Ok("2".to_string()),
+            ("name", []) => Ok("rustigo".to_string()),
+            ("version", []) => Ok(env!("CARGO_PKG_VERSION").to_string()),
+            ("known_command", [command]) => Ok(COMMANDS.contains(command).to_string()),
+            ("list_commands", []) => Ok(COMMANDS.join("\n")),
+            ("quit", []) => Ok(String::new()),
+            ("boardsize", [size]) => {
+                let size = size.parse::<usize>().map_err(|_| "syntax error")?;
+                let size = Size::try_from(size).map_err(|_| "unacceptable size")?;
+                self.game = board::new(size);
+                Ok(String::new())
+            }
+            ("clear_board", []) => {
+                let komi = self.game.komi();
+                self.game = board::new(self.game.size());
+                self.game.set_komi(komi);
+                Ok(String::new())
+            }
+            ("komi", [komi]) => {
+                let komi = komi
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|komi| komi.is_finite())
+                    .ok_or("syntax error")?;
+                self.game.set_komi(komi);
+                Ok(String::new())
+            }
+            ("play", [color, vertex]) => {
+                let stone = parse_color(color)?;
+                let played = if vertex.eq_ignore_ascii_case("pass") {
+                    self.game.pass(stone)
+                } else {
+                    let position = board::parse_coordinate(self.game.size(), vertex)
+                        .map_err(|_| "syntax error")?;
+                    self.game.play_stone(position, stone).map(|_| ())
+                };
+                // Players have to alternate, playing out of turn is reported as illegal too
+                played.map_err(|_| "illegal move")?;
+                Ok(String::new())
+            }
+            ("genmove", [color]) => {
+                let stone = parse_color(color)?;
+                if self.game.phase() != GamePhase::Playing || self.game.turn() != stone {
+                    return Err("illegal move".to_string());
+                }
+                match self.random_move(stone) {
+                    Some(position) => {
+                        self.game
+                            .play_stone(position, stone)
+                            .map_err(|_| "illegal move")?;
+                        Ok(board::format_coordinate(self.game.size(), position))
+                    }
+                    None => {
+                        self.game.pass(stone).map_err(|_| "illegal move")?;
+                        Ok("pass".to_string())
+                    }
+                }
+            }
+            // The board is preceded by a newline so it starts on a line of its own, a blank line
+            // would end the response early.
+            ("showboard", []) => Ok(self
+                .game
+                .to_string()
+                .lines()
+                .filter(|line| !line.is_empty())
+                .fold(String::new(), |board, line| board + "\n" + line)),
+            (name, _) if COMMANDS.contains(&name) => Err("syntax error".to_string()),
+            _ => Err("unknown command".to_string()),
+        }
+    }
+
+    // random_move picks a legal move for `stone` at random, never filling one of its own eyes so
+    // that games between random players end. None means there is nothing left to do but pass.
+    fn random_move(&self, stone: Stone) -> Option<Coordinate> {
+        let candidates: Vec<Coordinate> = self
+            .game
+            .legal_moves(stone)
+            .into_iter()
+            .filter(|position| !self.is_eye(*position, stone))
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(candidates[random(candidates.len())])
+    }
+
+    // is_eye tests if every point next to `position` holds one of `stone`'s stones.
+    fn is_eye(&self, (x, y): Coordinate, stone: Stone) -> bool {
+        let extent = self.game.size() as i8;
+        [(x, y - 1), (x + 1, y), (x, y + 1), (x - 1, y)]
+            .iter()
+            .filter(|(x, y)| *x >= 0 && *x < extent && *y >= 0 && *y < extent)
+            .all(|neighbour| self.game.stone_at(*neighbour) == Some(stone))
+    }
+}
+
+// run speaks GTP, reading commands from `input` and writing responses to `output` until the
+// input ends or the controller sends `quit`.
+pub fn run(input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut engine = Engine {
+        game: board::new(Size::Nineteen),
+    };
+
+    for line in input.lines() {
+        let line = line?;
+        // Comments run to the end of the line, control characters other than tabs are dropped
+        let line: String = line
+            .split('#')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| !c.is_control() || *c == '\t')
+            .map(|c| if c == '\t' { ' ' } else { c })
+            .collect();
+
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        let id = match words[0].parse::<u32>() {
+            Ok(id) => {
+                words.remove(0);
+                id.to_string()
+            }
+            Err(_) => String::new(),
+        };
+        let (name, arguments) = match words.split_first() {
+            Some(command) => command,
+            None => continue,
+        };
+
+        match engine.command(name, arguments) {
+            Ok(response) if response.is_empty() => write!(output, "={}\n\n", id)?,
+            Ok(response) => write!(output, "={} {}\n\n", id, response)?,
+            Err(message) => write!(output, "?{} {}\n\n", id, message)?,
+        }
+        output.flush()?;
+
+        if *name == "quit" {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_gtp_session() {
+    let input = "1 protocol_version\n\
+                 boardsize 9 # a comment\n\
+                 play black D4\n\
+                 play b D4\n\
+                 2 play w e5\n\
+                 boardsize 21\n\
+                 frobnicate\n\
+                 known_command genmove\n\
+                 quit\n\
+                 name\n";
+    let mut output = Vec::new();
+    run(input.as_bytes(), &mut output).unwrap();
+
+    assert_eq!(
+        "=1 2\n\n\
+         =\n\n\
+         =\n\n\
+         ? illegal move\n\n\
+         =2\n\n\
+         ? unacceptable size\n\n\
+         ? unknown command\n\n\
+         = true\n\n\
+         =\n\n",
+        String::from_utf8(output).unwrap()
+    );
+}
+
+#[test]
+fn test_gtp_genmove() {
+    let mut engine = Engine {
+        game: board::new(Size::Nine),
+    };
+    let vertex = engine.command("genmove", &["b"]).unwrap();
+    let position = board::parse_coordinate(Size::Nine, &vertex).unwrap();
+    assert_eq!(Some(Stone::Black), engine.game.stone_at(position));
+    assert_eq!(
+        Err("illegal move".to_string()),
+        engine.command("genmove", &["b"])
+    );
+
+    // Black's eye in the corner is suicide for White and must not be filled by Black
+    let stones = [((1, 0), Stone::Black), ((0, 1), Stone::Black)];
+    let mut engine = Engine {
+        game: board::setup(Size::Nine, stones, Stone::Black).unwrap(),
+    };
+    for _ in 0..20 {
+        assert_ne!(Some((0, 0)), engine.random_move(Stone::White));
+        assert_ne!(Some((0, 0)), engine.random_move(Stone::Black));
+    }
+
+    let board = engine.command("showboard", &[]).unwrap();
+    assert_eq!(true, board.starts_with("\n    A B C D E F G H J"));
+    assert_eq!(false, board.contains("\n\n"));
+}
@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use rocket::serde::uuid::Uuid;
+
+use crate::board::{self, Game, GamePhase};
+
+// SyncPolicy decides when journal entries are flushed to the disk. Syncing every entry survives
+// power failure, leaving it to the OS only survives the server crashing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncPolicy {
+    #[default]
+    Always,
+    Never,
+}
+
+// SNAPSHOT_EVERY is how many changes are logged after a snapshot before a game's journal is
+// rewritten as a new snapshot.
+const SNAPSHOT_EVERY: usize = 100;
+
+// GROWING are the segments of a game record that are lists growing as the game goes on, an undo
+// takes the last move off. Changes only log what was added to them.
+const GROWING: [&str; 3] = ["moves", "chat", "ledger"];
+
+// Lists are the items of each of the GROWING segments of a record.
+type Lists<'a> = [Vec<&'a str>; 3];
+
+// split separates a game record into the segments that keep their size and the growing lists.
+fn split(record: &str) -> (Vec<&str>, Lists<'_>) {
+    let mut head = Vec::new();
+    let mut lists = Lists::default();
+    for segment in record.split(';') {
+        let growing = segment.split_once('=').and_then(|(key, items)| {
+            let index = GROWING.iter().position(|growing| *growing == key)?;
+            Some((index, items))
+        });
+        match growing {
+            Some((index, items)) => lists[index] = items.split(':').collect(),
+            None => head.push(segment),
+        }
+    }
+    (head, lists)
+}
+
+// join puts a game record back together from its parts, it is the inverse of split.
+fn join(head: &[&str], lists: &Lists<'_>) -> String {
+    let mut record = head.join(";");
+    for (key, items) in GROWING.iter().zip(lists) {
+        if !items.is_empty() {
+            record.push_str(&format!(";{}={}", key, items.join(":")));
+        }
+    }
+    record
+}
+
+// change writes the journal entry taking a game from one record to the next: the segments that
+// keep their size, then for each growing list how many of its items were kept and those added.
+//
+// e.g:
+//  "+<id>;9;<board>;w;komi=6.5\tmoves=1,b2,2@1700000000000\tchat=0,\tledger=2,played-b2,2"
+fn change(from: &str, to: &str) -> String {
+    let (_, before) = split(from);
+    let (head, after) = split(to);
+    let mut entry = format!("+{}", head.join(";"));
+    for ((key, before), after) in GROWING.iter().zip(&before).zip(&after) {
+        let kept = before
+            .iter()
+            .zip(after)
+            .take_while(|(before, after)| before == after)
+            .count();
+        entry.push_str(&format!("\t{}={},{}", key, kept, after[kept..].join(":")));
+    }
+    entry
+}
+
+// apply plays a journal entry onto the record before it. A snapshot starts with `=`, lines with
+// neither `=` nor `+` are whole records too, as journals were written before changes were logged.
+fn apply(record: &str, entry: &str) -> Option<String> {
+    let change = match entry.strip_prefix('+') {
+        Some(change) => change,
+        None => return Some(entry.strip_prefix('=').unwrap_or(entry).to_string()),
+    };
+    let mut parts = change.split('\t');
+    let head: Vec<&str> = parts.next()?.split(';').collect();
+    let (_, mut lists) = split(record);
+    for part in parts {
+        let (key, items) = part.split_once('=')?;
+        let index = GROWING.iter().position(|growing| *growing == key)?;
+        let (kept, added) = items.split_once(',')?;
+        let kept: usize = kept.parse().ok()?;
+        if kept > lists[index].len() {
+            return None;
+        }
+        lists[index].truncate(kept);
+        lists[index].extend(added.split(':').filter(|item| !item.is_empty()));
+    }
+    Some(join(&head, &lists))
+}
+
+// Journal is a write-ahead log of every game, one file per game in `directory`. A journal starts
+// with a snapshot of the whole game record, each change after it logs the segments that keep
+// their size and the moves, chat and ledger entries added, written before the change is applied
+// in memory. Every SNAPSHOT_EVERY changes the journal is rewritten as a new snapshot. Journals of
+// finished games are moved to `directory/finished` as a snapshot. Restarting the server replays
+// the journals of the games still being played, finished ones are read when they are asked for.
+//
+// e.g. in Rocket.toml:
+//  [default]
+//  journal = "/var/lib/rustigo/journal"
+//  journal_sync = "always"
+pub struct Journal {
+    directory: PathBuf,
+    sync: SyncPolicy,
+    // logged holds the record each game's journal ends with and how many changes follow its
+    // snapshot, for the games still being played.
+    logged: Mutex<HashMap<Uuid, (String, usize)>>,
+}
+
+impl Journal {
+    pub fn open(directory: impl Into<PathBuf>, sync: SyncPolicy) -> io::Result<Journal> {
+        let directory = directory.into();
+        fs::create_dir_all(directory.join("finished"))?;
+        Ok(Journal {
+            directory,
+            sync,
+            logged: Mutex::default(),
+        })
+    }
+
+    fn path(&self, game_id: &Uuid) -> PathBuf {
+        self.directory.join(format!("{}.journal", game_id))
+    }
+
+    fn finished_path(&self, game_id: &Uuid) -> PathBuf {
+        self.directory
+            .join("finished")
+            .join(format!("{}.journal", game_id))
+    }
+
+    // append logs a change to a game, the game is only safe to apply once this returns.
+    pub fn append(&self, game_id: &Uuid, game: &Game) -> io::Result<()> {
+        let record = board::encode_with_history(game);
+        if game.phase() == GamePhase::Finished {
+            self.logged.lock().unwrap().remove(game_id);
+            return self.snapshot(game_id, &record, true);
+        }
+
+        let logged = self.logged.lock().unwrap().get(game_id).cloned();
+        let changes = match logged {
+            Some((last, changes)) if changes < SNAPSHOT_EVERY => {
+                let mut file = OpenOptions::new().append(true).open(self.path(game_id))?;
+                // A single write keeps entries whole if the server is killed mid append
+                file.write_all(format!("{}\n", change(&last, &record)).as_bytes())?;
+                if self.sync == SyncPolicy::Always {
+                    file.sync_data()?;
+                }
+                changes + 1
+            }
+            _ => {
+                self.snapshot(game_id, &record, false)?;
+                0
+            }
+        };
+        self.logged
+            .lock()
+            .unwrap()
+            .insert(*game_id, (record, changes));
+        Ok(())
+    }
+
+    // snapshot rewrites a game's journal as its whole record. The journal is replaced whole, by
+    // renaming a file written next to it.
+    fn snapshot(&self, game_id: &Uuid, record: &str, finished: bool) -> io::Result<()> {
+        let (path, other) = match finished {
+            true => (self.finished_path(game_id), self.path(game_id)),
+            false => (self.path(game_id), self.finished_path(game_id)),
+        };
+        let written = path.with_extension("tmp");
+        let mut file = File::create(&written)?;
+        file.write_all(format!("={}\n", record).as_bytes())?;
+        if self.sync == SyncPolicy::Always {
+            file.sync_data()?;
+        }
+        fs::rename(&written, &path)?;
+        remove(&other)?;
+        self.sync_directory(&self.directory)?;
+        self.sync_directory(&self.directory.join("finished"))
+    }
+
+    // sync_directory flushes the files created, renamed or removed in a directory to the disk, a
+    // new game's journal could be lost to a power failure without it.
+    fn sync_directory(&self, directory: &Path) -> io::Result<()> {
+        if self.sync == SyncPolicy::Always {
+            File::open(directory)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    // ping checks the journal's directory is still there to write to.
+    pub fn ping(&self) -> io::Result<()> {
+        let metadata = fs::metadata(&self.directory)?;
+        if !metadata.is_dir() || metadata.permissions().readonly() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "The journal can't be written to",
+            ));
+        }
+        Ok(())
+    }
+
+    // remove deletes a game's journal.
+    pub fn remove(&self, game_id: &Uuid) -> io::Result<()> {
+        self.logged.lock().unwrap().remove(game_id);
+        remove(&self.path(game_id))?;
+        remove(&self.finished_path(game_id))
+    }
+
+    // read plays back a journal, returning the record it ends with and how many changes follow
+    // its last snapshot. An entry torn by a crash has no newline, it is cut off so the next entry
+    // starts on a line of its own. Journals that can't be played back are reported as invalid.
+    fn read(path: &Path) -> io::Result<Option<(String, usize)>> {
+        let contents = match fs::read_to_string(path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            contents => contents?,
+        };
+        let complete = contents.rfind('\n').map_or(0, |end| end + 1);
+        if complete < contents.len() {
+            OpenOptions::new()
+                .write(true)
+                .open(path)?
+                .set_len(complete as u64)?;
+        }
+
+        let mut logged: Option<(String, usize)> = None;
+        for entry in contents[..complete].lines() {
+            let played = match (entry.starts_with('+'), &logged) {
+                (false, _) => apply("", entry).map(|record| (record, 0)),
+                (true, Some((record, changes))) => {
+                    apply(record, entry).map(|record| (record, changes + 1))
+                }
+                (true, None) => None,
+            };
+            logged = Some(played.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "A journal entry can't be played",
+                )
+            })?);
+        }
+        Ok(logged)
+    }
+
+    // load reads back a game that wasn't replayed at startup, a finished game or one that was
+    // left behind.
+    pub fn load(&self, game_id: &Uuid) -> io::Result<Option<Game>> {
+        for (path, finished) in [
+            (self.finished_path(game_id), true),
+            (self.path(game_id), false),
+        ] {
+            let (record, changes) = match Journal::read(&path)? {
+                Some(logged) => logged,
+                None => continue,
+            };
+            let game = board::decode(&record)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+            if !finished {
+                self.logged
+                    .lock()
+                    .unwrap()
+                    .insert(*game_id, (record, changes));
+            }
+            return Ok(Some(game));
+        }
+        Ok(None)
+    }
+
+    // finished lists the finished games, which aren't replayed.
+    pub fn finished(&self) -> io::Result<Vec<Uuid>> {
+        let mut games = Vec::new();
+        for entry in fs::read_dir(self.directory.join("finished"))? {
+            let path = entry?.path();
+            if let Some(game_id) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".journal"))
+                .and_then(|name| name.parse::<Uuid>().ok())
+            {
+                games.push(game_id);
+            }
+        }
+        Ok(games)
+    }
+
+    // replay reads back the latest state of every game still being played.
+    pub fn replay(&self) -> io::Result<Vec<(Uuid, Game)>> {
+        let mut games = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            let game_id = match path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".journal"))
+                .and_then(|name| name.parse::<Uuid>().ok())
+            {
+                Some(game_id) => game_id,
+                None => continue,
+            };
+
+            let played = Journal::read(&path).and_then(|logged| {
+                let (record, changes) = match logged {
+                    Some(logged) => logged,
+                    None => return Ok(None),
+                };
+                let game = board::decode(&record)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+                Ok(Some((game, record, changes)))
+            });
+            match played {
+                Ok(Some((game, record, changes))) => {
+                    self.logged
+                        .lock()
+                        .unwrap()
+                        .insert(game_id, (record, changes));
+                    games.push((game_id, game));
+                }
+                Ok(None) => (),
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    tracing::error!(?path, error = ?err, "Failed to replay journal")
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(games)
+    }
+}
+
+// remove deletes a file, if it is there.
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+#[test]
+fn test_journal() {
+    let directory = std::env::temp_dir().join(format!("rustigo-journal-{}", Uuid::new_v4()));
+    let journal = Journal::open(&directory, SyncPolicy::Always).unwrap();
+    let game_id = Uuid::new_v4();
+
+    let mut game = board::new(board::Size::Nine);
+    game.id = game_id;
+    journal.append(&game_id, &game).unwrap();
+    assert_eq!(true, game.play_stone((2, 2), board::Stone::Black).is_ok());
+    journal.append(&game_id, &game).unwrap();
+
+    // A torn write from a crash is cut off, leaving the last complete entry
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(journal.path(&game_id))
+        .unwrap();
+    file.write_all(b"not a whole ent").unwrap();
+    fs::write(directory.join("notes.txt"), "ignored").unwrap();
+
+    let games = journal.replay().unwrap();
+    assert_eq!(1, games.len());
+    assert_eq!(game_id, games[0].0);
+    assert_eq!(
+        board::encode_with_history(&game),
+        board::encode_with_history(&games[0].1)
+    );
+
+    assert_eq!(true, game.play_stone((3, 3), board::Stone::White).is_ok());
+    journal.append(&game_id, &game).unwrap();
+    let games = journal.replay().unwrap();
+    assert_eq!(
+        board::encode_with_history(&game),
+        board::encode_with_history(&games[0].1)
+    );
+
+    // Changes only log what was added, undoing takes a move off
+    let entries = fs::read_to_string(journal.path(&game_id)).unwrap();
+    let last = entries.lines().last().unwrap();
+    assert_eq!(true, last.starts_with('+'));
+    assert_eq!(true, last.ends_with("\tmoves=1,w3,3\tchat=0,\tledger=0,"));
+    assert_eq!(true, game.undo().is_ok());
+    journal.append(&game_id, &game).unwrap();
+    assert_eq!(
+        board::encode_with_history(&game),
+        board::encode_with_history(&journal.load(&game_id).unwrap().unwrap())
+    );
+
+    // The journal is rewritten as a snapshot once enough changes follow the last one
+    for _ in 0..SNAPSHOT_EVERY {
+        journal.append(&game_id, &game).unwrap();
+    }
+    let entries = fs::read_to_string(journal.path(&game_id)).unwrap();
+    assert_eq!(true, entries.lines().count() < SNAPSHOT_EVERY);
+    assert_eq!(true, entries.starts_with('='));
+
+    // Finished games are archived and no longer replayed
+    assert_eq!(true, game.resign(board::Stone::White).is_ok());
+    journal.append(&game_id, &game).unwrap();
+    assert_eq!(false, journal.path(&game_id).exists());
+    assert_eq!(true, journal.replay().unwrap().is_empty());
+    assert_eq!(vec![game_id], journal.finished().unwrap());
+    assert_eq!(
+        board::encode_with_history(&game),
+        board::encode_with_history(&journal.load(&game_id).unwrap().unwrap())
+    );
+
+    journal.remove(&game_id).unwrap();
+    journal.remove(&game_id).unwrap();
+    assert_eq!(true, journal.load(&game_id).unwrap().is_none());
+
+    // Journals written before changes were logged hold whole records
+    let legacy = board::new(board::Size::Nine);
+    fs::write(
+        journal.path(&legacy.id),
+        format!("{}\n", board::encode_with_history(&legacy)),
+    )
+    .unwrap();
+    assert_eq!(legacy.id, journal.replay().unwrap()[0].0);
+    fs::write(journal.path(&legacy.id), "+;;;\tmoves=5,a1\n").unwrap();
+    assert_eq!(true, journal.replay().unwrap().is_empty());
+
+    assert_eq!(true, journal.ping().is_ok());
+    fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(true, journal.ping().is_err());
+}
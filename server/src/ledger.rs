@@ -0,0 +1,349 @@
+use thiserror::Error;
+
+use crate::board::{
+    self, BoardParseError, Coordinate, Game, LedgerEntry, LedgerEvent, PlayError, Stone,
+};
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum LedgerError {
+    #[error("The ledger doesn't start with the game being created")]
+    NotCreated,
+    #[error("The board the game was created on can't be read: {0}")]
+    BadBoard(BoardParseError),
+    #[error("Entry {index} can't be replayed: {error}")]
+    Rejected { index: usize, error: PlayError },
+}
+
+// fold plays a ledger back from the board the game was created on, returning the game as it was
+// after the last entry. Folding part of a ledger brings the game back to any earlier point.
+pub fn fold(entries: &[LedgerEntry]) -> Result<Game, LedgerError> {
+    let board = match entries.first().map(|entry| &entry.event) {
+        Some(LedgerEvent::GameCreated { board }) => board,
+        _ => return Err(LedgerError::NotCreated),
+    };
+    let mut game = board::decode(board).map_err(LedgerError::BadBoard)?;
+    game.open_ledger();
+
+    for (index, entry) in entries.iter().enumerate() {
+        let moves = game.history().len();
+        match entry.event {
+            LedgerEvent::GameCreated { .. } if index == 0 => (),
+            LedgerEvent::GameCreated { .. } => return Err(LedgerError::NotCreated),
+            ref event => {
+                apply(&mut game, event).map_err(|error| LedgerError::Rejected { index, error })?;
+            }
+        }
+        if let Some(at) = entry.at {
+            game.stamp_ledger(index, at);
+            game.run_clock(moves, at);
+        }
+    }
+    Ok(game)
+}
+
+// apply makes the change to a game an event describes, it's how both players' actions and fold
+// change games, so the game is always what its ledger folds back into. The event is added to the
+// game's ledger if it is being kept. Returns the positions of any stones captured.
+pub fn apply(game: &mut Game, event: &LedgerEvent) -> Result<Vec<Coordinate>, PlayError> {
+    match *event {
+        LedgerEvent::GameCreated { .. } => return Err(PlayError::NotPlaying),
+        LedgerEvent::PlayerJoined { stone } => {
+            if game.host().is_none() {
+                game.set_host(Some(foe(stone)));
+            }
+            if !game.seat_guest() {
+                return Err(PlayError::NotPlaying);
+            }
+        }
+        LedgerEvent::StonePlayed { stone, position } => {
+            return Ok(game.play_stone(position, stone)?.captured);
+        }
+        LedgerEvent::Passed { stone } => game.pass(stone)?,
+        LedgerEvent::Resigned { stone } => game.resign(stone)?,
+        LedgerEvent::DeadToggled { position } => game.toggle_dead(position)?,
+        LedgerEvent::ScoreAccepted { stone } => game.accept_score(stone)?,
+        LedgerEvent::UndoRequested { stone } => game.request_undo(stone)?,
+        LedgerEvent::UndoAnswered { stone, accepted } => {
+            game.answer_undo(stone, accepted)?;
+        }
+        LedgerEvent::TimedOut { stone } => game.time_out(stone)?,
+    }
+    Ok(Vec::new())
+}
+
+fn foe(stone: Stone) -> Stone {
+    match stone {
+        Stone::Black => Stone::White,
+        Stone::White => Stone::Black,
+    }
+}
+
+fn parse_stone(value: &str) -> Option<Stone> {
+    match value {
+        "b" => Some(Stone::Black),
+        "w" => Some(Stone::White),
+        _ => None,
+    }
+}
+
+fn format_stone(stone: Stone) -> &'static str {
+    match stone {
+        Stone::Black => "b",
+        Stone::White => "w",
+    }
+}
+
+// hex encodes text so it can be kept in a segment of a game record whatever separators it holds.
+pub fn hex(value: &str) -> String {
+    value.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+// unhex decodes text encoded by hex.
+pub fn unhex(value: &str) -> Option<String> {
+    if value.len() % 2 == 1 || !value.is_ascii() {
+        return None;
+    }
+    let bytes = (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+// parse_entry reads an entry from the ledger segment of a game record. The board a game was
+// created on is hex encoded so it can hold any of the separators.
+//
+// e.g:
+//  "played-b3,4@1700000000000" => Black played (3, 4), at a time in milliseconds since the UNIX
+//  epoch
+//  "passed-w" => White passed
+//  "answered-b,yes" => Black let their opponent take back their last move
+pub fn parse_entry(value: &str) -> Option<LedgerEntry> {
+    let (value, at) = match value.rsplit_once('@') {
+        Some((value, at)) => (value, Some(at.parse().ok()?)),
+        None => (value, None),
+    };
+    let (name, args) = value.split_once('-')?;
+    let pair = |value: &str| {
+        let (x, y) = value.split_once(',')?;
+        Some((x.parse().ok()?, y.parse().ok()?))
+    };
+    let event = match name {
+        "created" => LedgerEvent::GameCreated {
+            board: unhex(args)?,
+        },
+        "joined" => LedgerEvent::PlayerJoined {
+            stone: parse_stone(args)?,
+        },
+        "played" => LedgerEvent::StonePlayed {
+            stone: parse_stone(args.get(..1)?)?,
+            position: pair(args.get(1..)?)?,
+        },
+        "passed" => LedgerEvent::Passed {
+            stone: parse_stone(args)?,
+        },
+        "resigned" => LedgerEvent::Resigned {
+            stone: parse_stone(args)?,
+        },
+        "dead" => LedgerEvent::DeadToggled {
+            position: pair(args)?,
+        },
+        "accepted" => LedgerEvent::ScoreAccepted {
+            stone: parse_stone(args)?,
+        },
+        "undo" => LedgerEvent::UndoRequested {
+            stone: parse_stone(args)?,
+        },
+        "answered" => {
+            let (stone, answer) = args.split_once(',')?;
+            LedgerEvent::UndoAnswered {
+                stone: parse_stone(stone)?,
+                accepted: match answer {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return None,
+                },
+            }
+        }
+        "timeout" => LedgerEvent::TimedOut {
+            stone: parse_stone(args)?,
+        },
+        _ => return None,
+    };
+    Some(LedgerEntry { at, event })
+}
+
+// format_entry writes an entry for the ledger segment of a game record, it is the inverse of
+// parse_entry.
+pub fn format_entry(entry: &LedgerEntry) -> String {
+    let mut output = match &entry.event {
+        LedgerEvent::GameCreated { board } => format!("created-{}", hex(board)),
+        LedgerEvent::PlayerJoined { stone } => format!("joined-{}", format_stone(*stone)),
+        LedgerEvent::StonePlayed {
+            stone,
+            position: (x, y),
+        } => format!("played-{}{},{}", format_stone(*stone), x, y),
+        LedgerEvent::Passed { stone } => format!("passed-{}", format_stone(*stone)),
+        LedgerEvent::Resigned { stone } => format!("resigned-{}", format_stone(*stone)),
+        LedgerEvent::DeadToggled { position: (x, y) } => format!("dead-{},{}", x, y),
+        LedgerEvent::ScoreAccepted { stone } => format!("accepted-{}", format_stone(*stone)),
+        LedgerEvent::UndoRequested { stone } => format!("undo-{}", format_stone(*stone)),
+        LedgerEvent::TimedOut { stone } => format!("timeout-{}", format_stone(*stone)),
+        LedgerEvent::UndoAnswered { stone, accepted } => format!(
+            "answered-{},{}",
+            format_stone(*stone),
+            if *accepted { "yes" } else { "no" }
+        ),
+    };
+    if let Some(at) = entry.at {
+        output.push_str(&format!("@{}", at));
+    }
+    output
+}
+
+#[test]
+fn test_entry_round_trip() {
+    let entries = [
+        LedgerEvent::GameCreated {
+            board: "id;9;.........;b;komi=6.5".to_string(),
+        },
+        LedgerEvent::PlayerJoined {
+            stone: Stone::White,
+        },
+        LedgerEvent::StonePlayed {
+            stone: Stone::Black,
+            position: (3, 14),
+        },
+        LedgerEvent::Passed {
+            stone: Stone::White,
+        },
+        LedgerEvent::Resigned {
+            stone: Stone::Black,
+        },
+        LedgerEvent::DeadToggled { position: (0, 2) },
+        LedgerEvent::ScoreAccepted {
+            stone: Stone::White,
+        },
+        LedgerEvent::UndoRequested {
+            stone: Stone::Black,
+        },
+        LedgerEvent::UndoAnswered {
+            stone: Stone::White,
+            accepted: false,
+        },
+        LedgerEvent::TimedOut {
+            stone: Stone::Black,
+        },
+    ];
+    for (index, event) in entries.into_iter().enumerate() {
+        let entry = LedgerEntry {
+            at: (index % 2 == 0).then_some(1700000000000 + index as u64),
+            event,
+        };
+        let formatted = format_entry(&entry);
+        assert_eq!(false, formatted.contains([':', ';']));
+        assert_eq!(Some(entry), parse_entry(&formatted));
+    }
+    assert_eq!(None, parse_entry("played-b3"));
+    assert_eq!(None, parse_entry("spoke-b"));
+    assert_eq!(None, parse_entry("created-6"));
+}
+
+#[test]
+fn test_fold() {
+    let mut game = board::new(board::Size::Nine);
+    game.set_host(None);
+    game.request_seat();
+    game.open_ledger();
+    let guest = game.accept_join().unwrap();
+    assert_eq!(true, game.play_stone((2, 2), Stone::Black).is_ok());
+    assert_eq!(true, game.request_undo(Stone::Black).is_ok());
+    assert_eq!(true, game.answer_undo(Stone::White, true).is_ok());
+    assert_eq!(true, game.play_stone((3, 3), Stone::Black).is_ok());
+    assert_eq!(true, game.play_stone((4, 4), Stone::White).is_ok());
+    assert_eq!(true, game.pass(Stone::Black).is_ok());
+    assert_eq!(true, game.pass(Stone::White).is_ok());
+    assert_eq!(true, game.toggle_dead((4, 4)).is_ok());
+    assert_eq!(true, game.accept_score(Stone::Black).is_ok());
+    assert_eq!(true, game.resign(Stone::White).is_ok());
+    assert_eq!(12, game.ledger().len());
+    game.stamp_ledger(0, 1700000000000);
+
+    // The ledger brings the game back to where it is, or to any point before
+    let folded = fold(game.ledger()).unwrap();
+    assert_eq!(board::encode(&game), board::encode(&folded));
+    assert_eq!(game.ledger(), folded.ledger());
+    assert_eq!(Some(guest), folded.guest());
+    let folded = fold(&game.ledger()[..6]).unwrap();
+    assert_eq!(Some(Stone::Black), folded.stone_at((3, 3)));
+    assert_eq!(None, folded.stone_at((2, 2)));
+    assert_eq!(Stone::White, folded.turn());
+
+    // The ledger survives the game's record
+    let decoded = board::decode(&board::encode_with_history(&game)).unwrap();
+    assert_eq!(game.ledger(), decoded.ledger());
+
+    assert_eq!(
+        Err(LedgerError::NotCreated),
+        fold(&game.ledger()[1..]).map(|_| ())
+    );
+    let mut forged = game.ledger()[..2].to_vec();
+    forged.push(LedgerEntry {
+        at: None,
+        event: LedgerEvent::Passed {
+            stone: Stone::White,
+        },
+    });
+    assert_eq!(
+        Err(LedgerError::Rejected {
+            index: 2,
+            error: PlayError::NotYourTurn
+        }),
+        fold(&forged).map(|_| ())
+    );
+}
+
+#[test]
+fn test_apply() {
+    let mut game = board::new(board::Size::Nine);
+    game.open_ledger();
+    let events = [
+        LedgerEvent::StonePlayed {
+            stone: Stone::Black,
+            position: (1, 0),
+        },
+        LedgerEvent::StonePlayed {
+            stone: Stone::White,
+            position: (0, 0),
+        },
+        LedgerEvent::StonePlayed {
+            stone: Stone::Black,
+            position: (0, 1),
+        },
+    ];
+    let captured: Vec<_> = events
+        .iter()
+        .map(|event| apply(&mut game, event).unwrap())
+        .collect();
+    assert_eq!(vec![(0, 0)], captured[2]);
+
+    // Every event applied is in the ledger, and folds back into the game
+    let logged: Vec<_> = game.ledger()[1..]
+        .iter()
+        .map(|entry| &entry.event)
+        .collect();
+    assert_eq!(events.iter().collect::<Vec<_>>(), logged);
+    assert_eq!(
+        board::encode(&game),
+        board::encode(&fold(game.ledger()).unwrap())
+    );
+
+    let board = board::encode(&game);
+    let passed = LedgerEvent::Passed {
+        stone: Stone::Black,
+    };
+    assert_eq!(Err(PlayError::NotYourTurn), apply(&mut game, &passed));
+    let created = LedgerEvent::GameCreated { board };
+    assert_eq!(true, apply(&mut game, &created).is_err());
+    assert_eq!(4, game.ledger().len());
+}
@@ -1,3 +1,5 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::net::IpAddr;
 use std::path::PathBuf;
 
 #[macro_use]
@@ -7,31 +9,154 @@ extern crate serde_derive;
 #[macro_use]
 extern crate rocket_include_static_resources;
 
-use rocket::{Build, Rocket};
-use rocket::http::{Cookie, CookieJar, Status};
-use rocket::response::stream::{Event, EventStream};
+use rocket::data::{Limits, ToByteUnit};
+use rocket::form::{self, error::ErrorKind, Form};
+use rocket::http::{ContentType, CookieJar, Status};
+use rocket::response::stream::{Event, EventStream, TextStream};
 use rocket::response::Redirect;
-use rocket::serde::json::{from_str, to_string, Json};
+use rocket::serde::json::{json, to_string, Json};
 use rocket::serde::uuid::Uuid;
 use rocket::tokio::select;
-use rocket::tokio::sync::broadcast::{channel, error::RecvError, Sender};
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::tokio::time::{sleep_until, Duration, Instant};
+use rocket::{Build, Request, Rocket};
 use rocket::{Shutdown, State};
 use rocket_dyn_templates::{context, Template};
 use rocket_include_static_resources::{EtagIfNoneMatch, StaticContextManager, StaticResponse};
 
-mod board;
+pub mod board;
+mod bots;
+mod chaos;
+mod chat;
+mod clock;
+mod cors;
+mod database;
+mod dispatcher;
+mod engine;
+mod expiry;
+mod feed;
+mod grid;
+pub mod gtp;
+mod journal;
+mod ledger;
+mod lobby;
+mod logging;
+mod maintenance;
+mod notify;
+mod outbound;
+pub mod players;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod privacy;
+mod registry;
+#[cfg(feature = "redis")]
+mod relay;
+mod services;
+mod sgf;
+mod sizes;
+mod store;
+mod turn;
+mod webhook;
+
+use chat::{ChatError, ChatLimiter};
+use engine::Engines;
+use feed::{EventQueue, GameEvent, LastEventId};
+use lobby::Lobby;
+use logging::RequestSpan;
+use maintenance::{Admin, AdminToken, Maintenance};
+use players::{Host, PlayerSession, SeatError, Seats, Spectator};
+use registry::GameRegistry;
+use rustigo_protocol::{
+    AcceptPlayerMessage, AcceptScoreMessage, BotMessage, BotTokenMessage, ChatLogMessage,
+    ChatMessage, Color, DeadStonesMessage, DeliveryCounts, ErrorMessage, GameCreatedMessage,
+    GameEntry, GameEventMessage, GameListMessage, GameSnapshotMessage, GameStateMessage,
+    HealthMessage, JoinMessage, LedgerEvent, LedgerMessage, MaintenanceMessage, MoveEntry,
+    MoveListMessage, NewGameMessage, NotifyMessage, Opponent, PassMessage, PlacePieceMessage,
+    PolledEvent, PreviewMessage, PreviewResultMessage, ReadinessMessage, ResignMessage,
+    SetupMessage, TimeWarningsMessage, TurnMessage, UndoAcceptMessage, UndoRequestMessage,
+    ViewportMessage, WebhookCreatedMessage, WebhookMessage,
+};
+use services::Services;
+use sizes::BoardSizes;
+
+// Prefix is the path the app is mounted under, so it can share a host with other apps behind a
+// reverse proxy. It is empty when the app is mounted at the root. Pages link to each other with
+// relative URLs, only redirects need the prefix.
+//
+// e.g. in Rocket.toml:
+//  [default]
+//  prefix = "/go"
+pub struct Prefix(String);
+
+impl Prefix {
+    pub fn new(path: &str) -> Prefix {
+        match path.trim_matches('/') {
+            "" => Prefix(String::new()),
+            path => Prefix(format!("/{}", path)),
+        }
+    }
+
+    // mount_point is where the routes are mounted.
+    fn mount_point(&self) -> &str {
+        match self.0.as_str() {
+            "" => "/",
+            path => path,
+        }
+    }
+
+    // path is the absolute path of a page within the app.
+    fn path(&self, path: impl std::fmt::Display) -> String {
+        format!("{}{}", self.0, path)
+    }
+
+    // redirect sends the client to a path within the app.
+    fn redirect(&self, path: impl std::fmt::Display) -> Redirect {
+        Redirect::to(self.path(path))
+    }
+}
 
 #[get("/")]
-fn redirect_to_root() -> Redirect {
-    Redirect::to("/index.html")
+fn redirect_to_root(prefix: &State<Prefix>) -> Redirect {
+    prefix.redirect("/index.html")
 }
 
+// serve_index serves the start page, offering the board sizes this server allows.
 #[get("/index.html")]
-fn serve_index(
-    static_resources: &State<StaticContextManager>,
-    etag_if_none_match: EtagIfNoneMatch,
-) -> StaticResponse {
-    static_resources.build(&etag_if_none_match, "index")
+fn serve_index(sizes: &State<BoardSizes>) -> Template {
+    let default_size = sizes.default_size() as u8;
+    let sizes = size_options(sizes);
+    Template::render("index", context! { sizes, default_size })
+}
+
+// size_options lists the sizes offered for a new game, as they appear in links and forms.
+fn size_options(sizes: &BoardSizes) -> Vec<u8> {
+    sizes.allowed().iter().map(|size| *size as u8).collect()
+}
+
+// check_size refuses to start a game with a size this server doesn't offer.
+fn check_size(sizes: &BoardSizes, size: board::Size) -> Result<(), (Status, Json<ErrorMessage>)> {
+    if sizes.allows(size) {
+        return Ok(());
+    }
+    Err((
+        Status::UnprocessableEntity,
+        Json(
+            ErrorMessage::new(
+                "SizeNotAllowed",
+                format!(
+                    "{}x{} games aren't offered on this server",
+                    size as u8, size as u8
+                ),
+            )
+            // The sizes that are offered
+            .with_details(
+                size_options(sizes)
+                    .iter()
+                    .map(|size| format!("{}x{}", size, size))
+                    .collect(),
+            ),
+        ),
+    ))
 }
 
 #[get("/favicon.ico")]
@@ -54,236 +179,3847 @@ fn serve_static_image(
         .map_err(|_| Status::NotFound)
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-struct BlackGameState {
-    size: u8,
-    private_key: String,
+// NewGameOptions are the optional settings chosen when creating a game.
+#[derive(Debug, FromForm)]
+struct NewGameOptions {
+    rules: Option<board::ScoringRules>,
+    komi: Option<f32>,
+    handicap: Option<u8>,
+    // free lets Black place the handicap stones anywhere instead of on the star points.
+    free: Option<bool>,
+    suicide: Option<bool>,
+    // vs is who the creator plays, `vs=engine` for the server's engine or `vs=bot` for a bot on
+    // the bot API.
+    vs: Option<Opponent>,
+    // color is the creator's stone, `color=random` draws the stones when their opponent joins.
+    color: Option<Color>,
+    // time times the game, e.g. `time=10m+5x30s`, see TimeControl::parse.
+    time: Option<clock::TimeControl>,
+    // warn is when players are warned their time is running out, e.g. `warn=30s,10s,last`, see
+    // TimeWarnings::parse.
+    warn: Option<clock::TimeWarnings>,
+}
+
+impl TryFrom<NewGameMessage> for NewGameOptions {
+    type Error = clock::TimeControlError;
+
+    fn try_from(message: NewGameMessage) -> Result<NewGameOptions, Self::Error> {
+        Ok(NewGameOptions {
+            rules: message.rules,
+            komi: message.komi,
+            handicap: message.handicap,
+            free: message.free,
+            suicide: message.suicide,
+            vs: message.vs,
+            color: message.color,
+            time: message
+                .time
+                .as_deref()
+                .map(clock::TimeControl::parse)
+                .transpose()?,
+            warn: message
+                .warn
+                .as_deref()
+                .map(clock::TimeWarnings::parse)
+                .transpose()?,
+        })
+    }
+}
+
+// host_stone is the stone the creator of a game plays, None while it is still to be drawn.
+fn host_stone(color: Color) -> Option<board::Stone> {
+    match color {
+        Color::Black => Some(board::Stone::Black),
+        Color::White => Some(board::Stone::White),
+        Color::Random => None,
+    }
+}
+
+// NewGameError is why a game wasn't started. A missing or unsupported size gets a page offering
+// the supported sizes, as it usually comes from a hand edited or stale link.
+#[derive(Responder)]
+enum NewGameError {
+    Size((Status, Template)),
+    Failed(Status),
+}
+
+impl From<Status> for NewGameError {
+    fn from(status: Status) -> NewGameError {
+        NewGameError::Failed(status)
+    }
+}
+
+// size_problem explains why the size asked for a new game was refused.
+fn size_problem(errors: &form::Errors<'_>) -> (Status, &'static str) {
+    let missing = errors
+        .iter()
+        .any(|error| matches!(error.kind, ErrorKind::Missing));
+    if missing {
+        (Status::BadRequest, "Choose a size for the board.")
+    } else {
+        (
+            Status::UnprocessableEntity,
+            "That board size isn't supported, choose one of these instead.",
+        )
+    }
+}
+
+#[get("/new?<size>&<options..>")]
+fn serve_new_game(
+    size: form::Result<'_, board::Size>,
+    options: NewGameOptions,
+    cookies: &CookieJar<'_>,
+    services: Services<'_>,
+    prefix: &State<Prefix>,
+    span: RequestSpan,
+) -> Result<Redirect, NewGameError> {
+    let _span = span.enter();
+    let size_page = |(status, message): (Status, &str)| {
+        let sizes = size_options(services.sizes);
+        NewGameError::Size((
+            status,
+            Template::render("size", context! { message, sizes }),
+        ))
+    };
+    let size = size.map_err(|errors| size_page(size_problem(&errors)))?;
+    if !services.sizes.allows(size) {
+        return Err(size_page((
+            Status::UnprocessableEntity,
+            "That board size isn't offered on this server, choose one of these instead.",
+        )));
+    }
+    if services.maintenance.active() {
+        return Err(Status::ServiceUnavailable.into());
+    }
+    let game_id = start_game(size, options, cookies, &services).map_err(|(status, _)| status)?;
+    Ok(prefix.redirect(format!("/{}/game.html", game_id)))
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-struct WhiteGameState {
-    size: u8,
-    public_key: String,
+// api_new_game starts a game for an API client, who takes the creator's seat.
+#[post("/api/v1/games", format = "application/json", data = "<message>")]
+fn api_new_game(
+    message: Json<NewGameMessage>,
+    cookies: &CookieJar<'_>,
+    services: Services<'_>,
+    span: RequestSpan,
+) -> Result<(Status, Json<GameCreatedMessage>), (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    check_maintenance(services.maintenance)?;
+    check_size(services.sizes, message.size)?;
+    let size = message.size;
+    let options = NewGameOptions::try_from(message.into_inner())
+        .map_err(|err| (Status::UnprocessableEntity, Json(err.into())))?;
+    let id = start_game(size, options, cookies, &services)?;
+    Ok((Status::Created, Json(GameCreatedMessage { id })))
 }
 
-#[get("/new?<size..>")]
-fn serve_new_game(size: board::Size, cookies: &CookieJar<'_>) -> Redirect {
+// start_game sets up a game with the options chosen, records it and seats its creator. The pages
+// and the API start games the same way.
+fn start_game(
+    size: board::Size,
+    options: NewGameOptions,
+    cookies: &CookieJar<'_>,
+    services: &Services<'_>,
+) -> Result<Uuid, (Status, Json<ErrorMessage>)> {
+    let Services {
+        registry,
+        queue,
+        engines,
+        ticker,
+        ..
+    } = *services;
     let game_id = Uuid::new_v4();
-    let size = size as u8;
 
-    let black_game_state = BlackGameState {
-        size,
-        private_key: "".to_string(),
+    let mut game = board::new(size);
+    game.id = game_id;
+    if let Some(rules) = options.rules {
+        game.set_rules(rules);
+    }
+    game.set_suicide_allowed(options.suicide.unwrap_or(false));
+    if let Some(control) = options.time {
+        game.set_time_control(control, ticker.lag_ms);
+    }
+    game.set_time_warnings(options.warn);
+    if let Some(stones) = options.handicap.filter(|stones| *stones > 0) {
+        let placed = if options.free.unwrap_or(false) {
+            game.start_free_handicap(stones)
+        } else {
+            game.place_handicap(stones)
+        };
+        if let Err(err) = placed {
+            tracing::info!(stones, error = ?err, "Invalid handicap");
+            return Err((Status::UnprocessableEntity, Json(err.into())));
+        }
+        // White no longer needs compensating for moving second
+        game.set_komi(0.5);
+    }
+    if let Some(komi) = options.komi.filter(|komi| komi.is_finite()) {
+        game.set_komi(komi);
+    }
+    let host = options.color.map_or(Some(board::Stone::Black), host_stone);
+    game.set_host(host);
+    if options.vs == Some(Opponent::Bot) {
+        game.set_bot(Some(bots::BotSeat::Wanted));
+    }
+    if options.vs == Some(Opponent::Engine) {
+        // The engine only plays White
+        if host != Some(board::Stone::Black) {
+            return Err((
+                Status::UnprocessableEntity,
+                Json(ErrorMessage::new(
+                    "EngineNeedsBlack",
+                    "The engine only plays White, take Black to play it",
+                )),
+            ));
+        }
+        engines.start(game_id).map_err(|err| {
+            tracing::error!(error = ?err, "Failed to start engine");
+            (
+                Status::ServiceUnavailable,
+                Json(ErrorMessage::new(
+                    "EngineUnavailable",
+                    "The engine couldn't be started, try again later",
+                )),
+            )
+        })?;
+        game.seat_guest();
+    }
+    let token = game.issue_host_token();
+    if let Err(err) = record(registry, game_id, game) {
+        // Without a game to play in the engine would never be let go of
+        engines.stop(game_id);
+        return Err(err);
+    }
+    PlayerSession::start(cookies, game_id, host, size as u8, token);
+    // White moves first after a handicap
+    engines.respond(game_id, registry, queue);
+    Ok(game_id)
+}
+
+// check_maintenance refuses to start games while the server is in maintenance mode.
+fn check_maintenance(maintenance: &Maintenance) -> Result<(), (Status, Json<ErrorMessage>)> {
+    match maintenance.announcement() {
+        Some(announcement) => Err((
+            Status::ServiceUnavailable,
+            Json(ErrorMessage::new("Maintenance", announcement)),
+        )),
+        None => Ok(()),
+    }
+}
+
+// start_maintenance announces maintenance to every game and stops new games from starting.
+#[put("/admin/maintenance", format = "application/json", data = "<message>")]
+fn start_maintenance(
+    _admin: Admin,
+    message: Json<MaintenanceMessage>,
+    maintenance: &State<Maintenance>,
+    queue: &State<EventQueue>,
+) -> Json<MaintenanceMessage> {
+    maintenance.start(message.announcement.clone(), registry::now());
+    broadcast(
+        queue,
+        None,
+        GameStateMessage::Maintenance {
+            announcement: Some(message.announcement.clone()),
+        },
+    );
+    message
+}
+
+// end_maintenance lets games be started again and restarts the clocks paused by maintenance.
+#[delete("/admin/maintenance")]
+fn end_maintenance(
+    _admin: Admin,
+    maintenance: &State<Maintenance>,
+    queue: &State<EventQueue>,
+    registry: &State<GameRegistry>,
+) -> Status {
+    if let Some(paused) = maintenance.end() {
+        clock::resume_clocks(registry, queue, paused, registry::now());
+    }
+    broadcast(
+        queue,
+        None,
+        GameStateMessage::Maintenance { announcement: None },
+    );
+    Status::NoContent
+}
+
+// serve_delivery_counts returns what became of the notices sent on each kind of channel, for
+// admins.
+#[get("/admin/notifications")]
+fn serve_delivery_counts(
+    _admin: Admin,
+    dispatcher: &State<dispatcher::Dispatcher>,
+) -> Json<BTreeMap<String, DeliveryCounts>> {
+    Json(dispatcher.counts())
+}
+
+// expire_game drops an abandoned game, from memory and from the store, and tells its subscribers.
+#[delete("/admin/games/<game_id>")]
+fn expire_game(
+    _admin: Admin,
+    game_id: Uuid,
+    registry: &State<GameRegistry>,
+    queue: &State<EventQueue>,
+    span: RequestSpan,
+) -> Result<Status, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    match registry.expire(&game_id) {
+        Ok(true) => {
+            broadcast(
+                queue,
+                Some(game_id),
+                GameStateMessage::GameExpired { archived: false },
+            );
+            queue.forget(&game_id);
+            Ok(Status::NoContent)
+        }
+        Ok(false) => Ok(Status::NotFound),
+        Err(err) => Err(journal_failed(game_id, err)),
+    }
+}
+
+// serve_maintenance returns the current maintenance announcement, if there is one.
+#[get("/maintenance")]
+fn serve_maintenance(maintenance: &State<Maintenance>) -> Option<Json<MaintenanceMessage>> {
+    maintenance
+        .announcement()
+        .map(|announcement| Json(MaintenanceMessage { announcement }))
+}
+
+// serve_health answers liveness probes.
+#[get("/healthz")]
+fn serve_health() -> Json<HealthMessage> {
+    Json(HealthMessage {
+        status: "ok".to_string(),
+    })
+}
+
+// serve_readiness answers readiness probes, with 503 Service Unavailable while the server can't
+// take players.
+#[get("/readyz")]
+fn serve_readiness(
+    registry: &State<GameRegistry>,
+    queue: &State<EventQueue>,
+    span: RequestSpan,
+) -> (Status, Json<ReadinessMessage>) {
+    let _span = span.enter();
+    let storage = match registry.ping() {
+        Ok(()) => true,
+        Err(err) => {
+            tracing::error!(error = ?err, "The game store can't be reached");
+            false
+        }
     };
-    let mut game_cookie = Cookie::named("b");
-    game_cookie.set_value(to_string(&black_game_state).unwrap());
-    cookies.add(game_cookie);
+    let events = queue.relaying();
+    let readiness = ReadinessMessage {
+        ready: storage && events,
+        storage,
+        events,
+        games: registry.active(),
+    };
+    match readiness.ready {
+        true => (Status::Ok, Json(readiness)),
+        false => (Status::ServiceUnavailable, Json(readiness)),
+    }
+}
+
+#[derive(Debug, FromForm)]
+struct ImportForm {
+    sgf: String,
+}
+
+// import_game creates a game from an uploaded SGF record, so games played elsewhere can be
+// reviewed on the board.
+#[post("/import", data = "<form>")]
+fn import_game(
+    form: Form<ImportForm>,
+    cookies: &CookieJar<'_>,
+    registry: &State<GameRegistry>,
+    maintenance: &State<Maintenance>,
+    prefix: &State<Prefix>,
+    sizes: &State<BoardSizes>,
+    span: RequestSpan,
+) -> Result<Redirect, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    check_maintenance(maintenance)?;
+    let mut game = sgf::import(&form.sgf).map_err(|err| {
+        tracing::info!(error = ?err, "Invalid SGF");
+        (Status::UnprocessableEntity, Json(err.into()))
+    })?;
+    check_size(sizes, game.size())?;
+
+    let game_id = Uuid::new_v4();
+    game.id = game_id;
+    let size = game.size();
+    let token = game.issue_host_token();
+    record(registry, game_id, game)?;
+    PlayerSession::start(
+        cookies,
+        game_id,
+        Some(board::Stone::Black),
+        size as u8,
+        token,
+    );
+
+    Ok(prefix.redirect(format!("/{}/game.html", game_id)))
+}
+
+// serve_editor serves the board editor, where a position can be composed stone by stone. Sizes
+// this server doesn't offer open the default size instead.
+#[get("/editor.html?<size>")]
+fn serve_editor(size: Option<board::Size>, sizes: &State<BoardSizes>) -> Template {
+    let size = size
+        .filter(|size| sizes.allows(*size))
+        .unwrap_or(sizes.default_size()) as u8;
+    let board_size = (1..=size).collect::<Vec<_>>();
+    let piece_size = format!("{:.2}", 80.0 / size as f32);
+    Template::render("editor", context! { size, board_size, piece_size })
+}
+
+// setup_game builds a game from a position composed in the board editor.
+fn setup_game(message: SetupMessage) -> Result<board::Game, (Status, Json<ErrorMessage>)> {
+    board::setup(message.size, message.stones, message.turn).map_err(|err| {
+        tracing::info!(error = ?err, "Invalid setup");
+        (Status::UnprocessableEntity, Json(err.into()))
+    })
+}
+
+// export_setup writes a position composed in the board editor as an SGF record.
+#[put("/editor/game.sgf", format = "application/json", data = "<message>")]
+fn export_setup(
+    message: Json<SetupMessage>,
+    span: RequestSpan,
+) -> Result<(ContentType, String), (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let game = setup_game(message.into_inner())?;
+    Ok((
+        ContentType::new("application", "x-go-sgf"),
+        sgf::export(&game),
+    ))
+}
+
+// start_setup starts a live game from a position composed in the board editor, the editor takes
+// the black seat.
+#[put("/editor/games", format = "application/json", data = "<message>")]
+fn start_setup(
+    message: Json<SetupMessage>,
+    cookies: &CookieJar<'_>,
+    registry: &State<GameRegistry>,
+    maintenance: &State<Maintenance>,
+    sizes: &State<BoardSizes>,
+    span: RequestSpan,
+) -> Result<Json<GameCreatedMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    check_maintenance(maintenance)?;
+    check_size(sizes, message.size)?;
+    let mut game = setup_game(message.into_inner())?;
 
-    // Purge any leftover cookies
-    cookies.remove(Cookie::named("w"));
+    let game_id = Uuid::new_v4();
+    game.id = game_id;
+    let size = game.size();
+    let token = game.issue_host_token();
+    record(registry, game_id, game)?;
+    PlayerSession::start(
+        cookies,
+        game_id,
+        Some(board::Stone::Black),
+        size as u8,
+        token,
+    );
 
-    Redirect::to(format!("/{}/game.html", game_id))
+    Ok(Json(GameCreatedMessage { id: game_id }))
 }
 
 #[get("/<game_id>/join.html")]
-fn serve_join_game(game_id: Uuid, cookies: &CookieJar<'_>) -> Template {
-    // Purge any existing cookies
-    cookies.remove(Cookie::named("b"));
-    cookies.remove(Cookie::named("w"));
-    Template::render("join", context! { game_id })
+fn serve_join_game(
+    game_id: Uuid,
+    cookies: &CookieJar<'_>,
+    registry: &State<GameRegistry>,
+) -> Template {
+    // Purge any existing seat in this game
+    PlayerSession::end(cookies, game_id);
+    let (full, guest) = registry
+        .with(&game_id, |game| (game.guest_seated(), game.guest()))
+        .unwrap_or((false, Some(board::Stone::White)));
+    // The player joining sees the stone they will play, or both while it is still to be drawn
+    let pieces = match guest {
+        Some(board::Stone::Black) => vec!["blackpiece"],
+        Some(board::Stone::White) => vec!["whitepiece"],
+        None => vec!["blackpiece", "whitepiece"],
+    };
+    Template::render("join", context! { game_id, full, pieces })
+}
+
+// GameFilter picks the games listed, only games waiting for an opponent can be listed.
+#[derive(Debug, PartialEq, FromFormField)]
+enum GameFilter {
+    Open,
+}
+
+// open_games lists the games waiting for an opponent whose creators are around, newest first.
+fn open_games(registry: &GameRegistry, lobby: &Lobby, prefix: &Prefix) -> Vec<GameEntry> {
+    let open = registry.filter_map(|(id, game)| {
+        // Games waiting for a bot are listed on the bot API
+        if game.guest_seated() || game.phase() == board::GamePhase::Finished || game.bot().is_some()
+        {
+            return None;
+        }
+        let entry = GameEntry {
+            id: *id,
+            size: game.size(),
+            created_at: game.created_at(),
+            join: prefix.path(format!("/{}/join.html", id)),
+        };
+        Some((entry, game.active_at()))
+    });
+    lobby.retain(|game_id| open.iter().any(|(entry, _)| entry.id == *game_id));
+    let now = registry::now();
+    let mut games: Vec<GameEntry> = open
+        .into_iter()
+        .filter(|(entry, active)| lobby.listed(&entry.id, *active, now))
+        .map(|(entry, _)| entry)
+        .collect();
+    games.sort_by_key(|game| std::cmp::Reverse(game.created_at));
+    games
+}
+
+// serve_lobby serves a page listing the games waiting for an opponent, so players can find one
+// without sharing links.
+#[get("/lobby")]
+fn serve_lobby(
+    registry: &State<GameRegistry>,
+    lobby: &State<Lobby>,
+    prefix: &State<Prefix>,
+) -> Template {
+    let games = open_games(registry, lobby, prefix);
+    Template::render("lobby", context! { games })
 }
 
+// list_games lists the games waiting for an opponent, as on the lobby page.
+#[get("/api/games?<state>")]
+fn list_games(
+    state: GameFilter,
+    registry: &State<GameRegistry>,
+    lobby: &State<Lobby>,
+    prefix: &State<Prefix>,
+) -> Json<GameListMessage> {
+    let games = match state {
+        GameFilter::Open => open_games(registry, lobby, prefix),
+    };
+    Json(GameListMessage { games })
+}
+
+// serve_game serves the board to a seated player, anyone else is sent to join the game. The
+// player who created the game is served it before their stones are drawn too.
 #[get("/<game_id>/game.html")]
-fn serve_game(game_id: Uuid, cookies: &CookieJar<'_>) -> Template {
-    if let Some(game_cookie) = cookies.get("b") {
-        let black_game_state: BlackGameState = from_str(game_cookie.value()).unwrap();
+fn serve_game(
+    game_id: Uuid,
+    spectator: Spectator,
+    registry: &State<GameRegistry>,
+    queue: &State<EventQueue>,
+    prefix: &State<Prefix>,
+) -> Result<Result<Template, Redirect>, Status> {
+    let player = match spectator.seat {
+        Some(seat) => Some(seat.stone),
+        None if spectator.host => None,
+        None => return Ok(Err(prefix.redirect(format!("/{}/join.html", game_id)))),
+    };
+    board_page(registry, queue, game_id, player, spectator.host)
+        .map(Ok)
+        .ok_or(Status::NotFound)
+}
 
-        let size = black_game_state.size;
+// serve_watch serves the board read-only, to anyone, seated or not.
+#[get("/<game_id>/watch.html")]
+fn serve_watch(
+    game_id: Uuid,
+    _spectator: Spectator,
+    registry: &State<GameRegistry>,
+    queue: &State<EventQueue>,
+) -> Option<Template> {
+    board_page(registry, queue, game_id, None, false)
+}
 
-        let board_size = (1..=size).collect::<Vec<_>>();
-        let piece_size = format!("{:.2}", 80.0 / size as f32);
-        Template::render(
-            "board",
-            context! { game_id, size, board_size, piece_size, black_player: true },
+// board_page renders the board of a game for a player, or read-only for anyone else unless they
+// created the game.
+fn board_page(
+    registry: &GameRegistry,
+    queue: &EventQueue,
+    game_id: Uuid,
+    player: Option<board::Stone>,
+    host: bool,
+) -> Option<Template> {
+    let (size, board, komi, handicap, rules, suicide, clock) = registry.with(&game_id, |game| {
+        (
+            game.size() as u8,
+            board::encode(game),
+            game.komi(),
+            game.handicap(),
+            format!("{:?}", game.rules()),
+            game.suicide_allowed(),
+            game.clock().cloned(),
         )
-    } else if let Some(game_cookie) = cookies.get("w") {
-        let white_game_state: WhiteGameState = from_str(game_cookie.value()).unwrap();
+    })?;
+    // The running player's time is counted down from how long ago it started
+    let time = clock.as_ref().map(|clock| clock.control().to_string());
+    let clock_elapsed = clock
+        .as_ref()
+        .and_then(|clock| clock.running())
+        .map_or(0, |(_, since)| registry::now().saturating_sub(since));
+    let clock = match clock {
+        Some(clock) => to_string(&clock.message()).unwrap(),
+        None => "null".to_string(),
+    };
+    let watching = player.is_none() && !host;
+    let player = player.map(|stone| format!("{:?}", stone));
+    let spectators = queue.spectators(&game_id);
 
-        let size = white_game_state.size;
+    let board_size = (1..=size).collect::<Vec<_>>();
+    let piece_size = format!("{:.2}", 80.0 / size as f32);
+    Some(Template::render(
+        "board",
+        context! {
+            game_id,
+            size,
+            board_size,
+            piece_size,
+            board,
+            player,
+            host,
+            watching,
+            spectators,
+            komi,
+            handicap,
+            rules,
+            suicide,
+            time,
+            clock,
+            clock_elapsed,
+        },
+    ))
+}
 
-        let board_size = (1..=size).collect::<Vec<_>>();
-        let piece_size = format!("{:.2}", 80.0 / size as f32);
-        Template::render(
-            "board",
-            context! { game_id, size, board_size, piece_size, black_player: false },
-        )
-    } else {
-        unimplemented!("404 here");
-    }
+// record stores the latest position of a game, failing the request if it can't be journaled so
+// a move is never acknowledged and then lost.
+fn record(
+    registry: &GameRegistry,
+    game_id: Uuid,
+    game: board::Game,
+) -> Result<(), (Status, Json<ErrorMessage>)> {
+    registry
+        .insert(game_id, game)
+        .map_err(|err| journal_failed(game_id, err))
+}
+
+fn journal_failed(game_id: Uuid, err: std::io::Error) -> (Status, Json<ErrorMessage>) {
+    tracing::error!(%game_id, error = ?err, "Failed to journal game");
+    (
+        Status::ServiceUnavailable,
+        Json(ErrorMessage::new(
+            "JournalFailed",
+            "The game could not be saved, try again",
+        )),
+    )
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub enum GameStateMessage {
-    Join { id: Uuid },
-    JoinAccepted { id: Uuid, size: u8 },
-    Update { board: String },
+// broadcast posts a message about a game to its subscribers on the SSE queue, or to every game when
+// `game_id` is None. Posting only fails when no one on this server is subscribed, which isn't an
+// error: the event is still numbered and kept for clients that catch up later.
+fn broadcast(queue: &EventQueue, game_id: Option<Uuid>, state: GameStateMessage) {
+    let _ = queue.send(game_id, state);
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct AcceptPlayerMessage {
-    pub size: board::Size,
+// seat_taken refuses a player asking for a seat in a game that already has two players.
+fn seat_taken() -> (Status, Json<ErrorMessage>) {
+    (Status::Conflict, Json(SeatError::SeatTaken.into()))
 }
 
-#[put("/<game_id>/players", format = "application/json", data = "<message>")]
+// accept_player gives the guest's seat to the player waiting to join, the seat is locked so no
+// one else can be accepted. Stones left to chance are drawn now, both players are told the joining
+// player's stone and the host's seat cookie is brought up to date. The game's size was checked
+// when it was created, the size sent along isn't checked again.
+#[put("/<game_id>/players", format = "application/json", data = "<_message>")]
 fn accept_player(
     game_id: Uuid,
-    message: Json<AcceptPlayerMessage>,
-    queue: &State<Sender<GameStateMessage>>,
-) -> Result<Json<GameStateMessage>, Status> {
+    host: Host,
+    _message: Json<AcceptPlayerMessage>,
+    cookies: &CookieJar<'_>,
+    services: Services<'_>,
+    span: RequestSpan,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let Services {
+        registry, queue, ..
+    } = services;
+    let (size, guest, stone) = registry
+        .update(
+            game_id,
+            || Err((Status::NotFound, Json(SeatError::UnknownGame.into()))),
+            |game| {
+                if game.guest_seated() {
+                    return Err(seat_taken());
+                }
+                match game.accept_join() {
+                    Some(guest) => Ok((game.size(), guest, game.host())),
+                    None => Err((Status::Conflict, Json(SeatError::NoJoin.into()))),
+                }
+            },
+        )
+        .map_err(|err| journal_failed(game_id, err))??;
+    PlayerSession::start(cookies, game_id, stone, host.size, host.token);
+
     let state = GameStateMessage::JoinAccepted {
-        id: game_id.clone(),
-        size: message.size as u8,
+        id: game_id,
+        size: size as u8,
+        stone: guest,
     };
-    let result = queue.send(state.clone());
-    if result.is_err() {
-        eprintln!("Failed to post to SSE queue {:?}", result.err());
-        // TODO: 500
-    }
+    broadcast(queue, Some(game_id), state.clone());
+    // Black's time starts once both seats are taken
+    clock::announce(registry, queue, game_id);
     Ok(Json(state))
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct JoinMessage {}
-
-#[put("/<game_id>/joins", format = "application/json", data = "<message>")]
+// request_join gives the player asking to join a pending seat as the guest, which they hold once
+// the host accepts them. Their stone is left out of the cookie while it is still to be drawn.
+#[put("/<game_id>/joins", format = "application/json", data = "<_message>")]
 fn request_join(
     game_id: Uuid,
-    message: Json<JoinMessage>,
-    queue: &State<Sender<GameStateMessage>>,
-) -> Result<Json<GameStateMessage>, Status> {
+    _message: Json<JoinMessage>,
+    cookies: &CookieJar<'_>,
+    queue: &State<EventQueue>,
+    registry: &State<GameRegistry>,
+    span: RequestSpan,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    join_game(game_id, cookies, queue, registry).map(Json)
+}
+
+// api_join asks for the guest's seat of a game for an API client, see request_join.
+#[post("/api/v1/games/<game_id>/join")]
+fn api_join(
+    game_id: Uuid,
+    cookies: &CookieJar<'_>,
+    queue: &State<EventQueue>,
+    registry: &State<GameRegistry>,
+    span: RequestSpan,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    join_game(game_id, cookies, queue, registry).map(Json)
+}
+
+// join_game seats the caller as the guest of a game pending the host's answer, and tells the host.
+fn join_game(
+    game_id: Uuid,
+    cookies: &CookieJar<'_>,
+    queue: &EventQueue,
+    registry: &GameRegistry,
+) -> Result<GameStateMessage, (Status, Json<ErrorMessage>)> {
+    let (size, guest, token) = registry
+        .update(
+            game_id,
+            || Err((Status::NotFound, Json(SeatError::UnknownGame.into()))),
+            |game| match game.request_seat() {
+                // The guest's seat is kept for a bot
+                Some(_) if game.bot().is_some() => Err(seat_taken()),
+                Some(token) => Ok((game.size(), game.guest(), token)),
+                None => Err(seat_taken()),
+            },
+        )
+        .map_err(|err| journal_failed(game_id, err))??;
+    // The seat is pending until the host accepts
+    PlayerSession::start(cookies, game_id, guest, size as u8, token);
+
     let state = GameStateMessage::Join { id: game_id };
-    let result = queue.send(state.clone());
-    if result.is_err() {
-        eprintln!("Failed to post to SSE queue {:?}", result.err());
-        // TODO: 500
+    broadcast(queue, Some(game_id), state.clone());
+    Ok(state)
+}
+
+impl From<board::PlayError> for ErrorMessage {
+    fn from(error: board::PlayError) -> Self {
+        ErrorMessage::new(format!("{:?}", error), error.to_string())
     }
+}
+
+// send_chat adds a line to a game's chat. Players chat with each other, anyone else chats on the
+// spectators' channel. Lines are kept with the game's history.
+#[put("/<game_id>/chat", format = "application/json", data = "<message>")]
+fn send_chat(
+    game_id: Uuid,
+    spectator: Spectator,
+    address: Option<IpAddr>,
+    message: Json<ChatMessage>,
+    services: Services<'_>,
+    limiter: &State<ChatLimiter>,
+    span: RequestSpan,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let Services {
+        registry, queue, ..
+    } = services;
+    let text = chat::check_text(&message.text)
+        .map_err(|err| (Status::UnprocessableEntity, Json(err.into())))?;
+    let from = spectator.seat.map(|seat| seat.stone);
+    let speaker = match (from, address) {
+        (Some(stone), _) => format!("{:?}", stone),
+        (None, Some(address)) => address.to_string(),
+        (None, None) => "unknown".to_string(),
+    };
+    let at = registry::now();
+    limiter
+        .allow(game_id, speaker, at)
+        .map_err(|err| (Status::TooManyRequests, Json(err.into())))?;
+
+    let line = board::ChatEntry { from, text, at };
+    registry
+        .update(
+            game_id,
+            || Err((Status::NotFound, Json(SeatError::UnknownGame.into()))),
+            |game| {
+                game.say(line.clone());
+                Ok(())
+            },
+        )
+        .map_err(|err| journal_failed(game_id, err))??;
+
+    let state = GameStateMessage::Chat {
+        from: line.from,
+        text: line.text,
+        at: line.at,
+    };
+    broadcast(queue, Some(game_id), state.clone());
     Ok(Json(state))
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct PlacePieceMessage {
-    pub board: String,
-    pub coordinate: board::Coordinate,
-    pub stone: board::Stone,
-    pub size: board::Size,
+// serve_chat lists a game's chat so far, so it reloads with the board.
+#[get("/<game_id>/chat")]
+fn serve_chat(
+    game_id: Uuid,
+    _spectator: Spectator,
+    registry: &State<GameRegistry>,
+) -> Option<Json<ChatLogMessage>> {
+    registry.with(&game_id, |game| {
+        Json(ChatLogMessage {
+            lines: game.chat().to_vec(),
+        })
+    })
 }
 
-#[put("/<game_id>/games", format = "application/json", data = "<message>")]
-fn play_piece(
+// set_notify changes where the player is told it has become their turn, by email or webhook, or
+// stops telling them.
+#[put("/<game_id>/notify", format = "application/json", data = "<message>")]
+fn set_notify(
     game_id: Uuid,
-    message: Json<PlacePieceMessage>,
-    queue: &State<Sender<GameStateMessage>>,
-) -> Result<Json<GameStateMessage>, Status> {
-    println!(
-        "Got play {:?}:{:?} = {:?}",
-        message.coordinate, message.stone, message.board
-    );
+    player: PlayerSession,
+    message: Json<NotifyMessage>,
+    registry: &State<GameRegistry>,
+    notifier: &State<notify::Notifier>,
+    span: RequestSpan,
+) -> Result<Json<NotifyMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let contact = message
+        .contact
+        .as_deref()
+        .map(notify::Contact::parse)
+        .transpose()
+        .map_err(|err| (Status::UnprocessableEntity, Json(err.into())))?;
+    if let Some(contact) = &contact {
+        notifier
+            .check(contact)
+            .map_err(|err| (Status::UnprocessableEntity, Json(err.into())))?;
+    }
+    registry
+        .update(
+            game_id,
+            || Err((Status::NotFound, Json(SeatError::UnknownGame.into()))),
+            |game| {
+                game.set_contact(player.stone, contact.clone());
+                Ok(())
+            },
+        )
+        .map_err(|err| journal_failed(game_id, err))??;
+    Ok(Json(NotifyMessage {
+        contact: contact.map(|contact| contact.to_string()),
+    }))
+}
 
-    let mut game = if message.board.is_empty() {
-        println!("Board empty, initialize a new one");
-        board::new(message.size)
-    } else {
-        match board::decode(&message.board) {
-            Ok(game) => game,
-            Err(err) => {
-                println!("Invalid board {:?}, error: {:?}", message.board, err);
-                return Err(Status::UnprocessableEntity);
-            }
-        }
-    };
+// set_time_warnings changes when the player is warned their time is running out, or leaves it to
+// the game again.
+#[put(
+    "/<game_id>/time_warnings",
+    format = "application/json",
+    data = "<message>"
+)]
+fn set_time_warnings(
+    game_id: Uuid,
+    player: PlayerSession,
+    message: Json<TimeWarningsMessage>,
+    registry: &State<GameRegistry>,
+    span: RequestSpan,
+) -> Result<Json<TimeWarningsMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let warnings = message
+        .warnings
+        .as_deref()
+        .map(clock::TimeWarnings::parse)
+        .transpose()
+        .map_err(|err| (Status::UnprocessableEntity, Json(err.into())))?;
+    let chosen = registry
+        .update(
+            game_id,
+            || Err((Status::NotFound, Json(SeatError::UnknownGame.into()))),
+            |game| {
+                game.choose_time_warnings(player.stone, warnings.clone());
+                Ok(game.time_warnings(player.stone))
+            },
+        )
+        .map_err(|err| journal_failed(game_id, err))??;
+    Ok(Json(TimeWarningsMessage {
+        warnings: Some(chosen.to_string()),
+    }))
+}
 
-    dbg!(&game);
+// export_data is what the server keeps about the caller's seats, as a zip archive of their games'
+// records and what was kept about each seat.
+#[get("/my/data.zip")]
+fn export_data(
+    seats: Seats,
+    registry: &State<GameRegistry>,
+    span: RequestSpan,
+) -> Result<(ContentType, Vec<u8>), (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let archive = privacy::export(registry, &seats.0).map_err(|err| {
+        tracing::error!(error = ?err, "Failed to export player data");
+        (
+            Status::InternalServerError,
+            Json(ErrorMessage::new(
+                "ExportFailed",
+                "Your data could not be exported, try again",
+            )),
+        )
+    })?;
+    Ok((ContentType::ZIP, archive))
+}
 
-    if game.play_stone(message.coordinate, message.stone) {
-        println!(
-            "Valid play {:?}:{:?}, new game: {:?}",
-            message.coordinate, message.stone, &game
+// forget_data strips the caller's identity from their finished games, keeping the moves played,
+// and drops the games they created that no one joined. Games still being played have to be
+// finished first. Their seat cookies are removed.
+#[delete("/my/data")]
+fn forget_data(
+    seats: Seats,
+    cookies: &CookieJar<'_>,
+    registry: &State<GameRegistry>,
+    queue: &State<EventQueue>,
+    span: RequestSpan,
+) -> Result<Status, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let dropped = privacy::forget(registry, &seats.0)
+        .map_err(|err| {
+            tracing::error!(error = ?err, "Failed to forget player data");
+            (
+                Status::ServiceUnavailable,
+                Json(ErrorMessage::new(
+                    "JournalFailed",
+                    "Your data could not be forgotten, try again",
+                )),
+            )
+        })?
+        .map_err(|err| (Status::Conflict, Json(err.into())))?;
+    for game_id in dropped {
+        broadcast(
+            queue,
+            Some(game_id),
+            GameStateMessage::GameExpired { archived: false },
         );
-        let state = GameStateMessage::Update {
-            board: board::encode(&game),
-        };
-        let result = queue.send(state.clone());
-        if result.is_err() {
-            eprintln!("Failed to post to SSE queue {:?}", result.err());
-            // TODO: 500
-        }
-        Ok(Json(state))
-    } else {
-        println!("Invalid play {:?}:{:?}", message.coordinate, message.stone);
-        Err(Status::UnprocessableEntity)
+        queue.forget(&game_id);
     }
+    for seat in &seats.0 {
+        PlayerSession::end(cookies, seat.game_id);
+    }
+    Ok(Status::NoContent)
 }
 
-#[get("/<game_id>/events")]
-async fn events(
+// add_webhook has a game's joins, moves and results posted to a URL, for its creator. The secret
+// deliveries are signed with is only ever sent in the answer.
+#[post("/<game_id>/webhooks", format = "application/json", data = "<message>")]
+fn add_webhook(
+    game_id: Uuid,
+    _host: Host,
+    message: Json<WebhookMessage>,
+    registry: &State<GameRegistry>,
+    span: RequestSpan,
+) -> Result<(Status, Json<WebhookCreatedMessage>), (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let hook = webhook::Webhook::new(&message.url)
+        .map_err(|err| (Status::UnprocessableEntity, Json(err.into())))?;
+    registry
+        .update(
+            game_id,
+            || Err((Status::NotFound, Json(SeatError::UnknownGame.into()))),
+            |game| {
+                game.add_webhook(hook.clone())
+                    .map_err(|err| (Status::UnprocessableEntity, Json(err.into())))
+            },
+        )
+        .map_err(|err| journal_failed(game_id, err))??;
+    Ok((
+        Status::Created,
+        Json(WebhookCreatedMessage {
+            url: hook.url,
+            secret: hook.secret,
+        }),
+    ))
+}
+
+// register_bot issues a bot the token it plays on the bot API with, for admins. Registering a bot
+// again replaces its token.
+#[post("/api/v1/bots", format = "application/json", data = "<message>")]
+fn register_bot(
+    _admin: Admin,
+    message: Json<BotMessage>,
+    bots: &State<bots::Bots>,
+) -> Result<Json<BotTokenMessage>, (Status, Json<ErrorMessage>)> {
+    let token = bots
+        .register(&message.name)
+        .map_err(|err| (Status::UnprocessableEntity, Json(err.into())))?;
+    Ok(Json(BotTokenMessage {
+        name: message.name.clone(),
+        token,
+    }))
+}
+
+// list_bot_games lists the games waiting for a bot to take the guest's seat, newest first.
+#[get("/api/v1/games")]
+fn list_bot_games(
+    _bot: bots::Bot,
+    registry: &State<GameRegistry>,
+    prefix: &State<Prefix>,
+) -> Json<GameListMessage> {
+    let mut games = registry.filter_map(|(id, game)| {
+        if game.bot() != Some(&bots::BotSeat::Wanted)
+            || game.guest_seated()
+            || game.phase() == board::GamePhase::Finished
+        {
+            return None;
+        }
+        Some(GameEntry {
+            id: *id,
+            size: game.size(),
+            created_at: game.created_at(),
+            join: prefix.path(format!("/api/v1/games/{}/seat", id)),
+        })
+    });
+    games.sort_by_key(|game| std::cmp::Reverse(game.created_at));
+    Json(GameListMessage { games })
+}
+
+// seat_bot gives a bot the guest's seat of a game waiting for one, there is no host to accept it.
+#[post("/api/v1/games/<game_id>/seat")]
+fn seat_bot(
+    game_id: Uuid,
+    bot: bots::Bot,
+    queue: &State<EventQueue>,
+    registry: &State<GameRegistry>,
+    span: RequestSpan,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let (size, stone) = registry
+        .update(
+            game_id,
+            || Err((Status::NotFound, Json(SeatError::UnknownGame.into()))),
+            |game| {
+                bots::seat(game, &bot.name)
+                    .map(|stone| (game.size(), stone))
+                    .map_err(|err| (Status::Conflict, Json(err.into())))
+            },
+        )
+        .map_err(|err| journal_failed(game_id, err))??;
+
+    let state = GameStateMessage::JoinAccepted {
+        id: game_id,
+        size: size as u8,
+        stone,
+    };
+    broadcast(queue, Some(game_id), state.clone());
+    clock::announce(registry, queue, game_id);
+    Ok(Json(state))
+}
+
+// api_state returns everything about a game as it stands, see serve_state.
+#[get("/api/v1/games/<game_id>")]
+fn api_state(game_id: Uuid, registry: &State<GameRegistry>) -> Option<Json<GameSnapshotMessage>> {
+    registry.with(&game_id, |game| Json(game_snapshot(game)))
+}
+
+// api_events streams a game's events to API clients and bots as newline delimited JSON, starting
+// with the whole board. The stream ends once the game expires.
+#[get("/api/v1/games/<game_id>/events")]
+fn api_events(
     game_id: Uuid,
-    queue: &State<Sender<GameStateMessage>>,
+    queue: &State<EventQueue>,
+    registry: &State<GameRegistry>,
     mut end: Shutdown,
-) -> EventStream![] {
+    span: RequestSpan,
+) -> Option<(ContentType, TextStream![String])> {
+    let _span = span.enter();
+    let (queue, registry) = (queue.inner().clone(), registry.inner().clone());
+    // Subscribe before reading the board, so nothing is missed in between
     let mut rx = queue.subscribe();
-    EventStream! {
+    let first = resync(&queue, &registry, game_id)?;
+    let stream = TextStream! {
+        let mut seen = first.seq;
+        yield format!("{}\n", event_payload(&first, PROTOCOL_VERSION).unwrap());
         loop {
             let msg = select! {
                 msg = rx.recv() => match msg {
                     Ok(msg) => msg,
                     Err(RecvError::Closed) => break,
-                    Err(RecvError::Lagged(_)) => continue,
+                    // Events were dropped while the client fell behind
+                    Err(RecvError::Lagged(_)) => match resync(&queue, &registry, game_id) {
+                        Some(msg) => msg,
+                        None => break,
+                    },
                 },
                 _ = &mut end => break,
             };
+            if !msg.concerns(&game_id) {
+                continue;
+            }
+            if msg.game_id.is_some() {
+                if msg.seq <= seen {
+                    continue;
+                }
+                seen = msg.seq;
+            }
+            yield format!("{}\n", event_payload(&msg, PROTOCOL_VERSION).unwrap());
+            if let GameStateMessage::GameExpired { .. } = msg.state {
+                break;
+            }
+        }
+    };
+    Some((ContentType::new("application", "x-ndjson"), stream))
+}
+
+// api_move makes a move in a game for the player seated by their cookie, or for the bot playing
+// it, returning the event for it. Moves are vertices such as `d4`, or `pass`, `resign` and
+// `accept` to agree to the marked score.
+#[put(
+    "/api/v1/games/<game_id>/moves",
+    format = "application/json",
+    data = "<message>"
+)]
+fn api_move(
+    game_id: Uuid,
+    bot: Option<bots::Bot>,
+    player: Option<PlayerSession>,
+    message: Json<TurnMessage>,
+    services: Services<'_>,
+    span: RequestSpan,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let seat = Seat { bot, player };
+    take_turn(
+        game_id,
+        &seat,
+        |size| turn::Turn::parse(size, &message.vertex),
+        &services,
+    )
+}
+
+// api_pass passes for the player or bot, see api_move.
+#[post("/api/v1/games/<game_id>/pass")]
+fn api_pass(
+    game_id: Uuid,
+    bot: Option<bots::Bot>,
+    player: Option<PlayerSession>,
+    services: Services<'_>,
+    span: RequestSpan,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let seat = Seat { bot, player };
+    take_turn(game_id, &seat, |_| Ok(turn::Turn::Pass), &services)
+}
+
+// api_resign gives up the game for the player or bot, see api_move.
+#[post("/api/v1/games/<game_id>/resign")]
+fn api_resign(
+    game_id: Uuid,
+    bot: Option<bots::Bot>,
+    player: Option<PlayerSession>,
+    services: Services<'_>,
+    span: RequestSpan,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let seat = Seat { bot, player };
+    take_turn(game_id, &seat, |_| Ok(turn::Turn::Resign), &services)
+}
+
+// Seat is whoever is moving on the API, a bot by its token or a player by their seat cookie.
+struct Seat {
+    bot: Option<bots::Bot>,
+    player: Option<PlayerSession>,
+}
 
-            yield Event::json(&msg);
+impl Seat {
+    // stone finds the stone the seat plays in a game.
+    fn stone(&self, game: &board::Game) -> Result<board::Stone, (Status, Json<ErrorMessage>)> {
+        match (&self.bot, &self.player) {
+            (Some(bot), _) => {
+                bots::stone_of(game, &bot.name).map_err(|err| (Status::Forbidden, Json(err.into())))
+            }
+            (None, Some(player)) => Ok(player.stone),
+            (None, None) => Err((Status::Forbidden, Json(SeatError::NoSeat.into()))),
         }
     }
 }
 
-pub fn rocket() -> Rocket<Build> {
-    let config = rocket::Config::figment().merge(("port", 8080));
+// take_turn plays the turn chosen for a seat in a game, on a board of the game's size, and tells
+// everyone about it.
+fn take_turn(
+    game_id: Uuid,
+    seat: &Seat,
+    choose: impl FnOnce(board::Size) -> Result<turn::Turn, board::CoordinateError>,
+    services: &Services<'_>,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let Services {
+        registry,
+        queue,
+        engines,
+        ..
+    } = *services;
+    let events = registry
+        .update(
+            game_id,
+            || Err((Status::NotFound, Json(SeatError::UnknownGame.into()))),
+            |game| {
+                let stone = seat.stone(game)?;
+                let chosen = choose(game.size())
+                    .map_err(|err| (Status::UnprocessableEntity, Json(err.into())))?;
+                turn::take(game, stone, chosen)
+                    .map_err(|err| (Status::UnprocessableEntity, Json(err.into())))
+            },
+        )
+        .map_err(|err| journal_failed(game_id, err))??;
+    for state in &events {
+        broadcast(queue, Some(game_id), state.clone());
+    }
+    clock::announce(registry, queue, game_id);
+    engines.respond(game_id, registry, queue);
+    Ok(Json(events.into_iter().next().unwrap()))
+}
 
-    rocket::custom(config)
-        .attach(static_resources_initializer!(
-                "index" => "site/index.html",
+impl From<board::HandicapError> for ErrorMessage {
+    fn from(error: board::HandicapError) -> Self {
+        ErrorMessage::new("InvalidHandicap", error.to_string())
+    }
+}
 
-                "favicon" => "site/images/favicon.ico",
-                "favicon-16x16.png" => "site/images/favicon-16x16.png",
-                "favicon-32x32.png" => "site/images/favicon-32x32.png",
-                "android-chrome-192x192.png" => "site/images/android-chrome-192x192.png",
-                "android-chrome-512x512.png" => "site/images/android-chrome-512x512.png",
-                "apple-touch-icon.png" => "site/images/apple-touch-icon.png",
+impl From<clock::TimeControlError> for ErrorMessage {
+    fn from(error: clock::TimeControlError) -> Self {
+        let code = match error {
+            clock::TimeControlError::BadWarnings(_) => "InvalidTimeWarnings",
+            _ => "InvalidTimeControl",
+        };
+        ErrorMessage::new(code, error.to_string())
+    }
+}
 
-                "blackpiece.png" => "site/images/blackpiece.png",
-                "whitepiece.png" => "site/images/whitepiece.png",
-                "tilecenter.png" => "site/images/tilecenter.png",
-                "join.png" => "site/images/join.png",
-        ))
-        .attach(Template::custom(move |engines| {
-            engines.handlebars.set_strict_mode(true);
-        }))
-        .manage(channel::<GameStateMessage>(1024).0)
-        .mount(
-            "/",
-            routes![
-                redirect_to_root,
-                serve_static_favicon,
-                serve_static_image,
-                serve_index,
-                serve_new_game,
-                serve_join_game,
-                serve_game,
-                accept_player,
-                request_join,
-                play_piece,
-                events
-            ],
-        )
+impl From<bots::BotError> for ErrorMessage {
+    fn from(error: bots::BotError) -> Self {
+        ErrorMessage::new(format!("{:?}", error), error.to_string())
+    }
+}
+
+impl From<webhook::WebhookError> for ErrorMessage {
+    fn from(error: webhook::WebhookError) -> Self {
+        ErrorMessage::new(format!("{:?}", error), error.to_string())
+    }
+}
+
+impl From<notify::NotifyError> for ErrorMessage {
+    fn from(error: notify::NotifyError) -> Self {
+        ErrorMessage::new(format!("{:?}", error), error.to_string())
+    }
+}
+
+impl From<privacy::PrivacyError> for ErrorMessage {
+    fn from(error: privacy::PrivacyError) -> Self {
+        match error {
+            privacy::PrivacyError::Unfinished(_) => {
+                ErrorMessage::new("Unfinished", error.to_string())
+            }
+        }
+    }
+}
+
+impl From<ChatError> for ErrorMessage {
+    fn from(error: ChatError) -> Self {
+        ErrorMessage::new(format!("{:?}", error), error.to_string())
+    }
+}
+
+impl From<ledger::LedgerError> for ErrorMessage {
+    fn from(error: ledger::LedgerError) -> Self {
+        ErrorMessage::new("BadLedger", error.to_string())
+    }
+}
+
+impl From<SeatError> for ErrorMessage {
+    fn from(error: SeatError) -> Self {
+        ErrorMessage::new(format!("{:?}", error), error.to_string())
+    }
+}
+
+impl From<board::CoordinateError> for ErrorMessage {
+    fn from(error: board::CoordinateError) -> Self {
+        ErrorMessage::new("InvalidCoordinate", error.to_string())
+    }
+}
+
+impl From<sgf::SgfError> for ErrorMessage {
+    fn from(error: sgf::SgfError) -> Self {
+        ErrorMessage::new("InvalidSgf", error.to_string())
+    }
+}
+
+impl From<board::SetupError> for ErrorMessage {
+    fn from(error: board::SetupError) -> Self {
+        ErrorMessage::new("InvalidSetup", error.to_string())
+    }
+}
+
+impl From<board::BoardParseError> for ErrorMessage {
+    fn from(error: board::BoardParseError) -> Self {
+        use board::BoardParseError::*;
+        let reason = match error {
+            MissingSegment(_) => "MissingSegment",
+            BadGameId(_) => "BadGameId",
+            BadSize(_) => "BadSize",
+            BadTurn(_) => "BadTurn",
+            BadTile { .. } => "BadTile",
+            WrongLength { .. } => "WrongLength",
+            BadSegment(_) => "BadSegment",
+            ImpossiblePosition(_) => "ImpossiblePosition",
+        };
+        ErrorMessage::new(reason, error.to_string())
+    }
+}
+
+// apply makes a change to the server's copy of a game, games it doesn't know are refused.
+fn apply<R>(
+    registry: &GameRegistry,
+    game_id: Uuid,
+    change: impl FnOnce(&mut board::Game) -> Result<R, (Status, Json<ErrorMessage>)>,
+) -> Result<R, (Status, Json<ErrorMessage>)> {
+    registry
+        .update(
+            game_id,
+            || Err((Status::NotFound, Json(SeatError::UnknownGame.into()))),
+            change,
+        )
+        .map_err(|err| journal_failed(game_id, err))?
+}
+
+// resolve_coordinate picks the position a client sent, either as a coordinate or as a vertex in
+// letter + number notation.
+fn resolve_coordinate(
+    coordinate: Option<board::Coordinate>,
+    vertex: &Option<String>,
+    size: board::Size,
+) -> Result<board::Coordinate, (Status, Json<ErrorMessage>)> {
+    match (coordinate, vertex) {
+        (Some(coordinate), _) => Ok(coordinate),
+        (None, Some(vertex)) => board::parse_coordinate(size, vertex)
+            .map_err(|err| (Status::UnprocessableEntity, Json(err.into()))),
+        (None, None) => Err((
+            Status::UnprocessableEntity,
+            Json(board::CoordinateError::Empty.into()),
+        )),
+    }
+}
+
+#[put("/<game_id>/games", format = "application/json", data = "<message>")]
+fn play_piece(
+    game_id: Uuid,
+    player: PlayerSession,
+    message: Json<PlacePieceMessage>,
+    services: Services<'_>,
+    span: RequestSpan,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let Services {
+        registry,
+        queue,
+        engines,
+        ..
+    } = services;
+    tracing::debug!(
+        coordinate = ?message.coordinate,
+        vertex = ?message.vertex,
+        "Got play"
+    );
+
+    // Clients showing part of the board send coordinates relative to their viewport
+    let (origin_x, origin_y) = message.origin.unwrap_or((0, 0));
+    let relative = message
+        .coordinate
+        .map(|(x, y)| (x.saturating_add(origin_x), y.saturating_add(origin_y)));
+
+    let (state, phase, new_phase) = apply(registry, game_id, |game| {
+        let coordinate = resolve_coordinate(relative, &message.vertex, game.size())?;
+        let phase = game.phase();
+        let played = LedgerEvent::StonePlayed {
+            stone: player.stone,
+            position: coordinate,
+        };
+        let captured = ledger::apply(game, &played).map_err(|err| {
+            tracing::info!(?coordinate, error = ?err, "Invalid play");
+            (Status::UnprocessableEntity, Json(err.into()))
+        })?;
+        tracing::debug!(?coordinate, ?game, "Valid play");
+        let state = turn::update(game, captured);
+        Ok((state, phase, game.phase()))
+    })?;
+
+    broadcast(queue, Some(game_id), state.clone());
+    if new_phase != phase {
+        broadcast(
+            queue,
+            Some(game_id),
+            GameStateMessage::Phase { phase: new_phase },
+        );
+    }
+    clock::announce(registry, queue, game_id);
+    engines.respond(game_id, registry, queue);
+    Ok(Json(state))
+}
+
+#[put("/<game_id>/pass", format = "application/json", data = "<_message>")]
+fn pass(
+    game_id: Uuid,
+    player: PlayerSession,
+    _message: Json<PassMessage>,
+    services: Services<'_>,
+    span: RequestSpan,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let Services {
+        registry,
+        queue,
+        engines,
+        ..
+    } = services;
+    let (state, phase, new_phase, score) = apply(registry, game_id, |game| {
+        let phase = game.phase();
+        let passed = LedgerEvent::Passed {
+            stone: player.stone,
+        };
+        ledger::apply(game, &passed).map_err(|err| {
+            tracing::info!(error = ?err, "Invalid pass");
+            (Status::UnprocessableEntity, Json(err.into()))
+        })?;
+        let state = GameStateMessage::Pass {
+            stone: player.stone,
+            board: board::encode(game),
+        };
+        Ok((state, phase, game.phase(), game.score()))
+    })?;
+    broadcast(queue, Some(game_id), state.clone());
+
+    if new_phase != phase {
+        broadcast(
+            queue,
+            Some(game_id),
+            GameStateMessage::Phase { phase: new_phase },
+        );
+        if new_phase == board::GamePhase::Scoring {
+            broadcast(queue, Some(game_id), GameStateMessage::Score { score });
+        }
+    }
+    clock::announce(registry, queue, game_id);
+
+    engines.respond(game_id, registry, queue);
+
+    Ok(Json(state))
+}
+
+// resign gives up the game for the player, their opponent wins.
+#[put("/<game_id>/resign", format = "application/json", data = "<_message>")]
+fn resign(
+    game_id: Uuid,
+    player: PlayerSession,
+    _message: Json<ResignMessage>,
+    queue: &State<EventQueue>,
+    registry: &State<GameRegistry>,
+    span: RequestSpan,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let state = apply(registry, game_id, |game| {
+        let resigned = LedgerEvent::Resigned {
+            stone: player.stone,
+        };
+        ledger::apply(game, &resigned).map_err(|err| {
+            tracing::info!(error = ?err, "Invalid resignation");
+            (Status::UnprocessableEntity, Json(err.into()))
+        })?;
+        Ok(GameStateMessage::Resign {
+            stone: player.stone,
+            board: board::encode(game),
+        })
+    })?;
+    broadcast(queue, Some(game_id), state.clone());
+    broadcast(
+        queue,
+        Some(game_id),
+        GameStateMessage::Phase {
+            phase: board::GamePhase::Finished,
+        },
+    );
+    clock::announce(registry, queue, game_id);
+
+    Ok(Json(state))
+}
+
+#[put(
+    "/<game_id>/dead_stones",
+    format = "application/json",
+    data = "<message>"
+)]
+fn dead_stones(
+    game_id: Uuid,
+    _player: PlayerSession,
+    message: Json<DeadStonesMessage>,
+    queue: &State<EventQueue>,
+    registry: &State<GameRegistry>,
+    span: RequestSpan,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let (state, score) = apply(registry, game_id, |game| {
+        let coordinate = resolve_coordinate(message.coordinate, &message.vertex, game.size())?;
+        let toggled = LedgerEvent::DeadToggled {
+            position: coordinate,
+        };
+        ledger::apply(game, &toggled).map_err(|err| {
+            tracing::info!(?coordinate, error = ?err, "Invalid dead stone");
+            (Status::UnprocessableEntity, Json(err.into()))
+        })?;
+        let state = GameStateMessage::Dead {
+            board: board::encode(game),
+            dead: game.dead(),
+        };
+        Ok((state, game.score()))
+    })?;
+    broadcast(queue, Some(game_id), state.clone());
+    broadcast(queue, Some(game_id), GameStateMessage::Score { score });
+
+    Ok(Json(state))
+}
+
+#[put(
+    "/<game_id>/accept_score",
+    format = "application/json",
+    data = "<_message>"
+)]
+fn accept_score(
+    game_id: Uuid,
+    player: PlayerSession,
+    _message: Json<AcceptScoreMessage>,
+    services: Services<'_>,
+    span: RequestSpan,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let Services {
+        registry,
+        queue,
+        engines,
+        ..
+    } = services;
+    let (state, phase, score) = apply(registry, game_id, |game| {
+        let accepted = LedgerEvent::ScoreAccepted {
+            stone: player.stone,
+        };
+        ledger::apply(game, &accepted).map_err(|err| {
+            tracing::info!(error = ?err, "Invalid score acceptance");
+            (Status::UnprocessableEntity, Json(err.into()))
+        })?;
+        let state = GameStateMessage::Accept {
+            stone: player.stone,
+            board: board::encode(game),
+        };
+        Ok((state, game.phase(), game.score()))
+    })?;
+    broadcast(queue, Some(game_id), state.clone());
+
+    // The score is final once both players have accepted it
+    if phase == board::GamePhase::Finished {
+        broadcast(queue, Some(game_id), GameStateMessage::Phase { phase });
+        broadcast(queue, Some(game_id), GameStateMessage::Score { score });
+    }
+
+    engines.respond(game_id, registry, queue);
+
+    Ok(Json(state))
+}
+
+#[put(
+    "/<game_id>/undo_request",
+    format = "application/json",
+    data = "<_message>"
+)]
+fn undo_request(
+    game_id: Uuid,
+    player: PlayerSession,
+    _message: Json<UndoRequestMessage>,
+    queue: &State<EventQueue>,
+    registry: &State<GameRegistry>,
+    span: RequestSpan,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let state = apply(registry, game_id, |game| {
+        let requested = LedgerEvent::UndoRequested {
+            stone: player.stone,
+        };
+        ledger::apply(game, &requested).map_err(|err| {
+            tracing::info!(error = ?err, "Invalid undo request");
+            (Status::UnprocessableEntity, Json(err.into()))
+        })?;
+        Ok(GameStateMessage::UndoRequest {
+            stone: player.stone,
+            board: board::encode(game),
+        })
+    })?;
+    broadcast(queue, Some(game_id), state.clone());
+
+    Ok(Json(state))
+}
+
+#[put(
+    "/<game_id>/undo_accept",
+    format = "application/json",
+    data = "<message>"
+)]
+fn undo_accept(
+    game_id: Uuid,
+    player: PlayerSession,
+    message: Json<UndoAcceptMessage>,
+    queue: &State<EventQueue>,
+    registry: &State<GameRegistry>,
+    span: RequestSpan,
+) -> Result<Json<GameStateMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let (state, phase, new_phase) = apply(registry, game_id, |game| {
+        let phase = game.phase();
+        let answered = LedgerEvent::UndoAnswered {
+            stone: player.stone,
+            accepted: message.accepted,
+        };
+        ledger::apply(game, &answered).map_err(|err| {
+            tracing::info!(error = ?err, "Invalid undo answer");
+            (Status::UnprocessableEntity, Json(err.into()))
+        })?;
+        let state = GameStateMessage::Undo {
+            accepted: message.accepted,
+            board: board::encode(game),
+        };
+        Ok((state, phase, game.phase()))
+    })?;
+    broadcast(queue, Some(game_id), state.clone());
+    if new_phase != phase {
+        broadcast(
+            queue,
+            Some(game_id),
+            GameStateMessage::Phase { phase: new_phase },
+        );
+    }
+    clock::announce(registry, queue, game_id);
+
+    Ok(Json(state))
+}
+
+// serve_sgf lets players download a game record to open in other Go software.
+#[get("/<game_id>/game.sgf")]
+fn serve_sgf(
+    game_id: Uuid,
+    _spectator: Spectator,
+    registry: &State<GameRegistry>,
+) -> Option<(ContentType, String)> {
+    let sgf = registry.with(&game_id, sgf::export)?;
+    Some((ContentType::new("application", "x-go-sgf"), sgf))
+}
+
+// serve_protocol_schema describes the events on a game's event stream as JSON Schema.
+#[get("/protocol.json")]
+fn serve_protocol_schema() -> Json<rustigo_protocol::RootSchema> {
+    Json(rustigo_protocol::schema())
+}
+
+// MINIMAP_BLOCK is the number of points along each side of a minimap block.
+const MINIMAP_BLOCK: u8 = 3;
+
+// serve_viewport returns a region of the board and a minimap of the whole board, for phones too
+// small to show a 19x19 board. The region is moved back onto the board if it hangs off an edge.
+#[get("/<game_id>/viewport?<x>&<y>&<width>&<height>")]
+fn serve_viewport(
+    game_id: Uuid,
+    _spectator: Spectator,
+    x: u8,
+    y: u8,
+    width: u8,
+    height: u8,
+    registry: &State<GameRegistry>,
+) -> Option<Json<ViewportMessage>> {
+    registry.with(&game_id, |game| {
+        let size = game.size() as u8;
+        let (width, height) = (width.clamp(1, size), height.clamp(1, size));
+        let origin = (x.min(size - width) as i8, y.min(size - height) as i8);
+        Json(ViewportMessage {
+            origin,
+            width,
+            height,
+            tiles: game.tiles(origin, width, height),
+            turn: game.turn(),
+            block: MINIMAP_BLOCK,
+            minimap: game.minimap(MINIMAP_BLOCK),
+        })
+    })
+}
+
+// serve_state returns everything about a game as it stands.
+#[get("/<game_id>/state")]
+fn serve_state(
+    game_id: Uuid,
+    _spectator: Spectator,
+    registry: &State<GameRegistry>,
+) -> Option<Json<GameSnapshotMessage>> {
+    registry.with(&game_id, |game| Json(game_snapshot(game)))
+}
+
+// game_snapshot describes a game in full for clients.
+fn game_snapshot(game: &board::Game) -> GameSnapshotMessage {
+    let size = game.size() as u8;
+    let tiles = game.tiles((0, 0), size, size);
+    GameSnapshotMessage {
+        id: game.id,
+        size: game.size(),
+        tiles: (0..size as usize)
+            .map(|row| tiles[row * size as usize..(row + 1) * size as usize].to_string())
+            .collect(),
+        board: board::encode(game),
+        turn: game.turn(),
+        phase: game.phase(),
+        move_number: game.move_number(),
+        black_prisoners: game.prisoners(board::Stone::Black),
+        white_prisoners: game.prisoners(board::Stone::White),
+        komi: game.komi(),
+        handicap: game.handicap(),
+        rules: game.rules(),
+        last_move: game.last_move().map(|(position, _)| position),
+        ko: game.ko_point(),
+        dead: game.dead(),
+        score: match game.phase() {
+            board::GamePhase::Scoring | board::GamePhase::Finished => Some(game.score()),
+            _ => None,
+        },
+    }
+}
+
+// MOVES_PAGE is how many moves are listed at once unless a client asks for another amount, up to
+// MAX_MOVES_PAGE.
+const MOVES_PAGE: usize = 100;
+const MAX_MOVES_PAGE: usize = 500;
+
+// serve_moves lists a game's moves a page at a time, `from` is the number of the first move
+// listed, counting from 1.
+#[get("/<game_id>/moves?<from>&<limit>")]
+fn serve_moves(
+    game_id: Uuid,
+    _spectator: Spectator,
+    from: Option<usize>,
+    limit: Option<usize>,
+    registry: &State<GameRegistry>,
+) -> Option<Json<MoveListMessage>> {
+    let skip = from.unwrap_or(1).max(1) - 1;
+    let limit = limit.unwrap_or(MOVES_PAGE).clamp(1, MAX_MOVES_PAGE);
+    registry.with(&game_id, |game| {
+        let history = game.history();
+        let moves = history
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .take(limit)
+            .map(|(index, played)| MoveEntry {
+                number: index + 1,
+                stone: played.stone,
+                action: played.action,
+                vertex: match played.action {
+                    board::Action::Play(position) | board::Action::Handicap(position) => {
+                        Some(board::format_coordinate(game.size(), position))
+                    }
+                    board::Action::Pass => None,
+                },
+                captured: played.captured.clone(),
+                played_at: played.played_at,
+            })
+            .collect();
+        Json(MoveListMessage {
+            total: history.len(),
+            moves,
+            next: (skip + limit < history.len()).then(|| skip + limit + 1),
+        })
+    })
+}
+
+// serve_ledger lists everything that happened in a game, for auditing it or going back over it.
+// `upto` stops after that many entries, along with the board as it was then. Games the server
+// hasn't changed since it started keeping ledgers have none.
+#[get("/<game_id>/ledger?<upto>")]
+fn serve_ledger(
+    game_id: Uuid,
+    _spectator: Spectator,
+    upto: Option<usize>,
+    registry: &State<GameRegistry>,
+    span: RequestSpan,
+) -> Result<Json<LedgerMessage>, (Status, Json<ErrorMessage>)> {
+    let _span = span.enter();
+    let (entries, board) = registry
+        .with(&game_id, |game| {
+            let ledger = game.ledger();
+            let upto = upto.unwrap_or(ledger.len()).min(ledger.len());
+            (ledger[..upto].to_vec(), board::encode(game))
+        })
+        .ok_or_else(|| (Status::NotFound, Json(SeatError::UnknownGame.into())))?;
+    if entries.is_empty() {
+        return Ok(Json(LedgerMessage { entries, board }));
+    }
+    let game = ledger::fold(&entries).map_err(|err| {
+        tracing::error!(error = ?err, "Failed to fold the ledger");
+        (Status::InternalServerError, Json(err.into()))
+    })?;
+    Ok(Json(LedgerMessage {
+        entries,
+        board: board::encode(&game),
+    }))
+}
+
+// serve_legal_moves lists where a player may place a stone, the player to move by default.
+#[get("/<game_id>/legal_moves?<stone>")]
+fn serve_legal_moves(
+    game_id: Uuid,
+    _spectator: Spectator,
+    stone: Option<board::Stone>,
+    registry: &State<GameRegistry>,
+) -> Option<Json<Vec<board::Coordinate>>> {
+    registry.with(&game_id, |game| {
+        Json(game.legal_moves(stone.unwrap_or_else(|| game.turn())))
+    })
+}
+
+// preview_play tries a play on a copy of the game, so clients can show what it would do without
+// knowing the rules. Nothing is committed or broadcast.
+#[post("/<game_id>/preview", format = "application/json", data = "<message>")]
+fn preview_play(
+    game_id: Uuid,
+    _spectator: Spectator,
+    message: Json<PreviewMessage>,
+    registry: &State<GameRegistry>,
+) -> Result<Json<PreviewResultMessage>, (Status, Json<ErrorMessage>)> {
+    let mut game = registry
+        .with(&game_id, board::Game::clone)
+        .ok_or((Status::NotFound, Json(SeatError::UnknownGame.into())))?;
+    let coordinate = resolve_coordinate(message.coordinate, &message.vertex, game.size())?;
+    let stone = message.stone.unwrap_or_else(|| game.turn());
+
+    let preview = match game.play_stone(coordinate, stone) {
+        Ok(outcome) => PreviewResultMessage {
+            legal: true,
+            board: board::encode(&game),
+            captured: outcome.captured,
+            atari: game.stones_in_atari(),
+            error: None,
+        },
+        Err(err) => PreviewResultMessage {
+            legal: false,
+            board: board::encode(&game),
+            captured: Vec::new(),
+            atari: game.stones_in_atari(),
+            error: Some(ErrorMessage::from(err).error),
+        },
+    };
+    Ok(Json(preview))
+}
+
+#[get("/<game_id>/board.txt")]
+fn serve_board_text(
+    game_id: Uuid,
+    _spectator: Spectator,
+    registry: &State<GameRegistry>,
+) -> Option<String> {
+    registry.with(&game_id, |game| game.to_string())
+}
+
+// DEFAULT_EVENT_BUDGET is the most bytes an event should take on the wire, mobile clients on
+// cellular connections receive every event of a game. It can be changed with `event_budget`.
+const DEFAULT_EVENT_BUDGET: usize = 1024;
+
+// Streaming is how events are sent on event streams: events bigger than `budget` bytes are logged,
+// and `chaos` may drop events or close streams on purpose.
+pub struct Streaming {
+    budget: usize,
+    chaos: chaos::Chaos,
+}
+
+// PROTOCOL_VERSION is the newest event schema, clients that don't ask for a version are assumed
+// to understand version 1: Join, JoinAccepted and Update with only a board. From version 3 every
+// event is named by its type, which EventSource only delivers to listeners for that name. From
+// version 4 events are tagged with their `type` and carry their game and sequence number, older
+// versions are sent events wrapped in an object named after the event, e.g. `{"Pass": {...}}`.
+const PROTOCOL_VERSION: u8 = 4;
+
+// NAMED_EVENTS is the first protocol version whose events are named on the SSE stream.
+const NAMED_EVENTS: u8 = 3;
+
+// event_payload serializes a message as compact JSON for the SSE stream, downgrading it for
+// clients on an older protocol version. Returns None if the client has no equivalent event.
+fn event_payload(msg: &GameEvent, protocol: u8) -> Option<String> {
+    let state = &msg.state;
+    if protocol >= 4 {
+        let numbered = GameEventMessage {
+            game_id: msg.game_id,
+            seq: msg.seq,
+            state: state.clone(),
+        };
+        return Some(to_string(&numbered).unwrap());
+    }
+    // Version 3 names the events and resyncs clients that fall behind, version 2 clients are
+    // sent the board as an update instead
+    if let (GameStateMessage::Resync { board }, 2) = (state, protocol) {
+        let update = GameStateMessage::Update {
+            board: board.clone(),
+            position: None,
+            stone: None,
+            captured: Vec::new(),
+            atari: Vec::new(),
+            black_prisoners: 0,
+            white_prisoners: 0,
+            move_number: 0,
+            turn: None,
+        };
+        return Some(legacy_payload(&update));
+    }
+    if protocol >= 2 {
+        return Some(legacy_payload(state));
+    }
+
+    match state {
+        GameStateMessage::Join { .. } | GameStateMessage::JoinAccepted { .. } => {
+            Some(legacy_payload(state))
+        }
+        // Anything that changes the board is an Update to a version 1 client
+        GameStateMessage::Update { board, .. }
+        | GameStateMessage::Pass { board, .. }
+        | GameStateMessage::Dead { board, .. }
+        | GameStateMessage::Accept { board, .. }
+        | GameStateMessage::Undo { board, .. }
+        | GameStateMessage::Resign { board, .. }
+        | GameStateMessage::Timeout { board, .. }
+        | GameStateMessage::Resync { board } => {
+            Some(to_string(&json!({ "Update": { "board": board } })).unwrap())
+        }
+        GameStateMessage::Phase { .. }
+        | GameStateMessage::Score { .. }
+        | GameStateMessage::UndoRequest { .. }
+        | GameStateMessage::Maintenance { .. }
+        | GameStateMessage::Spectators { .. }
+        | GameStateMessage::Chat { .. }
+        | GameStateMessage::Clock { .. }
+        | GameStateMessage::ClockSync { .. }
+        | GameStateMessage::TimeWarning { .. }
+        | GameStateMessage::GameExpired { .. } => None,
+    }
+}
+
+// legacy_payload serializes a message the way events were before version 4, as an object named
+// after the event holding its fields.
+//
+// e.g:
+//  {"type":"join_accepted","id":...} => {"JoinAccepted":{"id":...}}
+fn legacy_payload(state: &GameStateMessage) -> String {
+    let json = to_string(state).unwrap();
+    // The tag is always written first
+    let tagged = json
+        .strip_prefix(r#"{"type":""#)
+        .and_then(|rest| rest.split_once('"'));
+    let (tag, rest) = match tagged {
+        Some(tagged) => tagged,
+        None => return json,
+    };
+    let name: String = tag
+        .split('_')
+        .flat_map(|word| {
+            let mut letters = word.chars();
+            letters
+                .next()
+                .map(|first| first.to_ascii_uppercase())
+                .into_iter()
+                .chain(letters)
+        })
+        .collect();
+    // The rest holds the event's fields and the closing brace
+    let fields = rest.strip_prefix(',').unwrap_or(rest);
+    format!(r#"{{"{}":{{{}}}"#, name, fields)
+}
+
+// catch_up finds the events a reconnecting client missed after event `seq`. When they are no
+// longer kept the client is resynced instead.
+fn catch_up(
+    queue: &EventQueue,
+    registry: &GameRegistry,
+    game_id: Uuid,
+    seq: u64,
+) -> VecDeque<GameEvent> {
+    match queue.replay(&game_id, seq) {
+        Some(missed) => missed.into(),
+        None => resync(queue, registry, game_id).into_iter().collect(),
+    }
+}
+
+// resync sends the whole board to a client that missed events, numbered as the latest event so
+// anything older still on its way is skipped.
+fn resync(queue: &EventQueue, registry: &GameRegistry, game_id: Uuid) -> Option<GameEvent> {
+    let seq = queue.sequence(&game_id);
+    registry.with(&game_id, |game| GameEvent {
+        game_id: Some(game_id),
+        seq,
+        state: GameStateMessage::Resync {
+            board: board::encode(game),
+        },
+    })
+}
+
+#[get("/<_>/events?<protocol>")]
+async fn events(
+    spectator: Spectator,
+    protocol: Option<u8>,
+    last_event_id: Option<LastEventId>,
+    services: Services<'_>,
+    streaming: &State<Streaming>,
+    mut end: Shutdown,
+    span: RequestSpan,
+) -> EventStream![] {
+    let _span = span.enter();
+    // The game is the one the spectator was found watching, it is known to the server
+    let game_id = spectator.game_id;
+    let (budget, chaos) = (streaming.budget, streaming.chaos);
+    let protocol = protocol.unwrap_or(1);
+    let (queue, registry) = (services.queue.clone(), services.registry.clone());
+    // Subscribe before catching up, so nothing is missed in between
+    let mut rx = queue.subscribe();
+    // Anyone without a seat is counted as watching while subscribed, including themselves
+    let watching = spectator.seat.is_none() && !spectator.host;
+    let watcher = watching.then(|| queue.watch(game_id));
+    // The game's creator is around while subscribed, keeping an open game in the lobby
+    let present = spectator.host.then(|| services.lobby.arrive(game_id));
+    let mut backlog = match last_event_id {
+        Some(LastEventId(seq)) => catch_up(&queue, &registry, game_id, seq),
+        None => VecDeque::new(),
+    };
+    // seen is the latest event of the game sent, events caught up on also arrive live
+    let mut seen = 0;
+    EventStream! {
+        let (_watcher, _present) = (watcher, present);
+        loop {
+            let msg = match backlog.pop_front() {
+                Some(msg) => msg,
+                None => select! {
+                    msg = rx.recv() => match msg {
+                        Ok(msg) => msg,
+                        Err(RecvError::Closed) => break,
+                        // Events were dropped while the client fell behind
+                        Err(RecvError::Lagged(_)) => match resync(&queue, &registry, game_id) {
+                            Some(msg) => msg,
+                            None => continue,
+                        },
+                    },
+                    _ = &mut end => break,
+                },
+            };
+            if !msg.concerns(&game_id) {
+                continue;
+            }
+            if msg.game_id.is_some() {
+                if msg.seq <= seen {
+                    continue;
+                }
+                seen = msg.seq;
+            }
+            // Nothing more is sent about an expired game
+            let expired = matches!(msg.state, GameStateMessage::GameExpired { .. });
+
+            let payload = match event_payload(&msg, protocol) {
+                Some(payload) => payload,
+                None if expired => break,
+                None => continue,
+            };
+            if payload.len() > budget {
+                tracing::warn!(%game_id, bytes = payload.len(), budget, "Event exceeds budget");
+            }
+            if chaos.drop_event() {
+                continue;
+            }
+            let mut event = Event::data(payload);
+            // Server wide events aren't part of the game's sequence
+            if msg.game_id.is_some() {
+                event = event.id(msg.seq.to_string());
+            }
+            if protocol >= NAMED_EVENTS {
+                event = event.event(msg.state.event_type());
+            }
+            yield event;
+            if expired || chaos.disconnect() {
+                break;
+            }
+        }
+    }
+}
+
+// DEFAULT_POLL_TIMEOUT is how many seconds a poll waits for an event before answering with none,
+// short of the idle timeout of most proxies. It can be changed with `poll_timeout`.
+const DEFAULT_POLL_TIMEOUT: u64 = 25;
+
+pub struct PollTimeout(u64);
+
+// poll serves the events of a game sent after event `since`, for clients that can't use the event
+// stream, e.g. behind a proxy that buffers it. When there are none yet it waits for the next one,
+// answering with none if nothing is sent in time. Clients that haven't seen an event poll from 0
+// and are sent the whole board first.
+#[get("/<game_id>/poll?<since>")]
+async fn poll(
+    game_id: Uuid,
+    since: Option<u64>,
+    host: Option<Host>,
+    services: Services<'_>,
+    timeout: &State<PollTimeout>,
+    mut end: Shutdown,
+    span: RequestSpan,
+) -> Option<Json<Vec<PolledEvent>>> {
+    let (queue, registry) = (services.queue, services.registry);
+    let since = since.unwrap_or(0);
+    // Subscribe before catching up, so nothing is missed in between
+    let mut rx = queue.subscribe();
+    // The span is only entered while catching up, it can't be held while waiting
+    let mut missed: Vec<GameEvent> = {
+        let _span = span.enter();
+        registry.with(&game_id, |_| ())?;
+        // The game's creator is around while polling, keeping an open game in the lobby
+        if host.is_some() {
+            services.lobby.seen(game_id, registry::now());
+        }
+        match since {
+            0 => resync(queue, registry, game_id)
+                .into_iter()
+                .filter(|msg| msg.seq > 0)
+                .collect(),
+            seq => catch_up(queue, registry, game_id, seq).into(),
+        }
+    };
+    let wanted =
+        |msg: &GameEvent| msg.concerns(&game_id) && (msg.game_id.is_none() || msg.seq > since);
+    if missed.is_empty() {
+        let deadline = Instant::now() + Duration::from_secs(timeout.0);
+        while missed.is_empty() {
+            select! {
+                msg = rx.recv() => match msg {
+                    Ok(msg) if wanted(&msg) => missed.push(msg),
+                    Ok(_) => continue,
+                    Err(RecvError::Closed) => break,
+                    // Events were dropped while waiting
+                    Err(RecvError::Lagged(_)) => missed.extend(resync(queue, registry, game_id)),
+                },
+                _ = sleep_until(deadline) => break,
+                _ = &mut end => break,
+            }
+        }
+        // Events sent along with the first are answered together
+        while let Ok(msg) = rx.try_recv() {
+            if wanted(&msg) {
+                missed.push(msg);
+            }
+        }
+    }
+    Some(Json(
+        missed
+            .into_iter()
+            .map(|msg| PolledEvent {
+                seq: msg.seq,
+                event: msg.state.event_type().to_string(),
+                state: msg.state,
+            })
+            .collect(),
+    ))
+}
+
+// Failure is the body of an error response, JSON for API clients and a page for everyone else.
+#[derive(Responder)]
+enum Failure {
+    Json(Json<ErrorMessage>),
+    Page(Template),
+}
+
+// failure describes an error in the form the client accepts.
+fn failure(status: Status, message: &str, request: &Request<'_>) -> Failure {
+    let json = request
+        .accept()
+        .is_some_and(|accept| accept.preferred().is_json());
+    if json {
+        return Failure::Json(Json(ErrorMessage::new(
+            status.reason_lossy().replace(' ', ""),
+            message,
+        )));
+    }
+    let start = request.rocket().state::<Prefix>().map_or_else(
+        || "/index.html".to_string(),
+        |prefix| prefix.path("/index.html"),
+    );
+    Failure::Page(Template::render(
+        "error",
+        context! { code: status.code, message, start },
+    ))
+}
+
+#[catch(400)]
+fn bad_request(request: &Request<'_>) -> Failure {
+    failure(
+        Status::BadRequest,
+        "The request was malformed, check it and try again.",
+        request,
+    )
+}
+
+#[catch(404)]
+fn not_found(request: &Request<'_>) -> Failure {
+    failure(
+        Status::NotFound,
+        "There's nothing here, the game may have been removed or the link mistyped.",
+        request,
+    )
+}
+
+#[catch(422)]
+fn unprocessable(request: &Request<'_>) -> Failure {
+    failure(
+        Status::UnprocessableEntity,
+        "The request couldn't be understood, check it and try again.",
+        request,
+    )
+}
+
+#[catch(429)]
+fn too_many_requests(request: &Request<'_>) -> Failure {
+    failure(
+        Status::TooManyRequests,
+        "Slow down, try again in a moment.",
+        request,
+    )
+}
+
+#[catch(500)]
+fn internal_error(request: &Request<'_>) -> Failure {
+    failure(
+        Status::InternalServerError,
+        "Something went wrong on the server, try again in a moment.",
+        request,
+    )
+}
+
+pub fn rocket() -> Rocket<Build> {
+    // Uploaded SGF records are read as strings, allow for long commented games
+    let limits = Limits::default().limit("string", 256.kibibytes());
+    let config = rocket::Config::figment()
+        .merge(("port", 8080))
+        .merge(("limits", limits));
+    let event_budget = config
+        .extract_inner::<usize>("event_budget")
+        .unwrap_or(DEFAULT_EVENT_BUDGET);
+    let poll_timeout = config
+        .extract_inner::<u64>("poll_timeout")
+        .unwrap_or(DEFAULT_POLL_TIMEOUT);
+    logging::LogFormat::from_figment(&config).init();
+    let chaos = chaos::Chaos::from_figment(&config);
+    let expiry = expiry::Expiry::from_figment(&config);
+    let ticker = clock::Ticker::from_figment(&config);
+    let notifier = notify::Notifier::from_figment(&config);
+    let retention = privacy::Retention::from_figment(&config);
+    let lobby = Lobby::from_figment(&config);
+    let bots = bots::Bots::from_figment(&config);
+    let sizes = BoardSizes::from_figment(&config);
+    let engines = Engines::new(config.extract_inner("engine").ok());
+    let admin_token = AdminToken(config.extract_inner("admin_token").ok());
+    let prefix = Prefix::new(&config.extract_inner::<String>("prefix").unwrap_or_default());
+    let mount_point = prefix.mount_point().to_string();
+    let cors = cors::Cors::from_figment(&config).under(prefix.path("/api/"));
+    let registry =
+        GameRegistry::from_figment(&config).expect("Failed to load games from the store");
+    let queue = EventQueue::new(1024);
+    #[cfg(feature = "redis")]
+    let queue = match config.extract_inner::<String>("redis") {
+        Ok(url) => {
+            let channel = config
+                .extract_inner("redis_channel")
+                .unwrap_or_else(|_| relay::DEFAULT_CHANNEL.to_string());
+            relay::relay(&url, channel, queue).expect("Failed to connect to Redis")
+        }
+        Err(_) => queue,
+    };
+    let dispatcher = dispatcher::Dispatcher::new(
+        webhook::Delivery::from_figment(&config),
+        outbound::Outbound::from_figment(&config),
+    );
+    let queue = dispatcher::fan_out(
+        queue,
+        registry.clone(),
+        notifier.clone(),
+        dispatcher.clone(),
+    );
+    let queue = engines.watch(queue);
+
+    rocket::custom(config)
+        .attach(static_resources_initializer!(
+                "favicon" => "site/images/favicon.ico",
+                "favicon-16x16.png" => "site/images/favicon-16x16.png",
+                "favicon-32x32.png" => "site/images/favicon-32x32.png",
+                "android-chrome-192x192.png" => "site/images/android-chrome-192x192.png",
+                "android-chrome-512x512.png" => "site/images/android-chrome-512x512.png",
+                "apple-touch-icon.png" => "site/images/apple-touch-icon.png",
+
+                "blackpiece.png" => "site/images/blackpiece.png",
+                "whitepiece.png" => "site/images/whitepiece.png",
+                "tilecenter.png" => "site/images/tilecenter.png",
+                "join.png" => "site/images/join.png",
+        ))
+        .attach(Template::custom(move |engines| {
+            engines.handlebars.set_strict_mode(true);
+        }))
+        .manage(queue)
+        .manage(registry)
+        .manage(engines)
+        .manage(dispatcher)
+        .manage(Maintenance::default())
+        .manage(ChatLimiter::default())
+        .manage(admin_token)
+        .manage(prefix)
+        .manage(sizes)
+        .manage(Streaming {
+            budget: event_budget,
+            chaos,
+        })
+        .manage(PollTimeout(poll_timeout))
+        .attach(logging::RequestLog)
+        .attach(chaos)
+        .attach(cors)
+        .attach(expiry)
+        .attach(ticker)
+        .manage(ticker)
+        .manage(lobby)
+        .attach(retention)
+        .attach(notifier.clone())
+        .manage(notifier)
+        .manage(bots)
+        .register(
+            mount_point.clone(),
+            catchers![
+                bad_request,
+                not_found,
+                unprocessable,
+                too_many_requests,
+                internal_error
+            ],
+        )
+        .mount(
+            mount_point,
+            routes![
+                redirect_to_root,
+                serve_static_favicon,
+                serve_static_image,
+                serve_index,
+                serve_health,
+                serve_readiness,
+                serve_new_game,
+                api_new_game,
+                start_maintenance,
+                end_maintenance,
+                serve_delivery_counts,
+                serve_maintenance,
+                expire_game,
+                import_game,
+                serve_editor,
+                export_setup,
+                start_setup,
+                serve_join_game,
+                serve_lobby,
+                list_games,
+                serve_game,
+                serve_watch,
+                accept_player,
+                request_join,
+                api_join,
+                send_chat,
+                serve_chat,
+                set_notify,
+                set_time_warnings,
+                export_data,
+                forget_data,
+                add_webhook,
+                register_bot,
+                list_bot_games,
+                seat_bot,
+                api_state,
+                api_events,
+                api_move,
+                api_pass,
+                api_resign,
+                play_piece,
+                pass,
+                resign,
+                dead_stones,
+                accept_score,
+                undo_request,
+                undo_accept,
+                serve_viewport,
+                serve_state,
+                serve_moves,
+                serve_ledger,
+                serve_legal_moves,
+                preview_play,
+                serve_board_text,
+                serve_sgf,
+                serve_protocol_schema,
+                events,
+                poll
+            ],
+        )
+}
+
+#[test]
+fn test_move_event_within_budget() {
+    let mut game = board::new(board::Size::Nineteen);
+    for y in 0..19 {
+        for x in 0..9 {
+            assert_eq!(true, game.play_stone((x, y), board::Stone::Black).is_ok());
+            assert_eq!(
+                true,
+                game.play_stone((x + 10, y), board::Stone::White).is_ok()
+            );
+        }
+    }
+
+    let state = turn::update(&game, vec![(9, 9), (9, 10), (9, 11)]);
+    let msg = GameEvent {
+        game_id: Some(Uuid::new_v4()),
+        seq: 1000,
+        state,
+    };
+    let payload = event_payload(&msg, PROTOCOL_VERSION).unwrap();
+    assert_eq!(false, payload.contains(char::is_whitespace));
+    assert_eq!(true, payload.len() <= DEFAULT_EVENT_BUDGET);
+}
+
+#[test]
+fn test_event_payload_downgrades() {
+    let game_id = Uuid::nil();
+    let numbered = |state| GameEvent {
+        game_id: Some(game_id),
+        seq: 3,
+        state,
+    };
+    let update = numbered(GameStateMessage::Update {
+        board: "board".to_string(),
+        position: Some((1, 2)),
+        stone: Some(board::Stone::White),
+        captured: vec![(1, 1)],
+        atari: Vec::new(),
+        black_prisoners: 0,
+        white_prisoners: 1,
+        move_number: 4,
+        turn: Some(board::Stone::Black),
+    });
+    let fields = r#""board":"board","position":[1,2],"stone":"White","captured":[[1,1]],"atari":[],"black_prisoners":0,"white_prisoners":1,"move_number":4,"turn":"Black""#;
+    assert_eq!(
+        Some(format!(
+            r#"{{"game_id":"{}","seq":3,"type":"update",{}}}"#,
+            game_id, fields
+        )),
+        event_payload(&update, PROTOCOL_VERSION)
+    );
+    assert_eq!(
+        Some(format!(r#"{{"Update":{{{}}}}}"#, fields)),
+        event_payload(&update, 3)
+    );
+    assert_eq!(
+        Some(r#"{"Update":{"board":"board"}}"#.to_string()),
+        event_payload(&update, 1)
+    );
+
+    let pass = numbered(GameStateMessage::Pass {
+        stone: board::Stone::Black,
+        board: "board".to_string(),
+    });
+    assert_eq!(
+        Some(r#"{"Update":{"board":"board"}}"#.to_string()),
+        event_payload(&pass, 1)
+    );
+
+    let phase = numbered(GameStateMessage::Phase {
+        phase: board::GamePhase::Scoring,
+    });
+    assert_eq!(None, event_payload(&phase, 1));
+
+    let join = numbered(GameStateMessage::Join { id: game_id });
+    assert_eq!(event_payload(&join, 3), event_payload(&join, 1));
+
+    let accepted = numbered(GameStateMessage::JoinAccepted {
+        id: game_id,
+        size: 9,
+        stone: board::Stone::White,
+    });
+    assert_eq!(
+        Some(format!(
+            r#"{{"JoinAccepted":{{"id":"{}","size":9,"stone":"White"}}}}"#,
+            game_id
+        )),
+        event_payload(&accepted, 1)
+    );
+
+    let resync = numbered(GameStateMessage::Resync {
+        board: "board".to_string(),
+    });
+    assert_eq!(
+        Some(r#"{"Resync":{"board":"board"}}"#.to_string()),
+        event_payload(&resync, 3)
+    );
+    assert_eq!(
+        Some(
+            r#"{"Update":{"board":"board","position":null,"stone":null,"captured":[],"atari":[],"black_prisoners":0,"white_prisoners":0,"move_number":0,"turn":null}}"#
+                .to_string()
+        ),
+        event_payload(&resync, 2)
+    );
+    assert_eq!(
+        Some(r#"{"Update":{"board":"board"}}"#.to_string()),
+        event_payload(&resync, 1)
+    );
+}
+
+#[test]
+fn test_prefix() {
+    assert_eq!("/", Prefix::new("").mount_point());
+    assert_eq!("/", Prefix::new("/").mount_point());
+    assert_eq!("/go", Prefix::new("go/").mount_point());
+    assert_eq!("/go/play", Prefix::new("/go/play/").mount_point());
+}
+
+#[test]
+fn test_new_game_size() {
+    use rocket::local::blocking::Client;
+
+    let config = rocket::Config::figment().merge((
+        "template_dir",
+        concat!(env!("CARGO_MANIFEST_DIR"), "/../templates"),
+    ));
+    let registry = GameRegistry::default();
+    let rocket = rocket::custom(config)
+        .attach(Template::fairing())
+        .manage(registry.clone())
+        .manage(EventQueue::new(16))
+        .manage(Engines::new(None))
+        .manage(Maintenance::default())
+        .manage(Prefix::new(""))
+        .manage(BoardSizes::new(
+            &[board::Size::Nine, board::Size::Thirteen],
+            None,
+        ))
+        .manage(clock::Ticker {
+            lag_ms: 300,
+            sync: 10,
+        })
+        .manage(Lobby::default())
+        .mount("/", routes![serve_new_game]);
+    let client = Client::untracked(rocket).unwrap();
+    let status = |query: &str| client.get(format!("/new{}", query)).dispatch().status();
+
+    assert_eq!(Status::SeeOther, status("?size=9"));
+    assert_eq!(Status::BadRequest, status(""));
+    assert_eq!(Status::BadRequest, status("?komi=6.5"));
+    assert_eq!(Status::UnprocessableEntity, status("?size=7"));
+    assert_eq!(Status::UnprocessableEntity, status("?size=nine"));
+    // Supported, but not offered on this server
+    assert_eq!(Status::UnprocessableEntity, status("?size=19"));
+
+    // Timed games start with the server's lag compensation
+    assert_eq!(Status::SeeOther, status("?size=9&time=10m%2B5x30s"));
+    let timed = registry.filter_map(|(_, game)| {
+        game.clock()
+            .map(|clock| (clock.control().to_string(), clock.lag()))
+    });
+    assert_eq!(vec![("10m+5x30s".to_string(), 300)], timed);
+}
+
+#[test]
+fn test_preview_play() {
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    assert_eq!(true, game.play_stone((4, 4), board::Stone::Black).is_ok());
+    registry.insert(game_id, game.clone()).unwrap();
+    let rocket = rocket::build()
+        .manage(registry.clone())
+        .mount("/", routes![preview_play]);
+    let client = Client::untracked(rocket).unwrap();
+    let preview = |body: &str| {
+        let response = client
+            .post(format!("/{}/preview", game_id))
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+        (
+            response.status(),
+            response.into_json::<PreviewResultMessage>(),
+        )
+    };
+
+    let (status, result) = preview(r#"{"vertex":"C3"}"#);
+    assert_eq!(Status::Ok, status);
+    let result = result.unwrap();
+    assert_eq!(true, result.legal);
+    let mut played = game.clone();
+    assert_eq!(true, played.play_stone((2, 6), board::Stone::White).is_ok());
+    assert_eq!(board::encode(&played), result.board);
+    // The game itself is untouched
+    assert_eq!(
+        Some(board::encode(&game)),
+        registry.with(&game_id, board::encode)
+    );
+
+    let (status, result) = preview(r#"{"coordinate":[4,4],"stone":"White"}"#);
+    assert_eq!(Status::Ok, status);
+    let result = result.unwrap();
+    assert_eq!(false, result.legal);
+    assert_eq!("Occupied", result.error.unwrap().code);
+    assert_eq!(board::encode(&game), result.board);
+
+    assert_eq!(Status::UnprocessableEntity, preview("{}").0);
+}
+
+#[test]
+fn test_serve_state() {
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    game.id = game_id;
+    assert_eq!(true, game.play_stone((2, 0), board::Stone::Black).is_ok());
+    assert_eq!(true, game.play_stone((1, 0), board::Stone::White).is_ok());
+    assert_eq!(Ok(()), game.pass(board::Stone::Black));
+    registry.insert(game_id, game.clone()).unwrap();
+    let rocket = rocket::build()
+        .manage(registry)
+        .mount("/", routes![serve_state]);
+    let client = Client::untracked(rocket).unwrap();
+
+    let response = client.get(format!("/{}/state", game_id)).dispatch();
+    assert_eq!(Status::Ok, response.status());
+    let snapshot = response.into_json::<GameSnapshotMessage>().unwrap();
+    assert_eq!(game_snapshot(&game), snapshot);
+    assert_eq!(".wb......", snapshot.tiles[0]);
+    assert_eq!(9, snapshot.tiles.len());
+    assert_eq!(board::Stone::White, snapshot.turn);
+    assert_eq!(3, snapshot.move_number);
+    assert_eq!(Some((1, 0)), snapshot.last_move);
+    assert_eq!(None, snapshot.score);
+
+    let response = client.get(format!("/{}/state", Uuid::new_v4())).dispatch();
+    assert_eq!(Status::NotFound, response.status());
+}
+
+#[test]
+fn test_serve_moves() {
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    for x in 0..3 {
+        assert_eq!(true, game.play_stone((x, 0), board::Stone::Black).is_ok());
+        assert_eq!(Ok(()), game.pass(board::Stone::White));
+    }
+    registry.insert(game_id, game).unwrap();
+    let rocket = rocket::build()
+        .manage(registry)
+        .mount("/", routes![serve_moves]);
+    let client = Client::untracked(rocket).unwrap();
+    let moves = |query: &str| {
+        client
+            .get(format!("/{}/moves{}", game_id, query))
+            .dispatch()
+            .into_json::<MoveListMessage>()
+            .unwrap()
+    };
+
+    let all = moves("");
+    assert_eq!(6, all.total);
+    assert_eq!(6, all.moves.len());
+    assert_eq!(None, all.next);
+    assert_eq!(Some("A9".to_string()), all.moves[0].vertex);
+    assert_eq!(None, all.moves[1].vertex);
+
+    let page = moves("?from=3&limit=2");
+    assert_eq!(
+        vec![3, 4],
+        page.moves
+            .iter()
+            .map(|entry| entry.number)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(board::Action::Play((1, 0)), page.moves[0].action);
+    assert_eq!(Some(5), page.next);
+    assert_eq!(true, moves("?from=7").moves.is_empty());
+}
+
+#[test]
+fn test_seat_locking() {
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    let host = game.issue_host_token();
+    registry.insert(game_id, game).unwrap();
+    let rocket = rocket::build()
+        .manage(registry.clone())
+        .manage(EventQueue::new(16))
+        .manage(Engines::new(None))
+        .manage(BoardSizes::new(&[], None))
+        .manage(Maintenance::default())
+        .manage(clock::Ticker::default())
+        .manage(Lobby::default())
+        .mount("/", routes![request_join, accept_player]);
+    let client = Client::untracked(rocket).unwrap();
+    // join asks for the guest's seat in a game, returning the seat cookie of the pending seat
+    let join = |game_id: Uuid| {
+        let response = client
+            .put(format!("/{}/joins", game_id))
+            .header(ContentType::JSON)
+            .body("{}")
+            .dispatch();
+        let state = response
+            .cookies()
+            .get_private(&players::cookie_name(&game_id))
+            .map(|cookie| {
+                let state: players::SeatState =
+                    rocket::serde::json::from_str(cookie.value()).unwrap();
+                assert_eq!(game_id, state.game_id);
+                state
+            });
+        (response.status(), state)
+    };
+    // accept accepts the player waiting to join as the host, returning the host's seat cookie
+    let accept = |game_id: Uuid, key: &str| {
+        let response = client
+            .put(format!("/{}/players", game_id))
+            .header(ContentType::JSON)
+            .private_cookie(players::seat_cookie(
+                &game_id,
+                format!(
+                    r#"{{"version":3,"game_id":"{}","size":9,"stone":"Black","key":"{}"}}"#,
+                    game_id, key
+                ),
+            ))
+            .body(r#"{"size":9}"#)
+            .dispatch();
+        let state = response
+            .cookies()
+            .get_private(&players::cookie_name(&game_id))
+            .map(|cookie| {
+                rocket::serde::json::from_str::<players::SeatState>(cookie.value()).unwrap()
+            });
+        (
+            response.status(),
+            response.into_json::<GameStateMessage>(),
+            state,
+        )
+    };
+    let white_token_matches = |token: &str| {
+        registry
+            .with(&game_id, |game| {
+                game.token_matches(board::Stone::White, token)
+            })
+            .unwrap()
+    };
+
+    // No one is waiting to be accepted yet
+    assert_eq!(Status::Conflict, accept(game_id, &host).0);
+    let (status, first) = join(game_id);
+    assert_eq!(Status::Ok, status);
+    let (status, second) = join(game_id);
+    assert_eq!(Status::Ok, status);
+    let second = second.unwrap();
+    assert_eq!(Some(board::Stone::White), second.stone);
+    // The seat is only held once the host accepts, by the latest player to ask
+    assert_eq!(false, white_token_matches(&second.key));
+    let (status, state, _) = accept(game_id, &host);
+    assert_eq!(Status::Ok, status);
+    assert_eq!(
+        Some(GameStateMessage::JoinAccepted {
+            id: game_id,
+            size: 9,
+            stone: board::Stone::White,
+        }),
+        state
+    );
+    assert_eq!(
+        Some(true),
+        registry.with(&game_id, board::Game::guest_seated)
+    );
+    assert_eq!(true, white_token_matches(&second.key));
+    assert_eq!(false, white_token_matches(&first.unwrap().key));
+    assert_eq!((Status::Conflict, None), join(game_id));
+    assert_eq!(Status::Conflict, accept(game_id, &host).0);
+
+    // With the stones left to chance they are drawn once the host accepts, both players are told
+    // the guest's stone and the host's cookie is brought up to date
+    let nigiri = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    game.set_host(None);
+    let host = game.issue_host_token();
+    registry.insert(nigiri, game).unwrap();
+    let (status, guest) = join(nigiri);
+    assert_eq!(Status::Ok, status);
+    let guest = guest.unwrap();
+    assert_eq!(None, guest.stone);
+    assert_eq!(Status::Forbidden, accept(nigiri, &guest.key).0);
+    let (status, state, cookie) = accept(nigiri, &host);
+    assert_eq!(Status::Ok, status);
+    let drawn = registry
+        .with(&nigiri, |game| game.guest())
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        Some(GameStateMessage::JoinAccepted {
+            id: nigiri,
+            size: 9,
+            stone: drawn,
+        }),
+        state
+    );
+    let cookie = cookie.unwrap();
+    assert_eq!(
+        registry.with(&nigiri, board::Game::host).unwrap(),
+        cookie.stone
+    );
+    assert_eq!(host, cookie.key);
+}
+
+#[test]
+fn test_not_found() {
+    use rocket::http::Accept;
+    use rocket::local::blocking::Client;
+
+    let config = rocket::Config::figment().merge((
+        "template_dir",
+        concat!(env!("CARGO_MANIFEST_DIR"), "/../templates"),
+    ));
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    registry
+        .insert(game_id, board::new(board::Size::Nine))
+        .unwrap();
+    let rocket = rocket::custom(config)
+        .attach(Template::fairing())
+        .manage(registry)
+        .manage(EventQueue::new(16))
+        .manage(Prefix::new("/go"))
+        .register(
+            "/",
+            catchers![
+                bad_request,
+                not_found,
+                unprocessable,
+                too_many_requests,
+                internal_error
+            ],
+        )
+        .mount("/", routes![serve_game, serve_watch]);
+    let client = Client::untracked(rocket).unwrap();
+
+    // Visitors without a seat are sent to join the game, but may watch it
+    let response = client.get(format!("/{}/game.html", game_id)).dispatch();
+    assert_eq!(Status::SeeOther, response.status());
+    assert_eq!(
+        Some(format!("/go/{}/join.html", game_id).as_str()),
+        response.headers().get_one("Location")
+    );
+    let response = client.get(format!("/{}/watch.html", game_id)).dispatch();
+    assert_eq!(Status::Ok, response.status());
+    let response = client
+        .get(format!("/{}/watch.html", Uuid::new_v4()))
+        .dispatch();
+    assert_eq!(Status::NotFound, response.status());
+
+    let unknown = format!("/{}/game.html", Uuid::new_v4());
+    let response = client.get(unknown.clone()).dispatch();
+    assert_eq!(Status::NotFound, response.status());
+    assert_eq!(Some(ContentType::HTML), response.content_type());
+
+    let response = client.get(unknown).header(Accept::JSON).dispatch();
+    assert_eq!(Status::NotFound, response.status());
+    let body: rocket::serde::json::Value = response.into_json().unwrap();
+    assert_eq!(json!("NotFound"), body["error"]["code"]);
+    assert_eq!(json!([]), body["error"]["details"]);
+    assert_eq!(true, body["error"]["message"].is_string());
+}
+
+#[test]
+fn test_list_games() {
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let older = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    game.stamp_created(1700000000000);
+    registry.insert(older, game).unwrap();
+    let newer = Uuid::new_v4();
+    let mut game = board::new(board::Size::Thirteen);
+    game.stamp_created(1700000060000);
+    registry.insert(newer, game).unwrap();
+    // Games with both seats taken aren't waiting for anyone
+    let mut game = board::new(board::Size::Nine);
+    game.seat_guest();
+    registry.insert(Uuid::new_v4(), game).unwrap();
+    let rocket = rocket::build()
+        .manage(registry)
+        .manage(Lobby::default())
+        .manage(Prefix::new("/go"))
+        .mount("/", routes![list_games]);
+    let client = Client::untracked(rocket).unwrap();
+
+    let response = client.get("/api/games?state=open").dispatch();
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!(
+        Some(GameListMessage {
+            games: vec![
+                GameEntry {
+                    id: newer,
+                    size: board::Size::Thirteen,
+                    created_at: Some(1700000060000),
+                    join: format!("/go/{}/join.html", newer),
+                },
+                GameEntry {
+                    id: older,
+                    size: board::Size::Nine,
+                    created_at: Some(1700000000000),
+                    join: format!("/go/{}/join.html", older),
+                },
+            ]
+        }),
+        response.into_json::<GameListMessage>()
+    );
+
+    let response = client.get("/api/games?state=finished").dispatch();
+    assert_eq!(false, response.status() == Status::Ok);
+}
+
+#[test]
+fn test_chat() {
+    use rocket::http::Cookie;
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    let host = game.issue_host_token();
+    registry.insert(game_id, game).unwrap();
+    let rocket = rocket::build()
+        .manage(registry.clone())
+        .manage(EventQueue::new(16))
+        .manage(Engines::new(None))
+        .manage(BoardSizes::new(&[], None))
+        .manage(Maintenance::default())
+        .manage(clock::Ticker::default())
+        .manage(Lobby::default())
+        .manage(ChatLimiter::default())
+        .mount("/", routes![send_chat, serve_chat]);
+    let client = Client::untracked(rocket).unwrap();
+    let black = players::seat_cookie(
+        &game_id,
+        format!(
+            r#"{{"version":3,"game_id":"{}","size":9,"stone":"Black","key":"{}"}}"#,
+            game_id, host
+        ),
+    );
+    let say = |text: &str, cookie: Option<&Cookie<'static>>| {
+        let request = client
+            .put(format!("/{}/chat", game_id))
+            .header(ContentType::JSON)
+            .body(format!(r#"{{"text":"{}"}}"#, text));
+        let request = match cookie {
+            Some(cookie) => request.private_cookie(cookie.clone()),
+            None => request,
+        };
+        let response = request.dispatch();
+        let status = response.status();
+        let spoken = match response.into_json::<GameStateMessage>() {
+            Some(GameStateMessage::Chat { from, text, .. }) => Some((from, text)),
+            _ => None,
+        };
+        (status, spoken)
+    };
+
+    // Players chat as their stone, anyone else as a spectator
+    assert_eq!(
+        (
+            Status::Ok,
+            Some((Some(board::Stone::Black), "Good luck".to_string()))
+        ),
+        say(" Good luck ", Some(&black))
+    );
+    assert_eq!(
+        (Status::Ok, Some((None, "Hello".to_string()))),
+        say("Hello", None)
+    );
+    assert_eq!(Status::UnprocessableEntity, say(" ", None).0);
+
+    // Chat reloads with the game
+    let log = client
+        .get(format!("/{}/chat", game_id))
+        .dispatch()
+        .into_json::<ChatLogMessage>()
+        .unwrap();
+    let said: Vec<_> = log.lines.iter().map(|line| line.text.as_str()).collect();
+    assert_eq!(vec!["Good luck", "Hello"], said);
+
+    // Each speaker may only say so much at once
+    for _ in 1..chat::CHAT_BURST {
+        assert_eq!(Status::Ok, say("gg", Some(&black)).0);
+    }
+    assert_eq!(Status::TooManyRequests, say("gg", Some(&black)).0);
+    assert_eq!(Status::Ok, say("gg", None).0);
+}
+
+#[test]
+fn test_expire_game() {
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    registry
+        .insert(game_id, board::new(board::Size::Nine))
+        .unwrap();
+    let queue = EventQueue::new(16);
+    let mut rx = queue.subscribe();
+    let rocket = rocket::build()
+        .manage(registry.clone())
+        .manage(queue)
+        .manage(AdminToken(Some("secret".to_string())))
+        .mount("/", routes![expire_game]);
+    let client = Client::untracked(rocket).unwrap();
+    let expire = |token: &str| {
+        client
+            .delete(format!("/admin/games/{}", game_id))
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch()
+            .status()
+    };
+
+    assert_eq!(Status::Unauthorized, expire("guess"));
+    assert_eq!(Some(()), registry.with(&game_id, |_| ()));
+    assert_eq!(Status::NoContent, expire("secret"));
+    assert_eq!(None, registry.with(&game_id, |_| ()));
+    assert_eq!(
+        GameStateMessage::GameExpired { archived: false },
+        rx.try_recv().unwrap().state
+    );
+    assert_eq!(Status::NotFound, expire("secret"));
+}
+
+#[test]
+fn test_serve_delivery_counts() {
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+
+    let rocket = rocket::build()
+        .manage(dispatcher::Dispatcher::new(
+            webhook::Delivery::default(),
+            outbound::Outbound::new(outbound::Settings::default()),
+        ))
+        .manage(AdminToken(Some("secret".to_string())))
+        .mount("/", routes![serve_delivery_counts]);
+    let client = Client::untracked(rocket).unwrap();
+    let counts = |token: &str| {
+        client
+            .get("/admin/notifications")
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch()
+    };
+
+    assert_eq!(Status::Unauthorized, counts("guess").status());
+    let response = counts("secret");
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!(
+        Some(BTreeMap::new()),
+        response.into_json::<BTreeMap<String, DeliveryCounts>>()
+    );
+}
+
+#[test]
+fn test_resign() {
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    let token = game.issue_host_token();
+    registry.insert(game_id, game).unwrap();
+    let rocket = rocket::build()
+        .manage(registry.clone())
+        .manage(EventQueue::new(16))
+        .manage(Engines::new(None))
+        .manage(BoardSizes::new(&[], None))
+        .manage(Maintenance::default())
+        .manage(clock::Ticker::default())
+        .manage(Lobby::default())
+        .mount("/", routes![play_piece, resign, serve_ledger]);
+    let client = Client::untracked(rocket).unwrap();
+    let cookie = || {
+        players::seat_cookie(
+            &game_id,
+            format!(
+                r#"{{"version":3,"game_id":"{}","size":9,"stone":"Black","key":"{}"}}"#,
+                game_id, token
+            ),
+        )
+    };
+    let response = client
+        .put(format!("/{}/games", game_id))
+        .header(ContentType::JSON)
+        .private_cookie(cookie())
+        .body(r#"{"coordinate":[2,2]}"#)
+        .dispatch();
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!(
+        Some(Some(board::Stone::Black)),
+        registry.with(&game_id, |game| game.stone_at((2, 2)))
+    );
+
+    // Games the server doesn't know can't be played, whatever the client sends
+    let unknown = Uuid::new_v4();
+    let response = client
+        .put(format!("/{}/games", unknown))
+        .header(ContentType::JSON)
+        .private_cookie(players::seat_cookie(
+            &unknown,
+            format!(
+                r#"{{"version":3,"game_id":"{}","size":9,"stone":"Black","key":"{}"}}"#,
+                unknown, token
+            ),
+        ))
+        .body(r#"{"board":"","coordinate":[2,2],"size":9}"#)
+        .dispatch();
+    assert_eq!(Status::NotFound, response.status());
+
+    let resign = || {
+        client
+            .put(format!("/{}/resign", game_id))
+            .header(ContentType::JSON)
+            .private_cookie(cookie())
+            .body("{}")
+            .dispatch()
+            .status()
+    };
+    assert_eq!(Status::Ok, resign());
+    assert_eq!(
+        Some(Some(board::Stone::White)),
+        registry.with(&game_id, board::Game::winner)
+    );
+    // Finished games can't be resigned again
+    assert_eq!(Status::UnprocessableEntity, resign());
+
+    // The ledger goes back over the game, from when it was created
+    let ledger = |query: &str| {
+        client
+            .get(format!("/{}/ledger{}", game_id, query))
+            .dispatch()
+            .into_json::<LedgerMessage>()
+            .unwrap()
+    };
+    let events: Vec<_> = ledger("")
+        .entries
+        .into_iter()
+        .map(|entry| entry.event)
+        .collect();
+    assert_eq!(3, events.len());
+    assert_eq!(
+        board::LedgerEvent::Resigned {
+            stone: board::Stone::Black
+        },
+        events[2]
+    );
+    let board = ledger("?upto=1").board;
+    assert_eq!(None, board::decode(&board).unwrap().stone_at((2, 2)));
+}
+
+#[test]
+fn test_set_notify() {
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    let token = game.issue_host_token();
+    registry.insert(game_id, game).unwrap();
+    let rocket = rocket::build()
+        .manage(registry.clone())
+        .manage(notify::Notifier::default())
+        .mount("/", routes![set_notify]);
+    let client = Client::untracked(rocket).unwrap();
+    let notify = |body: &str| {
+        client
+            .put(format!("/{}/notify", game_id))
+            .header(ContentType::JSON)
+            .private_cookie(players::seat_cookie(
+                &game_id,
+                format!(
+                    r#"{{"version":3,"game_id":"{}","size":9,"stone":"Black","key":"{}"}}"#,
+                    game_id, token
+                ),
+            ))
+            .body(body)
+            .dispatch()
+            .status()
+    };
+
+    assert_eq!(
+        Status::Ok,
+        notify(r#"{"contact":"https://example.com/black"}"#)
+    );
+    let contact = registry.with(&game_id, |game| game.contact(board::Stone::Black).cloned());
+    assert_eq!(
+        Some(Some(notify::Contact::Webhook(
+            "https://example.com/black".to_string()
+        ))),
+        contact
+    );
+    // This server doesn't send email
+    assert_eq!(
+        Status::UnprocessableEntity,
+        notify(r#"{"contact":"mailto:black@example.com"}"#)
+    );
+    assert_eq!(
+        Status::UnprocessableEntity,
+        notify(r#"{"contact":"black"}"#)
+    );
+    assert_eq!(Status::Ok, notify(r#"{"contact":null}"#));
+    assert_eq!(
+        Some(None),
+        registry.with(&game_id, |game| game.contact(board::Stone::Black).cloned())
+    );
+}
+
+#[test]
+fn test_player_data() {
+    use rocket::http::Cookie;
+    use rocket::local::blocking::{Client, LocalRequest};
+    use std::io::Cursor;
+
+    // seated sends a request with seat cookies
+    fn seated<'c>(request: LocalRequest<'c>, cookies: Vec<Cookie<'static>>) -> LocalRequest<'c> {
+        cookies
+            .into_iter()
+            .fold(request, |request, cookie| request.private_cookie(cookie))
+    }
+
+    let registry = GameRegistry::default();
+    let seat = |game_id: Uuid, token: &str| {
+        players::seat_cookie(
+            &game_id,
+            format!(
+                r#"{{"version":3,"game_id":"{}","size":9,"stone":"Black","key":"{}"}}"#,
+                game_id, token
+            ),
+        )
+    };
+    let mut cookies = Vec::new();
+    for finished in [true, false] {
+        let game_id = Uuid::new_v4();
+        let mut game = board::new(board::Size::Nine);
+        let token = game.issue_host_token();
+        game.request_seat().unwrap();
+        game.accept_join().unwrap();
+        game.set_contact(
+            board::Stone::Black,
+            Some(notify::Contact::Webhook(
+                "https://example.com/black".to_string(),
+            )),
+        );
+        if finished {
+            assert_eq!(true, game.resign(board::Stone::White).is_ok());
+        }
+        registry.insert(game_id, game).unwrap();
+        cookies.push((game_id, seat(game_id, &token)));
+    }
+    let open = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    let token = game.issue_host_token();
+    registry.insert(open, game).unwrap();
+    cookies.push((open, seat(open, &token)));
+    let (finished, playing) = (cookies[0].0, cookies[1].0);
+
+    let rocket = rocket::build()
+        .manage(registry.clone())
+        .manage(EventQueue::new(16))
+        .mount("/", routes![export_data, forget_data]);
+    let client = Client::untracked(rocket).unwrap();
+    let held = |games: &[Uuid]| {
+        cookies
+            .iter()
+            .filter(|(game_id, _)| games.contains(game_id))
+            .map(|(_, cookie)| cookie.clone())
+            .collect::<Vec<_>>()
+    };
+
+    // Every game the player holds a seat in is exported
+    let response = seated(client.get("/my/data.zip"), held(&[finished, open])).dispatch();
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!(Some(ContentType::ZIP), response.content_type());
+    let mut archive = zip::ZipArchive::new(Cursor::new(response.into_bytes().unwrap())).unwrap();
+    let mut names: Vec<_> = archive.file_names().map(str::to_string).collect();
+    names.sort();
+    let mut expected = vec![
+        format!("{}/game.sgf", finished),
+        format!("{}/seat.json", finished),
+        format!("{}/game.sgf", open),
+        format!("{}/seat.json", open),
+    ];
+    expected.sort();
+    assert_eq!(expected, names);
+    let mut held_seat = String::new();
+    std::io::Read::read_to_string(
+        &mut archive.by_name(&format!("{}/seat.json", finished)).unwrap(),
+        &mut held_seat,
+    )
+    .unwrap();
+    let held_seat: rustigo_protocol::SeatDataMessage =
+        rocket::serde::json::from_str(&held_seat).unwrap();
+    assert_eq!(
+        Some("https://example.com/black".to_string()),
+        held_seat.contact
+    );
+
+    // Nothing is forgotten while a game is being played
+    let forget = |games: &[Uuid]| {
+        seated(client.delete("/my/data"), held(games))
+            .dispatch()
+            .status()
+    };
+    assert_eq!(Status::Conflict, forget(&[finished, playing, open]));
+    assert_eq!(Some(()), registry.with(&open, |_| ()));
+
+    // Finished games keep their moves, open ones are dropped
+    assert_eq!(Status::NoContent, forget(&[finished, open]));
+    assert_eq!(None, registry.with(&open, |_| ()));
+    assert_eq!(
+        Some((None, Some(board::Stone::White))),
+        registry.with(&finished, |game| (
+            game.contact(board::Stone::Black).cloned(),
+            game.resigned()
+        ))
+    );
+    let response = seated(client.get("/my/data.zip"), held(&[finished])).dispatch();
+    let archive = zip::ZipArchive::new(Cursor::new(response.into_bytes().unwrap())).unwrap();
+    assert_eq!(0, archive.len());
+}
+
+#[test]
+fn test_set_time_warnings() {
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    let token = game.issue_host_token();
+    game.set_time_warnings(Some(clock::TimeWarnings::parse("1m").unwrap()));
+    registry.insert(game_id, game).unwrap();
+    let rocket = rocket::build()
+        .manage(registry.clone())
+        .mount("/", routes![set_time_warnings]);
+    let client = Client::untracked(rocket).unwrap();
+    let choose = |body: &str| {
+        let response = client
+            .put(format!("/{}/time_warnings", game_id))
+            .header(ContentType::JSON)
+            .private_cookie(players::seat_cookie(
+                &game_id,
+                format!(
+                    r#"{{"version":3,"game_id":"{}","size":9,"stone":"Black","key":"{}"}}"#,
+                    game_id, token
+                ),
+            ))
+            .body(body)
+            .dispatch();
+        (
+            response.status(),
+            response
+                .into_json::<TimeWarningsMessage>()
+                .and_then(|message| message.warnings),
+        )
+    };
+
+    assert_eq!(
+        (Status::Ok, Some("10s,last".to_string())),
+        choose(r#"{"warnings":"last,10s"}"#)
+    );
+    let chosen = registry.with(&game_id, |game| {
+        (
+            game.time_warnings(board::Stone::Black).to_string(),
+            game.time_warnings(board::Stone::White).to_string(),
+        )
+    });
+    assert_eq!(Some(("10s,last".to_string(), "1m".to_string())), chosen);
+    assert_eq!(
+        Status::UnprocessableEntity,
+        choose(r#"{"warnings":"soon"}"#).0
+    );
+    // Players can go back to the game's warnings
+    assert_eq!(
+        (Status::Ok, Some("1m".to_string())),
+        choose(r#"{"warnings":null}"#)
+    );
+}
+
+#[test]
+fn test_add_webhook() {
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    let token = game.issue_host_token();
+    registry.insert(game_id, game).unwrap();
+    let rocket = rocket::build()
+        .manage(registry.clone())
+        .mount("/", routes![add_webhook]);
+    let client = Client::untracked(rocket).unwrap();
+    let cookie = players::seat_cookie(
+        &game_id,
+        format!(
+            r#"{{"version":3,"game_id":"{}","size":9,"stone":"Black","key":"{}"}}"#,
+            game_id, token
+        ),
+    );
+
+    let response = client
+        .post(format!("/{}/webhooks", game_id))
+        .header(ContentType::JSON)
+        .private_cookie(cookie.clone())
+        .body(r#"{"url":"https://example.com/hook"}"#)
+        .dispatch();
+    assert_eq!(Status::Created, response.status());
+    let created = response.into_json::<WebhookCreatedMessage>().unwrap();
+    assert_eq!("https://example.com/hook", created.url);
+    // The webhook and its secret are kept with the game's record
+    let record = registry.with(&game_id, board::encode_with_history).unwrap();
+    let webhooks = board::decode(&record).unwrap().webhooks().to_vec();
+    assert_eq!(
+        vec![webhook::Webhook {
+            url: created.url,
+            secret: created.secret,
+        }],
+        webhooks
+    );
+
+    let add = |body: &'static str, cookie: Option<rocket::http::Cookie<'static>>| {
+        let request = client
+            .post(format!("/{}/webhooks", game_id))
+            .header(ContentType::JSON)
+            .body(body);
+        match cookie {
+            Some(cookie) => request.private_cookie(cookie),
+            None => request,
+        }
+        .dispatch()
+        .status()
+    };
+    assert_eq!(
+        Status::UnprocessableEntity,
+        add(r#"{"url":"example.com"}"#, Some(cookie.clone()))
+    );
+    // Only the game's creator can add webhooks
+    assert_eq!(
+        Status::Forbidden,
+        add(r#"{"url":"https://example.com/other"}"#, None)
+    );
+}
+
+#[test]
+fn test_bot_api() {
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(board::Size::Nine);
+    game.set_host(Some(board::Stone::Black));
+    game.set_bot(Some(bots::BotSeat::Wanted));
+    registry.insert(game_id, game).unwrap();
+    // Games waiting for people aren't offered to bots
+    registry
+        .insert(Uuid::new_v4(), board::new(board::Size::Nine))
+        .unwrap();
+    let rocket = rocket::build()
+        .manage(registry.clone())
+        .manage(EventQueue::new(16))
+        .manage(AdminToken(Some("secret".to_string())))
+        .manage(bots::Bots::default())
+        .manage(Prefix::new(""))
+        .manage(Engines::new(None))
+        .manage(BoardSizes::new(&[], None))
+        .manage(Maintenance::default())
+        .manage(clock::Ticker::default())
+        .manage(Lobby::default())
+        .mount(
+            "/",
+            routes![register_bot, list_bot_games, seat_bot, api_move],
+        );
+    let client = Client::untracked(rocket).unwrap();
+    let bearer = |token: &str| Header::new("Authorization", format!("Bearer {}", token));
+
+    let response = client
+        .post("/api/v1/bots")
+        .header(ContentType::JSON)
+        .header(bearer("secret"))
+        .body(r#"{"name":"gnugo"}"#)
+        .dispatch();
+    assert_eq!(Status::Ok, response.status());
+    let token = response.into_json::<BotTokenMessage>().unwrap().token;
+    let response = client
+        .post("/api/v1/bots")
+        .header(ContentType::JSON)
+        .header(bearer(&token))
+        .body(r#"{"name":"katago"}"#)
+        .dispatch();
+    assert_eq!(Status::Unauthorized, response.status());
+
+    let response = client
+        .get("/api/v1/games")
+        .header(bearer(&token))
+        .dispatch();
+    let games = response.into_json::<GameListMessage>().unwrap().games;
+    assert_eq!(
+        vec![game_id],
+        games.iter().map(|game| game.id).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        Status::Unauthorized,
+        client.get("/api/v1/games").dispatch().status()
+    );
+
+    let seat = || {
+        client
+            .post(format!("/api/v1/games/{}/seat", game_id))
+            .header(bearer(&token))
+            .dispatch()
+    };
+    let response = seat();
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!(
+        Some(GameStateMessage::JoinAccepted {
+            id: game_id,
+            size: 9,
+            stone: board::Stone::White,
+        }),
+        response.into_json::<GameStateMessage>()
+    );
+    assert_eq!(Status::Conflict, seat().status());
+
+    let play = |vertex: &str| {
+        client
+            .put(format!("/api/v1/games/{}/moves", game_id))
+            .header(ContentType::JSON)
+            .header(bearer(&token))
+            .body(format!(r#"{{"move":"{}"}}"#, vertex))
+            .dispatch()
+            .status()
+    };
+    // Black moves first
+    assert_eq!(Status::UnprocessableEntity, play("pass"));
+    registry
+        .update(
+            game_id,
+            || Err(()),
+            |game| game.play_stone((2, 2), board::Stone::Black).map_err(|_| ()),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(Status::UnprocessableEntity, play("z99"));
+    assert_eq!(Status::Ok, play("e5"));
+    assert_eq!(
+        Some(board::Stone::White),
+        registry
+            .with(&game_id, |game| game.stone_at((4, 4)))
+            .flatten()
+    );
+}
+
+#[test]
+fn test_poll() {
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    registry
+        .insert(game_id, board::new(board::Size::Nine))
+        .unwrap();
+    let queue = EventQueue::new(16);
+    // Events are only sent while someone is subscribed
+    let _rx = queue.subscribe();
+    let rocket = rocket::build()
+        .manage(registry)
+        .manage(queue.clone())
+        .manage(Engines::new(None))
+        .manage(BoardSizes::new(&[], None))
+        .manage(Maintenance::default())
+        .manage(clock::Ticker::default())
+        .manage(Lobby::default())
+        .manage(PollTimeout(0))
+        .mount("/", routes![poll]);
+    let client = Client::untracked(rocket).unwrap();
+    let poll = |since: u64| {
+        client
+            .get(format!("/{}/poll?since={}", game_id, since))
+            .dispatch()
+            .into_json::<Vec<PolledEvent>>()
+            .unwrap()
+            .into_iter()
+            .map(|polled| (polled.seq, polled.event))
+            .collect::<Vec<_>>()
+    };
+
+    // Nothing happened yet, the poll times out
+    assert_eq!(Vec::<(u64, String)>::new(), poll(0));
+    queue
+        .send(Some(game_id), GameStateMessage::Join { id: game_id })
+        .unwrap();
+    queue
+        .send(Some(game_id), GameStateMessage::Spectators { count: 1 })
+        .unwrap();
+    // A client starting out is sent the board, then only what it missed
+    assert_eq!(vec![(2, "resync".to_string())], poll(0));
+    assert_eq!(vec![(2, "spectators".to_string())], poll(1));
+    assert_eq!(Vec::<(u64, String)>::new(), poll(2));
+
+    let response = client
+        .get(format!("/{}/poll?since=0", Uuid::new_v4()))
+        .dispatch();
+    assert_eq!(Status::NotFound, response.status());
+}
+
+#[test]
+fn test_api() {
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let rocket = rocket::build()
+        .manage(registry.clone())
+        .manage(EventQueue::new(16))
+        .manage(Engines::new(None))
+        .manage(Maintenance::default())
+        .manage(BoardSizes::new(&[], None))
+        .manage(clock::Ticker::default())
+        .manage(Lobby::default())
+        .mount(
+            "/",
+            routes![
+                api_new_game,
+                api_state,
+                api_join,
+                api_move,
+                api_pass,
+                api_resign
+            ],
+        );
+    let client = Client::untracked(rocket).unwrap();
+    let create = |body: &'static str| {
+        client
+            .post("/api/v1/games")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+    };
+
+    let response = create(r#"{"size":9,"time":"soon"}"#);
+    assert_eq!(Status::UnprocessableEntity, response.status());
+    assert_eq!(
+        "InvalidTimeControl",
+        response.into_json::<ErrorMessage>().unwrap().error.code
+    );
+    let response = create(r#"{"size":9,"komi":0.5}"#);
+    assert_eq!(Status::Created, response.status());
+    // The creator is seated by the cookie written with the response
+    let name = response.cookies().iter().next().unwrap().name().to_string();
+    let cookie = response.cookies().get_private(&name).unwrap().into_owned();
+    let game_id = response.into_json::<GameCreatedMessage>().unwrap().id;
+    assert_eq!(players::cookie_name(&game_id), name);
+    assert_eq!(
+        Some((0.5, Some(board::Stone::Black))),
+        registry.with(&game_id, |game| (game.komi(), game.host()))
+    );
+
+    let response = client.get(format!("/api/v1/games/{}", game_id)).dispatch();
+    assert_eq!(Status::Ok, response.status());
+    let response = client
+        .post(format!("/api/v1/games/{}/join", game_id))
+        .dispatch();
+    assert_eq!(
+        Some(GameStateMessage::Join { id: game_id }),
+        response.into_json::<GameStateMessage>()
+    );
+
+    let seat = players::seat_cookie(&game_id, cookie.value().to_string());
+    let response = client
+        .put(format!("/api/v1/games/{}/moves", game_id))
+        .header(ContentType::JSON)
+        .private_cookie(seat.clone())
+        .body(r#"{"move":"e5"}"#)
+        .dispatch();
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!(
+        Some(board::Stone::Black),
+        registry
+            .with(&game_id, |game| game.stone_at((4, 4)))
+            .flatten()
+    );
+    let pass = |seat: Option<rocket::http::Cookie<'static>>| {
+        let request = client.post(format!("/api/v1/games/{}/pass", game_id));
+        match seat {
+            Some(seat) => request.private_cookie(seat),
+            None => request,
+        }
+        .dispatch()
+        .status()
+    };
+    // Only the players can move, each on their turn
+    assert_eq!(Status::Forbidden, pass(None));
+    assert_eq!(Status::UnprocessableEntity, pass(Some(seat)));
+}
+
+#[test]
+fn test_cors() {
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+
+    let mut cors = cors::Cors::default();
+    cors.allowed_origins = vec!["https://example.com".to_string()];
+    let rocket = rocket::build()
+        .manage(GameRegistry::default())
+        .attach(cors)
+        .register("/", catchers![not_found])
+        .mount("/", routes![api_state, serve_protocol_schema]);
+    let client = Client::untracked(rocket).unwrap();
+    let origin = |origin: &'static str| Header::new("Origin", origin);
+    let path = format!("/api/v1/games/{}", Uuid::new_v4());
+
+    let response = client
+        .options(path.clone())
+        .header(origin("https://example.com"))
+        .header(Header::new("Access-Control-Request-Method", "PUT"))
+        .dispatch();
+    assert_eq!(Status::NoContent, response.status());
+    let headers = response.headers();
+    assert_eq!(
+        Some("https://example.com"),
+        headers.get_one("Access-Control-Allow-Origin")
+    );
+    assert_eq!(
+        Some("GET, POST, PUT"),
+        headers.get_one("Access-Control-Allow-Methods")
+    );
+    assert_eq!(None, headers.get_one("Access-Control-Allow-Credentials"));
+
+    // Other origins are refused
+    let response = client
+        .options(path.clone())
+        .header(origin("https://example.org"))
+        .header(Header::new("Access-Control-Request-Method", "PUT"))
+        .dispatch();
+    assert_eq!(Status::NotFound, response.status());
+    assert_eq!(
+        None,
+        response.headers().get_one("Access-Control-Allow-Origin")
+    );
+
+    // Errors from the API can be read cross-origin too, pages outside it can't
+    let response = client
+        .get(path)
+        .header(origin("https://example.com"))
+        .dispatch();
+    assert_eq!(Status::NotFound, response.status());
+    assert_eq!(
+        Some("https://example.com"),
+        response.headers().get_one("Access-Control-Allow-Origin")
+    );
+    let response = client
+        .get("/protocol.json")
+        .header(origin("https://example.com"))
+        .dispatch();
+    assert_eq!(
+        None,
+        response.headers().get_one("Access-Control-Allow-Origin")
+    );
+}
+
+#[test]
+fn test_health() {
+    use rocket::local::blocking::Client;
+    use std::sync::mpsc;
+
+    let registry = GameRegistry::default();
+    registry
+        .insert(Uuid::new_v4(), board::new(board::Size::Nine))
+        .unwrap();
+    let (outbox, relayed) = mpsc::channel();
+    let queue = EventQueue::new(16).relayed(outbox);
+    let rocket = rocket::build()
+        .manage(registry)
+        .manage(queue.clone())
+        .mount("/", routes![serve_health, serve_readiness]);
+    let client = Client::untracked(rocket).unwrap();
+
+    let response = client.get("/healthz").dispatch();
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!("ok", response.into_json::<HealthMessage>().unwrap().status);
+
+    let response = client.get("/readyz").dispatch();
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!(
+        ReadinessMessage {
+            ready: true,
+            storage: true,
+            events: true,
+            games: 1,
+        },
+        response.into_json().unwrap()
+    );
+
+    // Events that can't reach the other servers leave the server unready
+    drop(relayed);
+    let _ = queue.send(None, GameStateMessage::Maintenance { announcement: None });
+    let response = client.get("/readyz").dispatch();
+    assert_eq!(Status::ServiceUnavailable, response.status());
+    assert_eq!(
+        false,
+        response.into_json::<ReadinessMessage>().unwrap().events
+    );
 }
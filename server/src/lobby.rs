@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use rocket::figment::Figment;
+use rocket::serde::uuid::Uuid;
+
+use crate::registry;
+
+// DEFAULT_AWAY is how long the creator of an open game can be away before it leaves the lobby, in
+// seconds.
+pub const DEFAULT_AWAY: u64 = 10 * 60;
+
+// Lobby decides which open games are listed, by keeping track of whether their creators are still
+// around. A creator is present while they are subscribed to their game's events and is last seen
+// when they unsubscribe or poll for them, creators not seen since the server started are taken to
+// have been seen when it did or when their game was last active. Games whose creators have been
+// away for longer than `away` seconds leave the lobby, and are listed again when their creators
+// return if `relist` is set. An `away` of 0 lists every open game.
+//
+// e.g. in Rocket.toml:
+//  [default.lobby]
+//  away = 600
+//  relist = true
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Lobby {
+    pub away: u64,
+    pub relist: bool,
+    // started is when the server started, in milliseconds since the UNIX epoch.
+    #[serde(skip, default = "registry::now")]
+    started: u64,
+    #[serde(skip)]
+    hosts: Arc<Mutex<HashMap<Uuid, Presence>>>,
+}
+
+// Presence is what is known of the creator of an open game: how many of their subscriptions to
+// its events are open, when they were last seen and whether their game left the lobby.
+#[derive(Debug, Default)]
+struct Presence {
+    connections: usize,
+    seen: u64,
+    delisted: bool,
+}
+
+impl Default for Lobby {
+    fn default() -> Lobby {
+        Lobby {
+            away: DEFAULT_AWAY,
+            relist: true,
+            started: registry::now(),
+            hosts: Arc::default(),
+        }
+    }
+}
+
+impl Lobby {
+    pub fn from_figment(figment: &Figment) -> Lobby {
+        figment.extract_inner("lobby").unwrap_or_default()
+    }
+
+    // arrive counts the creator of a game as present while the guard it returns is held.
+    pub fn arrive(&self, game_id: Uuid) -> Present {
+        let mut hosts = self.hosts.lock().unwrap();
+        let presence = hosts.entry(game_id).or_default();
+        presence.connections += 1;
+        if self.relist {
+            presence.delisted = false;
+        }
+        Present {
+            lobby: self.clone(),
+            game_id,
+        }
+    }
+
+    // seen records the creator of a game being around at `at`, in milliseconds since the UNIX
+    // epoch, e.g. polling for its events.
+    pub fn seen(&self, game_id: Uuid, at: u64) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let presence = hosts.entry(game_id).or_default();
+        presence.seen = presence.seen.max(at);
+        if self.relist {
+            presence.delisted = false;
+        }
+    }
+
+    // leave closes one of the creator's subscriptions at `at`.
+    fn leave(&self, game_id: Uuid, at: u64) {
+        let mut hosts = self.hosts.lock().unwrap();
+        if let Some(presence) = hosts.get_mut(&game_id) {
+            presence.connections = presence.connections.saturating_sub(1);
+            presence.seen = presence.seen.max(at);
+        }
+    }
+
+    // listed tests if an open game last active at `active` is in the lobby at `now`, in
+    // milliseconds since the UNIX epoch, taking games whose creators have been away too long out
+    // of it.
+    pub fn listed(&self, game_id: &Uuid, active: Option<u64>, now: u64) -> bool {
+        if self.away == 0 {
+            return true;
+        }
+        let mut hosts = self.hosts.lock().unwrap();
+        let presence = hosts.entry(*game_id).or_default();
+        if presence.connections == 0 && !presence.delisted {
+            let seen = [Some(presence.seen), Some(self.started), active]
+                .into_iter()
+                .flatten()
+                .max()
+                .unwrap_or(0);
+            presence.delisted = now.saturating_sub(seen) > self.away * 1000;
+        }
+        !presence.delisted
+    }
+
+    // retain forgets the creators of the games that are no longer open, unless they are still
+    // subscribed.
+    pub fn retain(&self, open: impl Fn(&Uuid) -> bool) {
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts.retain(|game_id, presence| presence.connections > 0 || open(game_id));
+    }
+}
+
+// Present is the creator of a game counted as present while they are subscribed to its events.
+pub struct Present {
+    lobby: Lobby,
+    game_id: Uuid,
+}
+
+impl Drop for Present {
+    fn drop(&mut self) {
+        self.lobby.leave(self.game_id, registry::now());
+    }
+}
+
+#[test]
+fn test_lobby() {
+    const MINUTE: u64 = 60_000;
+    let lobby = Lobby {
+        away: 60,
+        started: 0,
+        ..Lobby::default()
+    };
+    let game_id = Uuid::new_v4();
+    let active = Some(MINUTE);
+
+    // A new game is listed until its creator has been away too long since it was created
+    assert_eq!(true, lobby.listed(&game_id, active, 2 * MINUTE));
+    assert_eq!(false, lobby.listed(&game_id, active, 2 * MINUTE + 1));
+
+    // It comes back while its creator is subscribed, and stays while any subscription is open
+    let first = lobby.arrive(game_id);
+    assert_eq!(true, lobby.listed(&game_id, active, 10 * MINUTE));
+    let second = lobby.arrive(game_id);
+    drop(first);
+    assert_eq!(true, lobby.listed(&game_id, active, 1000 * MINUTE));
+    let left = registry::now();
+    drop(second);
+    assert_eq!(true, lobby.listed(&game_id, active, left + MINUTE / 2));
+    assert_eq!(false, lobby.listed(&game_id, active, left + 2 * MINUTE));
+    // Polling counts as coming back
+    lobby.seen(game_id, left + 2 * MINUTE);
+    assert_eq!(true, lobby.listed(&game_id, active, left + 3 * MINUTE));
+
+    // Games aren't listed again unless it is asked for
+    let lobby = Lobby {
+        relist: false,
+        ..lobby
+    };
+    let other = Uuid::new_v4();
+    assert_eq!(false, lobby.listed(&other, active, 3 * MINUTE));
+    let _present = lobby.arrive(other);
+    assert_eq!(false, lobby.listed(&other, active, 3 * MINUTE));
+
+    // A server that just started waits for creators to come back
+    let lobby = Lobby::default();
+    assert_eq!(true, lobby.listed(&other, active, registry::now()));
+    let lobby = Lobby { away: 0, ..lobby };
+    assert_eq!(true, lobby.listed(&other, active, u64::MAX));
+
+    lobby.retain(|_| false);
+    assert_eq!(true, lobby.hosts.lock().unwrap().is_empty());
+}
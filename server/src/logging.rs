@@ -0,0 +1,162 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::figment::Figment;
+use rocket::http::Header;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::uuid::Uuid;
+use rocket::{Data, Response};
+use tracing::field::{self, Empty};
+use tracing::span::Entered;
+use tracing::Span;
+use tracing_subscriber::EnvFilter;
+
+use crate::board::Stone;
+
+// REQUEST_ID_HEADER carries the id of a request, given by a proxy in front of the server or
+// generated when it isn't. The id is sent back with the response.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+// LogFormat is how log lines are written, Json for log collectors. Lines are filtered with
+// RUST_LOG, e.g. RUST_LOG=server=debug, and everything at info and above is written without it.
+//
+// e.g. in Rocket.toml:
+//  [release]
+//  log_format = "json"
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    pub fn from_figment(figment: &Figment) -> LogFormat {
+        figment.extract_inner("log_format").unwrap_or_default()
+    }
+
+    // init starts writing logs, Rocket's own included. Only the first call has any effect.
+    pub fn init(self) {
+        let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+        let logs = tracing_subscriber::fmt().with_env_filter(filter);
+        let _ = match self {
+            LogFormat::Text => logs.try_init(),
+            LogFormat::Json => logs.json().try_init(),
+        };
+    }
+}
+
+// RequestSpan is the span a request is handled in, naming the request, the game it is about and,
+// once they are known, the stone of the player making it. Routes enter it so what they log can be
+// traced back to the request.
+#[derive(Clone)]
+pub struct RequestSpan {
+    pub id: String,
+    span: Span,
+}
+
+impl RequestSpan {
+    // of returns the span of a request, opening it on first use.
+    pub fn of<'r>(request: &'r Request<'_>) -> &'r RequestSpan {
+        request.local_cache(|| {
+            let id = request
+                .headers()
+                .get_one(REQUEST_ID_HEADER)
+                .filter(|id| valid_id(id))
+                .map_or_else(|| Uuid::new_v4().simple().to_string(), str::to_string);
+            let span = tracing::info_span!(
+                "request",
+                id = %id,
+                method = %request.method(),
+                path = %request.uri().path(),
+                game_id = Empty,
+                stone = Empty,
+            );
+            let game_id = request
+                .uri()
+                .path()
+                .segments()
+                .find_map(|segment| segment.parse::<Uuid>().ok());
+            if let Some(game_id) = game_id {
+                span.record("game_id", field::display(game_id));
+            }
+            RequestSpan { id, span }
+        })
+    }
+
+    pub fn enter(&self) -> Entered<'_> {
+        self.span.enter()
+    }
+
+    // seated records the stone of the player making the request.
+    pub fn seated(&self, stone: Stone) {
+        self.span.record("stone", field::debug(stone));
+    }
+}
+
+// valid_id tests if a request id given by a proxy is safe to log and send back.
+fn valid_id(id: &str) -> bool {
+    (1..=64).contains(&id.len())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestSpan {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RequestSpan::of(request).clone())
+    }
+}
+
+// RequestLog opens a span for every request and logs each response in it, sending the request's
+// id back in REQUEST_ID_HEADER.
+pub struct RequestLog;
+
+#[rocket::async_trait]
+impl Fairing for RequestLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request log",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        RequestSpan::of(request);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let span = RequestSpan::of(request);
+        span.span.in_scope(|| {
+            tracing::info!(status = response.status().code, "Answered request");
+        });
+        response.set_header(Header::new(REQUEST_ID_HEADER, span.id.clone()));
+    }
+}
+
+#[test]
+fn test_request_span() {
+    use rocket::local::blocking::Client;
+
+    let client = Client::untracked(rocket::build().attach(RequestLog)).unwrap();
+    let response = client.get("/").dispatch();
+    let id = response.headers().get_one(REQUEST_ID_HEADER).unwrap();
+    assert_eq!(32, id.len());
+
+    // Ids from a proxy are kept when they are safe to log
+    let given = |id: &'static str| {
+        let response = client
+            .get("/")
+            .header(Header::new(REQUEST_ID_HEADER, id))
+            .dispatch();
+        response
+            .headers()
+            .get_one(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!("lb-1234", given("lb-1234"));
+    assert_eq!(32, given("a b;c").len());
+}
@@ -0,0 +1,82 @@
+use std::sync::{Arc, Mutex};
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+
+// Maintenance holds the announcement shown while the server is in maintenance mode, and when it
+// started. No new games can be started and clocks are paused until it ends. Clones share the
+// same mode.
+#[derive(Default, Clone)]
+pub struct Maintenance {
+    announcement: Arc<Mutex<Option<(String, u64)>>>,
+}
+
+impl Maintenance {
+    pub fn announcement(&self) -> Option<String> {
+        let announcement = self.announcement.lock().unwrap();
+        announcement.as_ref().map(|(text, _)| text.clone())
+    }
+
+    pub fn active(&self) -> bool {
+        self.announcement.lock().unwrap().is_some()
+    }
+
+    // start announces maintenance from `at`, in milliseconds since the UNIX epoch. A new
+    // announcement keeps the time maintenance started.
+    pub fn start(&self, announcement: String, at: u64) {
+        let mut current = self.announcement.lock().unwrap();
+        let started = current.as_ref().map_or(at, |(_, started)| *started);
+        *current = Some((announcement, started));
+    }
+
+    // end returns when the maintenance that ended started, if there was any.
+    pub fn end(&self) -> Option<u64> {
+        let ended = self.announcement.lock().unwrap().take();
+        ended.map(|(_, started)| started)
+    }
+}
+
+// AdminToken is the bearer token operators authenticate with, read from `admin_token`. Admin
+// routes are refused when it isn't set.
+pub struct AdminToken(pub Option<String>);
+
+// Admin is a request guard for operator routes.
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let expected = match request.rocket().state::<AdminToken>() {
+            Some(AdminToken(Some(token))) => token,
+            _ => return Outcome::Failure((Status::Forbidden, ())),
+        };
+        let given = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match given {
+            Some(token) if token == expected => Outcome::Success(Admin),
+            _ => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[test]
+fn test_maintenance() {
+    let maintenance = Maintenance::default();
+    assert_eq!(false, maintenance.active());
+
+    maintenance.start("Restarting at 12:00 UTC".to_string(), 1_000);
+    assert_eq!(true, maintenance.active());
+    assert_eq!(
+        Some("Restarting at 12:00 UTC".to_string()),
+        maintenance.announcement()
+    );
+
+    maintenance.start("Restarting at 12:30 UTC".to_string(), 2_000);
+    assert_eq!(Some(1_000), maintenance.end());
+    assert_eq!(None, maintenance.announcement());
+    assert_eq!(None, maintenance.end());
+}
@@ -0,0 +1,471 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lettre::message::Mailbox;
+use lettre::{Message, SendmailTransport, Transport};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::figment::Figment;
+use rocket::serde::json::to_string;
+use rocket::serde::uuid::Uuid;
+use rocket::tokio::select;
+use rocket::tokio::time::interval;
+use rocket::{Orbit, Rocket};
+use rustigo_protocol::{GameOverNotice, TurnNotice};
+use thiserror::Error;
+
+use crate::board::{GamePhase, Stone};
+use crate::clock;
+use crate::dispatcher::{Channel, DispatchError, Dispatcher, Notice};
+use crate::ledger;
+use crate::outbound::{self, Outbound};
+use crate::registry::{self, GameRegistry};
+use crate::webhook;
+
+// DEFAULT_SENDMAIL is the command email is handed to.
+pub const DEFAULT_SENDMAIL: &str = "/usr/sbin/sendmail";
+
+// SWEEP_INTERVAL is how often games are looked at for players whose turn it has become.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum NotifyError {
+    #[error("Players are told at a mailto: address or a public http(s):// webhook, not {0:?}")]
+    BadContact(String),
+    #[error("This server doesn't send email")]
+    NoEmail,
+    #[error("The notice could not be delivered: {0}")]
+    Undelivered(String),
+}
+
+// Contact is where a player asked to be told it is their turn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Contact {
+    Email(String),
+    Webhook(String),
+}
+
+impl Contact {
+    // parse reads a contact as a `mailto:` address or an `http://` or `https://` URL.
+    //
+    // e.g:
+    //  "mailto:player@example.com" => email to player@example.com
+    //  "https://example.com/rustigo" => a POST to that URL
+    pub fn parse(value: &str) -> Result<Contact, NotifyError> {
+        let value = value.trim();
+        let bad = || NotifyError::BadContact(value.to_string());
+        if value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(bad());
+        }
+        if let Some(address) = value.strip_prefix("mailto:") {
+            return match address.split_once('@') {
+                Some((user, host)) if !user.is_empty() && !host.is_empty() => {
+                    Ok(Contact::Email(address.to_string()))
+                }
+                _ => Err(bad()),
+            };
+        }
+        outbound::check_url(value).map_err(|_| bad())?;
+        Ok(Contact::Webhook(value.to_string()))
+    }
+}
+
+// Contact is written the way it is parsed.
+impl fmt::Display for Contact {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Contact::Email(address) => write!(formatter, "mailto:{}", address),
+            Contact::Webhook(url) => write!(formatter, "{}", url),
+        }
+    }
+}
+
+// parse_contact reads a contact from the `bnotify` and `wnotify` segments of a game record, which
+// are hex encoded as URLs can hold any of the separators.
+pub fn parse_contact(value: &str) -> Option<Contact> {
+    Contact::parse(&ledger::unhex(value)?).ok()
+}
+
+// format_contact writes a contact for a game record, it is the inverse of parse_contact.
+pub fn format_contact(contact: &Contact) -> String {
+    ledger::hex(&contact.to_string())
+}
+
+// Notifier tells players who asked for it when it becomes their turn and when their game is over,
+// with an email handed to `sendmail` or a JSON POST of a TurnNotice or GameOverNotice to their
+// webhook, sent by the Dispatcher. Email is only sent when `from` is configured, links to the game
+// when `base_url` is. Each turn is told once, although a restart may tell the turns being waited
+// on again.
+//
+// e.g. in Rocket.toml:
+//  [default.notify]
+//  from = "rustigo@example.com"
+//  sendmail = "/usr/sbin/sendmail"
+//  base_url = "https://go.example.com"
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Notifier {
+    pub from: Option<String>,
+    pub sendmail: String,
+    pub base_url: Option<String>,
+    // told holds the turn each game's player was last told of, as the stone to move and the
+    // number of moves played.
+    #[serde(skip)]
+    told: Arc<Mutex<HashMap<Uuid, (Stone, usize)>>>,
+}
+
+impl Default for Notifier {
+    fn default() -> Notifier {
+        Notifier {
+            from: None,
+            sendmail: DEFAULT_SENDMAIL.to_string(),
+            base_url: None,
+            told: Arc::default(),
+        }
+    }
+}
+
+impl Notifier {
+    pub fn from_figment(figment: &Figment) -> Notifier {
+        figment.extract_inner("notify").unwrap_or_default()
+    }
+
+    // check refuses contacts this server can't reach.
+    pub fn check(&self, contact: &Contact) -> Result<(), NotifyError> {
+        match contact {
+            Contact::Email(_) if self.from.is_none() => Err(NotifyError::NoEmail),
+            _ => Ok(()),
+        }
+    }
+
+    // due finds the players whose turn it has become since the last look and who asked to be
+    // told, returning where to tell them and what.
+    pub fn due(&self, registry: &GameRegistry) -> Vec<(Contact, TurnNotice)> {
+        let turns = registry.filter_map(|(game_id, game)| {
+            if game.phase() != GamePhase::Playing || !game.guest_seated() {
+                return None;
+            }
+            let stone = game.turn();
+            let contact = game.contact(stone).cloned();
+            let deadline = game.deadline();
+            Some((*game_id, (stone, game.move_number()), contact, deadline))
+        });
+        let mut told = self.told.lock().unwrap();
+        // Games no longer waiting on anyone are forgotten
+        told.retain(|game_id, _| turns.iter().any(|(waiting, ..)| waiting == game_id));
+        turns
+            .into_iter()
+            .filter_map(|(game_id, turn, contact, deadline)| {
+                if told.insert(game_id, turn) == Some(turn) {
+                    return None;
+                }
+                let notice = TurnNotice {
+                    game_id,
+                    stone: turn.0,
+                    deadline,
+                    url: self.url(game_id),
+                };
+                Some((contact?, notice))
+            })
+            .collect()
+    }
+
+    // channel is how a player is told at their contact.
+    pub fn channel(&self, contact: &Contact) -> Result<Box<dyn Channel>, NotifyError> {
+        match contact {
+            Contact::Email(address) => {
+                let from = self.from.as_ref().ok_or(NotifyError::NoEmail)?;
+                Ok(Box::new(Email {
+                    from: from
+                        .parse()
+                        .map_err(|err| NotifyError::Undelivered(format!("{:?}", err)))?,
+                    to: address
+                        .parse()
+                        .map_err(|_| NotifyError::BadContact(contact.to_string()))?,
+                    sendmail: self.sendmail.clone(),
+                }))
+            }
+            Contact::Webhook(url) => Ok(Box::new(Post(url.clone()))),
+        }
+    }
+
+    // turn_notice is what a player is sent when it becomes their turn, `now` is in milliseconds
+    // since the UNIX epoch.
+    pub fn turn_notice(&self, notice: &TurnNotice, now: u64) -> Notice {
+        Notice {
+            event: "turn".to_string(),
+            subject: format!("It's your turn to play {:?}", notice.stone),
+            text: describe(notice, now),
+            body: to_string(notice).unwrap(),
+        }
+    }
+
+    // over finds the players of a game who asked to be told of their turns, to tell them it is
+    // over.
+    pub fn over(&self, registry: &GameRegistry, game_id: Uuid) -> Vec<(Contact, GameOverNotice)> {
+        registry
+            .with(&game_id, |game| {
+                [Stone::Black, Stone::White]
+                    .into_iter()
+                    .filter_map(|stone| {
+                        let notice = GameOverNotice {
+                            game_id,
+                            stone,
+                            winner: game.winner(),
+                            url: self.url(game_id),
+                        };
+                        Some((game.contact(stone)?.clone(), notice))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // over_notice is what a player is sent when their game is over.
+    pub fn over_notice(&self, notice: &GameOverNotice) -> Notice {
+        Notice {
+            event: "game_over".to_string(),
+            subject: format!("Your game as {:?} is over", notice.stone),
+            text: describe_over(notice),
+            body: to_string(notice).unwrap(),
+        }
+    }
+
+    // url is a game's page, if the server knows where it is served from.
+    fn url(&self, game_id: Uuid) -> Option<String> {
+        self.base_url
+            .as_ref()
+            .map(|base| format!("{}/{}/game.html", base.trim_end_matches('/'), game_id))
+    }
+}
+
+// Email is a notice handed to `sendmail` for a player's address.
+struct Email {
+    from: Mailbox,
+    to: Mailbox,
+    sendmail: String,
+}
+
+impl Channel for Email {
+    fn kind(&self) -> &'static str {
+        "email"
+    }
+
+    fn send(&self, _: &Outbound, notice: &Notice) -> Result<(), DispatchError> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(self.to.clone())
+            .subject(notice.subject.clone())
+            .body(notice.text.clone())
+            .map_err(|err| DispatchError::Refused(format!("{:?}", err)))?;
+        SendmailTransport::new_with_command(&self.sendmail)
+            .send(&message)
+            .map_err(|err| DispatchError::Failed(format!("{:?}", err)))
+    }
+}
+
+// Post is a notice's body POSTed to a player's webhook, named in EVENT_HEADER.
+struct Post(String);
+
+impl Channel for Post {
+    fn kind(&self) -> &'static str {
+        "notify_webhook"
+    }
+
+    fn send(&self, outbound: &Outbound, notice: &Notice) -> Result<(), DispatchError> {
+        let headers = [(webhook::EVENT_HEADER, notice.event.as_str())];
+        Ok(outbound.post(&self.0, &headers, &notice.body)?)
+    }
+}
+
+// describe writes a notice out for an email.
+fn describe(notice: &TurnNotice, now: u64) -> String {
+    let mut text = format!(
+        "It's your turn to play {:?} in game {}.\n",
+        notice.stone, notice.game_id
+    );
+    if let Some(deadline) = notice.deadline {
+        text.push_str(&format!(
+            "You have {} to move.\n",
+            clock::format_duration(deadline.saturating_sub(now))
+        ));
+    }
+    if let Some(url) = &notice.url {
+        text.push_str(&format!("\n{}\n", url));
+    }
+    text
+}
+
+// describe_over writes the end of a game out for an email.
+fn describe_over(notice: &GameOverNotice) -> String {
+    let mut text = match notice.winner {
+        Some(winner) if winner == notice.stone => {
+            format!("You won game {} as {:?}.\n", notice.game_id, notice.stone)
+        }
+        Some(_) => format!("You lost game {} as {:?}.\n", notice.game_id, notice.stone),
+        None => format!("Game {} ended in a draw.\n", notice.game_id),
+    };
+    if let Some(url) = &notice.url {
+        text.push_str(&format!("\n{}\n", url));
+    }
+    text
+}
+
+// notify tells every player whose turn it has become, returning how many notices were sent off.
+pub fn notify(
+    notifier: &Notifier,
+    dispatcher: &Dispatcher,
+    registry: &GameRegistry,
+    now: u64,
+) -> usize {
+    let mut sent = 0;
+    for (contact, notice) in notifier.due(registry) {
+        let queued = notifier.channel(&contact).and_then(|channel| {
+            dispatcher
+                .send(channel, notifier.turn_notice(&notice, now))
+                .map_err(|err| NotifyError::Undelivered(err.to_string()))
+        });
+        match queued {
+            Ok(()) => sent += 1,
+            Err(err) => tracing::error!(
+                game_id = %notice.game_id,
+                stone = ?notice.stone,
+                error = %err,
+                "Failed to notify player"
+            ),
+        }
+    }
+    sent
+}
+
+#[rocket::async_trait]
+impl Fairing for Notifier {
+    fn info(&self) -> Info {
+        Info {
+            name: "Notifier",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let (registry, dispatcher) =
+            match (rocket.state::<GameRegistry>(), rocket.state::<Dispatcher>()) {
+                (Some(registry), Some(dispatcher)) => (registry.clone(), dispatcher.clone()),
+                _ => return,
+            };
+        let notifier = self.clone();
+        let mut end = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            let mut sweeps = interval(SWEEP_INTERVAL);
+            loop {
+                select! {
+                    _ = sweeps.tick() => (),
+                    _ = &mut end => break,
+                }
+                notify(&notifier, &dispatcher, &registry, registry::now());
+            }
+        });
+    }
+}
+
+#[test]
+fn test_contact() {
+    let cases = [
+        (
+            "mailto:player@example.com",
+            Contact::Email("player@example.com".to_string()),
+        ),
+        (
+            "https://example.com/hook?a=b;c",
+            Contact::Webhook("https://example.com/hook?a=b;c".to_string()),
+        ),
+    ];
+    for (value, contact) in cases {
+        assert_eq!(Ok(contact.clone()), Contact::parse(value));
+        assert_eq!(value, contact.to_string());
+        let formatted = format_contact(&contact);
+        assert_eq!(false, formatted.contains([':', ';', '=']));
+        assert_eq!(Some(contact), parse_contact(&formatted));
+    }
+    for value in [
+        "mailto:player",
+        "ftp://example.com",
+        "https://",
+        "mailto:a b@c",
+        "http://localhost/turn",
+    ] {
+        assert_eq!(
+            Err(NotifyError::BadContact(value.to_string())),
+            Contact::parse(value)
+        );
+    }
+}
+
+#[test]
+fn test_due() {
+    use crate::board::{self, Size};
+
+    let registry = GameRegistry::default();
+    let notifier = Notifier {
+        base_url: Some("https://go.example.com/".to_string()),
+        ..Notifier::default()
+    };
+    let game_id = Uuid::new_v4();
+    let mut game = board::new(Size::Nine);
+    game.set_time_control(clock::TimeControl::parse("3d/move").unwrap(), 0);
+    game.set_contact(
+        Stone::White,
+        Some(Contact::Webhook("https://example.com/white".to_string())),
+    );
+    registry.insert(game_id, game.clone()).unwrap();
+
+    // No one is told while they wait for an opponent
+    assert_eq!(true, notifier.due(&registry).is_empty());
+    game.seat_guest();
+    registry.insert(game_id, game).unwrap();
+    // Black didn't ask to be told
+    assert_eq!(true, notifier.due(&registry).is_empty());
+
+    registry
+        .update(
+            game_id,
+            || Err(()),
+            |game| game.pass(Stone::Black).map_err(|_| ()),
+        )
+        .unwrap()
+        .unwrap();
+    let due = notifier.due(&registry);
+    assert_eq!(1, due.len());
+    let (contact, notice) = &due[0];
+    assert_eq!(
+        &Contact::Webhook("https://example.com/white".to_string()),
+        contact
+    );
+    assert_eq!(Stone::White, notice.stone);
+    assert_eq!(
+        registry.with(&game_id, |game| game.deadline()).flatten(),
+        notice.deadline
+    );
+    assert_eq!(
+        Some(format!("https://go.example.com/{}/game.html", game_id)),
+        notice.url
+    );
+    let deadline = notice.deadline.unwrap();
+    assert_eq!(
+        true,
+        describe(notice, deadline - 259_200_000).contains("You have 3d to move")
+    );
+
+    // Each turn is told once
+    assert_eq!(true, notifier.due(&registry).is_empty());
+
+    // Only servers that send email take email addresses
+    let email = Contact::Email("player@example.com".to_string());
+    assert_eq!(Err(NotifyError::NoEmail), notifier.check(&email));
+    let notifier = Notifier {
+        from: Some("rustigo@example.com".to_string()),
+        ..Notifier::default()
+    };
+    assert_eq!(Ok(()), notifier.check(&email));
+}
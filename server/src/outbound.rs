@@ -0,0 +1,283 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rocket::figment::Figment;
+use thiserror::Error;
+
+// TIMEOUT is how long a server has to answer a request.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// DEFAULT_WORKERS is how many requests are sent at once by default, DEFAULT_QUEUE how many more
+// can wait for a worker.
+pub const DEFAULT_WORKERS: usize = 8;
+pub const DEFAULT_QUEUE: usize = 1024;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum OutboundError {
+    #[error("Requests are only sent to public http:// or https:// URLs, not {0:?}")]
+    BadUrl(String),
+    #[error("The request was answered with status {0}")]
+    Status(u16),
+    #[error("The request failed: {0}")]
+    Failed(String),
+    #[error("Too many requests are waiting to be sent")]
+    Busy,
+}
+
+impl OutboundError {
+    // retryable tests if a request that failed may be taken if it is sent again.
+    pub fn retryable(&self) -> bool {
+        match self {
+            OutboundError::Status(status) => *status >= 500 || *status == 429,
+            OutboundError::Failed(_) => true,
+            OutboundError::BadUrl(_) | OutboundError::Busy => false,
+        }
+    }
+}
+
+// public tests if an address is on the internet, rather than the server's own host or network:
+// loopback, private, link local (cloud metadata services among them), shared and reserved
+// ranges are not.
+pub fn public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+// check_url refuses URLs requests can't be sent to: anything but http:// and https://, and hosts
+// that are plainly the server's own network. Names are looked up when a request is sent, as they
+// can point elsewhere by then.
+pub fn check_url(url: &str) -> Result<(), OutboundError> {
+    let bad = || OutboundError::BadUrl(url.to_string());
+    if url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(bad());
+    }
+    let rest = match url.split_once("://") {
+        Some(("http" | "https", rest)) => rest,
+        _ => return Err(bad()),
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let netloc = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = match netloc.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+        None => netloc.split(':').next().unwrap_or_default(),
+    };
+    let host = host.to_ascii_lowercase();
+    let private = match host.parse::<IpAddr>() {
+        Ok(ip) => !public(ip),
+        Err(_) => host == "localhost" || host.ends_with(".localhost"),
+    };
+    if host.is_empty() || private {
+        return Err(bad());
+    }
+    Ok(())
+}
+
+// Settings are how many requests are sent at once and how many can wait, requests past those
+// are dropped. Servers on private networks are only sent requests when `allow_private` is set.
+//
+// e.g. in Rocket.toml:
+//  [default.outbound]
+//  workers = 8
+//  queue = 1024
+//  allow_private = false
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub workers: usize,
+    pub queue: usize,
+    pub allow_private: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            workers: DEFAULT_WORKERS,
+            queue: DEFAULT_QUEUE,
+            allow_private: false,
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// Outbound sends the server's requests to other servers, webhooks and the like, on a fixed set
+// of worker threads. Every address a name is looked up to is checked before it is connected to,
+// and redirects aren't followed, so a URL can't reach the server's own network.
+#[derive(Clone)]
+pub struct Outbound {
+    agent: ureq::Agent,
+    jobs: SyncSender<Job>,
+    allow_private: bool,
+}
+
+impl Outbound {
+    pub fn from_figment(figment: &Figment) -> Outbound {
+        Outbound::new(figment.extract_inner("outbound").unwrap_or_default())
+    }
+
+    pub fn new(settings: Settings) -> Outbound {
+        let allow_private = settings.allow_private;
+        let agent = ureq::AgentBuilder::new()
+            .timeout(TIMEOUT)
+            .redirects(0)
+            .resolver(move |netloc: &str| resolve(netloc, allow_private))
+            .build();
+
+        let (jobs, waiting) = mpsc::sync_channel::<Job>(settings.queue);
+        let waiting = Arc::new(Mutex::new(waiting));
+        for _ in 0..settings.workers.max(1) {
+            let waiting = waiting.clone();
+            thread::spawn(move || loop {
+                let job = match waiting.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    // Every Outbound is gone
+                    Err(_) => return,
+                };
+                job();
+            });
+        }
+        Outbound {
+            agent,
+            jobs,
+            allow_private,
+        }
+    }
+
+    // spawn runs a job on a worker, refusing it when too many are waiting.
+    pub fn spawn(&self, job: impl FnOnce() + Send + 'static) -> Result<(), OutboundError> {
+        self.jobs
+            .try_send(Box::new(job))
+            .map_err(|_| OutboundError::Busy)
+    }
+
+    // post sends a JSON body to a URL once, with the given headers. Anything but a 2xx answer is
+    // a failure.
+    pub fn post(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> Result<(), OutboundError> {
+        if !self.allow_private {
+            check_url(url)?;
+        }
+        let mut request = self.agent.post(url).set("Content-Type", "application/json");
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        match request.send_string(body) {
+            Ok(response) if (200..300).contains(&response.status()) => Ok(()),
+            Ok(response) => Err(OutboundError::Status(response.status())),
+            Err(ureq::Error::Status(status, _)) => Err(OutboundError::Status(status)),
+            Err(ureq::Error::Transport(err)) => Err(OutboundError::Failed(err.to_string())),
+        }
+    }
+}
+
+// resolve looks up the addresses of a `host:port`, leaving out those on private networks unless
+// they are allowed.
+fn resolve(netloc: &str, allow_private: bool) -> io::Result<Vec<SocketAddr>> {
+    let addresses = netloc
+        .to_socket_addrs()?
+        .filter(|address| allow_private || public(address.ip()))
+        .collect::<Vec<_>>();
+    if addresses.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} has no public address", netloc),
+        ));
+    }
+    Ok(addresses)
+}
+
+#[test]
+fn test_check_url() {
+    for url in [
+        "https://example.com/hook?a=b;c",
+        "http://user@example.com:8080",
+        "http://8.8.8.8/",
+        "https://[2001:4860::8888]:443/",
+    ] {
+        assert_eq!(Ok(()), check_url(url));
+    }
+    for url in [
+        "ftp://example.com",
+        "https://",
+        "example.com",
+        "https://a b",
+        "http://localhost:8000/",
+        "http://127.0.0.1/",
+        "http://10.1.2.3/",
+        "http://192.168.0.1/",
+        "http://169.254.169.254/latest/meta-data/",
+        "http://100.64.0.1/",
+        "http://0.0.0.0/",
+        "http://[::1]/",
+        "http://[fd00::1]/",
+        "http://[::ffff:127.0.0.1]/",
+        "http://example.com@127.0.0.1/",
+    ] {
+        assert_eq!(Err(OutboundError::BadUrl(url.to_string())), check_url(url));
+    }
+}
+
+#[test]
+fn test_resolve() {
+    assert_eq!(true, resolve("127.0.0.1:80", false).is_err());
+    assert_eq!(true, resolve("[::1]:80", false).is_err());
+    assert_eq!(1, resolve("127.0.0.1:80", true).unwrap().len());
+    assert_eq!(1, resolve("8.8.8.8:80", false).unwrap().len());
+}
+
+#[test]
+fn test_spawn() {
+    let outbound = Outbound::new(Settings {
+        workers: 1,
+        queue: 1,
+        allow_private: false,
+    });
+    // The worker is kept busy and one job waits for it, there's no room for another
+    let (started, running) = mpsc::channel();
+    let (finish, finished) = mpsc::channel::<()>();
+    assert_eq!(
+        Ok(()),
+        outbound.spawn(move || {
+            started.send(()).unwrap();
+            let _ = finished.recv();
+        })
+    );
+    running.recv().unwrap();
+    assert_eq!(Ok(()), outbound.spawn(|| ()));
+    assert_eq!(Err(OutboundError::Busy), outbound.spawn(|| ()));
+    finish.send(()).unwrap();
+}
@@ -0,0 +1,554 @@
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::{from_str, to_string};
+use rocket::serde::uuid::Uuid;
+use thiserror::Error;
+
+use crate::board::Stone;
+use crate::logging::RequestSpan;
+use crate::registry::GameRegistry;
+
+// COOKIE_VERSION is the version of the seat cookies written by this server, cookies from a newer
+// server are dropped. Since version 2 seat cookies are private, so players can't edit them, and
+// name the game they are for so they can't be moved to another game. Since version 3 a game has
+// one seat cookie, naming the stone held, which may still be drawn.
+pub const COOKIE_VERSION: u8 = 3;
+
+// SeatState is kept in the `seat-<game_id>` cookie of a player seated in a game, with the token
+// issued for their seat as its key. The stone is None until it is drawn, it is filled in on the
+// player's first request after.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeatState {
+    #[serde(default)]
+    pub version: u8,
+    pub game_id: Uuid,
+    pub size: u8,
+    pub stone: Option<Stone>,
+    pub key: String,
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeatError {
+    #[error("There is no such game")]
+    UnknownGame,
+    #[error("You don't have a seat in this game")]
+    NoSeat,
+    #[error("Your seat cookie can't be read")]
+    BadCookie,
+    #[error("Your seat is in a different game")]
+    WrongGame,
+    #[error("This is your opponent's seat")]
+    WrongSeat,
+    #[error("Both seats in this game are taken")]
+    SeatTaken,
+    #[error("No one is waiting to join this game")]
+    NoJoin,
+    #[error("Your seat's token doesn't match the one issued for it")]
+    BadToken,
+    #[error("Your stones are drawn once your opponent joins")]
+    NotDrawn,
+}
+
+// PlayerSession is the caller seated in the game named by a route's `<game_id>`, which must be
+// the first segment of the route naming a game. Sessions are kept in the game's `seat-<game_id>`
+// cookie and must be for a game of the same size, holding the token issued for the seat, which
+// identifies the player and their stone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerSession {
+    pub game_id: Uuid,
+    pub stone: Stone,
+    pub size: u8,
+    pub token: String,
+}
+
+impl PlayerSession {
+    // start seats the caller in a game, with the token issued for their seat. The stone is None
+    // while it is still to be drawn.
+    pub fn start(
+        cookies: &CookieJar<'_>,
+        game_id: Uuid,
+        stone: Option<Stone>,
+        size: u8,
+        token: String,
+    ) {
+        let state = SeatState {
+            version: COOKIE_VERSION,
+            game_id,
+            size,
+            stone,
+            key: token,
+        };
+        let value = to_string(&state).unwrap();
+        cookies.add_private(seat_cookie(&game_id, value));
+        purge_shared_cookies(cookies);
+    }
+
+    // end gives up any seat the caller holds in a game.
+    pub fn end(cookies: &CookieJar<'_>, game_id: Uuid) {
+        cookies.remove_private(Cookie::named(cookie_name(&game_id)));
+        purge_shared_cookies(cookies);
+    }
+}
+
+// purge_shared_cookies removes the plain seat cookies from before seats were kept per game.
+fn purge_shared_cookies(cookies: &CookieJar<'_>) {
+    cookies.remove(Cookie::named("b"));
+    cookies.remove(Cookie::named("w"));
+}
+
+// Host is the session of whoever created the game, whether or not their stone has been drawn.
+pub struct Host {
+    pub game_id: Uuid,
+    pub size: u8,
+    pub token: String,
+}
+
+// Spectator is anyone looking at a game the server knows, seated or not. host is set for whoever
+// created the game, who has no seat while their stone is still to be drawn.
+pub struct Spectator {
+    pub game_id: Uuid,
+    pub seat: Option<PlayerSession>,
+    pub host: bool,
+}
+
+// game_id reads the game a route is about, its first segment naming a game, failing for routes
+// without one.
+fn game_id(request: &Request<'_>) -> Result<Uuid, (Status, SeatError)> {
+    request
+        .routed_segments(0..)
+        .find_map(|segment| segment.parse::<Uuid>().ok())
+        .ok_or((Status::NotFound, SeatError::UnknownGame))
+}
+
+// read_cookie decrypts and parses the seat cookie for a game. A cookie that can't be read, e.g. a
+// tampered one, one from another game or one written by a newer server, is dropped so the player
+// can join the game again.
+fn read_cookie(cookies: &CookieJar<'_>, game_id: &Uuid) -> Result<Option<SeatState>, SeatError> {
+    let name = cookie_name(game_id);
+    if cookies.get(&name).is_none() {
+        return Ok(None);
+    }
+    let state = cookies
+        .get_private(&name)
+        .and_then(|cookie| from_str::<SeatState>(cookie.value()).ok());
+    match state {
+        Some(state) if state.version <= COOKIE_VERSION && state.game_id == *game_id => {
+            Ok(Some(state))
+        }
+        _ => {
+            cookies.remove_private(Cookie::named(name));
+            Err(SeatError::BadCookie)
+        }
+    }
+}
+
+// seat_cookie builds the private cookie holding a seat in a game. Scripts can't read it and it is
+// only sent along with top level navigation from other sites.
+pub fn seat_cookie(game_id: &Uuid, value: String) -> Cookie<'static> {
+    Cookie::build(cookie_name(game_id), value)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .finish()
+}
+
+// cookie_name names the cookie holding a seat in a game, so one browser can play several games.
+pub fn cookie_name(game_id: &Uuid) -> String {
+    format!("seat-{}", game_id)
+}
+
+// Holder is a request's seat cookie checked against the game it is for.
+struct Holder {
+    game_id: Uuid,
+    stone: Option<Stone>,
+    size: u8,
+    token: String,
+    host: bool,
+}
+
+// resolve finds a request's seat in the game it is about.
+fn resolve(request: &Request<'_>) -> Result<Option<Holder>, (Status, SeatError)> {
+    resolve_seat(request, game_id(request)?)
+}
+
+// resolve_seat finds a request's seat in a game. The stone is looked up by the seat's token, a
+// cookie written before the stones were drawn is brought up to date. Seats in games the server
+// doesn't know aren't taken.
+fn resolve_seat(
+    request: &Request<'_>,
+    game_id: Uuid,
+) -> Result<Option<Holder>, (Status, SeatError)> {
+    let state = match read_cookie(request.cookies(), &game_id) {
+        Ok(Some(state)) => state,
+        Ok(None) => return Ok(None),
+        Err(err) => return Err((Status::BadRequest, err)),
+    };
+
+    let known = request
+        .rocket()
+        .state::<GameRegistry>()
+        .and_then(|registry| {
+            registry.with(&game_id, |game| {
+                (
+                    game.size() as u8,
+                    game.seat_of(&state.key),
+                    game.is_host(&state.key),
+                )
+            })
+        });
+    let (stone, host) = match known {
+        Some((game_size, _, _)) if game_size != state.size => {
+            return Err((Status::Forbidden, SeatError::WrongGame))
+        }
+        Some((_, None, false)) => return Err((Status::Forbidden, SeatError::BadToken)),
+        Some((_, stone, host)) => (stone, host),
+        None => return Err((Status::NotFound, SeatError::UnknownGame)),
+    };
+    if stone != state.stone {
+        PlayerSession::start(
+            request.cookies(),
+            game_id,
+            stone,
+            state.size,
+            state.key.clone(),
+        );
+    }
+    Ok(Some(Holder {
+        game_id,
+        stone,
+        size: state.size,
+        token: state.key,
+        host,
+    }))
+}
+
+impl Holder {
+    fn session(self) -> Option<PlayerSession> {
+        let stone = self.stone?;
+        Some(PlayerSession {
+            game_id: self.game_id,
+            stone,
+            size: self.size,
+            token: self.token,
+        })
+    }
+}
+
+// Seats are every seat a request's cookies hold, in the games the server knows, e.g. for a player
+// to take their data with them. Cookies that can't be read or whose seats the server doesn't know
+// are left out.
+pub struct Seats(pub Vec<Seat>);
+
+// Seat is a game a player holds a seat in, their stone is None while it is still to be drawn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Seat {
+    pub game_id: Uuid,
+    pub stone: Option<Stone>,
+    pub host: bool,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Seats {
+    type Error = SeatError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let mut games: Vec<Uuid> = request
+            .cookies()
+            .iter()
+            .filter_map(|cookie| cookie.name().strip_prefix("seat-")?.parse().ok())
+            .collect();
+        games.sort();
+        let seats = games
+            .into_iter()
+            .filter_map(|game_id| match resolve_seat(request, game_id) {
+                Ok(Some(holder)) => Some(Seat {
+                    game_id,
+                    stone: holder.stone,
+                    host: holder.host,
+                }),
+                _ => None,
+            })
+            .collect();
+        Outcome::Success(Seats(seats))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PlayerSession {
+    type Error = SeatError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match resolve(request) {
+            Ok(Some(holder)) => match holder.session() {
+                Some(session) => {
+                    RequestSpan::of(request).seated(session.stone);
+                    Outcome::Success(session)
+                }
+                None => Outcome::Failure((Status::Forbidden, SeatError::NotDrawn)),
+            },
+            Ok(None) => Outcome::Failure((Status::Forbidden, SeatError::NoSeat)),
+            Err(failure) => Outcome::Failure(failure),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Host {
+    type Error = SeatError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match resolve(request) {
+            Ok(Some(holder)) if holder.host => Outcome::Success(Host {
+                game_id: holder.game_id,
+                size: holder.size,
+                token: holder.token,
+            }),
+            Ok(Some(_)) => Outcome::Failure((Status::Forbidden, SeatError::WrongSeat)),
+            Ok(None) => Outcome::Failure((Status::Forbidden, SeatError::NoSeat)),
+            Err(failure) => Outcome::Failure(failure),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Spectator {
+    type Error = SeatError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let game_id = match game_id(request) {
+            Ok(game_id) => game_id,
+            Err(failure) => return Outcome::Failure(failure),
+        };
+        let known = request
+            .rocket()
+            .state::<GameRegistry>()
+            .and_then(|registry| registry.with(&game_id, |_| ()))
+            .is_some();
+        if !known {
+            return Outcome::Failure((Status::NotFound, SeatError::UnknownGame));
+        }
+        // A seat that can't be read still lets the player watch
+        let (seat, host) = match resolve(request) {
+            Ok(Some(holder)) => {
+                let host = holder.host;
+                (holder.session(), host)
+            }
+            _ => (None, false),
+        };
+        Outcome::Success(Spectator {
+            game_id,
+            seat,
+            host,
+        })
+    }
+}
+
+#[cfg(test)]
+#[get("/<_game_id>/seat")]
+fn seat_of(_game_id: Uuid, session: PlayerSession) -> String {
+    format!("{:?}", session.stone)
+}
+
+#[cfg(test)]
+#[get("/<_game_id>/watch")]
+fn watch(_game_id: Uuid, spectator: Spectator) -> String {
+    format!("{:?}", spectator.seat.map(|seat| seat.stone))
+}
+
+#[cfg(test)]
+#[put("/<_game_id>/players")]
+fn host_only(_game_id: Uuid, _host: Host) -> Status {
+    Status::Ok
+}
+
+#[test]
+fn test_seat_guards() {
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    let mut game = crate::board::new(crate::board::Size::Nine);
+    game.seat_guest();
+    let (black_key, white_key) = (
+        game.issue_token(Stone::Black),
+        game.issue_token(Stone::White),
+    );
+    registry.insert(game_id, game).unwrap();
+    let rocket = rocket::build()
+        .manage(registry)
+        .mount("/", routes![seat_of, watch, host_only]);
+    let client = Client::untracked(rocket).unwrap();
+    let get = |path: &str, cookie: Option<Cookie<'static>>| {
+        let request = client.get(format!("/{}/{}", game_id, path));
+        let request = match cookie {
+            Some(cookie) => request.private_cookie(cookie),
+            None => request,
+        };
+        let response = request.dispatch();
+        (
+            response.status(),
+            response.into_string().unwrap_or_default(),
+        )
+    };
+    let state = |version: u8, id: Uuid, size: u8, stone: &str| {
+        let key = match stone {
+            "White" => &white_key,
+            _ => &black_key,
+        };
+        format!(
+            r#"{{"version":{},"game_id":"{}","size":{},"stone":"{}","key":"{}"}}"#,
+            version, id, size, stone, key
+        )
+    };
+    let cookie = |value: String| Some(seat_cookie(&game_id, value));
+    let black = || cookie(state(3, game_id, 9, "Black"));
+    let white = || cookie(state(3, game_id, 9, "White"));
+
+    // The stone is the one the seat's token was issued for
+    assert_eq!((Status::Ok, "Black".to_string()), get("seat", black()));
+    assert_eq!((Status::Ok, "White".to_string()), get("seat", white()));
+    assert_eq!(Status::Forbidden, get("seat", None).0);
+    let forged = format!(
+        r#"{{"version":3,"game_id":"{}","size":9,"stone":"White","key":"a"}}"#,
+        game_id
+    );
+    assert_eq!(Status::Forbidden, get("seat", cookie(forged)).0);
+    assert_eq!(
+        Status::Forbidden,
+        get("seat", cookie(state(3, game_id, 19, "Black"))).0
+    );
+    assert_eq!(Status::BadRequest, get("seat", cookie("{".to_string())).0);
+
+    let response = client
+        .put(format!("/{}/players", game_id))
+        .private_cookie(black().unwrap())
+        .dispatch();
+    assert_eq!(Status::Ok, response.status());
+    let response = client.put(format!("/{}/players", game_id)).dispatch();
+    assert_eq!(Status::Forbidden, response.status());
+
+    assert_eq!((Status::Ok, "None".to_string()), get("watch", None));
+    assert_eq!(
+        (Status::Ok, "Some(Black)".to_string()),
+        get("watch", black())
+    );
+    let response = client.get(format!("/{}/watch", Uuid::new_v4())).dispatch();
+    assert_eq!(Status::NotFound, response.status());
+
+    // Cookies from a newer server aren't read
+    assert_eq!(
+        Status::BadRequest,
+        get("seat", cookie(state(4, game_id, 9, "Black"))).0
+    );
+
+    // Cookies players edited, or moved over from another game, aren't read
+    let response = client
+        .get(format!("/{}/seat", game_id))
+        .cookie(seat_cookie(&game_id, state(3, game_id, 9, "Black")))
+        .dispatch();
+    assert_eq!(Status::BadRequest, response.status());
+    assert_eq!(
+        Status::BadRequest,
+        get("seat", cookie(state(3, Uuid::new_v4(), 9, "Black"))).0
+    );
+
+    // Unreadable cookies are dropped, leaving the player to watch or join again
+    let name = cookie_name(&game_id);
+    let response = client
+        .get(format!("/{}/watch", game_id))
+        .private_cookie(seat_cookie(&game_id, "{".to_string()))
+        .dispatch();
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!(
+        Some(""),
+        response.cookies().get(&name).map(|cookie| cookie.value())
+    );
+    assert_eq!("None", response.into_string().unwrap());
+
+    // Seats in games the server doesn't know aren't taken on trust
+    let unknown = Uuid::new_v4();
+    let response = client
+        .get(format!("/{}/seat", unknown))
+        .private_cookie(seat_cookie(&unknown, state(3, unknown, 9, "Black")))
+        .dispatch();
+    assert_eq!(Status::NotFound, response.status());
+
+    // Seats are kept per game, a seat in another game doesn't count
+    let other = Uuid::new_v4();
+    let response = client
+        .get(format!("/{}/seat", game_id))
+        .private_cookie(seat_cookie(&other, state(3, other, 9, "Black")))
+        .dispatch();
+    assert_eq!(Status::Forbidden, response.status());
+
+    let cookie = seat_cookie(&game_id, String::new());
+    assert_eq!(Some(true), cookie.http_only());
+    assert_eq!(Some(SameSite::Lax), cookie.same_site());
+}
+
+#[test]
+fn test_seat_tokens() {
+    use rocket::local::blocking::Client;
+
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    let mut game = crate::board::new(crate::board::Size::Nine);
+    game.set_host(None);
+    let token = game.issue_host_token();
+    let pending = game.request_seat().unwrap();
+    registry.insert(game_id, game).unwrap();
+    let rocket = rocket::build()
+        .manage(registry.clone())
+        .mount("/", routes![seat_of, watch, host_only]);
+    let client = Client::untracked(rocket).unwrap();
+    let request = |path: &str, key: &str| {
+        let value = format!(
+            r#"{{"version":3,"game_id":"{}","size":9,"stone":null,"key":"{}"}}"#,
+            game_id, key
+        );
+        let cookie = seat_cookie(&game_id, value);
+        match path {
+            "players" => client.put(format!("/{}/players", game_id)),
+            _ => client.get(format!("/{}/{}", game_id, path)),
+        }
+        .private_cookie(cookie)
+        .dispatch()
+    };
+    let seat = |key: &str| request("seat", key).status();
+
+    // The player who created the game has no stone until it is drawn, but can still accept
+    // their opponent
+    assert_eq!(Status::Forbidden, seat(&token));
+    assert_eq!(Status::Ok, request("players", &token).status());
+    assert_eq!("None", request("watch", &token).into_string().unwrap());
+    assert_eq!(Status::Forbidden, seat(""));
+    assert_eq!(Status::Forbidden, seat("0123"));
+    assert_eq!(Status::Forbidden, request("players", &pending).status());
+    // The guest's seat is only held once the host accepts the player asking for it
+    assert_eq!(Status::Forbidden, seat(&pending));
+    let guest = registry
+        .update(game_id, || Err(()), |game| game.accept_join().ok_or(()))
+        .unwrap()
+        .unwrap();
+    let host = registry
+        .with(&game_id, |game| game.host())
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        format!("{:?}", host),
+        request("seat", &token).into_string().unwrap()
+    );
+    assert_eq!(
+        format!("{:?}", guest),
+        request("seat", &pending).into_string().unwrap()
+    );
+
+    // Cookies written before the stones were drawn are brought up to date
+    let response = request("seat", &pending);
+    let cookie = response
+        .cookies()
+        .get_private(&cookie_name(&game_id))
+        .unwrap();
+    let state = from_str::<SeatState>(cookie.value()).unwrap();
+    assert_eq!(Some(guest), state.stone);
+    assert_eq!(pending, state.key);
+}
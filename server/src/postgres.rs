@@ -0,0 +1,254 @@
+use std::io;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+use rocket::serde::uuid::Uuid;
+use rocket::tokio::runtime;
+use tokio_postgres::{Client, NoTls};
+
+use crate::board::{self, Game, GamePhase};
+use crate::store::{self, GameStore};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS games (
+    id TEXT PRIMARY KEY,
+    record TEXT NOT NULL,
+    phase TEXT NOT NULL
+);
+ALTER TABLE games ADD COLUMN IF NOT EXISTS deadline BIGINT";
+
+// Query is a request for the connection's thread, answered on `reply`.
+enum Query {
+    Write {
+        game_id: String,
+        record: String,
+        phase: String,
+        deadline: Option<i64>,
+        reply: Sender<io::Result<()>>,
+    },
+    Load {
+        game_id: String,
+        reply: Sender<io::Result<Option<String>>>,
+    },
+    List {
+        reply: Sender<io::Result<Vec<(String, String)>>>,
+    },
+    Finished {
+        reply: Sender<io::Result<Vec<String>>>,
+    },
+    Delete {
+        game_id: String,
+        reply: Sender<io::Result<()>>,
+    },
+    Ping {
+        reply: Sender<io::Result<()>>,
+    },
+}
+
+// Postgres keeps every game in a Postgres database, for deployments that share games between
+// servers. Stores are synchronous, so the connection runs on a thread of its own with its own
+// runtime, and each query blocks its caller until it is answered. Only the lock of the game being
+// written is held meanwhile, and routes make the call with store::blocking.
+//
+// e.g. in Rocket.toml:
+//  [default]
+//  postgres = "host=localhost user=rustigo dbname=rustigo"
+pub struct Postgres {
+    queries: Mutex<Sender<Query>>,
+}
+
+// sql_error reports a database error the way the other stores do.
+fn sql_error(err: tokio_postgres::Error) -> io::Error {
+    io::Error::other(err)
+}
+
+// closed reports the connection's thread having stopped.
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "The Postgres connection closed")
+}
+
+impl Postgres {
+    // connect opens a connection with a libpq style config string and creates the schema. The
+    // server's own runtime can't be blocked on, so the connection is opened on its thread.
+    pub fn connect(config: &str) -> io::Result<Postgres> {
+        let config = config.to_string();
+        let (queries, received) = mpsc::channel();
+        let (ready, connected) = mpsc::channel();
+        thread::spawn(move || {
+            let opened = runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .and_then(|runtime| {
+                    let client = runtime.block_on(open(&config))?;
+                    Ok((runtime, client))
+                });
+            let (runtime, client) = match opened {
+                Ok(opened) => {
+                    let _ = ready.send(Ok(()));
+                    opened
+                }
+                Err(err) => {
+                    let _ = ready.send(Err(err));
+                    return;
+                }
+            };
+            for query in received {
+                runtime.block_on(answer(&client, query));
+            }
+        });
+        connected.recv().map_err(|_| closed())??;
+        Ok(Postgres {
+            queries: Mutex::new(queries),
+        })
+    }
+
+    // ask sends a query to the connection's thread and waits for the answer.
+    fn ask<R>(&self, query: impl FnOnce(Sender<io::Result<R>>) -> Query) -> io::Result<R> {
+        let (reply, answered) = mpsc::channel();
+        self.queries
+            .lock()
+            .unwrap()
+            .send(query(reply))
+            .map_err(|_| closed())?;
+        answered.recv().map_err(|_| closed())?
+    }
+
+    fn write(&self, game_id: &Uuid, game: &Game) -> io::Result<()> {
+        self.ask(|reply| Query::Write {
+            game_id: game_id.to_string(),
+            record: board::encode_with_history(game),
+            phase: store::phase_name(game.phase()).to_string(),
+            deadline: game
+                .deadline()
+                .map(|deadline| i64::try_from(deadline).unwrap_or(i64::MAX)),
+            reply,
+        })
+    }
+}
+
+// open connects to the database, the connection is driven while queries are answered.
+async fn open(config: &str) -> io::Result<Client> {
+    let (client, connection) = tokio_postgres::connect(config, NoTls)
+        .await
+        .map_err(sql_error)?;
+    rocket::tokio::spawn(async move {
+        if let Err(err) = connection.await {
+            tracing::error!(error = ?err, "Lost the Postgres connection");
+        }
+    });
+    client.batch_execute(SCHEMA).await.map_err(sql_error)?;
+    Ok(client)
+}
+
+// answer runs a query, a caller that stopped waiting is ignored.
+async fn answer(client: &Client, query: Query) {
+    match query {
+        Query::Write {
+            game_id,
+            record,
+            phase,
+            deadline,
+            reply,
+        } => {
+            let written = client
+                .execute(
+                    "INSERT INTO games (id, record, phase, deadline) VALUES ($1, $2, $3, $4)
+                     ON CONFLICT (id) DO UPDATE SET record = $2, phase = $3, deadline = $4",
+                    &[&game_id, &record, &phase, &deadline],
+                )
+                .await;
+            let _ = reply.send(written.map(|_| ()).map_err(sql_error));
+        }
+        Query::Load { game_id, reply } => {
+            let row = client
+                .query_opt("SELECT record FROM games WHERE id = $1", &[&game_id])
+                .await;
+            let _ = reply.send(row.map(|row| row.map(|row| row.get(0))).map_err(sql_error));
+        }
+        Query::List { reply } => {
+            let finished = store::phase_name(GamePhase::Finished);
+            let rows = client
+                .query(
+                    "SELECT id, record FROM games WHERE phase != $1",
+                    &[&finished],
+                )
+                .await;
+            let _ = reply.send(
+                rows.map(|rows| rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+                    .map_err(sql_error),
+            );
+        }
+        Query::Finished { reply } => {
+            let finished = store::phase_name(GamePhase::Finished);
+            let rows = client
+                .query("SELECT id FROM games WHERE phase = $1", &[&finished])
+                .await;
+            let _ = reply.send(
+                rows.map(|rows| rows.iter().map(|row| row.get(0)).collect())
+                    .map_err(sql_error),
+            );
+        }
+        Query::Delete { game_id, reply } => {
+            let deleted = client
+                .execute("DELETE FROM games WHERE id = $1", &[&game_id])
+                .await;
+            let _ = reply.send(deleted.map(|_| ()).map_err(sql_error));
+        }
+        Query::Ping { reply } => {
+            let answered = client.simple_query("SELECT 1").await;
+            let _ = reply.send(answered.map(|_| ()).map_err(sql_error));
+        }
+    }
+}
+
+// Postgres lists the games still being played, finished ones are loaded when asked for.
+impl GameStore for Postgres {
+    fn create(&self, game_id: &Uuid, game: &Game) -> io::Result<()> {
+        self.write(game_id, game)
+    }
+
+    fn load(&self, game_id: &Uuid) -> io::Result<Option<Game>> {
+        let game_id = game_id.to_string();
+        self.ask(|reply| Query::Load { game_id, reply })?
+            .map(|record| board::decode(&record))
+            .transpose()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err)))
+    }
+
+    fn append_move(&self, game_id: &Uuid, game: &Game) -> io::Result<()> {
+        self.write(game_id, game)
+    }
+
+    fn list(&self) -> io::Result<Vec<(Uuid, Game)>> {
+        let mut games = Vec::new();
+        for (id, record) in self.ask(|reply| Query::List { reply })? {
+            match (id.parse::<Uuid>(), board::decode(&record)) {
+                (Ok(game_id), Ok(game)) => games.push((game_id, game)),
+                (_, Err(err)) => tracing::error!(game_id = id, error = ?err, "Failed to load game"),
+                (Err(err), _) => tracing::error!(game_id = id, error = ?err, "Failed to load game"),
+            }
+        }
+        Ok(games)
+    }
+
+    fn archived(&self) -> io::Result<Vec<Uuid>> {
+        let mut games = Vec::new();
+        for id in self.ask(|reply| Query::Finished { reply })? {
+            match id.parse::<Uuid>() {
+                Ok(game_id) => games.push(game_id),
+                Err(err) => tracing::error!(game_id = id, error = ?err, "Failed to list game"),
+            }
+        }
+        Ok(games)
+    }
+
+    fn expire(&self, game_id: &Uuid) -> io::Result<()> {
+        let game_id = game_id.to_string();
+        self.ask(|reply| Query::Delete { game_id, reply })
+    }
+
+    fn ping(&self) -> io::Result<()> {
+        self.ask(|reply| Query::Ping { reply })
+    }
+}
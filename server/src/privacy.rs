@@ -0,0 +1,226 @@
+use std::io::{self, Cursor, Write};
+use std::time::Duration;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::figment::Figment;
+use rocket::serde::json::to_string;
+use rocket::serde::uuid::Uuid;
+use rocket::tokio::select;
+use rocket::tokio::task::spawn_blocking;
+use rocket::tokio::time::interval;
+use rocket::{Orbit, Rocket};
+use rustigo_protocol::SeatDataMessage;
+use thiserror::Error;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::board::{Game, GamePhase};
+use crate::players::Seat;
+use crate::registry::{self, GameRegistry};
+use crate::sgf;
+
+// DAY is a day in milliseconds.
+const DAY: u64 = 24 * 60 * 60 * 1000;
+
+// SWEEP_INTERVAL is how often finished games are looked at for what they no longer keep.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrivacyError {
+    #[error("Game {0} is still being played, finish or resign it first")]
+    Unfinished(Uuid),
+}
+
+// Retention is how long finished games keep their chat and their ledger, in days after the lines
+// were said and after the game was last played. 0 keeps them for good, which is the default. The
+// moves played are always kept.
+//
+// e.g. in Rocket.toml:
+//  [default.retention]
+//  chat_days = 30
+//  ledger_days = 365
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Retention {
+    pub chat_days: u64,
+    pub ledger_days: u64,
+}
+
+impl Retention {
+    pub fn from_figment(figment: &Figment) -> Retention {
+        figment.extract_inner("retention").unwrap_or_default()
+    }
+
+    // prune drops what a finished game keeps for longer than it should at `now`, in milliseconds
+    // since the UNIX epoch, returning whether anything was.
+    pub fn prune(&self, game: &mut Game, now: u64) -> bool {
+        let mut pruned = false;
+        if self.chat_days > 0 {
+            pruned |= game.forget_chat(now.saturating_sub(self.chat_days * DAY));
+        }
+        let before = now.saturating_sub(self.ledger_days * DAY);
+        if self.ledger_days > 0 && game.active_at().is_some_and(|played| played < before) {
+            pruned |= game.forget_ledger();
+        }
+        pruned
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Retention {
+    fn info(&self) -> Info {
+        Info {
+            name: "Retention",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let retention = *self;
+        let registry = match rocket.state::<GameRegistry>() {
+            Some(registry) if retention != Retention::default() => registry.clone(),
+            _ => return,
+        };
+        let mut end = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            let mut sweeps = interval(SWEEP_INTERVAL);
+            loop {
+                select! {
+                    _ = sweeps.tick() => (),
+                    _ = &mut end => break,
+                }
+                // Stores block, keep them off the server's threads
+                let registry = registry.clone();
+                let swept = spawn_blocking(move || {
+                    let now = registry::now();
+                    registry.prune(|game| retention.prune(game, now))
+                })
+                .await;
+                match swept {
+                    Ok(Ok(0)) => (),
+                    Ok(Ok(pruned)) => tracing::info!(pruned, "Pruned finished games"),
+                    Ok(Err(err)) => tracing::error!(error = ?err, "Failed to prune games"),
+                    Err(err) => tracing::error!(error = ?err, "Failed to prune games"),
+                }
+            }
+        });
+    }
+}
+
+// export writes what the server keeps about a player's seats out as a zip archive, with a
+// directory for each game holding its record and what was kept about their seat.
+pub fn export(registry: &GameRegistry, seats: &[Seat]) -> io::Result<Vec<u8>> {
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+    for seat in seats {
+        let data = registry.with(&seat.game_id, |game| {
+            let held = SeatDataMessage {
+                game_id: seat.game_id,
+                stone: seat.stone,
+                host: seat.host,
+                contact: seat
+                    .stone
+                    .and_then(|stone| game.contact(stone))
+                    .map(|contact| contact.to_string()),
+                time_warnings: seat
+                    .stone
+                    .filter(|_| game.deadline().is_some())
+                    .map(|stone| game.time_warnings(stone).to_string()),
+                chat: game
+                    .chat()
+                    .iter()
+                    .filter(|line| seat.stone.is_some() && line.from == seat.stone)
+                    .cloned()
+                    .collect(),
+            };
+            (sgf::export(game), to_string(&held).unwrap())
+        });
+        let (record, held) = match data {
+            Some(data) => data,
+            None => continue,
+        };
+        archive.start_file(format!("{}/game.sgf", seat.game_id), options)?;
+        archive.write_all(record.as_bytes())?;
+        archive.start_file(format!("{}/seat.json", seat.game_id), options)?;
+        archive.write_all(held.as_bytes())?;
+    }
+    Ok(archive.finish()?.into_inner())
+}
+
+// forget strips a player's identity from the games they hold seats in, keeping the moves played,
+// and drops the games they created that no one has joined. Nothing is forgotten while any of
+// their games is still being played. It returns the games dropped. The outer error is the store
+// failing.
+pub fn forget(
+    registry: &GameRegistry,
+    seats: &[Seat],
+) -> io::Result<Result<Vec<Uuid>, PrivacyError>> {
+    for seat in seats {
+        let playing = registry.with(&seat.game_id, |game| {
+            game.guest_seated() && game.phase() != GamePhase::Finished
+        });
+        if playing == Some(true) {
+            return Ok(Err(PrivacyError::Unfinished(seat.game_id)));
+        }
+    }
+    let mut dropped = Vec::new();
+    for seat in seats {
+        let stone = match seat.stone {
+            Some(stone) => stone,
+            None => {
+                if registry.expire(&seat.game_id)? {
+                    dropped.push(seat.game_id);
+                }
+                continue;
+            }
+        };
+        let forgotten = registry.update(
+            seat.game_id,
+            || Err(()),
+            |game| {
+                if !game.guest_seated() {
+                    return Ok(false);
+                }
+                game.forget_player(stone);
+                Ok(true)
+            },
+        )?;
+        if forgotten == Ok(false) && registry.expire(&seat.game_id)? {
+            dropped.push(seat.game_id);
+        }
+    }
+    Ok(Ok(dropped))
+}
+
+#[test]
+fn test_retention() {
+    use crate::board::{self, Size, Stone};
+    use rustigo_protocol::ChatEntry;
+
+    let said = DAY;
+    let mut game = board::new(Size::Nine);
+    game.stamp_created(said);
+    game.open_ledger();
+    game.say(ChatEntry {
+        from: Some(Stone::Black),
+        text: "gg".to_string(),
+        at: said,
+    });
+
+    // Nothing is dropped by default
+    assert_eq!(
+        false,
+        Retention::default().prune(&mut game, said + 1000 * DAY)
+    );
+    let retention = Retention {
+        chat_days: 1,
+        ledger_days: 2,
+    };
+    assert_eq!(false, retention.prune(&mut game, said + DAY));
+    assert_eq!(true, retention.prune(&mut game, said + DAY + 1));
+    assert_eq!(true, game.chat().is_empty());
+    assert_eq!(false, game.ledger().is_empty());
+    assert_eq!(true, retention.prune(&mut game, said + 2 * DAY + 1));
+    assert_eq!(true, game.ledger().is_empty());
+    assert_eq!(false, retention.prune(&mut game, said + 3 * DAY));
+}
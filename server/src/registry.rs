@@ -0,0 +1,445 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::figment::Figment;
+use rocket::serde::uuid::Uuid;
+
+use crate::board::{Game, GamePhase};
+use crate::database::Database;
+use crate::journal::{Journal, SyncPolicy};
+use crate::store::{self, GameStore, MemoryStore};
+
+// Slot holds a game with a lock of its own, so requests about different games don't wait on each
+// other while their changes are stored. An empty slot is a game being loaded, or one that turned
+// out to be unknown and is about to be let go of.
+type Slot = Arc<Mutex<Option<Game>>>;
+
+// GameRegistry remembers the most recent validated position of every game the server has seen,
+// so read-only views of a game can be served without a board string from the client. Every change
+// is written to its store first. Clones share the same games.
+#[derive(Clone)]
+pub struct GameRegistry {
+    games: Arc<Mutex<HashMap<Uuid, Slot>>>,
+    store: Arc<dyn GameStore>,
+}
+
+impl Default for GameRegistry {
+    fn default() -> GameRegistry {
+        GameRegistry {
+            games: Arc::default(),
+            store: Arc::new(MemoryStore),
+        }
+    }
+}
+
+impl GameRegistry {
+    // stored creates a registry that writes every change to a store first, starting with the
+    // games it lists.
+    pub fn stored(store: impl GameStore + 'static) -> io::Result<GameRegistry> {
+        let games = store
+            .list()?
+            .into_iter()
+            .map(|(game_id, game)| (game_id, Arc::new(Mutex::new(Some(game)))))
+            .collect();
+        Ok(GameRegistry {
+            games: Arc::new(Mutex::new(games)),
+            store: Arc::new(store),
+        })
+    }
+
+    // from_figment opens the store named in the config, games are only kept in memory if there
+    // is none.
+    pub fn from_figment(figment: &Figment) -> io::Result<GameRegistry> {
+        #[cfg(feature = "postgres")]
+        if let Ok(config) = figment.extract_inner::<String>("postgres") {
+            return GameRegistry::stored(crate::postgres::Postgres::connect(&config)?);
+        }
+        if let Ok(path) = figment.extract_inner::<PathBuf>("database") {
+            return GameRegistry::stored(Database::open(path)?);
+        }
+        if let Ok(directory) = figment.extract_inner::<PathBuf>("journal") {
+            let sync = figment
+                .extract_inner::<SyncPolicy>("journal_sync")
+                .unwrap_or_default();
+            return GameRegistry::stored(Journal::open(directory, sync)?);
+        }
+        Ok(GameRegistry::default())
+    }
+
+    // locked calls `f` with a game's slot locked, loading the game from the store if it was left
+    // behind. Only the game's own lock is held while the store is used. Games that fail to load
+    // are reported and treated as unknown, slots left empty are let go of.
+    fn locked<R>(&self, game_id: &Uuid, f: impl FnOnce(&mut Option<Game>) -> R) -> R {
+        loop {
+            let mut games = self.games.lock().unwrap();
+            let (slot, loading) = match games.get(game_id) {
+                Some(slot) => (slot.clone(), false),
+                None => (Slot::default(), true),
+            };
+            let mut game = if loading {
+                // The slot is locked before it is shared, no one sees it before it is loaded
+                let game = slot.lock().unwrap();
+                games.insert(*game_id, slot.clone());
+                drop(games);
+                game
+            } else {
+                drop(games);
+                slot.lock().unwrap()
+            };
+
+            if loading {
+                *game = match store::blocking(|| self.store.load(game_id)) {
+                    Ok(loaded) => loaded,
+                    Err(err) => {
+                        tracing::error!(%game_id, error = ?err, "Failed to load game");
+                        None
+                    }
+                };
+            } else if game.is_none() && !self.holds(game_id, &slot) {
+                // The slot was let go of while this waited for it
+                continue;
+            }
+
+            let result = f(&mut game);
+            let empty = game.is_none();
+            drop(game);
+            if empty {
+                self.let_go(game_id, &slot);
+            }
+            return result;
+        }
+    }
+
+    // holds tests if a slot is still where a game is kept.
+    fn holds(&self, game_id: &Uuid, slot: &Slot) -> bool {
+        let games = self.games.lock().unwrap();
+        games
+            .get(game_id)
+            .is_some_and(|held| Arc::ptr_eq(held, slot))
+    }
+
+    // let_go forgets a slot left empty, unless it has been filled since.
+    fn let_go(&self, game_id: &Uuid, slot: &Slot) {
+        let mut games = self.games.lock().unwrap();
+        if !games
+            .get(game_id)
+            .is_some_and(|held| Arc::ptr_eq(held, slot))
+        {
+            return;
+        }
+        // A slot in use is being filled, the map's lock is never held waiting on a game
+        let empty = slot.try_lock().is_ok_and(|game| game.is_none());
+        if empty {
+            games.remove(game_id);
+        }
+    }
+
+    // slots lists the games held in memory, without holding the registry's lock while they are
+    // looked at.
+    fn slots(&self) -> Vec<(Uuid, Slot)> {
+        let games = self.games.lock().unwrap();
+        games
+            .iter()
+            .map(|(game_id, slot)| (*game_id, slot.clone()))
+            .collect()
+    }
+
+    // insert records the latest position of a game, replacing any previous one. The position is
+    // not recorded if it can't be stored. The game keeps the time it was first seen.
+    pub fn insert(&self, game_id: Uuid, mut game: Game) -> io::Result<()> {
+        self.locked(&game_id, |slot| {
+            let created = slot.as_ref().map(Game::created_at);
+            let now = now();
+            game.stamp_created(created.flatten().unwrap_or(now));
+            game.open_ledger();
+            game.stamp_ledger(0, now);
+            game.run_clock(game.history().len(), now);
+            // Holding the game's lock keeps stored changes in the same order as the game
+            store::blocking(|| match created {
+                Some(_) => self.store.append_move(&game_id, &game),
+                None => self.store.create(&game_id, &game),
+            })?;
+            *slot = Some(game);
+            Ok(())
+        })
+    }
+
+    // update changes a game atomically, so concurrent requests can't both play on the same
+    // position. `change` works on a copy of the game, which is stored and kept only if the change
+    // succeeds, any moves and ledger entries it made are stamped with the time and charged to the
+    // clock of a timed game. A game the registry doesn't know is first created with `missing`. The
+    // outer error is the store failing.
+    pub fn update<R, E>(
+        &self,
+        game_id: Uuid,
+        missing: impl FnOnce() -> Result<Game, E>,
+        change: impl FnOnce(&mut Game) -> Result<R, E>,
+    ) -> io::Result<Result<R, E>> {
+        self.locked(&game_id, |slot| {
+            let now = now();
+            let (mut game, created) = match slot {
+                Some(game) => (game.clone(), false),
+                None => match missing() {
+                    Ok(mut game) => {
+                        game.stamp_created(now);
+                        (game, true)
+                    }
+                    Err(err) => return Ok(Err(err)),
+                },
+            };
+            let (moves, entries) = (game.history().len(), game.ledger().len());
+            game.open_ledger();
+            let result = match change(&mut game) {
+                Ok(result) => result,
+                Err(err) => return Ok(Err(err)),
+            };
+            game.stamp_moves(moves, now);
+            game.stamp_ledger(entries, now);
+            game.run_clock(moves, now);
+            store::blocking(|| match created {
+                true => self.store.create(&game_id, &game),
+                false => self.store.append_move(&game_id, &game),
+            })?;
+            *slot = Some(game);
+            Ok(Ok(result))
+        })
+    }
+
+    // expire forgets a game, returning whether it was known. The game is kept if the store can't
+    // forget it.
+    pub fn expire(&self, game_id: &Uuid) -> io::Result<bool> {
+        self.locked(game_id, |slot| {
+            if slot.is_none() {
+                return Ok(false);
+            }
+            store::blocking(|| self.store.expire(game_id))?;
+            *slot = None;
+            Ok(true)
+        })
+    }
+
+    // expire_idle lets go of every game nothing has happened in since `since`, in milliseconds
+    // since the UNIX epoch, returning them along with whether they were archived. Finished games
+    // are archived, they stay in the store and only leave memory, the others are forgotten.
+    // Games that were never timed are kept, as are games the store fails to forget. Games with a
+    // deadline are left for their clock to end, correspondence games can be quiet for days.
+    pub fn expire_idle(&self, since: u64) -> Vec<(Uuid, bool)> {
+        let mut expired = Vec::new();
+        for (game_id, slot) in self.slots() {
+            let mut game = slot.lock().unwrap();
+            let archived = match game.as_ref() {
+                Some(game)
+                    if game.deadline().is_none()
+                        && game.active_at().is_some_and(|at| at < since) =>
+                {
+                    game.phase() == GamePhase::Finished
+                }
+                _ => continue,
+            };
+            if !archived {
+                if let Err(err) = store::blocking(|| self.store.expire(&game_id)) {
+                    tracing::error!(%game_id, error = ?err, "Failed to expire game");
+                    continue;
+                }
+            }
+            *game = None;
+            drop(game);
+            self.let_go(&game_id, &slot);
+            expired.push((game_id, archived));
+        }
+        expired
+    }
+
+    // prune changes every finished game, those held in memory and those archived in the store,
+    // storing the games `change` reports it changed, returning how many were. Archived games are
+    // let go of again, games the store fails to write are left as they were.
+    pub fn prune(&self, mut change: impl FnMut(&mut Game) -> bool) -> io::Result<usize> {
+        let held: HashSet<Uuid> = self.slots().into_iter().map(|(id, _)| id).collect();
+        let archived = store::blocking(|| self.store.archived())?;
+        let mut pruned = 0;
+        for game_id in held
+            .iter()
+            .chain(archived.iter().filter(|id| !held.contains(id)))
+        {
+            self.locked(game_id, |slot| {
+                let changed = match slot.as_ref() {
+                    Some(game) if game.phase() == GamePhase::Finished => {
+                        let mut game = game.clone();
+                        change(&mut game).then_some(game)
+                    }
+                    _ => None,
+                };
+                if let Some(game) = changed {
+                    match store::blocking(|| self.store.append_move(game_id, &game)) {
+                        Ok(()) => {
+                            *slot = Some(game);
+                            pruned += 1;
+                        }
+                        Err(err) => tracing::error!(%game_id, error = ?err, "Failed to prune game"),
+                    }
+                }
+                if !held.contains(game_id) {
+                    *slot = None;
+                }
+            });
+        }
+        Ok(pruned)
+    }
+
+    // with calls `f` with the latest position of a game, if the game is known.
+    pub fn with<R>(&self, game_id: &Uuid, f: impl FnOnce(&Game) -> R) -> Option<R> {
+        self.locked(game_id, |slot| slot.as_ref().map(f))
+    }
+
+    // ping checks the store can still be written to.
+    pub fn ping(&self) -> io::Result<()> {
+        store::blocking(|| self.store.ping())
+    }
+
+    // active counts the games held in memory that aren't finished.
+    pub fn active(&self) -> usize {
+        self.filter_map(|(_, game)| (game.phase() != GamePhase::Finished).then_some(()))
+            .len()
+    }
+
+    // filter_map calls `f` with the latest position of every game, keeping what it returns.
+    pub fn filter_map<R>(&self, mut f: impl FnMut((&Uuid, &Game)) -> Option<R>) -> Vec<R> {
+        self.slots()
+            .into_iter()
+            .filter_map(|(game_id, slot)| {
+                let game = slot.lock().unwrap();
+                f((&game_id, game.as_ref()?))
+            })
+            .collect()
+    }
+}
+
+// now is the time in milliseconds since the UNIX epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+#[test]
+fn test_registry_update() {
+    use crate::board::{self, Size, Stone};
+
+    let registry = GameRegistry::default();
+    let game_id = Uuid::new_v4();
+    let missing = || Ok::<_, ()>(board::new(Size::Nine));
+
+    // A game the registry doesn't know starts from `missing`
+    let played = registry.update(game_id, missing, |game| {
+        game.play_stone((2, 2), Stone::Black).map_err(|_| ())
+    });
+    assert_eq!(true, played.unwrap().is_ok());
+    assert_eq!(
+        Some(true),
+        registry.with(&game_id, |game| game.history()[0].played_at.is_some())
+    );
+    let created = registry.with(&game_id, board::Game::created_at).flatten();
+    assert_eq!(true, created.is_some());
+
+    // A rejected change leaves the game as it was
+    let played = registry.update(game_id, missing, |game| {
+        game.play_stone((3, 3), Stone::White).map_err(|_| ())?;
+        Err::<(), ()>(())
+    });
+    assert_eq!(Err(()), played.unwrap());
+    assert_eq!(
+        Some(Stone::Black),
+        registry
+            .with(&game_id, |game| game.stone_at((2, 2)))
+            .flatten()
+    );
+    assert_eq!(
+        Some(None),
+        registry.with(&game_id, |game| game.stone_at((3, 3)))
+    );
+
+    // Games keep the time they were first seen
+    registry.insert(game_id, board::new(Size::Nine)).unwrap();
+    assert_eq!(
+        Some(created),
+        registry.with(&game_id, board::Game::created_at)
+    );
+    assert_eq!(vec![game_id], registry.filter_map(|(id, _)| Some(*id)));
+}
+
+#[test]
+fn test_registry_database() {
+    use crate::board::{self, Size, Stone};
+
+    let path = std::env::temp_dir().join(format!("rustigo-{}.sqlite", Uuid::new_v4()));
+    let registry = GameRegistry::stored(Database::open(&path).unwrap()).unwrap();
+    let game_id = Uuid::new_v4();
+    let missing = || Ok::<_, ()>(board::new(Size::Nine));
+    let played = registry.update(game_id, missing, |game| {
+        game.pass(Stone::Black).map_err(|_| ())?;
+        game.pass(Stone::White).map_err(|_| ())?;
+        game.accept_score(Stone::White).map_err(|_| ())?;
+        game.accept_score(Stone::Black).map_err(|_| ())
+    });
+    assert_eq!(true, played.unwrap().is_ok());
+    let record = registry.with(&game_id, board::encode_with_history);
+
+    // Finished games aren't loaded at startup, but are still there when asked for
+    let figment = Figment::new().merge(("database", &path));
+    let registry = GameRegistry::from_figment(&figment).unwrap();
+    assert_eq!(true, registry.filter_map(|(id, _)| Some(*id)).is_empty());
+    assert_eq!(record, registry.with(&game_id, board::encode_with_history));
+    assert_eq!(None, registry.with(&Uuid::new_v4(), |_| ()));
+
+    // Archived games are pruned too, and let go of again
+    let registry = GameRegistry::from_figment(&figment).unwrap();
+    assert_eq!(1, registry.prune(|game| game.forget_ledger()).unwrap());
+    assert_eq!(true, registry.filter_map(|(id, _)| Some(*id)).is_empty());
+    assert_eq!(0, registry.prune(|game| game.forget_ledger()).unwrap());
+    assert_eq!(
+        Some(true),
+        registry.with(&game_id, |game| game.ledger().is_empty())
+    );
+
+    assert_eq!(true, registry.expire(&game_id).unwrap());
+    assert_eq!(false, registry.expire(&game_id).unwrap());
+    let registry = GameRegistry::from_figment(&figment).unwrap();
+    assert_eq!(None, registry.with(&game_id, |_| ()));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_registry_locks() {
+    use crate::board::{self, Size, Stone};
+
+    let registry = GameRegistry::default();
+    let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+    registry.insert(first, board::new(Size::Nine)).unwrap();
+    registry.insert(second, board::new(Size::Nine)).unwrap();
+
+    // Each game has a lock of its own, one can be looked at while another is changed
+    let played = registry.update(
+        first,
+        || Err(()),
+        |game| {
+            let turn = registry.with(&second, board::Game::turn).ok_or(())?;
+            game.play_stone((2, 2), turn).map_err(|_| ())
+        },
+    );
+    assert_eq!(true, played.unwrap().is_ok());
+    assert_eq!(
+        Some(Some(Stone::Black)),
+        registry.with(&first, |game| game.stone_at((2, 2)))
+    );
+
+    // Games that turn out to be unknown aren't kept
+    assert_eq!(None, registry.with(&Uuid::new_v4(), |_| ()));
+    assert_eq!(2, registry.slots().len());
+    assert_eq!(true, registry.expire(&first).unwrap());
+    assert_eq!(1, registry.slots().len());
+    assert_eq!(1, registry.active());
+}
@@ -0,0 +1,160 @@
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use redis::{Client, Connection, RedisResult};
+use rocket::serde::json::{from_str, to_string};
+use rocket::serde::uuid::Uuid;
+use rustigo_protocol::GameStateMessage;
+
+use crate::feed::EventQueue;
+
+// DEFAULT_CHANNEL is the Redis channel events are relayed on, it can be changed with
+// `redis_channel` to run several deployments on one Redis.
+pub const DEFAULT_CHANNEL: &str = "rustigo:events";
+
+// RECONNECT_DELAY is how long to wait before reconnecting to Redis after losing it.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+// Relayed is a message as it is sent between servers, tagged with the server that sent it so it
+// isn't delivered there twice.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Relayed {
+    origin: Uuid,
+    game_id: Option<Uuid>,
+    state: GameStateMessage,
+}
+
+// relay shares the events of a queue with every other server on the same Redis channel, so
+// spectators can watch a game from any server behind a load balancer. Only events are shared:
+// every server keeps its own copy of the games it has loaded, and a move made on one server isn't
+// seen by the others' copies. Players of a game must stay on one server, e.g. with sticky
+// sessions keyed on the game's id. Events are delivered at most once, any sent while Redis is
+// unreachable are lost, and subscribers see the events in the order their server received them.
+//
+// e.g. in Rocket.toml:
+//  [default]
+//  redis = "redis://127.0.0.1/"
+//  redis_channel = "rustigo:events"
+pub fn relay(url: &str, channel: String, queue: EventQueue) -> RedisResult<EventQueue> {
+    let client = Client::open(url)?;
+    let publisher = client.get_connection()?;
+    let subscriber = client.get_connection()?;
+    let origin = Uuid::new_v4();
+
+    let (outbox, outgoing) = mpsc::channel();
+    let queue = queue.relayed(outbox);
+    {
+        let (client, channel) = (client.clone(), channel.clone());
+        thread::spawn(move || publish(client, publisher, &channel, origin, outgoing));
+    }
+    {
+        let queue = queue.clone();
+        thread::spawn(move || subscribe(client, subscriber, &channel, origin, &queue));
+    }
+    Ok(queue)
+}
+
+// publish sends the messages posted on this server to the channel until the queue is dropped.
+fn publish(
+    client: Client,
+    mut connection: Connection,
+    channel: &str,
+    origin: Uuid,
+    outgoing: Receiver<(Option<Uuid>, GameStateMessage)>,
+) {
+    for (game_id, state) in outgoing {
+        let relayed = Relayed {
+            origin,
+            game_id,
+            state,
+        };
+        let payload = to_string(&relayed).unwrap();
+        let sent = redis::cmd("PUBLISH")
+            .arg(channel)
+            .arg(payload)
+            .query::<()>(&mut connection);
+        if let Err(err) = sent {
+            tracing::error!(error = ?err, "Failed to relay an event");
+            if let Ok(reconnected) = client.get_connection() {
+                connection = reconnected;
+            }
+        }
+    }
+}
+
+// subscribe delivers the messages other servers send on the channel to this server's
+// subscribers, reconnecting whenever Redis is lost.
+fn subscribe(
+    client: Client,
+    mut connection: Connection,
+    channel: &str,
+    origin: Uuid,
+    queue: &EventQueue,
+) {
+    loop {
+        if let Err(err) = listen(&mut connection, channel, origin, queue) {
+            tracing::error!(error = ?err, "Lost the Redis relay");
+        }
+        loop {
+            thread::sleep(RECONNECT_DELAY);
+            match client.get_connection() {
+                Ok(reconnected) => {
+                    connection = reconnected;
+                    break;
+                }
+                Err(err) => tracing::error!(error = ?err, "Failed to reconnect the Redis relay"),
+            }
+        }
+    }
+}
+
+fn listen(
+    connection: &mut Connection,
+    channel: &str,
+    origin: Uuid,
+    queue: &EventQueue,
+) -> RedisResult<()> {
+    let mut pubsub = connection.as_pubsub();
+    pubsub.subscribe(channel)?;
+    loop {
+        let payload: String = pubsub.get_message()?.get_payload()?;
+        if let Some((game_id, state)) = receive(&payload, origin) {
+            // No one may be listening on this server
+            let _ = queue.deliver(game_id, state);
+        }
+    }
+}
+
+// receive reads a message relayed from another server, messages this server sent and ones it
+// can't read, e.g. from a newer server, are skipped.
+fn receive(payload: &str, origin: Uuid) -> Option<(Option<Uuid>, GameStateMessage)> {
+    match from_str::<Relayed>(payload) {
+        Ok(relayed) if relayed.origin == origin => None,
+        Ok(relayed) => Some((relayed.game_id, relayed.state)),
+        Err(err) => {
+            tracing::warn!(error = ?err, "Failed to read a relayed event");
+            None
+        }
+    }
+}
+
+#[test]
+fn test_receive() {
+    let (origin, other, game_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    let relayed = |origin| {
+        to_string(&Relayed {
+            origin,
+            game_id: Some(game_id),
+            state: GameStateMessage::Join { id: game_id },
+        })
+        .unwrap()
+    };
+
+    assert_eq!(
+        Some((Some(game_id), GameStateMessage::Join { id: game_id })),
+        receive(&relayed(other), origin)
+    );
+    assert_eq!(None, receive(&relayed(origin), origin));
+    assert_eq!(None, receive("{", origin));
+}
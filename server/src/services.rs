@@ -0,0 +1,118 @@
+use std::any::type_name;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::Sentinel;
+use rocket::{Ignite, Rocket};
+use thiserror::Error;
+
+use crate::clock::Ticker;
+use crate::engine::Engines;
+use crate::feed::EventQueue;
+use crate::lobby::Lobby;
+use crate::maintenance::Maintenance;
+use crate::registry::GameRegistry;
+use crate::sizes::BoardSizes;
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceError {
+    #[error("{0} isn't managed")]
+    Unmanaged(&'static str),
+}
+
+// Services is the state shared by the routes that start and play games: the games themselves,
+// their events, the engines playing in them, who is in the lobby and the settings new games are
+// started with. Routes take it as one guard instead of each piece of state.
+pub struct Services<'r> {
+    pub registry: &'r GameRegistry,
+    pub queue: &'r EventQueue,
+    pub engines: &'r Engines,
+    pub sizes: &'r BoardSizes,
+    pub maintenance: &'r Maintenance,
+    pub ticker: &'r Ticker,
+    pub lobby: &'r Lobby,
+}
+
+// managed finds a piece of the server's state, naming it when it is missing.
+fn managed<'r, T: Send + Sync + 'static>(request: &'r Request<'_>) -> Result<&'r T, ServiceError> {
+    request
+        .rocket()
+        .state::<T>()
+        .ok_or(ServiceError::Unmanaged(type_name::<T>()))
+}
+
+impl<'r> Services<'r> {
+    fn of(request: &'r Request<'_>) -> Result<Services<'r>, ServiceError> {
+        Ok(Services {
+            registry: managed(request)?,
+            queue: managed(request)?,
+            engines: managed(request)?,
+            sizes: managed(request)?,
+            maintenance: managed(request)?,
+            ticker: managed(request)?,
+            lobby: managed(request)?,
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Services<'r> {
+    type Error = ServiceError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match Services::of(request) {
+            Ok(services) => Outcome::Success(services),
+            Err(err) => {
+                tracing::error!(error = %err, "Missing server state");
+                Outcome::Failure((Status::InternalServerError, err))
+            }
+        }
+    }
+}
+
+// Services refuses to launch a server that doesn't manage all of them, as `&State` does.
+impl Sentinel for Services<'_> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        rocket.state::<GameRegistry>().is_none()
+            || rocket.state::<EventQueue>().is_none()
+            || rocket.state::<Engines>().is_none()
+            || rocket.state::<BoardSizes>().is_none()
+            || rocket.state::<Maintenance>().is_none()
+            || rocket.state::<Ticker>().is_none()
+            || rocket.state::<Lobby>().is_none()
+    }
+}
+
+// active_games is a route to test Services with.
+#[cfg(test)]
+#[get("/services")]
+fn active_games(services: Services<'_>) -> String {
+    services.registry.active().to_string()
+}
+
+#[test]
+fn test_services() {
+    use rocket::error::ErrorKind;
+    use rocket::local::blocking::Client;
+
+    let rocket = || {
+        rocket::build()
+            .manage(GameRegistry::default())
+            .manage(EventQueue::new(16))
+            .manage(Engines::new(None))
+            .manage(BoardSizes::new(&[], None))
+            .manage(Maintenance::default())
+            .manage(Lobby::default())
+            .mount("/", routes![active_games])
+    };
+    // Like `&State`, a route can't be served without the state it needs
+    let aborted = Client::untracked(rocket())
+        .err()
+        .map(|err| matches!(err.kind(), ErrorKind::SentinelAborts(_)));
+    assert_eq!(Some(true), aborted);
+
+    let client = Client::untracked(rocket().manage(Ticker::default())).unwrap();
+    let response = client.get(uri!(active_games)).dispatch();
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!("0", response.into_string().unwrap());
+}
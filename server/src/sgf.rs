@@ -0,0 +1,465 @@
+use std::collections::BTreeSet;
+
+use thiserror::Error;
+
+use crate::board::{
+    self, Action, Coordinate, Game, GamePhase, HandicapError, PlayError, ScoringRules, SetupError,
+    Size, Stone,
+};
+
+// point writes a coordinate as an SGF point, columns then rows lettered from "a" at the top left.
+//
+// e.g:
+//  (3, 15) => "dp"
+fn point((x, y): Coordinate) -> String {
+    [x, y]
+        .iter()
+        .map(|value| (b'a' + *value as u8) as char)
+        .collect()
+}
+
+// color is the SGF property identifier of a player.
+fn color(stone: Stone) -> &'static str {
+    match stone {
+        Stone::Black => "B",
+        Stone::White => "W",
+    }
+}
+
+// result writes the RE property value of a finished game, e.g. "B+3.5", or "0" for a draw.
+fn result(game: &Game) -> String {
+    let score = game.score();
+    match score.winner {
+        Some(stone) => format!("{}+{}", color(stone), score.margin),
+        None => "0".to_string(),
+    }
+}
+
+// export writes a game as an SGF (Smart Game Format) record: its size, komi, rules, handicap or
+// composed setup, every move played and, once the game has finished, the result.
+pub fn export(game: &Game) -> String {
+    let rules = match game.rules() {
+        ScoringRules::Japanese => "Japanese",
+        ScoringRules::Chinese => "Chinese",
+    };
+    let mut output = format!(
+        "(;GM[1]FF[4]CA[UTF-8]AP[rustigo]SZ[{}]KM[{}]RU[{}]",
+        game.size() as u8,
+        game.komi(),
+        rules
+    );
+
+    if game.handicap() > 0 {
+        output.push_str(&format!("HA[{}]", game.handicap()));
+        let stones = game.handicap_stones();
+        if !stones.is_empty() {
+            output.push_str("AB");
+            for position in stones {
+                output.push_str(&format!("[{}]", point(position)));
+            }
+        }
+    }
+    let setup = game.setup_stones();
+    if !setup.is_empty() {
+        for stone in [Stone::Black, Stone::White] {
+            let points: Vec<String> = setup
+                .iter()
+                .filter(|(_, placed)| *placed == stone)
+                .map(|(position, _)| format!("[{}]", point(*position)))
+                .collect();
+            if !points.is_empty() {
+                output.push_str(&format!("A{}{}", color(stone), points.concat()));
+            }
+        }
+        let first = game
+            .history()
+            .first()
+            .map_or(game.turn(), |played| played.stone);
+        output.push_str(&format!("PL[{}]", color(first)));
+    }
+    if game.phase() == GamePhase::Finished {
+        output.push_str(&format!("RE[{}]", result(game)));
+    }
+
+    for played in game.history() {
+        match played.action {
+            Action::Play(position) => {
+                output.push_str(&format!(";{}[{}]", color(played.stone), point(position)))
+            }
+            Action::Pass => output.push_str(&format!(";{}[]", color(played.stone))),
+            // Free handicap stones are part of the setup, not moves
+            Action::Handicap(_) => (),
+        }
+    }
+
+    output.push(')');
+    output
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SgfError {
+    #[error("The file is not a valid SGF game record")]
+    Syntax,
+    #[error("Only 9x9, 13x13, 17x17 and 19x19 boards are supported, not {0}")]
+    UnsupportedSize(String),
+    #[error("Invalid property value {0}")]
+    InvalidValue(String),
+    #[error("Stones can only be added before the first move and never removed")]
+    UnsupportedSetup,
+    #[error("Invalid setup: {0}")]
+    Setup(SetupError),
+    #[error("Invalid handicap: {0}")]
+    Handicap(HandicapError),
+    #[error("Move {0} is not legal: {1}")]
+    IllegalMove(usize, PlayError),
+}
+
+// Node is the list of properties of a single SGF node, e.g. `;B[dd]C[comment]`.
+type Node = Vec<(String, Vec<String>)>;
+
+// Parser reads the main line of an SGF game tree, the first variation is followed wherever the
+// game branches.
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), SgfError> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(SgfError::Syntax),
+        }
+    }
+
+    // tree reads `(` nodes, then any variations, then `)`. Variations are followed with a stack of
+    // the trees still open rather than by recursion, so deeply nested records can't overflow the
+    // stack.
+    fn tree(&mut self) -> Result<Vec<Node>, SgfError> {
+        self.expect('(')?;
+        // Each open tree has the nodes read so far and whether its first variation has been read
+        let mut open: Vec<(Vec<Node>, bool)> = vec![(Vec::new(), false)];
+        loop {
+            self.skip_whitespace();
+            match self.chars.peek() {
+                Some(';') => {
+                    self.chars.next();
+                    let node = self.node()?;
+                    if let Some((nodes, _)) = open.last_mut() {
+                        nodes.push(node);
+                    }
+                }
+                Some('(') => {
+                    self.chars.next();
+                    open.push((Vec::new(), false));
+                }
+                Some(')') => {
+                    self.chars.next();
+                    let (variation, _) = open.pop().ok_or(SgfError::Syntax)?;
+                    match open.last_mut() {
+                        // Only the first variation is the game as played
+                        Some((nodes, branched)) if !*branched => {
+                            nodes.extend(variation);
+                            *branched = true;
+                        }
+                        Some(_) => (),
+                        None => return Ok(variation),
+                    }
+                }
+                _ => return Err(SgfError::Syntax),
+            }
+        }
+    }
+
+    // node reads the properties following a `;`.
+    fn node(&mut self) -> Result<Node, SgfError> {
+        let mut properties = Node::new();
+        loop {
+            self.skip_whitespace();
+            let mut identifier = String::new();
+            while let Some(c) = self.chars.next_if(|c| c.is_ascii_alphabetic()) {
+                identifier.push(c);
+            }
+            if identifier.is_empty() {
+                return Ok(properties);
+            }
+
+            let mut values = Vec::new();
+            self.skip_whitespace();
+            while self.chars.next_if_eq(&'[').is_some() {
+                values.push(self.value()?);
+                self.skip_whitespace();
+            }
+            if values.is_empty() {
+                return Err(SgfError::Syntax);
+            }
+            properties.push((identifier, values));
+        }
+    }
+
+    // value reads a property value up to the closing `]`, a backslash escapes the next character.
+    fn value(&mut self) -> Result<String, SgfError> {
+        let mut value = String::new();
+        loop {
+            match self.chars.next().ok_or(SgfError::Syntax)? {
+                ']' => return Ok(value),
+                '\\' => value.push(self.chars.next().ok_or(SgfError::Syntax)?),
+                c => value.push(c),
+            }
+        }
+    }
+}
+
+// parse_point reads an SGF point, an empty value or "tt" on boards up to 19x19 is a pass.
+fn parse_point(value: &str) -> Result<Option<Coordinate>, SgfError> {
+    if value.is_empty() || value == "tt" {
+        return Ok(None);
+    }
+    let invalid = || SgfError::InvalidValue(value.to_string());
+    let mut letters = value.bytes();
+    let mut axis = || match letters.next() {
+        Some(letter @ b'a'..=b's') => Ok((letter - b'a') as i8),
+        _ => Err(invalid()),
+    };
+    let position = (axis()?, axis()?);
+    if value.len() != 2 {
+        return Err(invalid());
+    }
+    Ok(Some(position))
+}
+
+// import builds a game from an SGF record by replaying the moves of its main line, so the game
+// has a full history. Stones added in the root node are handicap stones when the record gives a
+// handicap, otherwise they are a composed position.
+pub fn import(text: &str) -> Result<Game, SgfError> {
+    let nodes = Parser {
+        chars: text.chars().peekable(),
+    }
+    .tree()?;
+    let root = nodes.first().ok_or(SgfError::Syntax)?;
+    let property = |identifier: &str| {
+        root.iter()
+            .find(|(name, _)| name == identifier)
+            .map(|(_, values)| values)
+    };
+
+    let size = match property("SZ").map(|values| values[0].as_str()) {
+        None => Size::Nineteen,
+        Some(value) => value
+            .parse::<usize>()
+            .ok()
+            .and_then(|size| Size::try_from(size).ok())
+            .ok_or_else(|| SgfError::UnsupportedSize(value.to_string()))?,
+    };
+    let mut game = board::new(size);
+
+    if let Some(values) = property("KM") {
+        let komi = values[0]
+            .parse::<f32>()
+            .ok()
+            .filter(|komi| komi.is_finite())
+            .ok_or_else(|| SgfError::InvalidValue(values[0].clone()))?;
+        game.set_komi(komi);
+    }
+    if let Some(values) = property("RU") {
+        if values[0].eq_ignore_ascii_case("chinese") {
+            game.set_rules(ScoringRules::Chinese);
+        }
+    }
+
+    if property("AE").is_some() {
+        return Err(SgfError::UnsupportedSetup);
+    }
+    let points = |identifier: &str| {
+        property(identifier)
+            .map(|values| values.iter().map(|value| parse_point(value)).collect())
+            .transpose()
+            .map(Option::unwrap_or_default)
+            .and_then(|points: Vec<_>| {
+                points
+                    .into_iter()
+                    .collect::<Option<BTreeSet<Coordinate>>>()
+                    .ok_or(SgfError::UnsupportedSetup)
+            })
+    };
+    let black = points("AB")?;
+    let white = points("AW")?;
+
+    if property("HA").is_none() || !white.is_empty() {
+        // A composed position rather than a handicap
+        if !black.is_empty() || !white.is_empty() {
+            let turn = match property("PL").map(|values| values[0].as_str()) {
+                None | Some("B") => Stone::Black,
+                Some("W") => Stone::White,
+                Some(value) => return Err(SgfError::InvalidValue(value.to_string())),
+            };
+            let stones = black
+                .into_iter()
+                .map(|position| (position, Stone::Black))
+                .chain(white.into_iter().map(|position| (position, Stone::White)));
+            let mut composed = board::setup(size, stones, turn).map_err(SgfError::Setup)?;
+            composed.set_komi(game.komi());
+            composed.set_rules(game.rules());
+            game = composed;
+        }
+    } else if !black.is_empty() {
+        let stones = u8::try_from(black.len()).unwrap_or(u8::MAX);
+        let mut fixed = game.clone();
+        fixed.place_handicap(stones).map_err(SgfError::Handicap)?;
+        if fixed.handicap_stones().into_iter().collect::<BTreeSet<_>>() == black {
+            game = fixed;
+        } else {
+            game.start_free_handicap(stones)
+                .map_err(SgfError::Handicap)?;
+            for position in black {
+                game.play_stone(position, Stone::Black)
+                    .map_err(|err| SgfError::IllegalMove(0, err))?;
+            }
+        }
+    }
+
+    let mut number = 0;
+    for node in nodes.iter() {
+        for (identifier, values) in node.iter() {
+            let stone = match identifier.as_str() {
+                "B" => Stone::Black,
+                "W" => Stone::White,
+                "AB" | "AW" | "AE" if number > 0 => return Err(SgfError::UnsupportedSetup),
+                _ => continue,
+            };
+            number += 1;
+            let played = match parse_point(&values[0])? {
+                Some(position) => game.play_stone(position, stone).map(|_| ()),
+                None => game.pass(stone),
+            };
+            played.map_err(|err| SgfError::IllegalMove(number, err))?;
+        }
+    }
+
+    Ok(game)
+}
+
+#[test]
+fn test_export() {
+    let mut game = crate::board::new(crate::board::Size::Nine);
+    assert_eq!(true, game.play_stone((2, 2), Stone::Black).is_ok());
+    assert_eq!(true, game.play_stone((6, 6), Stone::White).is_ok());
+    assert_eq!(Ok(()), game.pass(Stone::Black));
+
+    assert_eq!(
+        "(;GM[1]FF[4]CA[UTF-8]AP[rustigo]SZ[9]KM[6.5]RU[Japanese];B[cc];W[gg];B[])",
+        export(&game)
+    );
+}
+
+#[test]
+fn test_export_handicap_and_result() {
+    let mut game = crate::board::new(crate::board::Size::Nineteen);
+    assert_eq!(Ok(()), game.place_handicap(2));
+    game.set_komi(0.5);
+    assert_eq!(true, game.play_stone((16, 3), Stone::White).is_ok());
+    assert_eq!(Ok(()), game.pass(Stone::Black));
+    assert_eq!(Ok(()), game.pass(Stone::White));
+    assert_eq!(Ok(()), game.accept_score(Stone::Black));
+    assert_eq!(Ok(()), game.accept_score(Stone::White));
+
+    assert_eq!(
+        "(;GM[1]FF[4]CA[UTF-8]AP[rustigo]SZ[19]KM[0.5]RU[Japanese]HA[2]AB[pd][dp]RE[W+0.5]\
+         ;W[qd];B[];W[])",
+        export(&game)
+    );
+}
+
+#[test]
+fn test_import() {
+    let game = import(
+        "(;GM[1]FF[4]SZ[9]KM[5.5]RU[Chinese]C[a \\] comment]
+          ;B[cc];W[gg]
+          (;B[gc];W[])
+          (;B[aa]))",
+    )
+    .unwrap();
+
+    assert_eq!(Size::Nine, game.size());
+    assert_eq!(5.5, game.komi());
+    assert_eq!(ScoringRules::Chinese, game.rules());
+    assert_eq!(4, game.history().len());
+    assert_eq!(true, game.has_stone((6, 2)));
+    assert_eq!(false, game.has_stone((0, 0)));
+    assert_eq!(Stone::Black, game.turn());
+}
+
+#[test]
+fn test_import_round_trip() {
+    let mut game = crate::board::new(crate::board::Size::Nineteen);
+    assert_eq!(Ok(()), game.start_free_handicap(2));
+    assert_eq!(true, game.play_stone((3, 3), Stone::Black).is_ok());
+    assert_eq!(true, game.play_stone((15, 3), Stone::Black).is_ok());
+    assert_eq!(true, game.play_stone((9, 9), Stone::White).is_ok());
+    assert_eq!(Ok(()), game.pass(Stone::Black));
+
+    let exported = export(&game);
+    assert_eq!(exported, export(&import(&exported).unwrap()));
+
+    let mut fixed = crate::board::new(crate::board::Size::Nineteen);
+    assert_eq!(Ok(()), fixed.place_handicap(4));
+    let exported = export(&fixed);
+    assert_eq!(exported, export(&import(&exported).unwrap()));
+}
+
+#[test]
+fn test_import_errors() {
+    assert_eq!(Some(SgfError::Syntax), import("(;SZ[9]").err());
+    assert_eq!(
+        Some(SgfError::UnsupportedSize("21".to_string())),
+        import("(;SZ[21])").err()
+    );
+    assert_eq!(
+        Some(SgfError::IllegalMove(2, PlayError::Occupied)),
+        import("(;SZ[9];B[cc];W[cc])").err()
+    );
+    assert_eq!(
+        Some(SgfError::UnsupportedSetup),
+        import("(;SZ[9]AE[cc])").err()
+    );
+    assert_eq!(
+        Some(SgfError::Setup(SetupError::NoLiberties((0, 0)))),
+        import("(;SZ[9]AB[ba][ab]AW[aa])").err()
+    );
+}
+
+#[test]
+fn test_import_nested() {
+    assert_eq!(
+        true,
+        import("(;SZ[9](;B[cc](;W[dd])(;W[ee]))(;B[gg]))")
+            .unwrap()
+            .has_stone((3, 3))
+    );
+
+    // Nesting as deep as an import allows is read without running out of stack
+    let nested = "(".repeat(256 * 1024);
+    assert_eq!(Some(SgfError::Syntax), import(&nested).err());
+    let nested = format!("(;SZ[9]{}{})", "(".repeat(100_000), ")".repeat(100_000));
+    assert_eq!(true, import(&nested).is_ok());
+}
+
+#[test]
+fn test_setup_round_trip() {
+    let stones = [((2, 2), Stone::Black), ((3, 2), Stone::White)];
+    let mut game = crate::board::setup(Size::Nine, stones, Stone::White).unwrap();
+    assert_eq!(true, game.play_stone((6, 6), Stone::White).is_ok());
+
+    let exported = export(&game);
+    assert_eq!(
+        "(;GM[1]FF[4]CA[UTF-8]AP[rustigo]SZ[9]KM[6.5]RU[Japanese]AB[cc]AW[dc]PL[W];W[gg])",
+        exported
+    );
+    let imported = import(&exported).unwrap();
+    assert_eq!(stones.to_vec(), imported.setup_stones());
+    assert_eq!(exported, export(&imported));
+}
@@ -0,0 +1,87 @@
+use rocket::figment::Figment;
+
+use crate::board::Size;
+
+// BoardSizes are the sizes games may be created with on this server, e.g. a server for beginners
+// may only offer 9x9 and 13x13. Every size is offered unless `sizes` is configured. The default
+// size is picked in the editor and on the start page, it is the largest offered unless
+// `default_size` is configured.
+//
+// e.g. in Rocket.toml:
+//  [default]
+//  sizes = [9, 13]
+//  default_size = 9
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoardSizes {
+    allowed: Vec<Size>,
+    default: Size,
+}
+
+impl BoardSizes {
+    pub fn new(allowed: &[Size], default: Option<Size>) -> BoardSizes {
+        let mut allowed: Vec<Size> = Size::ALL
+            .into_iter()
+            .filter(|size| allowed.is_empty() || allowed.contains(size))
+            .collect();
+        if allowed.is_empty() {
+            allowed = Size::ALL.to_vec();
+        }
+        let default = default
+            .filter(|size| allowed.contains(size))
+            .unwrap_or(allowed[allowed.len() - 1]);
+        BoardSizes { allowed, default }
+    }
+
+    // from_figment reads the sizes offered, settings that can't be read are reported and every
+    // size is offered instead.
+    pub fn from_figment(figment: &Figment) -> BoardSizes {
+        let allowed = match figment.extract_inner::<Vec<Size>>("sizes") {
+            Ok(allowed) => allowed,
+            Err(err) if err.missing() => Vec::new(),
+            Err(err) => {
+                tracing::warn!(error = %err, "Ignoring sizes");
+                Vec::new()
+            }
+        };
+        let default = figment.extract_inner::<Size>("default_size").ok();
+        BoardSizes::new(&allowed, default)
+    }
+
+    // allowed lists the sizes offered, smallest first.
+    pub fn allowed(&self) -> &[Size] {
+        &self.allowed
+    }
+
+    pub fn allows(&self, size: Size) -> bool {
+        self.allowed.contains(&size)
+    }
+
+    pub fn default_size(&self) -> Size {
+        self.default
+    }
+}
+
+#[test]
+fn test_board_sizes() {
+    let every = BoardSizes::new(&[], None);
+    assert_eq!(Size::ALL.to_vec(), every.allowed());
+    assert_eq!(Size::Nineteen, every.default_size());
+
+    let beginners = BoardSizes::new(&[Size::Thirteen, Size::Nine], Some(Size::Nine));
+    assert_eq!(vec![Size::Nine, Size::Thirteen], beginners.allowed());
+    assert_eq!(true, beginners.allows(Size::Nine));
+    assert_eq!(false, beginners.allows(Size::Nineteen));
+    assert_eq!(Size::Nine, beginners.default_size());
+    // A default that isn't offered falls back to the largest size offered
+    assert_eq!(
+        Size::Thirteen,
+        BoardSizes::new(&[Size::Nine, Size::Thirteen], Some(Size::Nineteen)).default_size()
+    );
+
+    let figment = Figment::new()
+        .merge(("sizes", [9, 13]))
+        .merge(("default_size", 9));
+    assert_eq!(beginners, BoardSizes::from_figment(&figment));
+    let figment = Figment::new().merge(("sizes", [7]));
+    assert_eq!(every, BoardSizes::from_figment(&figment));
+}
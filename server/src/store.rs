@@ -0,0 +1,158 @@
+use std::io;
+
+use rocket::serde::uuid::Uuid;
+use rocket::tokio::runtime::{Handle, RuntimeFlavor};
+use rocket::tokio::task::block_in_place;
+
+use crate::board::{Game, GamePhase};
+use crate::database::Database;
+use crate::journal::Journal;
+
+// GameStore is where the registry keeps games so they outlive the server. Every change is written
+// to the store before it is applied, the registry holds the games in memory in front of it.
+// Stores are synchronous: the registry calls them with only the game's own lock held, through
+// blocking so the server's other requests aren't held up behind them.
+//
+// Stores are chosen in Rocket.toml, the first one configured is used:
+//  [default]
+//  postgres = "host=localhost user=rustigo"  # with the postgres feature
+//  database = "/var/lib/rustigo/games.sqlite"
+//  journal = "/var/lib/rustigo/journal"
+pub trait GameStore: Send + Sync {
+    // create writes a game the store hasn't seen before.
+    fn create(&self, game_id: &Uuid, game: &Game) -> io::Result<()>;
+
+    // load reads back a game that wasn't listed at startup, None if it was never stored.
+    fn load(&self, game_id: &Uuid) -> io::Result<Option<Game>>;
+
+    // append_move writes the latest state of a game after an accepted change.
+    fn append_move(&self, game_id: &Uuid, game: &Game) -> io::Result<()>;
+
+    // list reads back the games to hold in memory at startup.
+    fn list(&self) -> io::Result<Vec<(Uuid, Game)>>;
+
+    // archived lists the finished games left out of list, which are only loaded when asked for.
+    fn archived(&self) -> io::Result<Vec<Uuid>>;
+
+    // expire forgets a game.
+    fn expire(&self, game_id: &Uuid) -> io::Result<()>;
+
+    // ping checks the store can still be written to, e.g. before a server is sent players.
+    fn ping(&self) -> io::Result<()>;
+}
+
+// blocking makes a call to a store. Routes call stores from the server's runtime, where the
+// worker hands its other tasks to the rest of the runtime before the call blocks it.
+pub fn blocking<R>(call: impl FnOnce() -> R) -> R {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => block_in_place(call),
+        _ => call(),
+    }
+}
+
+// phase_name is how a game's phase is kept in a store's index, so finished games can be left out
+// at startup. The names must not change, games already stored are listed by them.
+pub fn phase_name(phase: GamePhase) -> &'static str {
+    match phase {
+        GamePhase::Handicap => "Handicap",
+        GamePhase::Playing => "Playing",
+        GamePhase::Scoring => "Scoring",
+        GamePhase::Finished => "Finished",
+    }
+}
+
+// MemoryStore keeps nothing, games only live in the registry and are lost when the server stops.
+#[derive(Default)]
+pub struct MemoryStore;
+
+impl GameStore for MemoryStore {
+    fn create(&self, _game_id: &Uuid, _game: &Game) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load(&self, _game_id: &Uuid) -> io::Result<Option<Game>> {
+        Ok(None)
+    }
+
+    fn append_move(&self, _game_id: &Uuid, _game: &Game) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn list(&self) -> io::Result<Vec<(Uuid, Game)>> {
+        Ok(Vec::new())
+    }
+
+    fn archived(&self) -> io::Result<Vec<Uuid>> {
+        Ok(Vec::new())
+    }
+
+    fn expire(&self, _game_id: &Uuid) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn ping(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// The journal replays the games still being played, finished ones are loaded when asked for.
+impl GameStore for Journal {
+    fn create(&self, game_id: &Uuid, game: &Game) -> io::Result<()> {
+        self.append(game_id, game)
+    }
+
+    fn load(&self, game_id: &Uuid) -> io::Result<Option<Game>> {
+        Journal::load(self, game_id)
+    }
+
+    fn append_move(&self, game_id: &Uuid, game: &Game) -> io::Result<()> {
+        self.append(game_id, game)
+    }
+
+    fn list(&self) -> io::Result<Vec<(Uuid, Game)>> {
+        self.replay()
+    }
+
+    fn archived(&self) -> io::Result<Vec<Uuid>> {
+        self.finished()
+    }
+
+    fn expire(&self, game_id: &Uuid) -> io::Result<()> {
+        self.remove(game_id)
+    }
+
+    fn ping(&self) -> io::Result<()> {
+        Journal::ping(self)
+    }
+}
+
+// The database lists the games still being played, finished ones are loaded when asked for.
+impl GameStore for Database {
+    fn create(&self, game_id: &Uuid, game: &Game) -> io::Result<()> {
+        self.append(game_id, game)
+    }
+
+    fn load(&self, game_id: &Uuid) -> io::Result<Option<Game>> {
+        Database::load(self, game_id)
+    }
+
+    fn append_move(&self, game_id: &Uuid, game: &Game) -> io::Result<()> {
+        self.append(game_id, game)
+    }
+
+    fn list(&self) -> io::Result<Vec<(Uuid, Game)>> {
+        self.replay()
+    }
+
+    fn archived(&self) -> io::Result<Vec<Uuid>> {
+        self.finished()
+    }
+
+    fn expire(&self, game_id: &Uuid) -> io::Result<()> {
+        self.remove(game_id)
+    }
+
+    fn ping(&self) -> io::Result<()> {
+        Database::ping(self)
+    }
+}
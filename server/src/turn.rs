@@ -0,0 +1,129 @@
+use rustigo_protocol::GameStateMessage;
+
+use crate::board::{self, CoordinateError, Game, GamePhase, LedgerEvent, PlayError, Size, Stone};
+use crate::ledger;
+
+// Turn is what a player can do on their turn, as sent to the API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Turn {
+    Play(board::Coordinate),
+    Pass,
+    Resign,
+    // Accept agrees to the score marked once both players passed.
+    Accept,
+}
+
+impl Turn {
+    // parse reads a turn as a vertex in letter + number notation, `pass`, `resign` or `accept`.
+    pub fn parse(size: Size, value: &str) -> Result<Turn, CoordinateError> {
+        let value = value.trim();
+        match value.to_ascii_lowercase().as_str() {
+            "pass" => Ok(Turn::Pass),
+            "resign" => Ok(Turn::Resign),
+            "accept" => Ok(Turn::Accept),
+            _ => board::parse_coordinate(size, value).map(Turn::Play),
+        }
+    }
+}
+
+// update describes the stone just played for everyone else, with the stones it captured.
+pub fn update(game: &Game, captured: Vec<board::Coordinate>) -> GameStateMessage {
+    let played = game.last_move();
+    GameStateMessage::Update {
+        board: board::encode(game),
+        position: played.map(|(position, _)| position),
+        stone: played.map(|(_, stone)| stone),
+        captured,
+        atari: game.stones_in_atari(),
+        black_prisoners: game.prisoners(Stone::Black),
+        white_prisoners: game.prisoners(Stone::White),
+        move_number: game.move_number(),
+        turn: (game.phase() == GamePhase::Playing).then(|| game.turn()),
+    }
+}
+
+// take plays a turn, returning the events telling everyone else about it.
+pub fn take(game: &mut Game, stone: Stone, turn: Turn) -> Result<Vec<GameStateMessage>, PlayError> {
+    let phase = game.phase();
+    let mut events = Vec::new();
+    let event = match turn {
+        Turn::Play(position) => LedgerEvent::StonePlayed { stone, position },
+        Turn::Pass => LedgerEvent::Passed { stone },
+        Turn::Resign => LedgerEvent::Resigned { stone },
+        Turn::Accept => LedgerEvent::ScoreAccepted { stone },
+    };
+    let captured = ledger::apply(game, &event)?;
+    let board = board::encode(game);
+    events.push(match turn {
+        Turn::Play(_) => update(game, captured),
+        Turn::Pass => GameStateMessage::Pass { stone, board },
+        Turn::Resign => GameStateMessage::Resign { stone, board },
+        Turn::Accept => GameStateMessage::Accept { stone, board },
+    });
+    let new_phase = game.phase();
+    if new_phase != phase {
+        events.push(GameStateMessage::Phase { phase: new_phase });
+        if new_phase != GamePhase::Playing && turn != Turn::Resign {
+            events.push(GameStateMessage::Score {
+                score: game.score(),
+            });
+        }
+    }
+    Ok(events)
+}
+
+#[test]
+fn test_take() {
+    let mut game = board::new(Size::Nine);
+    assert_eq!(Ok(Turn::Play((3, 5))), Turn::parse(Size::Nine, "d4"));
+    assert_eq!(Ok(Turn::Pass), Turn::parse(Size::Nine, "PASS"));
+    assert_eq!(true, Turn::parse(Size::Nine, "z99").is_err());
+
+    assert_eq!(true, game.play_stone((2, 2), Stone::Black).is_ok());
+    let events = take(&mut game, Stone::White, Turn::Pass).unwrap();
+    assert_eq!(
+        vec!["pass"],
+        events.iter().map(|e| e.event_type()).collect::<Vec<_>>()
+    );
+    let events = take(&mut game, Stone::White, Turn::Pass);
+    assert_eq!(Err(PlayError::NotYourTurn), events.map(|_| ()));
+    let events = take(&mut game, Stone::Black, Turn::Pass).unwrap();
+    assert_eq!(
+        vec!["pass", "phase", "score"],
+        events.iter().map(|e| e.event_type()).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_update() {
+    let mut game = board::new(Size::Nine);
+    for (position, stone) in [
+        ((1, 0), Stone::Black),
+        ((0, 0), Stone::White),
+        ((8, 8), Stone::Black),
+    ] {
+        assert_eq!(true, game.play_stone(position, stone).is_ok());
+    }
+    let events = take(&mut game, Stone::White, Turn::Play((7, 7))).unwrap();
+    assert_eq!(true, game.play_stone((0, 1), Stone::Black).is_ok());
+    assert_eq!(
+        GameStateMessage::Update {
+            board: board::encode(&game),
+            position: Some((0, 1)),
+            stone: Some(Stone::Black),
+            captured: vec![(0, 0)],
+            atari: game.stones_in_atari(),
+            black_prisoners: 1,
+            white_prisoners: 0,
+            move_number: 5,
+            turn: Some(Stone::White),
+        },
+        update(&game, vec![(0, 0)])
+    );
+    match &events[..] {
+        [GameStateMessage::Update {
+            position, stone, ..
+        }] => assert_eq!((Some((7, 7)), Some(Stone::White)), (*position, *stone)),
+        other => panic!("expected an update, got {:?}", other),
+    }
+}
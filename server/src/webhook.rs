@@ -0,0 +1,281 @@
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use rocket::figment::Figment;
+use rocket::serde::json::to_string;
+use rocket::serde::uuid::Uuid;
+use rustigo_protocol::{GameStateMessage, WebhookEvent};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::dispatcher::{Channel, DispatchError, Notice};
+use crate::ledger;
+use crate::outbound::{self, Outbound};
+use crate::registry;
+
+// MAX_WEBHOOKS is how many webhooks a game can have.
+pub const MAX_WEBHOOKS: usize = 5;
+
+// SIGNATURE_HEADER carries the signature of a delivery, EVENT_HEADER the type of its event.
+pub const SIGNATURE_HEADER: &str = "X-Rustigo-Signature";
+pub const EVENT_HEADER: &str = "X-Rustigo-Event";
+
+// DEFAULT_RETRIES is how many times a failed delivery is tried again by default, DEFAULT_BACKOFF
+// how long to wait before the first retry, in milliseconds. Each wait is twice the last.
+pub const DEFAULT_RETRIES: u32 = 5;
+pub const DEFAULT_BACKOFF: u64 = 1000;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum WebhookError {
+    #[error("Webhooks are public http:// or https:// URLs, not {0:?}")]
+    BadUrl(String),
+    #[error("A game can have at most 5 webhooks")]
+    TooMany,
+}
+
+// Webhook is a URL a game's events are posted to, signed with its secret.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    pub url: String,
+    pub secret: String,
+}
+
+impl Webhook {
+    // new registers a URL with a fresh secret.
+    pub fn new(url: &str) -> Result<Webhook, WebhookError> {
+        let url = url.trim();
+        outbound::check_url(url).map_err(|_| WebhookError::BadUrl(url.to_string()))?;
+        Ok(Webhook {
+            url: url.to_string(),
+            secret: format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple()),
+        })
+    }
+}
+
+// parse_webhook reads a webhook from the `hooks` segment of a game record, as its hex encoded URL
+// and its secret.
+//
+// e.g:
+//  "68747470733a2f2f6578616d706c652e636f6d,0123abcd" => https://example.com, signed with 0123abcd
+pub fn parse_webhook(value: &str) -> Option<Webhook> {
+    let (url, secret) = value.split_once(',')?;
+    if secret.is_empty() || !secret.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some(Webhook {
+        url: ledger::unhex(url)?,
+        secret: secret.to_string(),
+    })
+}
+
+// format_webhook writes a webhook for a game record, it is the inverse of parse_webhook.
+pub fn format_webhook(webhook: &Webhook) -> String {
+    format!("{},{}", ledger::hex(&webhook.url), webhook.secret)
+}
+
+// sign is the signature of a delivery's body, sent in SIGNATURE_HEADER.
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body.as_bytes());
+    let digest: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("sha256={}", digest)
+}
+
+// hooked tests if webhooks are sent an event: players joining, moves and results.
+pub fn hooked(state: &GameStateMessage) -> bool {
+    matches!(
+        state,
+        GameStateMessage::Join { .. }
+            | GameStateMessage::JoinAccepted { .. }
+            | GameStateMessage::Update { .. }
+            | GameStateMessage::Pass { .. }
+            | GameStateMessage::Undo { .. }
+            | GameStateMessage::Phase { .. }
+            | GameStateMessage::Score { .. }
+            | GameStateMessage::Resign { .. }
+            | GameStateMessage::Timeout { .. }
+    )
+}
+
+// Delivery is how hard webhooks and turn notices are tried, a delivery that fails is tried again
+// up to `retries` times, first after `backoff_ms` and then twice as long each time. Webhooks that
+// answer with a client error other than 429 aren't tried again.
+//
+// e.g. in Rocket.toml:
+//  [default.webhooks]
+//  retries = 5
+//  backoff_ms = 1000
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Delivery {
+    pub retries: u32,
+    pub backoff_ms: u64,
+}
+
+impl Default for Delivery {
+    fn default() -> Delivery {
+        Delivery {
+            retries: DEFAULT_RETRIES,
+            backoff_ms: DEFAULT_BACKOFF,
+        }
+    }
+}
+
+impl Delivery {
+    pub fn from_figment(figment: &Figment) -> Delivery {
+        figment.extract_inner("webhooks").unwrap_or_default()
+    }
+
+    // backoff is how long to wait before retry number `retry`, counting from 0.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        Duration::from_millis(self.backoff_ms.saturating_mul(1 << retry.min(16)))
+    }
+}
+
+// notice is what a game's webhooks are sent for one of its events.
+pub fn notice(game_id: Uuid, state: &GameStateMessage) -> Notice {
+    let event = WebhookEvent {
+        game_id,
+        event: state.event_type().to_string(),
+        at: registry::now(),
+        state: state.clone(),
+    };
+    Notice {
+        subject: format!("{} in game {}", event.event, game_id),
+        text: String::new(),
+        body: to_string(&event).unwrap(),
+        event: event.event,
+    }
+}
+
+// Webhooks are sent the notice's body, signed with their secret.
+impl Channel for Webhook {
+    fn kind(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn send(&self, outbound: &Outbound, notice: &Notice) -> Result<(), DispatchError> {
+        let signature = sign(&self.secret, &notice.body);
+        let headers = [
+            (EVENT_HEADER, notice.event.as_str()),
+            (SIGNATURE_HEADER, signature.as_str()),
+        ];
+        Ok(outbound.post(&self.url, &headers, &notice.body)?)
+    }
+}
+
+#[test]
+fn test_webhook() {
+    let webhook = Webhook::new("https://example.com/hook?a=b;c").unwrap();
+    assert_eq!(64, webhook.secret.len());
+    let formatted = format_webhook(&webhook);
+    assert_eq!(false, formatted.contains([':', ';', '=']));
+    assert_eq!(Some(webhook), parse_webhook(&formatted));
+    for url in [
+        "ftp://example.com",
+        "https://",
+        "example.com",
+        "https://a b",
+        "http://169.254.169.254/latest/meta-data/",
+    ] {
+        assert_eq!(
+            Err(WebhookError::BadUrl(url.to_string())),
+            Webhook::new(url)
+        );
+    }
+
+    // RFC 4231 test case 2
+    assert_eq!(
+        "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        sign("Jefe", "what do ya want for nothing?")
+    );
+
+    assert_eq!(true, hooked(&GameStateMessage::Join { id: Uuid::new_v4() }));
+    assert_eq!(false, hooked(&GameStateMessage::Spectators { count: 1 }));
+}
+
+#[test]
+fn test_post() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::dispatcher::Dispatcher;
+
+    // The webhook fails the first delivery, then takes the retry
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for status in ["503 Service Unavailable", "200 OK"] {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut headers = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                headers.push(line.trim().to_string());
+            }
+            let length: usize = headers
+                .iter()
+                .find_map(|header| header.strip_prefix("Content-Length: "))
+                .map_or(0, |length| length.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            );
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            requests.push((headers, String::from_utf8(body).unwrap()));
+        }
+        requests
+    });
+
+    let webhook = Webhook {
+        url,
+        secret: "secret".to_string(),
+    };
+    let outbound = Outbound::new(outbound::Settings {
+        allow_private: true,
+        ..outbound::Settings::default()
+    });
+    let delivery = Delivery {
+        retries: 1,
+        backoff_ms: 1,
+    };
+    let dispatcher = Dispatcher::new(delivery, outbound);
+    let notice = Notice {
+        event: "pass".to_string(),
+        subject: String::new(),
+        text: String::new(),
+        body: r#"{"a":1}"#.to_string(),
+    };
+    assert_eq!(Ok(()), dispatcher.deliver(&webhook, &notice));
+    let requests = server.join().unwrap();
+    assert_eq!(2, requests.len());
+    let (headers, body) = &requests[1];
+    assert_eq!(r#"{"a":1}"#, body);
+    let signature = format!("{}: {}", SIGNATURE_HEADER, sign(&webhook.secret, body));
+    assert_eq!(true, headers.contains(&signature));
+    assert_eq!(true, headers.contains(&format!("{}: pass", EVENT_HEADER)));
+
+    assert_eq!(Duration::from_millis(4000), Delivery::default().backoff(2));
+
+    // Servers on the private network are only posted to when they are allowed
+    let outbound = Outbound::new(outbound::Settings::default());
+    assert_eq!(
+        Err(DispatchError::Refused(
+            outbound::OutboundError::BadUrl(webhook.url.clone()).to_string()
+        )),
+        webhook.send(&outbound, &notice)
+    );
+}